@@ -61,8 +61,20 @@ impl Client {
         self.post(&path, Some(credentials)).await
     }
 
+    /// Get the projects of all the accounts, going through all the pages
     pub async fn get_projects(&self) -> Result<Vec<project::AdminResponse>> {
-        self.get("/admin/projects").await
+        let mut projects = Vec::new();
+        let mut path = "/admin/projects".to_string();
+
+        loop {
+            let page: project::AdminPage = self.get(&path).await?;
+            projects.extend(page.projects);
+
+            match page.next_cursor {
+                Some(cursor) => path = format!("/admin/projects?cursor={cursor}"),
+                None => return Ok(projects),
+            }
+        }
     }
 
     pub async fn get_load(&self) -> Result<stats::LoadResponse> {
@@ -54,6 +54,7 @@ impl IntoResponse for Error {
             Json(ApiError {
                 message: self.to_string(),
                 status_code: code.as_u16(),
+                error: None,
             }),
         )
             .into_response()
@@ -54,7 +54,7 @@ pub async fn init(pool: SqlitePool, args: InitArgs) -> io::Result<()> {
     println!(
         "`{}` created as super user with key: {}",
         args.name,
-        key.as_ref()
+        key.expose()
     );
     Ok(())
 }
@@ -71,7 +71,7 @@ impl UserManagement for UserManager {
     }
 }
 
-#[derive(Clone, Deserialize, PartialEq, Eq, Serialize, Debug)]
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
 pub struct User {
     pub name: AccountName,
     pub key: ApiKey,
@@ -122,7 +122,8 @@ impl From<User> for shuttle_common::models::user::Response {
     fn from(user: User) -> Self {
         Self {
             name: user.name.to_string(),
-            key: user.key.as_ref().to_string(),
+            // The one place the key is handed back
+            key: user.key.expose().to_string(),
             account_tier: user.account_tier.to_string(),
         }
     }
@@ -179,6 +179,10 @@ pub struct ProjectStartArgs {
     /// How long to wait before putting the project in an idle state due to inactivity.
     /// 0 means the project will never idle
     pub idle_minutes: u64,
+    /// Whether the project can reach the internet. Without it, it can only reach the
+    /// services of the platform, such as its databases
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub allow_internet: bool,
 }
 
 #[derive(Parser, Clone, Debug)]
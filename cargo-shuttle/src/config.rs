@@ -132,7 +132,7 @@ impl GlobalConfig {
     }
 
     pub fn set_api_key(&mut self, api_key: ApiKey) -> Option<String> {
-        self.api_key.replace(api_key.as_ref().to_string())
+        self.api_key.replace(api_key.expose().to_string())
     }
 
     pub fn clear_api_key(&mut self) {
@@ -118,11 +118,11 @@ impl Shuttle {
             Command::Stop => self.stop(&self.client()?).await,
             Command::Clean => self.clean(&self.client()?).await,
             Command::Secrets => self.secrets(&self.client()?).await,
-            Command::Project(ProjectCommand::Start(ProjectStartArgs { idle_minutes })) => {
-                self.project_create(&self.client()?, idle_minutes).await
+            Command::Project(ProjectCommand::Start(args)) => {
+                self.project_create(&self.client()?, args).await
             }
-            Command::Project(ProjectCommand::Restart(ProjectStartArgs { idle_minutes })) => {
-                self.project_recreate(&self.client()?, idle_minutes).await
+            Command::Project(ProjectCommand::Restart(args)) => {
+                self.project_recreate(&self.client()?, args).await
             }
             Command::Project(ProjectCommand::Status { follow }) => {
                 self.project_status(&self.client()?, follow).await
@@ -235,7 +235,14 @@ impl Shuttle {
             project_args.working_directory = path;
 
             self.load_project(&mut project_args)?;
-            self.project_create(&self.client()?, IDLE_MINUTES).await?;
+            self.project_create(
+                &self.client()?,
+                ProjectStartArgs {
+                    idle_minutes: IDLE_MINUTES,
+                    allow_internet: true,
+                },
+            )
+            .await?;
         }
 
         Ok(())
@@ -938,8 +945,18 @@ impl Shuttle {
         }
     }
 
-    async fn project_create(&self, client: &Client, idle_minutes: u64) -> Result<()> {
-        let config = project::Config { idle_minutes };
+    async fn project_create(
+        &self,
+        client: &Client,
+        ProjectStartArgs {
+            idle_minutes,
+            allow_internet,
+        }: ProjectStartArgs,
+    ) -> Result<()> {
+        let config = project::Config {
+            idle_minutes,
+            allow_internet,
+        };
 
         self.wait_with_spinner(
             &[
@@ -957,9 +974,9 @@ impl Shuttle {
         Ok(())
     }
 
-    async fn project_recreate(&self, client: &Client, idle_minutes: u64) -> Result<()> {
+    async fn project_recreate(&self, client: &Client, args: ProjectStartArgs) -> Result<()> {
         self.project_delete(client).await?;
-        self.project_create(client, idle_minutes).await?;
+        self.project_create(client, args).await?;
 
         Ok(())
     }
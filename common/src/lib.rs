@@ -43,7 +43,10 @@ pub type Host = String;
 #[cfg(feature = "service")]
 pub type DeploymentId = Uuid;
 
-#[derive(Clone, Serialize, Deserialize)]
+/// An API key, which never shows up in logs: it is formatted as its first
+/// few characters only, and does not serialize. Getting the key itself
+/// takes an explicit [`ApiKey::expose`].
+#[derive(Clone, Deserialize)]
 #[cfg_attr(feature = "persist", derive(sqlx::Type, PartialEq, Hash, Eq))]
 #[cfg_attr(feature = "persist", serde(transparent))]
 #[cfg_attr(feature = "persist", sqlx(transparent))]
@@ -76,25 +79,34 @@ impl ApiKey {
 
         Self(Alphanumeric.sample_string(&mut rand::thread_rng(), 16))
     }
-}
 
-impl AsRef<str> for ApiKey {
-    fn as_ref(&self) -> &str {
+    /// The key itself, to send it or to give it to its owner. Never log it.
+    pub fn expose(&self) -> &str {
         &self.0
     }
+
+    /// What the key is formatted as: enough of it to tell keys apart
+    fn redacted(&self) -> String {
+        let prefix: String = self.0.chars().take(API_KEY_PREFIX_LEN).collect();
+
+        format!("{prefix}\u{2026}redacted\u{2026}")
+    }
 }
 
+/// How many characters of an [`ApiKey`] are left when formatting it
+const API_KEY_PREFIX_LEN: usize = 4;
+
 // Ensure we can't accidentaly log an ApiKey
 impl Debug for ApiKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ApiKey: REDACTED")
+        write!(f, "ApiKey({})", self.redacted())
     }
 }
 
 // Ensure we can't accidentaly log an ApiKey
 impl Display for ApiKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        f.write_str(&self.redacted())
     }
 }
 
@@ -213,7 +225,24 @@ mod tests {
     fn generated_api_key_is_valid() {
         let key = ApiKey::generate();
 
-        assert!(ApiKey::parse(key.as_ref()).is_ok());
+        assert!(ApiKey::parse(key.expose()).is_ok());
+    }
+
+    #[test]
+    fn api_key_is_redacted_when_formatted() {
+        let key = ApiKey::parse("dh9z58jttoes3qvq").unwrap();
+
+        for formatted in [format!("{key}"), format!("{key:?}"), format!("{key:#?}")] {
+            assert!(!formatted.contains("58jttoes3qvq"), "{formatted}");
+            assert!(
+                formatted.contains("dh9z\u{2026}redacted\u{2026}"),
+                "{formatted}"
+            );
+        }
+
+        // Nor when it is part of something else
+        let formatted = format!("{:?}", Some(vec![key.clone()]));
+        assert!(!formatted.contains(key.expose()), "{formatted}");
     }
 
     #[test]
@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
 
 use comfy_table::Color;
@@ -5,11 +6,33 @@ use crossterm::style::Stylize;
 use http::StatusCode;
 use serde::{Deserialize, Serialize};
 use tracing::{error, warn};
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[cfg_attr(feature = "openapi", schema(as = shuttle_common::models::error::ApiError))]
 pub struct ApiError {
     pub message: String,
     pub status_code: u16,
+    /// What kind of error this is, for clients to tell errors apart
+    /// without going by their message. Only errors made from an
+    /// [`ErrorKind`] have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorCode>,
+}
+
+/// The [code](ErrorKind::code) of the kind of an [`ApiError`], with its
+/// message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[cfg_attr(feature = "openapi", schema(as = shuttle_common::models::error::ErrorCode))]
+pub struct ErrorCode {
+    /// A string of the codes clients know might come from a newer server,
+    /// so this is not an [`ErrorKind`] outright
+    #[cfg_attr(feature = "openapi", schema(value_type = shuttle_common::models::error::ErrorKind))]
+    pub code: String,
+    pub message: String,
 }
 
 impl ApiError {
@@ -31,7 +54,13 @@ impl Display for ApiError {
 
 impl std::error::Error for ApiError {}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+/// Serialized as its [code](ErrorKind::code)
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::Display, strum::EnumIter,
+)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[cfg_attr(feature = "openapi", schema(as = shuttle_common::models::error::ErrorKind))]
 pub enum ErrorKind {
     KeyMissing,
     BadHost,
@@ -40,18 +69,122 @@ pub enum ErrorKind {
     Forbidden,
     UserNotFound,
     UserAlreadyExists,
+    InvalidAccountName,
+    InvalidEmail,
     ProjectNotFound,
     InvalidProjectName,
     ProjectAlreadyExists,
+    /// A project cannot take the request yet, as it is still starting or
+    /// has no deployment to serve it. Other projects are not affected.
     ProjectNotReady,
     ProjectUnavailable,
     CustomDomainNotFound,
+    OperationNotFound,
     InvalidCustomDomain,
     CustomDomainAlreadyExists,
     InvalidOperation,
     Internal,
+    /// The gateway itself cannot take requests yet, or anymore, as it is
+    /// starting or has lost what it needs to act on them (e.g. its worker).
+    /// Every project is affected.
     NotReady,
     ServiceUnavailable,
+    StateStoreUnavailable,
+    DockerUnavailable,
+    Conflict,
+    ArtifactTooLarge,
+    QuotaExceeded,
+    RateLimited,
+}
+
+impl ErrorKind {
+    /// What this kind is called in the body of error responses. These
+    /// are for clients to match on, so they must never change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::KeyMissing => "key_missing",
+            Self::BadHost => "bad_host",
+            Self::KeyMalformed => "key_malformed",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::UserNotFound => "user_not_found",
+            Self::UserAlreadyExists => "user_already_exists",
+            Self::InvalidAccountName => "invalid_account_name",
+            Self::InvalidEmail => "invalid_email",
+            Self::ProjectNotFound => "project_not_found",
+            Self::InvalidProjectName => "invalid_project_name",
+            Self::ProjectAlreadyExists => "project_already_exists",
+            Self::ProjectNotReady => "project_not_ready",
+            Self::ProjectUnavailable => "project_unavailable",
+            Self::CustomDomainNotFound => "custom_domain_not_found",
+            Self::OperationNotFound => "operation_not_found",
+            Self::InvalidCustomDomain => "invalid_custom_domain",
+            Self::CustomDomainAlreadyExists => "custom_domain_already_exists",
+            Self::InvalidOperation => "invalid_operation",
+            Self::Internal => "internal",
+            Self::NotReady => "not_ready",
+            Self::ServiceUnavailable => "service_unavailable",
+            Self::StateStoreUnavailable => "state_store_unavailable",
+            Self::DockerUnavailable => "docker_unavailable",
+            Self::Conflict => "conflict",
+            Self::ArtifactTooLarge => "artifact_too_large",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::RateLimited => "rate_limited",
+        }
+    }
+
+    /// The kind with this [code](Self::code), if any
+    pub fn from_code(code: &str) -> Option<Self> {
+        <Self as strum::IntoEnumIterator>::iter().find(|kind| kind.code() == code)
+    }
+
+    /// How bad an error of this kind is, higher being worse. Problems on
+    /// our side rank above the ones with the request.
+    pub fn severity(&self) -> u8 {
+        match self {
+            Self::Internal => 6,
+            Self::NotReady
+            | Self::ServiceUnavailable
+            | Self::StateStoreUnavailable
+            | Self::DockerUnavailable
+            | Self::ProjectUnavailable
+            | Self::ProjectNotReady => 5,
+            Self::Unauthorized | Self::KeyMissing | Self::KeyMalformed => 4,
+            Self::Forbidden => 3,
+            Self::UserNotFound
+            | Self::ProjectNotFound
+            | Self::CustomDomainNotFound
+            | Self::OperationNotFound => 2,
+            Self::BadHost
+            | Self::UserAlreadyExists
+            | Self::InvalidAccountName
+            | Self::InvalidEmail
+            | Self::InvalidProjectName
+            | Self::ProjectAlreadyExists
+            | Self::InvalidCustomDomain
+            | Self::CustomDomainAlreadyExists
+            | Self::InvalidOperation
+            | Self::Conflict
+            | Self::ArtifactTooLarge
+            | Self::QuotaExceeded
+            | Self::RateLimited => 1,
+        }
+    }
+}
+
+/// Kinds are ranked by [`ErrorKind::severity`]. Different kinds of the same
+/// severity cannot be compared.
+impl PartialOrd for ErrorKind {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self == other {
+            return Some(Ordering::Equal);
+        }
+
+        match self.severity().cmp(&other.severity()) {
+            Ordering::Equal => None,
+            ordering => Some(ordering),
+        }
+    }
 }
 
 impl From<ErrorKind> for ApiError {
@@ -67,6 +200,11 @@ impl From<ErrorKind> for ApiError {
             ErrorKind::BadHost => (StatusCode::BAD_REQUEST, "the 'Host' header is invalid"),
             ErrorKind::UserNotFound => (StatusCode::NOT_FOUND, "user not found"),
             ErrorKind::UserAlreadyExists => (StatusCode::BAD_REQUEST, "user already exists"),
+            ErrorKind::InvalidAccountName => (
+                StatusCode::BAD_REQUEST,
+                "invalid account name: it must be 3 to 32 lowercase letters, digits, `-` or `_`",
+            ),
+            ErrorKind::InvalidEmail => (StatusCode::BAD_REQUEST, "invalid email address"),
             ErrorKind::ProjectNotFound => (
                 StatusCode::NOT_FOUND,
                 "project not found. Run `cargo shuttle project start` to create a new project.",
@@ -97,16 +235,47 @@ impl From<ErrorKind> for ApiError {
             ),
             ErrorKind::InvalidCustomDomain => (StatusCode::BAD_REQUEST, "invalid custom domain"),
             ErrorKind::CustomDomainNotFound => (StatusCode::NOT_FOUND, "custom domain not found"),
+            ErrorKind::OperationNotFound => (StatusCode::NOT_FOUND, "operation not found"),
             ErrorKind::CustomDomainAlreadyExists => {
                 (StatusCode::BAD_REQUEST, "custom domain already in use")
             }
             ErrorKind::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized"),
             ErrorKind::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
-            ErrorKind::NotReady => (StatusCode::INTERNAL_SERVER_ERROR, "service not ready"),
+            ErrorKind::NotReady => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "the gateway is not ready, please try again in a little bit",
+            ),
+            ErrorKind::StateStoreUnavailable => {
+                (StatusCode::SERVICE_UNAVAILABLE, "state store unavailable")
+            }
+            ErrorKind::DockerUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "the container runtime is unavailable, please try again in a little bit",
+            ),
+            ErrorKind::ArtifactTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "the artifact is larger than the gateway accepts",
+            ),
+            ErrorKind::Conflict => (
+                StatusCode::CONFLICT,
+                "the resource was modified concurrently, please try again",
+            ),
+            ErrorKind::QuotaExceeded => (
+                StatusCode::FORBIDDEN,
+                "the account has used up its quota for this",
+            ),
+            ErrorKind::RateLimited => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too many requests, please try again later",
+            ),
         };
         Self {
             message: error_message.to_string(),
             status_code: status.as_u16(),
+            error: Some(ErrorCode {
+                code: kind.code().to_string(),
+                message: error_message.to_string(),
+            }),
         }
     }
 }
@@ -144,6 +313,116 @@ impl From<StatusCode> for ApiError {
         Self {
             message: message.to_string(),
             status_code: code.as_u16(),
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+
+    #[test]
+    fn error_kind_codes_are_stable() {
+        // Exhaustive, for a new kind to need its code and status written
+        // down here
+        let expected = |kind: ErrorKind| match kind {
+            ErrorKind::KeyMissing => ("key_missing", 401),
+            ErrorKind::BadHost => ("bad_host", 400),
+            ErrorKind::KeyMalformed => ("key_malformed", 400),
+            ErrorKind::Unauthorized => ("unauthorized", 401),
+            ErrorKind::Forbidden => ("forbidden", 403),
+            ErrorKind::UserNotFound => ("user_not_found", 404),
+            ErrorKind::UserAlreadyExists => ("user_already_exists", 400),
+            ErrorKind::InvalidAccountName => ("invalid_account_name", 400),
+            ErrorKind::InvalidEmail => ("invalid_email", 400),
+            ErrorKind::ProjectNotFound => ("project_not_found", 404),
+            ErrorKind::InvalidProjectName => ("invalid_project_name", 400),
+            ErrorKind::ProjectAlreadyExists => ("project_already_exists", 400),
+            ErrorKind::ProjectNotReady => ("project_not_ready", 503),
+            ErrorKind::ProjectUnavailable => ("project_unavailable", 502),
+            ErrorKind::CustomDomainNotFound => ("custom_domain_not_found", 404),
+            ErrorKind::OperationNotFound => ("operation_not_found", 404),
+            ErrorKind::InvalidCustomDomain => ("invalid_custom_domain", 400),
+            ErrorKind::CustomDomainAlreadyExists => ("custom_domain_already_exists", 400),
+            ErrorKind::InvalidOperation => ("invalid_operation", 400),
+            ErrorKind::Internal => ("internal", 500),
+            ErrorKind::NotReady => ("not_ready", 503),
+            ErrorKind::ServiceUnavailable => ("service_unavailable", 503),
+            ErrorKind::StateStoreUnavailable => ("state_store_unavailable", 503),
+            ErrorKind::DockerUnavailable => ("docker_unavailable", 503),
+            ErrorKind::Conflict => ("conflict", 409),
+            ErrorKind::ArtifactTooLarge => ("artifact_too_large", 413),
+            ErrorKind::QuotaExceeded => ("quota_exceeded", 403),
+            ErrorKind::RateLimited => ("rate_limited", 429),
+        };
+
+        for kind in ErrorKind::iter() {
+            let (code, status) = expected(kind);
+            let api_error = ApiError::from(kind);
+
+            assert_eq!(kind.code(), code);
+            assert_eq!(api_error.status_code, status, "{kind}");
+            assert_eq!(
+                api_error.error,
+                Some(ErrorCode {
+                    code: code.to_string(),
+                    message: api_error.message.clone(),
+                })
+            );
+
+            assert_eq!(serde_json::to_value(kind).unwrap(), code);
+            assert_eq!(ErrorKind::from_code(code), Some(kind));
+        }
+
+        assert_eq!(ErrorKind::from_code("ProjectNotFound"), None);
+    }
+
+    #[test]
+    fn api_error_body() {
+        let body = serde_json::to_value(ApiError::from(ErrorKind::Forbidden)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "message": "forbidden",
+                "status_code": 403,
+                "error": { "code": "forbidden", "message": "forbidden" },
+            })
+        );
+
+        // Bodies from before there were codes still make errors
+        let api_error: ApiError =
+            serde_json::from_str(r#"{"message": "forbidden", "status_code": 403}"#).unwrap();
+        assert_eq!(api_error.error, None);
+    }
+
+    #[test]
+    fn error_kind_ordering() {
+        assert!(ErrorKind::Internal > ErrorKind::Unauthorized);
+        assert!(ErrorKind::Unauthorized > ErrorKind::Forbidden);
+        assert!(ErrorKind::Forbidden > ErrorKind::ProjectNotFound);
+        assert!(ErrorKind::ProjectNotFound > ErrorKind::InvalidProjectName);
+        assert_eq!(
+            ErrorKind::ProjectNotFound.partial_cmp(&ErrorKind::UserNotFound),
+            None
+        );
+
+        for a in ErrorKind::iter() {
+            assert_eq!(a.partial_cmp(&a), Some(Ordering::Equal));
+
+            for b in ErrorKind::iter() {
+                // Antisymmetric
+                assert_eq!(a.partial_cmp(&b), b.partial_cmp(&a).map(Ordering::reverse));
+
+                for c in ErrorKind::iter() {
+                    // Transitive
+                    if a > b && b > c {
+                        assert!(a > c, "{a} > {b} > {c} but not {a} > {c}");
+                    }
+                }
+            }
         }
     }
 }
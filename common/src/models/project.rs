@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use comfy_table::{
     modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL, Cell, CellAlignment, Color,
     ContentArrangement, Table,
@@ -5,6 +6,7 @@ use comfy_table::{
 use crossterm::style::Stylize;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
 use strum::EnumString;
 #[cfg(feature = "openapi")]
 use utoipa::ToSchema;
@@ -17,6 +19,12 @@ pub const fn idle_minutes() -> u64 {
     IDLE_MINUTES
 }
 
+/// Function to let projects reach the internet by default, as a serde
+/// default
+pub const fn allow_internet() -> bool {
+    true
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 #[cfg_attr(feature = "openapi", derive(ToSchema))]
 #[cfg_attr(feature = "openapi", schema(as = shuttle_common::models::project::Response))]
@@ -24,6 +32,13 @@ pub struct Response {
     pub name: String,
     #[cfg_attr(feature = "openapi", schema(value_type = shuttle_common::models::project::State))]
     pub state: State,
+    /// The deployment the project is serving, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment_id: Option<String>,
+    /// The operation queued up on the project by the request, to follow
+    /// along with until it is over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation_id: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, EnumString)]
@@ -170,10 +185,123 @@ impl State {
     }
 }
 
+/// The state a project is in, in the one form every endpoint gives it out
+/// in: its name under `state`, and what there is to know about the project
+/// in that state under `details`, such as the container it runs in.
+/// Timestamps are those docker gave for the container.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "state", content = "details", rename_all = "lowercase")]
+pub enum ProjectStateView {
+    Creating {
+        recreate_count: usize,
+    },
+    Attaching {
+        container_id: Option<String>,
+        recreate_count: usize,
+    },
+    Recreating {
+        container_id: Option<String>,
+        recreate_count: usize,
+    },
+    Starting {
+        container_id: Option<String>,
+        restart_count: usize,
+    },
+    Restarting {
+        container_id: Option<String>,
+        restart_count: usize,
+    },
+    Started {
+        container_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        started_at: Option<DateTime<Utc>>,
+    },
+    Ready {
+        container_id: Option<String>,
+        target_ip: IpAddr,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        started_at: Option<DateTime<Utc>>,
+    },
+    Rebooting {
+        container_id: Option<String>,
+    },
+    Stopping {
+        container_id: Option<String>,
+    },
+    Stopped {
+        container_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        finished_at: Option<DateTime<Utc>>,
+    },
+    Destroying {
+        container_id: Option<String>,
+    },
+    Destroyed {},
+    Errored {
+        /// The container the project was in, if it had one yet
+        container_id: Option<String>,
+        kind: ErroredKind,
+        message: String,
+        /// The state the project errored out of, when known
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        previous: Option<String>,
+    },
+}
+
+/// Why a project errored
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErroredKind {
+    Internal,
+    NoNetwork,
+    TimedOut,
+    RetriesExhausted,
+    TaskDeadline,
+    InternalPanic,
+}
+
+/// The short form of the state, for the clients which only show it
+impl From<ProjectStateView> for State {
+    fn from(view: ProjectStateView) -> Self {
+        match view {
+            ProjectStateView::Creating { recreate_count } => Self::Creating { recreate_count },
+            ProjectStateView::Attaching { recreate_count, .. } => {
+                Self::Attaching { recreate_count }
+            }
+            ProjectStateView::Recreating { recreate_count, .. } => {
+                Self::Recreating { recreate_count }
+            }
+            ProjectStateView::Starting { restart_count, .. } => Self::Starting { restart_count },
+            ProjectStateView::Restarting { restart_count, .. } => {
+                Self::Restarting { restart_count }
+            }
+            ProjectStateView::Started { .. } => Self::Started,
+            ProjectStateView::Ready { .. } => Self::Ready,
+            ProjectStateView::Rebooting { .. } => Self::Rebooting,
+            ProjectStateView::Stopping { .. } => Self::Stopping,
+            ProjectStateView::Stopped { .. } => Self::Stopped,
+            ProjectStateView::Destroying { .. } => Self::Destroying,
+            ProjectStateView::Destroyed {} => Self::Destroyed,
+            ProjectStateView::Errored { message, .. } => Self::Errored { message },
+        }
+    }
+}
+
 /// Config when creating a new project
 #[derive(Deserialize, Serialize)]
 pub struct Config {
     pub idle_minutes: u64,
+    /// Whether the project can reach the internet, as opposed to only the
+    /// services of the platform
+    #[serde(default = "allow_internet")]
+    pub allow_internet: bool,
+}
+
+/// What the runtime of a project is allowed to reach over the network
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[cfg_attr(feature = "openapi", schema(as = shuttle_common::models::project::NetworkPolicy))]
+pub struct NetworkPolicy {
+    pub allow_internet: bool,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -182,6 +310,35 @@ pub struct Config {
 pub struct AdminResponse {
     pub project_name: String,
     pub account_name: String,
+    /// Whether the gateway gave up on the project after it kept failing
+    #[serde(default)]
+    pub dead_lettered: bool,
+    /// The state the project is in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<Object>))]
+    pub state: Option<ProjectStateView>,
+    /// When the project was first created, unknown for the projects which
+    /// predate it being recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, format = DateTime))]
+    pub created_at: Option<DateTime<Utc>>,
+    /// The status of the container of the project, as docker gives it
+    /// (e.g. `running`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_status: Option<String>,
+}
+
+/// A page of the projects of all the accounts
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[cfg_attr(feature = "openapi", schema(as = shuttle_common::models::project::AdminPage))]
+pub struct AdminPage {
+    pub projects: Vec<AdminResponse>,
+    /// How many projects there are across all the pages
+    pub total: u64,
+    /// What to pass as the `cursor` to get the next page, if there is one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
 pub fn get_table(projects: &Vec<Response>) -> String {
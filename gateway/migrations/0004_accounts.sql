@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS accounts (
+  account_name TEXT PRIMARY KEY,
+  account_tier TEXT DEFAULT "basic" NOT NULL,
+  suspended BOOLEAN DEFAULT FALSE NOT NULL,
+  super_user BOOLEAN DEFAULT FALSE NOT NULL,
+  created_at TEXT NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE projects ADD version INTEGER DEFAULT 0 NOT NULL;
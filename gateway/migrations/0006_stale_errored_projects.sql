@@ -0,0 +1,10 @@
+ALTER TABLE projects ADD errored_at TEXT;
+ALTER TABLE projects ADD last_interaction_at TEXT;
+ALTER TABLE projects ADD stale_notified_at TEXT;
+
+CREATE TABLE IF NOT EXISTS archived_projects (
+  project_name TEXT NOT NULL,
+  account_name TEXT NOT NULL,
+  project_state JSON NOT NULL,
+  archived_at TEXT NOT NULL
+);
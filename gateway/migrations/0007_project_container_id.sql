@@ -0,0 +1 @@
+ALTER TABLE projects ADD container_id TEXT;
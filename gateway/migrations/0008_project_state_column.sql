@@ -0,0 +1,3 @@
+ALTER TABLE projects ADD state TEXT;
+UPDATE projects SET state = (SELECT key FROM json_each(projects.project_state));
+CREATE INDEX IF NOT EXISTS projects_state ON projects (state);
@@ -0,0 +1,8 @@
+ALTER TABLE projects ADD destroyed_at TEXT;
+
+-- Projects destroyed before this was tracked start their retention
+-- window now
+UPDATE projects SET destroyed_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE state = 'destroyed';
+
+CREATE INDEX IF NOT EXISTS projects_destroyed_at ON projects (destroyed_at);
+CREATE INDEX IF NOT EXISTS archived_projects_archived_at ON archived_projects (archived_at);
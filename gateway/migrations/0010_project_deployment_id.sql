@@ -0,0 +1 @@
+ALTER TABLE projects ADD deployment_id TEXT;
@@ -0,0 +1,3 @@
+ALTER TABLE projects ADD lease_holder TEXT;
+-- Unix timestamp in milliseconds
+ALTER TABLE projects ADD lease_expires_at INTEGER;
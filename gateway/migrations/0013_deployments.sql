@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS deployments (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON DELETE CASCADE,
+  image TEXT NOT NULL,
+  -- Hex encoded SHA256 of the environment of the container, sorted
+  env_hash TEXT NOT NULL,
+  deployed_at TEXT NOT NULL,
+  deployed_by TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS deployments_project_name ON deployments (project_name);
@@ -0,0 +1,5 @@
+-- Failed attempts at the worker task currently running for a project
+ALTER TABLE projects ADD task_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE projects ADD task_next_retry_at TEXT;
+-- JSON array of the errors of the failed attempts, oldest first
+ALTER TABLE projects ADD task_errors TEXT;
@@ -0,0 +1,4 @@
+-- Set when the worker gave up on a project, which keeps it out of the
+-- automatic sweeps until a user or admin acts on it
+ALTER TABLE projects ADD dead_lettered_at TEXT;
+ALTER TABLE projects ADD dead_letter_error TEXT;
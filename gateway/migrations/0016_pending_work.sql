@@ -0,0 +1,8 @@
+-- Work the worker could not get through before the gateway shut down,
+-- to be picked up again on the next start
+CREATE TABLE IF NOT EXISTS pending_work (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON DELETE CASCADE,
+  description TEXT,
+  persisted_at TEXT NOT NULL
+);
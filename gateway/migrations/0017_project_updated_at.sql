@@ -0,0 +1,8 @@
+-- When the state of a project was last written, to tell the projects
+-- which are stuck in the middle of a transition
+ALTER TABLE projects ADD updated_at TEXT;
+
+-- Projects written before this was tracked start the clock now
+UPDATE projects SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now');
+
+CREATE INDEX IF NOT EXISTS projects_state_updated_at ON projects (state, updated_at);
@@ -0,0 +1,21 @@
+-- Tells the deployments of a project apart, and how each of them went
+ALTER TABLE deployments ADD deployment_id TEXT;
+ALTER TABLE deployments ADD finished_at TEXT;
+ALTER TABLE deployments ADD status TEXT NOT NULL DEFAULT 'superseded';
+
+-- Random version 4 UUIDs for the deployments made before they had one
+UPDATE deployments SET deployment_id = lower(
+  hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' ||
+  substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+);
+
+-- The latest deployment of each project is the one it is on, the others
+-- were over as soon as the next one was made
+UPDATE deployments SET status = 'active'
+  WHERE id IN (SELECT MAX(id) FROM deployments GROUP BY project_name);
+UPDATE deployments SET finished_at = (
+  SELECT MIN(later.deployed_at) FROM deployments AS later
+    WHERE later.project_name = deployments.project_name AND later.id > deployments.id
+) WHERE status = 'superseded';
+
+CREATE UNIQUE INDEX IF NOT EXISTS deployments_deployment_id ON deployments (deployment_id);
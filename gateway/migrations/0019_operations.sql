@@ -0,0 +1,17 @@
+-- The work queued up on projects and how it went, for the API to tell
+CREATE TABLE IF NOT EXISTS operations (
+  operation_id TEXT PRIMARY KEY,
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON DELETE CASCADE,
+  operation TEXT NOT NULL,
+  origin TEXT NOT NULL,
+  request_id TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'queued',
+  reason TEXT,
+  state TEXT,
+  enqueued_at TEXT NOT NULL,
+  started_at TEXT,
+  finished_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS operations_project_name ON operations (project_name, enqueued_at);
+CREATE INDEX IF NOT EXISTS operations_finished_at ON operations (finished_at);
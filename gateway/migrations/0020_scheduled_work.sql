@@ -0,0 +1,14 @@
+-- Work to be queued up for a project once `run_after` has passed, kept
+-- here so that it survives restarts of the gateway
+CREATE TABLE IF NOT EXISTS scheduled_work (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON DELETE CASCADE,
+  operation TEXT NOT NULL,
+  origin TEXT NOT NULL,
+  priority TEXT NOT NULL,
+  request_id TEXT NOT NULL,
+  run_after TEXT NOT NULL,
+  scheduled_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_work_run_after ON scheduled_work (run_after);
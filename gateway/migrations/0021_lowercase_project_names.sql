@@ -0,0 +1,62 @@
+-- Project names are parsed in lowercase, as the DNS labels they are served
+-- under are, so a project saved under a name with capitals in it can only
+-- be found again once it is saved under its lowercase name. Those are
+-- renamed here, along with everything that refers to them, unless another
+-- project already has the same name in some other case. Such collisions
+-- are left as they are, for the gateway to report when it starts, since
+-- there is no telling here which of the projects should keep the name.
+CREATE TEMP TABLE project_renames AS
+  SELECT project_name AS old_name, lower(project_name) AS new_name
+  FROM projects
+  WHERE project_name != lower(project_name)
+    AND (
+      SELECT COUNT(*) FROM projects other
+      WHERE lower(other.project_name) = lower(projects.project_name)
+    ) = 1;
+
+-- The references are renamed before the projects they refer to are
+PRAGMA defer_foreign_keys = ON;
+
+UPDATE custom_domains
+  SET project_name = (SELECT new_name FROM project_renames WHERE old_name = custom_domains.project_name)
+  WHERE project_name IN (SELECT old_name FROM project_renames);
+
+UPDATE archived_projects
+  SET project_name = (SELECT new_name FROM project_renames WHERE old_name = archived_projects.project_name)
+  WHERE project_name IN (SELECT old_name FROM project_renames);
+
+UPDATE artifacts
+  SET project_name = (SELECT new_name FROM project_renames WHERE old_name = artifacts.project_name)
+  WHERE project_name IN (SELECT old_name FROM project_renames);
+
+UPDATE deployments
+  SET project_name = (SELECT new_name FROM project_renames WHERE old_name = deployments.project_name)
+  WHERE project_name IN (SELECT old_name FROM project_renames);
+
+UPDATE pending_work
+  SET project_name = (SELECT new_name FROM project_renames WHERE old_name = pending_work.project_name)
+  WHERE project_name IN (SELECT old_name FROM project_renames);
+
+UPDATE operations
+  SET project_name = (SELECT new_name FROM project_renames WHERE old_name = operations.project_name)
+  WHERE project_name IN (SELECT old_name FROM project_renames);
+
+UPDATE scheduled_work
+  SET project_name = (SELECT new_name FROM project_renames WHERE old_name = scheduled_work.project_name)
+  WHERE project_name IN (SELECT old_name FROM project_renames);
+
+UPDATE projects
+  SET project_name = (SELECT new_name FROM project_renames WHERE old_name = projects.project_name)
+  WHERE project_name IN (SELECT old_name FROM project_renames);
+
+DROP TABLE project_renames;
+
+-- A unique index on `lower(project_name)` cannot be had for as long as
+-- there are collisions, so no new project can take a name which only
+-- differs in case from one already taken instead
+CREATE TRIGGER IF NOT EXISTS projects_unique_lowercase_name
+  BEFORE INSERT ON projects
+  WHEN EXISTS (SELECT 1 FROM projects WHERE lower(project_name) = lower(NEW.project_name))
+BEGIN
+  SELECT RAISE(ABORT, 'UNIQUE constraint failed: lower(projects.project_name)');
+END;
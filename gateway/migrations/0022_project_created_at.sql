@@ -0,0 +1,3 @@
+-- When a project was first created, for admins to list. Projects created
+-- before this was tracked have none, as there is no telling when that was.
+ALTER TABLE projects ADD created_at TEXT;
@@ -0,0 +1,9 @@
+-- The verified email address of an account, the only one it is ever
+-- notified at
+ALTER TABLE accounts ADD email TEXT;
+
+-- The address an account asked for and has yet to verify, with the hash
+-- of the token sent to it and until when that can be given back
+ALTER TABLE accounts ADD pending_email TEXT;
+ALTER TABLE accounts ADD email_token_hash TEXT;
+ALTER TABLE accounts ADD email_token_expires_at TEXT;
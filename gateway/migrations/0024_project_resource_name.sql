@@ -0,0 +1,31 @@
+-- The container and volume of a project are named after the project, and
+-- those of the projects which were renamed when names were lowercased are
+-- still named after the name in capitals they were created with. That name
+-- is kept here, for the projects to go on using the resources they have
+-- rather than to be recreated on empty volumes. The state of the project
+-- still has it, in the name of the project it was created with and in the
+-- labels of its container.
+ALTER TABLE projects ADD resource_name TEXT;
+
+UPDATE projects
+  SET resource_name = (
+    SELECT value FROM json_tree(projects.project_state)
+    WHERE key IN ('project_name', 'shuttle.project')
+      AND type = 'text'
+      AND lower(value) = projects.project_name
+      AND value != projects.project_name
+    LIMIT 1
+  )
+  WHERE json_valid(project_state);
+
+-- The name is lowercased when a project being created is read back, so
+-- those being created carry the name their resources are to go by
+UPDATE projects
+  SET project_state = json_set(project_state, '$.creating.resource_name', resource_name)
+  WHERE resource_name IS NOT NULL
+    AND json_type(project_state, '$.creating') = 'object';
+
+UPDATE projects
+  SET project_state = json_set(project_state, '$.errored.ctx.creating.resource_name', resource_name)
+  WHERE resource_name IS NOT NULL
+    AND json_type(project_state, '$.errored.ctx.creating') = 'object';
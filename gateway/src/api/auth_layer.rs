@@ -1,9 +1,9 @@
 use std::{convert::Infallible, net::Ipv4Addr, sync::Arc, time::Duration};
 
 use axum::{
-    body::{boxed, HttpBody},
+    body::HttpBody,
     headers::{authorization::Bearer, Authorization, Cookie, Header, HeaderMapExt},
-    response::Response,
+    response::{IntoResponse, Response},
 };
 use futures::future::BoxFuture;
 use http::{Request, StatusCode, Uri};
@@ -20,6 +20,8 @@ use tower::{Layer, Service};
 use tracing::{error, trace, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+use crate::{AccountName, Error, ErrorKind};
+
 static PROXY_CLIENT: Lazy<ReverseProxy<HttpConnector<GaiResolver>>> =
     Lazy::new(|| ReverseProxy::new(Client::new()));
 
@@ -100,10 +102,7 @@ where
                     Err(_) => {
                         error!("unexpected internal error from gateway");
 
-                        Ok(Response::builder()
-                            .status(StatusCode::SERVICE_UNAVAILABLE)
-                            .body(boxed(Body::empty()))
-                            .unwrap())
+                        Ok(Error::from_kind(ErrorKind::ServiceUnavailable).into_response())
                     }
                 }
             });
@@ -150,16 +149,19 @@ where
                     Err(error) => {
                         error!(?error, "failed to call authentication service");
 
-                        Ok(Response::builder()
-                            .status(StatusCode::SERVICE_UNAVAILABLE)
-                            .body(boxed(Body::empty()))
-                            .unwrap())
+                        Ok(Error::from_kind(ErrorKind::ServiceUnavailable).into_response())
                     }
                 }
             })
         } else {
             // Enrich the current key | session
 
+            // Setups which route on the account have its name in the path,
+            // which is all there is to go on until the token is upgraded
+            if let Ok(account_name) = AccountName::try_from(req.uri()) {
+                Span::current().record("request.params.account_name", &account_name.to_string());
+            }
+
             // TODO: read this page to get rid of this clone
             // https://github.com/tower-rs/tower/blob/master/guides/building-a-middleware-from-scratch.md
             let mut this = self.clone();
@@ -204,20 +206,22 @@ where
                                 Err(error) => {
                                     error!(?error, "failed to call authentication service");
 
-                                    return Ok(Response::builder()
-                                        .status(StatusCode::SERVICE_UNAVAILABLE)
-                                        .body(boxed(Body::empty()))
-                                        .unwrap());
+                                    return Ok(Error::from_kind(ErrorKind::ServiceUnavailable)
+                                        .into_response());
                                 }
                             };
 
-                            // Bubble up auth errors
+                            // Bubble up auth errors, in the shape of the
+                            // gateway's own
                             if token_response.status() != StatusCode::OK {
-                                let (parts, body) = token_response.into_parts();
-                                let body = <Body as HttpBody>::map_err(body, axum::Error::new)
-                                    .boxed_unsync();
-
-                                return Ok(Response::from_parts(parts, body));
+                                let kind = match token_response.status() {
+                                    StatusCode::UNAUTHORIZED => ErrorKind::Unauthorized,
+                                    StatusCode::FORBIDDEN => ErrorKind::Forbidden,
+                                    StatusCode::NOT_FOUND => ErrorKind::UserNotFound,
+                                    _ => ErrorKind::Internal,
+                                };
+
+                                return Ok(Error::from_kind(kind).into_response());
                             }
 
                             let body = match hyper::body::to_bytes(token_response.into_body()).await
@@ -229,10 +233,9 @@ where
                                         "failed to get response body"
                                     );
 
-                                    return Ok(Response::builder()
-                                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                        .body(boxed(Body::empty()))
-                                        .unwrap());
+                                    return Ok(
+                                        Error::from_kind(ErrorKind::Internal).into_response()
+                                    );
                                 }
                             };
 
@@ -244,10 +247,9 @@ where
                                         "failed to convert body to ConvertResponse"
                                     );
 
-                                    return Ok(Response::builder()
-                                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                        .body(boxed(Body::empty()))
-                                        .unwrap());
+                                    return Ok(
+                                        Error::from_kind(ErrorKind::Internal).into_response()
+                                    );
                                 }
                             };
 
@@ -269,10 +271,7 @@ where
                     Err(_) => {
                         error!("unexpected internal error from gateway");
 
-                        Ok(Response::builder()
-                            .status(StatusCode::SERVICE_UNAVAILABLE)
-                            .body(boxed(Body::empty()))
-                            .unwrap())
+                        Ok(Error::from_kind(ErrorKind::ServiceUnavailable).into_response())
                     }
                 }
             })
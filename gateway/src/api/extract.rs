@@ -0,0 +1,163 @@
+use std::error::Error as StdError;
+
+use axum::async_trait;
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use shuttle_common::models::error::ErrorKind;
+
+use crate::{Error, INVALID_PROJECT_NAME};
+
+/// Wraps an extractor such as [`Json`](axum::Json), [`Path`](axum::extract::Path)
+/// or [`Query`](axum::extract::Query) so that a [`ProjectName`](crate::ProjectName)
+/// which does not parse in what it extracts is rejected as
+/// [`ErrorKind::InvalidProjectName`], as it is everywhere else. Any other
+/// rejection is left as the extractor had it.
+pub struct Checked<E>(pub E);
+
+/// The message of the name which does not parse is only in the source
+/// of the rejections of [`Json`](axum::Json) and [`Query`](axum::extract::Query),
+/// which only say that what was sent could not be deserialized
+fn check_rejection<R>(rejection: R) -> Response
+where
+    R: IntoResponse + StdError + Send + Sync + 'static,
+{
+    let is_invalid_project_name =
+        std::iter::successors(Some(&rejection as &(dyn StdError + 'static)), |err| {
+            err.source()
+        })
+        .any(|err| err.to_string().contains(INVALID_PROJECT_NAME));
+
+    if is_invalid_project_name {
+        Error::source(ErrorKind::InvalidProjectName, rejection).into_response()
+    } else {
+        rejection.into_response()
+    }
+}
+
+#[async_trait]
+impl<S, E> FromRequestParts<S> for Checked<E>
+where
+    S: Send + Sync,
+    E: FromRequestParts<S>,
+    E::Rejection: StdError + Send + Sync + 'static,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        E::from_request_parts(parts, state)
+            .await
+            .map(Checked)
+            .map_err(check_rejection)
+    }
+}
+
+#[async_trait]
+impl<S, B, E> FromRequest<S, B> for Checked<E>
+where
+    S: Send + Sync,
+    B: Send + 'static,
+    E: FromRequest<S, B>,
+    E::Rejection: StdError + Send + Sync + 'static,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        E::from_request(req, state)
+            .await
+            .map(Checked)
+            .map_err(check_rejection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::{Path, Query};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use http::StatusCode;
+    use serde::Deserialize;
+    use shuttle_common::models::error::ApiError;
+    use tower::Service;
+
+    use super::*;
+    use crate::ProjectName;
+
+    #[derive(Deserialize)]
+    struct Named {
+        project: ProjectName,
+    }
+
+    #[tokio::test]
+    async fn invalid_project_names_are_rejected_wherever_they_come_from() {
+        let mut router = Router::new()
+            .route(
+                "/body",
+                post(|Checked(Json(named)): Checked<Json<Named>>| async move {
+                    named.project.to_string()
+                }),
+            )
+            .route(
+                "/path/:project/:fqdn",
+                get(
+                    |Checked(Path((project, _))): Checked<Path<(ProjectName, String)>>| async move {
+                        project.to_string()
+                    },
+                ),
+            )
+            .route(
+                "/query",
+                get(|Checked(Query(named)): Checked<Query<Named>>| async move {
+                    named.project.to_string()
+                }),
+            );
+
+        let mut call = |request: Request<Body>| {
+            let response = router.call(request);
+            async move { response.await.unwrap() }
+        };
+        let post_json = |body: &'static str| {
+            Request::post("/body")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let get = |uri: &'static str| Request::get(uri).body(Body::empty()).unwrap();
+
+        let invalid = ApiError::from(ErrorKind::InvalidProjectName);
+
+        for request in [
+            post_json(r#"{"project": "-matrix-"}"#),
+            get("/path/-matrix-/matrix.com"),
+            get("/query?project=-matrix-"),
+        ] {
+            let response = call(request).await;
+            assert_eq!(response.status(), invalid.status());
+
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let error: ApiError = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error.message, invalid.message);
+        }
+
+        for request in [
+            post_json(r#"{"project": "matrix"}"#),
+            get("/path/matrix/matrix.com"),
+            get("/query?project=matrix"),
+        ] {
+            let response = call(request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, "matrix");
+        }
+
+        // Other problems with what was sent are not about the project name
+        assert_eq!(
+            call(post_json(r#"{"name": "matrix"}"#)).await.status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(call(get("/query")).await.status(), StatusCode::BAD_REQUEST);
+    }
+}
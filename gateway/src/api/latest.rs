@@ -1,32 +1,42 @@
+use std::collections::{BTreeMap, HashMap};
 use std::io::Cursor;
 use std::net::SocketAddr;
 use std::ops::Sub;
 use std::sync::Arc;
 use std::time::Duration;
 
-use axum::body::Body;
-use axum::extract::{Extension, Path, State};
+use async_compression::tokio::write::GzipEncoder;
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Extension, FromRef, Multipart, Path, Query, State};
 use axum::handler::Handler;
 use axum::http::Request;
-use axum::middleware::from_extractor;
+use axum::middleware::{self, from_extractor};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
 use axum::response::Response;
-use axum::routing::{any, get, post};
-use axum::{Json as AxumJson, Router};
+use axum::routing::{any, get, post, put};
+use axum::{Json as AxumJson, Router, TypedHeader};
+use chrono::{DateTime, SubsecRound, Utc};
 use fqdn::FQDN;
-use futures::Future;
+use futures::{Stream, StreamExt};
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
 use http::{StatusCode, Uri};
+use hyper::server::conn::AddrIncoming;
 use instant_acme::{AccountCredentials, ChallengeType};
 use serde::{Deserialize, Serialize};
 use shuttle_common::backends::auth::{AuthPublicKey, JwtAuthenticationLayer, ScopedLayer};
 use shuttle_common::backends::cache::CacheManager;
+use shuttle_common::backends::headers::XShuttleAdminSecret;
 use shuttle_common::backends::metrics::{Metrics, TraceLayer};
 use shuttle_common::claims::{Scope, EXP_MINUTES};
 use shuttle_common::models::error::ErrorKind;
 use shuttle_common::models::{project, stats};
 use shuttle_common::request_span;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::{Mutex, MutexGuard};
-use tracing::{field, instrument, trace};
+use tokio_util::io::ReaderStream;
+use tower_http::cors::{Any, CorsLayer};
+use tracing::{debug, field, instrument, trace, warn, Level, Span};
 use ttl_cache::TtlCache;
 
 use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
@@ -39,18 +49,29 @@ use x509_parser::pem::parse_x509_pem;
 use x509_parser::time::ASN1Time;
 
 use crate::acme::{AcmeClient, CustomDomain};
+use crate::api::extract::Checked;
 use crate::auth::{ScopedUser, User};
+use crate::email::EmailAddress;
+use crate::logs::GatewayLogs;
+use crate::problem;
 use crate::project::{ContainerInspectResponseExt, Project, ProjectCreating};
-use crate::service::GatewayService;
-use crate::task::{self, BoxedTask, TaskResult};
+use crate::service::{
+    ContainerStatusFilter, DeadLetteredProject, Deployment, DeploymentDiff, GatewayContainer,
+    GatewayNetwork, GatewayService, NameUnavailable, ProjectOperation, StaleProject,
+};
+use crate::task::{self, BoxedTask, Operation, Origin, Priority, TaskResult, Work};
+use crate::telemetry;
 use crate::tls::{GatewayCertResolver, RENEWAL_VALIDITY_THRESHOLD_IN_DAYS};
-use crate::worker::WORKER_QUEUE_SIZE;
-use crate::{Error, ProjectName};
+use crate::worker::{WorkerHeartbeat, WorkerStatus, WorkerStatusHandle, LIFECYCLE_EXECUTOR};
+use crate::{Account, AccountName, AccountTier, Error, ProjectName};
 
 use super::auth_layer::ShuttleAuthLayer;
 
 pub const SVC_DEGRADED_THRESHOLD: usize = 128;
 
+/// How many of the latest operations on a project are listed
+pub const RECENT_OPERATIONS_LIMIT: u32 = 20;
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum GatewayStatus {
@@ -59,32 +80,128 @@ pub enum GatewayStatus {
     Unhealthy,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ContainerIdResponse {
+    pub container_id: String,
+}
+
+/// What changed with the latest deployment of a project
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DeploymentDiffResponse {
+    Diff(DeploymentDiff),
+    /// The project was not deployed at least twice
+    NoPrevious {
+        previous: (),
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeployResponse {
+    /// Tells this deployment apart from the other deployments of the
+    /// project
+    pub deployment_id: String,
+    pub artifact_id: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// The deployment a project is serving
+#[derive(Serialize, Deserialize)]
+pub struct ProjectDeployment {
+    pub deployment_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WorkerStatusResponse {
+    pub status: WorkerStatus,
+    pub current_task: Option<String>,
+    pub queued: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UptimeResponse {
+    pub started_at: DateTime<Utc>,
+    pub uptime_seconds: u64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct StatusResponse {
     status: GatewayStatus,
+    /// How many seconds ago the worker last showed signs of life, when
+    /// it is being tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    worker_heartbeat_age_secs: Option<f64>,
+    /// How far along the refresh of all projects is, as in `42%`, while
+    /// the gateway is starting up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reconciling: Option<String>,
+}
+
+/// Changes to apply to an account. Fields left out are not touched.
+#[derive(Serialize, Deserialize, Default)]
+pub struct AccountUpdate {
+    pub tier: Option<AccountTier>,
+    pub suspended: Option<bool>,
+    pub super_user: Option<bool>,
+}
+
+/// The address an account asks to be reached at
+#[derive(Serialize, Deserialize)]
+pub struct EmailUpdate {
+    pub email: EmailAddress,
+}
+
+/// The token which was sent to the address being verified
+#[derive(Serialize, Deserialize)]
+pub struct EmailVerification {
+    pub token: String,
+}
+
+/// Where an address asked for is at
+#[derive(Serialize, Deserialize)]
+pub struct EmailStatus {
+    pub email: EmailAddress,
+    pub verified: bool,
 }
 
 impl StatusResponse {
     pub fn healthy() -> Self {
         Self {
             status: GatewayStatus::Healthy,
+            worker_heartbeat_age_secs: None,
+            reconciling: None,
         }
     }
 
     pub fn degraded() -> Self {
         Self {
             status: GatewayStatus::Degraded,
+            worker_heartbeat_age_secs: None,
+            reconciling: None,
         }
     }
 
     pub fn unhealthy() -> Self {
         Self {
             status: GatewayStatus::Unhealthy,
+            worker_heartbeat_age_secs: None,
+            reconciling: None,
         }
     }
+
+    pub fn with_worker_heartbeat_age(mut self, age: Duration) -> Self {
+        self.worker_heartbeat_age_secs = Some(age.as_secs_f64());
+        self
+    }
+
+    pub fn with_reconciling(mut self, percent: usize) -> Self {
+        self.reconciling = Some(format!("{percent}%"));
+        self
+    }
 }
 
-#[instrument(skip(service))]
+#[instrument(skip(service), fields(project.state = field::Empty))]
 #[utoipa::path(
     get,
     path = "/projects/{project_name}",
@@ -97,18 +214,53 @@ impl StatusResponse {
     )
 )]
 async fn get_project(
-    State(RouterState { service, .. }): State<RouterState>,
+    State(service): State<Arc<GatewayService>>,
     ScopedUser { scope, .. }: ScopedUser,
 ) -> Result<AxumJson<project::Response>, Error> {
-    let state = service.find_project(&scope).await?.into();
+    let project = service.find_project(&scope).await?;
+    Span::current().record("project.state", project.label());
+
+    let state = project.into();
+    let deployment_id = service.find_deployment_id(&scope).await?;
     let response = project::Response {
         name: scope.to_string(),
         state,
+        deployment_id,
+        operation_id: None,
     };
 
     Ok(AxumJson(response))
 }
 
+#[instrument(skip(service))]
+#[utoipa::path(
+    get,
+    path = "/projects/{project_name}/container-id",
+    responses(
+        (status = 200, description = "Successfully got the ID of the container the project runs in."),
+        (status = 503, description = "The project is not running."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+    )
+)]
+async fn get_project_container_id(
+    State(service): State<Arc<GatewayService>>,
+    ScopedUser { scope, .. }: ScopedUser,
+) -> Result<AxumJson<ContainerIdResponse>, Error> {
+    if !service.find_project(&scope).await?.is_ready() {
+        return Err(Error::from_kind(ErrorKind::ProjectNotReady));
+    }
+
+    let container_id = service
+        .find_container_id(&scope)
+        .await?
+        .ok_or_else(|| Error::from_kind(ErrorKind::ProjectNotReady))?;
+
+    Ok(AxumJson(ContainerIdResponse { container_id }))
+}
+
 #[utoipa::path(
     get,
     path = "/projects",
@@ -118,21 +270,58 @@ async fn get_project(
     )
 )]
 async fn get_projects_list(
-    State(RouterState { service, .. }): State<RouterState>,
-    User { name, .. }: User,
+    State(service): State<Arc<GatewayService>>,
+    User { account, .. }: User,
 ) -> Result<AxumJson<Vec<project::Response>>, Error> {
     let projects = service
-        .iter_user_projects_detailed(name.clone())
+        .iter_user_projects_detailed(account.name)
         .await?
         .map(|project| project::Response {
             name: project.0.to_string(),
             state: project.1.into(),
+            deployment_id: None,
+            operation_id: None,
         })
         .collect();
 
     Ok(AxumJson(projects))
 }
 
+/// Whether a new project can be called what was asked about
+#[derive(Debug, Serialize)]
+pub struct NameAvailabilityResponse {
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<NameUnavailable>,
+}
+
+#[instrument(skip_all, fields(%project_name))]
+#[utoipa::path(
+    get,
+    path = "/projects/{project_name}/availability",
+    responses(
+        (status = 200, description = "Successfully checked whether a project can be created with the name, and why not if not (`invalid`, `reserved` or `taken`)."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name to check."),
+    )
+)]
+async fn get_project_name_availability(
+    State(service): State<Arc<GatewayService>>,
+    user: User,
+    Path(project_name): Path<String>,
+) -> Result<AxumJson<NameAvailabilityResponse>, Error> {
+    let reason = service
+        .project_name_availability(&project_name, &user.account.name, user.is_admin())
+        .await?;
+
+    Ok(AxumJson(NameAvailabilityResponse {
+        available: reason.is_none(),
+        reason,
+    }))
+}
+
 #[instrument(skip_all, fields(%project))]
 #[utoipa::path(
     post,
@@ -149,25 +338,34 @@ async fn create_project(
     State(RouterState {
         service, sender, ..
     }): State<RouterState>,
-    User { name, claim, .. }: User,
-    Path(project): Path<ProjectName>,
+    user: User,
+    project: ProjectName,
     AxumJson(config): AxumJson<project::Config>,
 ) -> Result<AxumJson<project::Response>, Error> {
-    let is_admin = claim.scopes.contains(&Scope::Admin);
+    let is_admin = user.is_admin();
 
     let state = service
-        .create_project(project.clone(), name.clone(), is_admin, config.idle_minutes)
+        .create_project(
+            project.clone(),
+            user.account.name,
+            is_admin,
+            config.idle_minutes,
+            config.allow_internet,
+        )
         .await?;
 
-    service
+    let handle = service
         .new_task()
-        .project(project.clone())
+        .work(Work::new(project.clone(), Operation::Start, Origin::Api))
+        .priority(Priority::Interactive)
         .send(&sender)
         .await?;
 
     let response = project::Response {
         name: project.to_string(),
         state: state.into(),
+        deployment_id: None,
+        operation_id: handle.operation_id().map(ToString::to_string),
     };
 
     Ok(AxumJson(response))
@@ -196,25 +394,373 @@ async fn destroy_project(
     let mut response = project::Response {
         name: project.to_string(),
         state: state.into(),
+        deployment_id: None,
+        operation_id: None,
     };
 
     if response.state == shuttle_common::models::project::State::Destroyed {
         return Ok(AxumJson(response));
     }
 
+    service.readmit_project(&project).await?;
+
+    // Whatever is still being done to the project is pointless now, and
+    // could leave a container behind if let run its course
+    if service.cancellations().cancel(&project) {
+        debug!("cancelled the work in flight for the project");
+    }
+
+    service.finish_deployment(&project).await?;
+
     // if project exists and isn't `Destroyed`, send destroy task
-    service
+    // An operation which is already queued up to destroy it is given
+    // back rather than queued up again
+    let handle = service
         .new_task()
-        .project(project)
-        .and_then(task::destroy())
+        .work(Work::new(project, Operation::Destroy, Origin::Api))
+        .priority(Priority::Interactive)
         .send(&sender)
         .await?;
+    response.operation_id = handle.operation_id().map(ToString::to_string);
 
     response.state = shuttle_common::models::project::State::Destroying;
 
     Ok(AxumJson(response))
 }
 
+#[instrument(skip_all, fields(%project, ?policy))]
+#[utoipa::path(
+    post,
+    path = "/projects/{project_name}/network-policy",
+    request_body = shuttle_common::models::project::NetworkPolicy,
+    responses(
+        (status = 200, description = "Successfully queued the recreation of the project under the new policy.", body = shuttle_common::models::project::Response),
+        (status = 400, description = "The project has no container, or this gateway cannot keep projects off the internet."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+    )
+)]
+async fn set_project_network_policy(
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+    ScopedUser { scope: project, .. }: ScopedUser,
+    AxumJson(policy): AxumJson<project::NetworkPolicy>,
+) -> Result<AxumJson<project::Response>, Error> {
+    let allow_internet = policy.allow_internet;
+    service.check_network_policy(allow_internet)?;
+
+    let state = service.find_project(&project).await?;
+    let Some(container) = state.container() else {
+        return Err(Error::custom(
+            ErrorKind::InvalidOperation,
+            "the project has no container to change the network policy of",
+        ));
+    };
+    let idle_minutes = container.idle_minutes();
+    let fqdn = match service.find_custom_domain_for_project(&project).await {
+        Ok(custom_domain) => Some(custom_domain.fqdn.to_string()),
+        Err(error) if error.kind() == ErrorKind::CustomDomainNotFound => None,
+        Err(error) => return Err(error),
+    };
+    let resource_name = service.find_resource_name(&project).await?;
+
+    // The policy is part of how the container is created, so it takes
+    // recreating the project for it to apply
+    let handle = service
+        .new_task()
+        .work(Work::new(project.clone(), Operation::Recreate, Origin::Api))
+        .priority(Priority::Interactive)
+        .and_then(task::run(move |ctx| {
+            let fqdn = fqdn.clone();
+            let resource_name = resource_name.clone();
+            async move {
+                let mut creating =
+                    ProjectCreating::new_with_random_initial_key(ctx.project_name, idle_minutes)
+                        .with_allow_internet(allow_internet)
+                        .with_resource_name(resource_name);
+                if let Some(fqdn) = fqdn {
+                    creating = creating.with_fqdn(fqdn);
+                }
+                TaskResult::Done(Project::Creating(creating))
+            }
+        }))
+        .send(&sender)
+        .await?;
+
+    let response = project::Response {
+        name: project.to_string(),
+        state: state.into(),
+        deployment_id: None,
+        operation_id: handle.operation_id().map(ToString::to_string),
+    };
+
+    Ok(AxumJson(response))
+}
+
+#[instrument(skip_all, fields(scope = %scope))]
+#[utoipa::path(
+    post,
+    path = "/projects/{project_name}/deploy",
+    request_body(content_type = "multipart/form-data", description = "The artifact to deploy, in a `binary` part."),
+    responses(
+        (status = 200, description = "Successfully stored the artifact to deploy."),
+        (status = 400, description = "The request has no `binary` part."),
+        (status = 413, description = "The artifact is too large."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+    )
+)]
+async fn deploy_project(
+    State(service): State<Arc<GatewayService>>,
+    ScopedUser { user, scope }: ScopedUser,
+    mut multipart: Multipart,
+) -> Result<AxumJson<DeployResponse>, Error> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| Error::source(ErrorKind::InvalidOperation, err))?
+    {
+        if field.name() != Some("binary") {
+            continue;
+        }
+
+        let artifact = service.store_artifact(&scope, field).await?;
+        let deployment_id = service
+            .record_current_deployment(&scope, user.name())
+            .await?;
+
+        return Ok(AxumJson(DeployResponse {
+            deployment_id,
+            artifact_id: artifact.artifact_id,
+            sha256: artifact.sha256,
+            size: artifact.size,
+        }));
+    }
+
+    Err(Error::custom(
+        ErrorKind::InvalidOperation,
+        "the request has no `binary` part",
+    ))
+}
+
+#[instrument(skip(service))]
+#[utoipa::path(
+    get,
+    path = "/projects/{project_name}/operations",
+    responses(
+        (status = 200, description = "Successfully got the latest operations on the project, latest first."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+    )
+)]
+async fn get_project_operations(
+    State(service): State<Arc<GatewayService>>,
+    ScopedUser { scope, .. }: ScopedUser,
+) -> Result<AxumJson<Vec<ProjectOperation>>, Error> {
+    let operations = service
+        .list_operations(&scope, RECENT_OPERATIONS_LIMIT)
+        .await?;
+
+    Ok(AxumJson(operations))
+}
+
+#[instrument(skip(service))]
+#[utoipa::path(
+    get,
+    path = "/projects/{project_name}/operations/{operation_id}",
+    responses(
+        (status = 200, description = "Successfully got how the operation on the project is going."),
+        (status = 404, description = "There is no such operation on the project."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+        ("operation_id" = String, Path, description = "The ID of the operation, as returned when it was queued up."),
+    )
+)]
+async fn get_project_operation(
+    State(service): State<Arc<GatewayService>>,
+    ScopedUser { scope, .. }: ScopedUser,
+    Path((_, operation_id)): Path<(String, String)>,
+) -> Result<AxumJson<ProjectOperation>, Error> {
+    let operation = service.find_operation(&scope, &operation_id).await?;
+
+    Ok(AxumJson(operation))
+}
+
+#[instrument(skip(service))]
+#[utoipa::path(
+    get,
+    path = "/projects/{project_name}/deployments",
+    responses(
+        (status = 200, description = "Successfully got the deployments of the project, latest first."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+    )
+)]
+async fn get_project_deployments(
+    State(service): State<Arc<GatewayService>>,
+    ScopedUser { scope, .. }: ScopedUser,
+) -> Result<AxumJson<Vec<Deployment>>, Error> {
+    let deployments = service.list_deployments(&scope).await?;
+
+    Ok(AxumJson(deployments))
+}
+
+#[instrument(skip(service))]
+#[utoipa::path(
+    get,
+    path = "/projects/{project_name}/diff",
+    responses(
+        (status = 200, description = "Successfully got what changed with the latest deployment of the project."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+    )
+)]
+async fn get_project_diff(
+    State(service): State<Arc<GatewayService>>,
+    ScopedUser { scope, .. }: ScopedUser,
+) -> Result<AxumJson<DeploymentDiffResponse>, Error> {
+    let response = match service.deployment_diff(&scope).await? {
+        Some(diff) => DeploymentDiffResponse::Diff(diff),
+        None => DeploymentDiffResponse::NoPrevious { previous: () },
+    };
+
+    Ok(AxumJson(response))
+}
+
+/// The query parameters of `GET /projects/{project_name}/logs/download`
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[instrument(skip(service))]
+#[utoipa::path(
+    get,
+    path = "/projects/{project_name}/logs/download",
+    responses(
+        (status = 200, description = "Successfully started streaming the logs of the project, as a gzip archive."),
+        (status = 400, description = "The range of the logs to download ends before it starts."),
+        (status = 503, description = "The project has no container to get the logs of."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+        ("since" = Option<String>, Query, description = "Only the logs from this RFC 3339 timestamp on."),
+        ("until" = Option<String>, Query, description = "Only the logs up to this RFC 3339 timestamp."),
+    )
+)]
+async fn download_project_logs(
+    State(service): State<Arc<GatewayService>>,
+    ScopedUser { scope, .. }: ScopedUser,
+    Query(LogsQuery { since, until }): Query<LogsQuery>,
+) -> Result<Response<Body>, Error> {
+    if let (Some(since), Some(until)) = (since, until) {
+        if since > until {
+            return Err(Error::custom(
+                ErrorKind::InvalidOperation,
+                "the logs to download end before they start",
+            ));
+        }
+    }
+
+    let logs = service.project_logs(&scope, since, until).await?;
+    let filename = format!("{scope}-{}.log.gz", Utc::now().format("%Y%m%dT%H%M%SZ"));
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/gzip")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+        )
+        .body(gzip_body(logs))
+        .unwrap())
+}
+
+/// How much of a gzip archive can be compressed ahead of it being sent
+const GZIP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Compress `chunks` into a response body as they come, so that however
+/// many there are only a little of them is held in memory at a time
+fn gzip_body<S>(chunks: S) -> Body
+where
+    S: Stream<Item = Result<Bytes, Error>> + Send + 'static,
+{
+    let (writer, reader) = tokio::io::duplex(GZIP_BUFFER_SIZE);
+
+    tokio::spawn(async move {
+        let mut encoder = GzipEncoder::new(writer);
+        let mut chunks = Box::pin(chunks);
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    // Leave the archive without its trailer, for whoever
+                    // downloads it to tell that it is incomplete
+                    warn!(error = %err, "failed to read what there is to compress");
+                    return;
+                }
+            };
+
+            if encoder.write_all(&chunk).await.is_err() {
+                // The response was dropped, the client having gone away
+                return;
+            }
+        }
+
+        if let Err(err) = encoder.shutdown().await {
+            debug!(error = %err, "could not finish a gzip archive");
+        }
+    });
+
+    Body::wrap_stream(ReaderStream::new(reader))
+}
+
+#[instrument(skip_all, fields(%project_name))]
+#[utoipa::path(
+    put,
+    path = "/internal/projects/{project_name}/deployment",
+    responses(
+        (status = 200, description = "Successfully recorded the deployment the project is serving."),
+        (status = 401, description = "The internal token of the project is missing or wrong."),
+        (status = 404, description = "No such project."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("project_name" = String, Path, description = "The name of the project."),
+    )
+)]
+async fn set_project_deployment(
+    State(service): State<Arc<GatewayService>>,
+    project_name: ProjectName,
+    token: Option<TypedHeader<XShuttleAdminSecret>>,
+    AxumJson(deployment): AxumJson<ProjectDeployment>,
+) -> Result<AxumJson<ProjectDeployment>, Error> {
+    let TypedHeader(XShuttleAdminSecret(token)) =
+        token.ok_or_else(|| Error::from_kind(ErrorKind::Unauthorized))?;
+
+    service.check_internal_token(&project_name, &token).await?;
+    service
+        .set_deployment_id(&project_name, deployment.deployment_id.as_deref())
+        .await?;
+
+    Ok(AxumJson(deployment))
+}
+
 #[instrument(skip_all, fields(scope = %scoped_user.scope))]
 async fn route_project(
     State(RouterState {
@@ -227,30 +773,91 @@ async fn route_project(
     let project = service.find_or_start_project(&project_name, sender).await?;
 
     service
-        .route(&project, &project_name, &scoped_user.user.name, req)
+        .route(&project, &project_name, scoped_user.user.name(), req)
         .await
 }
 
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "The gateway is ready to take requests."),
+        (status = 503, description = "The state store cannot be reached, or the worker is stalled, full or not running.")
+    )
+)]
+async fn get_readyz(
+    State(RouterState {
+        service,
+        sender,
+        worker_status,
+        ..
+    }): State<RouterState>,
+) -> Result<(), Error> {
+    // Whatever the reason, it is the gateway as a whole which is not ready
+    if let Err(err) = service.check_state_store().await {
+        return Err(Error::source(ErrorKind::NotReady, err));
+    }
+
+    let heartbeat = worker_status.as_ref().map(WorkerStatusHandle::heartbeat);
+    let unready = if heartbeat.map_or(false, WorkerHeartbeat::is_stalled) {
+        Some("the worker is stalled")
+    } else if sender.is_closed() {
+        Some("the worker is not running")
+    } else if sender.capacity() == 0 {
+        Some("the worker queue is full")
+    } else {
+        None
+    };
+
+    match unready {
+        Some(reason) => Err(Error::custom(ErrorKind::NotReady, reason)),
+        None => Ok(()),
+    }
+}
+
 #[utoipa::path(
     get,
     path = "/",
     responses(
         (status = 200, description = "Get the gateway operational status."),
-        (status = 500, description = "Server internal error.")
+        (status = 500, description = "Server internal error."),
+        (status = 503, description = "The state store cannot be reached, or the worker is stalled.")
     )
 )]
-async fn get_status(State(RouterState { sender, .. }): State<RouterState>) -> Response<Body> {
-    let (status, body) = if sender.is_closed() || sender.capacity() == 0 {
+async fn get_status(
+    State(RouterState {
+        service,
+        sender,
+        worker_status,
+        ..
+    }): State<RouterState>,
+) -> Response<Body> {
+    let heartbeat = worker_status.as_ref().map(WorkerStatusHandle::heartbeat);
+
+    let (status, mut body) = if let Err(err) = service.check_state_store().await {
+        warn!(error = %err, "state store is unavailable");
+        (StatusCode::SERVICE_UNAVAILABLE, StatusResponse::unhealthy())
+    } else if heartbeat.map_or(false, WorkerHeartbeat::is_stalled) {
+        (StatusCode::SERVICE_UNAVAILABLE, StatusResponse::unhealthy())
+    } else if sender.is_closed() || sender.capacity() == 0 {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             StatusResponse::unhealthy(),
         )
-    } else if sender.capacity() < WORKER_QUEUE_SIZE - SVC_DEGRADED_THRESHOLD {
+    } else if sender.capacity() < sender.max_capacity().saturating_sub(SVC_DEGRADED_THRESHOLD) {
         (StatusCode::OK, StatusResponse::degraded())
     } else {
         (StatusCode::OK, StatusResponse::healthy())
     };
 
+    if let Some(heartbeat) = heartbeat {
+        body = body.with_worker_heartbeat_age(heartbeat.age());
+    }
+
+    if let Some(percent) = service.refresh_progress().percent() {
+        body = body.with_reconciling(percent);
+    }
+
     let body = serde_json::to_vec(&body).unwrap();
     Response::builder()
         .status(status)
@@ -440,7 +1047,7 @@ async fn request_custom_domain_acme_certificate(
     }): State<RouterState>,
     Extension(acme_client): Extension<AcmeClient>,
     Extension(resolver): Extension<Arc<GatewayCertResolver>>,
-    Path((project_name, fqdn)): Path<(ProjectName, String)>,
+    Checked(Path((project_name, fqdn))): Checked<Path<(ProjectName, String)>>,
     AxumJson(credentials): AxumJson<AccountCredentials<'_>>,
 ) -> Result<String, Error> {
     let fqdn: FQDN = fqdn
@@ -452,24 +1059,33 @@ async fn request_custom_domain_acme_certificate(
         .await?;
 
     let project = service.find_project(&project_name).await?;
-    let idle_minutes = project.container().unwrap().idle_minutes();
+    let container = project.container().unwrap();
+    let idle_minutes = container.idle_minutes();
+    let allow_internet = container.allow_internet();
+    let resource_name = service.find_resource_name(&project_name).await?;
 
     // Destroy and recreate the project with the new domain.
     service
         .new_task()
-        .project(project_name.clone())
-        .and_then(task::destroy())
-        .and_then(task::run_until_done())
+        .work(Work::new(
+            project_name.clone(),
+            Operation::Recreate,
+            Origin::Admin,
+        ))
+        .priority(Priority::Interactive)
         .and_then(task::run({
             let fqdn = fqdn.to_string();
             move |ctx| {
                 let fqdn = fqdn.clone();
+                let resource_name = resource_name.clone();
                 async move {
                     let creating = ProjectCreating::new_with_random_initial_key(
                         ctx.project_name,
                         idle_minutes,
                     )
-                    .with_fqdn(fqdn);
+                    .with_fqdn(fqdn)
+                    .with_allow_internet(allow_internet)
+                    .with_resource_name(resource_name);
                     TaskResult::Done(Project::Creating(creating))
                 }
             }
@@ -503,10 +1119,10 @@ async fn request_custom_domain_acme_certificate(
     )
 )]
 async fn renew_custom_domain_acme_certificate(
-    State(RouterState { service, .. }): State<RouterState>,
+    State(service): State<Arc<GatewayService>>,
     Extension(acme_client): Extension<AcmeClient>,
     Extension(resolver): Extension<Arc<GatewayCertResolver>>,
-    Path((project_name, fqdn)): Path<(ProjectName, String)>,
+    Checked(Path((project_name, fqdn))): Checked<Path<(ProjectName, String)>>,
     AxumJson(credentials): AxumJson<AccountCredentials<'_>>,
 ) -> Result<String, Error> {
     let fqdn: FQDN = fqdn
@@ -577,7 +1193,7 @@ async fn renew_custom_domain_acme_certificate(
     )
 )]
 async fn renew_gateway_acme_certificate(
-    State(RouterState { service, .. }): State<RouterState>,
+    State(service): State<Arc<GatewayService>>,
     Extension(acme_client): Extension<AcmeClient>,
     Extension(resolver): Extension<Arc<GatewayCertResolver>>,
     AxumJson(credentials): AxumJson<AccountCredentials<'_>>,
@@ -588,26 +1204,438 @@ async fn renew_gateway_acme_certificate(
     Ok(r#""Renewed the gateway certificate.""#.to_string())
 }
 
+/// How many projects `GET /admin/projects` lists at once, unless asked
+/// for fewer
+const ADMIN_PROJECTS_PAGE_SIZE: u32 = 100;
+
+/// The query parameters of `GET /admin/projects`
+#[derive(Deserialize)]
+pub struct AdminProjectsQuery {
+    pub account: Option<AccountName>,
+    pub state: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[instrument(skip_all)]
 #[utoipa::path(
-    post,
+    get,
     path = "/admin/projects",
     responses(
-        (status = 200, description = "Successfully fetched the projects list.", body = shuttle_common::models::project::AdminResponse),
+        (status = 200, description = "Successfully fetched a page of the projects of all the accounts.", body = shuttle_common::models::project::AdminPage),
         (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("account" = Option<String>, Query, description = "Only list the projects of this account."),
+        ("state" = Option<String>, Query, description = "Only list the projects in this state (e.g. `ready`, `stopped`)."),
+        ("cursor" = Option<String>, Query, description = "Where to carry on from, as the `next_cursor` of the previous page."),
+        ("limit" = Option<u32>, Query, description = "How many projects to list at most, 100 by default and at most."),
     )
 )]
 async fn get_projects(
-    State(RouterState { service, .. }): State<RouterState>,
-) -> Result<AxumJson<Vec<project::AdminResponse>>, Error> {
-    let projects = service
-        .iter_projects_detailed()
-        .await?
-        .map(Into::into)
-        .collect();
+    State(service): State<Arc<GatewayService>>,
+    Query(AdminProjectsQuery {
+        account,
+        state,
+        cursor,
+        limit,
+    }): Query<AdminProjectsQuery>,
+) -> Result<AxumJson<project::AdminPage>, Error> {
+    let limit = limit
+        .unwrap_or(ADMIN_PROJECTS_PAGE_SIZE)
+        .clamp(1, ADMIN_PROJECTS_PAGE_SIZE);
+
+    // One more than asked for, to tell whether there is a next page
+    let (mut projects, total) = service
+        .page_projects_detailed(
+            account.as_ref(),
+            state.as_deref(),
+            cursor.as_deref(),
+            limit + 1,
+        )
+        .await?;
+
+    let next_cursor = if projects.len() > limit as usize {
+        projects.truncate(limit as usize);
+        projects
+            .last()
+            .map(|project| project.project_name.to_string())
+    } else {
+        None
+    };
+
+    Ok(AxumJson(project::AdminPage {
+        projects: projects.into_iter().map(Into::into).collect(),
+        total,
+        next_cursor,
+    }))
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/projects/stale",
+    responses(
+        (status = 200, description = "Successfully fetched the projects the stale errored projects sweep would act on."),
+        (status = 500, description = "Server internal error.")
+    )
+)]
+async fn get_stale_projects(
+    State(service): State<Arc<GatewayService>>,
+) -> Result<AxumJson<Vec<StaleProject>>, Error> {
+    let stale = service.stale_errored_projects().await?;
+
+    Ok(AxumJson(stale))
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/projects/dead-letter",
+    responses(
+        (status = 200, description = "Successfully fetched the projects the worker gave up on, with their last error."),
+        (status = 500, description = "Server internal error.")
+    )
+)]
+async fn get_dead_lettered_projects(
+    State(service): State<Arc<GatewayService>>,
+) -> Result<AxumJson<Vec<DeadLetteredProject>>, Error> {
+    let projects = service.dead_lettered_projects().await?;
 
     Ok(AxumJson(projects))
 }
 
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/networks",
+    responses(
+        (status = 200, description = "Successfully fetched the Docker networks labelled for this gateway."),
+        (status = 500, description = "Server internal error.")
+    )
+)]
+async fn get_networks(
+    State(service): State<Arc<GatewayService>>,
+) -> Result<AxumJson<Vec<GatewayNetwork>>, Error> {
+    let networks = service.list_networks().await?;
+
+    Ok(AxumJson(networks))
+}
+
+/// The query parameters of `GET /admin/containers`
+#[derive(Deserialize)]
+pub struct ContainersQuery {
+    #[serde(default)]
+    pub status: ContainerStatusFilter,
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/containers",
+    responses(
+        (status = 200, description = "Successfully fetched the Docker containers labelled for this gateway."),
+        (status = 400, description = "Unknown status to filter on."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("status" = Option<String>, Query, description = "Only list the `running` or the `stopped` containers, rather than `all` of them."),
+    )
+)]
+async fn get_containers(
+    State(service): State<Arc<GatewayService>>,
+    Query(ContainersQuery { status }): Query<ContainersQuery>,
+) -> Result<AxumJson<Vec<GatewayContainer>>, Error> {
+    let containers = service.list_containers(status).await?;
+
+    Ok(AxumJson(containers))
+}
+
+/// The query parameters of `GET /admin/logs`
+#[derive(Deserialize)]
+pub struct GatewayLogsQuery {
+    pub level: Option<String>,
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/logs",
+    responses(
+        (status = 200, description = "Successfully started streaming the logs of the gateway itself, as server-sent events of the latest ones and of those to come."),
+        (status = 400, description = "Unknown level to filter on."),
+        (status = 503, description = "The logs of the gateway are not kept."),
+    ),
+    params(
+        ("level" = Option<String>, Query, description = "Only stream the events at this level (e.g. `warn`) or more severe, rather than all of them."),
+    )
+)]
+async fn get_gateway_logs(
+    State(RouterState { logs, .. }): State<RouterState>,
+    Query(GatewayLogsQuery { level }): Query<GatewayLogsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, axum::Error>>>, Error> {
+    let logs = logs.ok_or_else(|| {
+        Error::custom(
+            ErrorKind::ServiceUnavailable,
+            "the logs of the gateway are not kept",
+        )
+    })?;
+
+    let level = match level {
+        Some(level) => level.parse().map_err(|_| {
+            Error::custom(
+                ErrorKind::InvalidOperation,
+                format!("`{level}` is not a log level"),
+            )
+        })?,
+        None => Level::TRACE,
+    };
+
+    let events = logs
+        .tail(level)
+        .map(|event| SseEvent::default().json_data(event));
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/metrics",
+    responses(
+        (status = 200, description = "Successfully fetched the gateway metrics, in the Prometheus text format."),
+    )
+)]
+async fn get_metrics(State(service): State<Arc<GatewayService>>) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(service.metrics().render().into())
+        .unwrap()
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/config",
+    responses(
+        (status = 200, description = "Successfully fetched the effective configuration of the gateway, with its sensitive values redacted."),
+        (status = 503, description = "The configuration is not known to the API."),
+    )
+)]
+async fn get_config(
+    State(RouterState { config, .. }): State<RouterState>,
+) -> Result<AxumJson<BTreeMap<&'static str, String>>, Error> {
+    let config = config.ok_or_else(|| {
+        Error::custom(
+            ErrorKind::ServiceUnavailable,
+            "the configuration was not handed to the API",
+        )
+    })?;
+
+    Ok(AxumJson(config.as_ref().clone()))
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/uptime",
+    responses(
+        (status = 200, description = "Successfully fetched when the gateway started."),
+    )
+)]
+async fn get_uptime() -> AxumJson<UptimeResponse> {
+    AxumJson(UptimeResponse {
+        started_at: DateTime::<Utc>::from(telemetry::started_at()).trunc_subsecs(0),
+        uptime_seconds: telemetry::uptime().as_secs(),
+    })
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/worker/status",
+    responses(
+        (status = 200, description = "Successfully fetched the status of the worker."),
+        (status = 503, description = "The status of the worker is not being tracked."),
+        (status = 500, description = "Server internal error.")
+    )
+)]
+async fn get_worker_status(
+    State(RouterState {
+        service,
+        sender,
+        worker_status,
+        ..
+    }): State<RouterState>,
+) -> Result<AxumJson<WorkerStatusResponse>, Error> {
+    let worker_status = worker_status.ok_or_else(|| {
+        Error::custom(
+            ErrorKind::ServiceUnavailable,
+            "the worker status is not being tracked",
+        )
+    })?;
+
+    let (status, current_task) = worker_status.get().await;
+
+    // Work is either still in the channel, or sorted into a lane already
+    let in_lanes: usize = service
+        .metrics()
+        .worker_queue_depth(LIFECYCLE_EXECUTOR)
+        .values()
+        .sum();
+
+    Ok(AxumJson(WorkerStatusResponse {
+        status,
+        current_task,
+        queued: sender.max_capacity() - sender.capacity() + in_lanes,
+    }))
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    responses(
+        (status = 200, description = "Successfully fetched the number of projects in each state.", body = HashMap<String, usize>),
+        (status = 500, description = "Server internal error.")
+    )
+)]
+async fn get_project_counts(
+    State(service): State<Arc<GatewayService>>,
+) -> Result<AxumJson<HashMap<String, usize>>, Error> {
+    let counts = service.project_count_by_state().await?;
+
+    Ok(AxumJson(counts))
+}
+
+#[instrument(skip_all, fields(%account_name))]
+#[utoipa::path(
+    get,
+    path = "/admin/accounts/{account_name}",
+    responses(
+        (status = 200, description = "Successfully fetched the account."),
+        (status = 404, description = "No such account."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("account_name" = String, Path, description = "The name of the account."),
+    )
+)]
+async fn get_account(
+    State(service): State<Arc<GatewayService>>,
+    Path(account_name): Path<AccountName>,
+) -> Result<AxumJson<Account>, Error> {
+    let account = service.get_account(&account_name).await?;
+
+    Ok(AxumJson(account))
+}
+
+#[instrument(skip_all, fields(%account_name))]
+#[utoipa::path(
+    patch,
+    path = "/admin/accounts/{account_name}",
+    responses(
+        (status = 200, description = "Successfully updated the account."),
+        (status = 404, description = "No such account."),
+        (status = 500, description = "Server internal error.")
+    ),
+    params(
+        ("account_name" = String, Path, description = "The name of the account."),
+    )
+)]
+async fn update_account(
+    State(service): State<Arc<GatewayService>>,
+    Path(account_name): Path<AccountName>,
+    AxumJson(update): AxumJson<AccountUpdate>,
+) -> Result<AxumJson<Account>, Error> {
+    if let Some(tier) = update.tier {
+        service.set_account_tier(&account_name, tier).await?;
+    }
+
+    if let Some(suspended) = update.suspended {
+        service
+            .set_account_suspended(&account_name, suspended)
+            .await?;
+    }
+
+    if let Some(super_user) = update.super_user {
+        service
+            .set_account_super_user(&account_name, super_user)
+            .await?;
+    }
+
+    let account = service.get_account(&account_name).await?;
+
+    Ok(AxumJson(account))
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    put,
+    path = "/users/me/email",
+    responses(
+        (status = 202, description = "Successfully sent a token to the address, which is the account's once the token is given back."),
+        (status = 400, description = "The address is not valid."),
+        (status = 500, description = "Server internal error.")
+    )
+)]
+async fn set_user_email(
+    State(service): State<Arc<GatewayService>>,
+    user: User,
+    AxumJson(EmailUpdate { email }): AxumJson<EmailUpdate>,
+) -> Result<(StatusCode, AxumJson<EmailStatus>), Error> {
+    service.request_account_email(user.name(), &email).await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        AxumJson(EmailStatus {
+            email,
+            verified: false,
+        }),
+    ))
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    post,
+    path = "/users/me/email/verify",
+    responses(
+        (status = 200, description = "Successfully verified the address, which is now the account's."),
+        (status = 400, description = "There is no address being verified."),
+        (status = 403, description = "The token is not the one sent to the address, or it expired."),
+        (status = 500, description = "Server internal error.")
+    )
+)]
+async fn verify_user_email(
+    State(service): State<Arc<GatewayService>>,
+    user: User,
+    AxumJson(EmailVerification { token }): AxumJson<EmailVerification>,
+) -> Result<AxumJson<EmailStatus>, Error> {
+    let email = service.verify_account_email(user.name(), &token).await?;
+
+    Ok(AxumJson(EmailStatus {
+        email,
+        verified: true,
+    }))
+}
+
+#[instrument(skip_all)]
+#[utoipa::path(
+    post,
+    path = "/admin/backup",
+    responses(
+        (status = 200, description = "Successfully created a backup of the state database.", content_type = "application/json", body = String),
+        (status = 500, description = "Server internal error.")
+    )
+)]
+async fn create_backup(
+    State(service): State<Arc<GatewayService>>,
+) -> Result<AxumJson<String>, Error> {
+    let path = service.backup().await?;
+
+    Ok(AxumJson(path.display().to_string()))
+}
+
 struct SecurityAddon;
 
 impl Modify for SecurityAddon {
@@ -629,13 +1657,39 @@ impl Modify for SecurityAddon {
         renew_custom_domain_acme_certificate,
         renew_gateway_acme_certificate,
         get_status,
+        get_readyz,
         get_projects_list,
         get_project,
+        get_project_container_id,
+        get_project_name_availability,
         destroy_project,
         create_project,
+        set_project_network_policy,
+        deploy_project,
+        get_project_diff,
+        download_project_logs,
+        get_project_deployments,
+        get_project_operations,
+        get_project_operation,
+        set_project_deployment,
         post_load,
         delete_load,
         get_projects,
+        get_project_counts,
+        get_stale_projects,
+        get_dead_lettered_projects,
+        get_networks,
+        get_containers,
+        get_worker_status,
+        get_gateway_logs,
+        get_metrics,
+        get_uptime,
+        get_config,
+        create_backup,
+        get_account,
+        update_account,
+        set_user_email,
+        verify_user_email,
         revive_projects,
         destroy_projects,
         get_load_admin,
@@ -646,8 +1700,13 @@ impl Modify for SecurityAddon {
         shuttle_common::models::project::Response,
         shuttle_common::models::stats::LoadResponse,
         shuttle_common::models::project::AdminResponse,
+        shuttle_common::models::project::AdminPage,
         shuttle_common::models::stats::LoadResponse,
-        shuttle_common::models::project::State
+        shuttle_common::models::project::State,
+        shuttle_common::models::project::NetworkPolicy,
+        shuttle_common::models::error::ApiError,
+        shuttle_common::models::error::ErrorCode,
+        shuttle_common::models::error::ErrorKind
     ))
 )]
 pub struct ApiDoc;
@@ -657,13 +1716,30 @@ pub(crate) struct RouterState {
     pub service: Arc<GatewayService>,
     pub sender: Sender<BoxedTask>,
     pub running_builds: Arc<Mutex<TtlCache<Uuid, ()>>>,
+    pub worker_status: Option<WorkerStatusHandle>,
+    pub config: Option<Arc<BTreeMap<&'static str, String>>>,
+    pub logs: Option<GatewayLogs>,
+}
+
+/// Lets the handlers which only need the service take it as their state,
+/// e.g. `State(service): State<Arc<GatewayService>>`, and be called with
+/// it alone in tests
+impl FromRef<RouterState> for Arc<GatewayService> {
+    fn from_ref(state: &RouterState) -> Self {
+        Arc::clone(&state.service)
+    }
 }
 
 pub struct ApiBuilder {
     router: Router<RouterState>,
     service: Option<Arc<GatewayService>>,
     sender: Option<Sender<BoxedTask>>,
+    worker_status: Option<WorkerStatusHandle>,
+    config: Option<Arc<BTreeMap<&'static str, String>>>,
+    logs: Option<GatewayLogs>,
     bind: Option<SocketAddr>,
+    bind_retries: u32,
+    bind_retry_delay: Duration,
 }
 
 impl Default for ApiBuilder {
@@ -678,7 +1754,12 @@ impl ApiBuilder {
             router: Router::new(),
             service: None,
             sender: None,
+            worker_status: None,
+            config: None,
+            logs: None,
             bind: None,
+            bind_retries: 0,
+            bind_retry_delay: Duration::from_secs(2),
         }
     }
 
@@ -725,16 +1806,48 @@ impl ApiBuilder {
         self
     }
 
+    pub fn with_worker_status(mut self, worker_status: WorkerStatusHandle) -> Self {
+        self.worker_status = Some(worker_status);
+        self
+    }
+
+    /// The configuration to show at `/admin/config`, as given by
+    /// iterating over the [`Args`](crate::args::Args)
+    pub fn with_config<I>(mut self, config: I) -> Self
+    where
+        I: IntoIterator<Item = (&'static str, String)>,
+    {
+        self.config = Some(Arc::new(config.into_iter().collect()));
+        self
+    }
+
+    /// The logs to stream at `/admin/logs`, as kept by the layer given to
+    /// the gateway's tracing subscriber
+    pub fn with_logs(mut self, logs: GatewayLogs) -> Self {
+        self.logs = Some(logs);
+        self
+    }
+
     pub fn binding_to(mut self, addr: SocketAddr) -> Self {
         self.bind = Some(addr);
         self
     }
 
+    /// Try binding up to `retries` more times when the address is taken,
+    /// waiting `delay` before the first retry and twice as long before
+    /// every one after it
+    pub fn with_bind_retry(mut self, retries: u32, delay: Duration) -> Self {
+        self.bind_retries = retries;
+        self.bind_retry_delay = delay;
+        self
+    }
+
     pub fn with_default_traces(mut self) -> Self {
         self.router = self.router.route_layer(from_extractor::<Metrics>()).layer(
             TraceLayer::new(|request| {
                 request_span!(
                     request,
+                    request.id = %problem::request_id(request.headers()),
                     account.name = field::Empty,
                     request.params.project_name = field::Empty,
                     request.params.account_name = field::Empty
@@ -746,9 +1859,38 @@ impl ApiBuilder {
         self
     }
 
+    /// Answer CORS preflight requests, letting browsers cache the answer
+    /// for `max_age`. This has to come last for the preflight requests to
+    /// be answered before they reach the authentication layers.
+    pub fn with_cors(mut self, max_age: Duration) -> Self {
+        self.router = self.router.layer(
+            CorsLayer::new()
+                .allow_origin(Any)
+                .allow_methods(Any)
+                .allow_headers(Any)
+                .max_age(max_age),
+        );
+        self
+    }
+
     pub fn with_default_routes(mut self) -> Self {
         let admin_routes = Router::new()
             .route("/projects", get(get_projects))
+            .route("/projects/stale", get(get_stale_projects))
+            .route("/projects/dead-letter", get(get_dead_lettered_projects))
+            .route("/networks", get(get_networks))
+            .route("/containers", get(get_containers))
+            .route("/stats", get(get_project_counts))
+            .route("/worker/status", get(get_worker_status))
+            .route("/logs", get(get_gateway_logs))
+            .route("/metrics", get(get_metrics))
+            .route("/uptime", get(get_uptime))
+            .route("/config", get(get_config))
+            .route("/backup", post(create_backup))
+            .route(
+                "/accounts/:account_name",
+                get(get_account).patch(update_account),
+            )
             .route("/revive", post(revive_projects))
             .route("/destroy", post(destroy_projects))
             .route("/stats/load", get(get_load_admin).delete(delete_load_admin))
@@ -760,6 +1902,7 @@ impl ApiBuilder {
         self.router = self
             .router
             .route("/", get(get_status))
+            .route("/readyz", get(get_readyz))
             .route(
                 "/projects",
                 get(get_projects_list.layer(ScopedLayer::new(vec![Scope::Project]))),
@@ -770,8 +1913,62 @@ impl ApiBuilder {
                     .delete(destroy_project.layer(ScopedLayer::new(vec![Scope::ProjectCreate])))
                     .post(create_project.layer(ScopedLayer::new(vec![Scope::ProjectCreate]))),
             )
+            .route(
+                "/projects/:project_name/availability",
+                get(get_project_name_availability.layer(ScopedLayer::new(vec![Scope::Project]))),
+            )
+            .route(
+                "/projects/:project_name/container-id",
+                get(get_project_container_id.layer(ScopedLayer::new(vec![Scope::Project]))),
+            )
+            .route(
+                "/projects/:project_name/network-policy",
+                post(
+                    set_project_network_policy.layer(ScopedLayer::new(vec![Scope::ProjectCreate])),
+                ),
+            )
+            .route(
+                "/projects/:project_name/diff",
+                get(get_project_diff.layer(ScopedLayer::new(vec![Scope::Project]))),
+            )
+            .route(
+                "/projects/:project_name/logs/download",
+                get(download_project_logs.layer(ScopedLayer::new(vec![Scope::Logs]))),
+            )
+            .route(
+                "/projects/:project_name/deployments",
+                get(get_project_deployments.layer(ScopedLayer::new(vec![Scope::Project]))),
+            )
+            .route(
+                "/projects/:project_name/operations",
+                get(get_project_operations.layer(ScopedLayer::new(vec![Scope::Project]))),
+            )
+            .route(
+                "/projects/:project_name/operations/:operation_id",
+                get(get_project_operation.layer(ScopedLayer::new(vec![Scope::Project]))),
+            )
+            .route(
+                "/projects/:project_name/deploy",
+                // The size of the artifact is checked as it comes in
+                post(deploy_project.layer(ScopedLayer::new(vec![Scope::DeploymentPush])))
+                    .layer(DefaultBodyLimit::disable()),
+            )
             .route("/projects/:project_name/*any", any(route_project))
+            .route(
+                "/internal/projects/:project_name/deployment",
+                put(set_project_deployment),
+            )
             .route("/stats/load", post(post_load).delete(delete_load))
+            // Any user can see to their own address, whatever they can do
+            // with projects
+            .route(
+                "/users/me/email",
+                put(set_user_email.layer(ScopedLayer::new(Vec::new()))),
+            )
+            .route(
+                "/users/me/email/verify",
+                post(verify_user_email.layer(ScopedLayer::new(Vec::new()))),
+            )
             .nest("/admin", admin_routes);
 
         self
@@ -805,26 +2002,66 @@ impl ApiBuilder {
 
         let running_builds = Arc::new(Mutex::new(TtlCache::new(concurrent_builds)));
 
-        self.router.with_state(RouterState {
-            service,
-            sender,
-            running_builds,
-        })
+        // Outermost, for the request id it gives to be the one in the logs
+        self.router
+            .layer(middleware::from_fn(problem::negotiate))
+            .with_state(RouterState {
+                service,
+                sender,
+                running_builds,
+                worker_status: self.worker_status,
+                config: self.config,
+                logs: self.logs,
+            })
     }
 
-    pub fn serve(self) -> impl Future<Output = Result<(), hyper::Error>> {
+    pub async fn serve(self) -> Result<(), hyper::Error> {
         let bind = self.bind.expect("a socket address to bind to is required");
+        let (retries, delay) = (self.bind_retries, self.bind_retry_delay);
         let router = self.into_router();
-        axum::Server::bind(&bind).serve(router.into_make_service())
+
+        bind_with_retry(bind, retries, delay)
+            .await?
+            .serve(router.into_make_service())
+            .await
     }
 }
 
+/// Bind to `addr`, trying again up to `retries` times while it fails,
+/// with a wait starting at `delay` and doubling between the tries
+async fn bind_with_retry(
+    addr: SocketAddr,
+    retries: u32,
+    mut delay: Duration,
+) -> Result<hyper::server::Builder<AddrIncoming>, hyper::Error> {
+    for retry in 1..=retries {
+        match axum::Server::try_bind(&addr) {
+            Ok(builder) => return Ok(builder),
+            Err(err) => {
+                warn!(
+                    error = %err,
+                    retry,
+                    retries,
+                    "failed to bind the api to {addr}, retrying in {delay:?}"
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+
+    axum::Server::try_bind(&addr)
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::sync::Arc;
+    use std::time::Instant;
 
     use axum::body::Body;
+    use axum::headers::authorization::Bearer;
     use axum::headers::Authorization;
+    use axum::http::header::RETRY_AFTER;
     use axum::http::Request;
     use futures::TryFutureExt;
     use hyper::StatusCode;
@@ -833,10 +2070,12 @@ pub mod tests {
     use tower::Service;
 
     use super::*;
-    use crate::service::GatewayService;
+    use crate::service::{DeploymentStatus, GatewayService};
+    use crate::task::TASK_SEND_TIMEOUT;
     use crate::tests::{RequestBuilderExt, World};
 
     #[tokio::test]
+    #[ignore]
     async fn api_create_get_delete_projects() -> anyhow::Result<()> {
         let world = World::new().await;
         let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
@@ -922,6 +2161,23 @@ pub mod tests {
             .await
             .unwrap();
 
+        let get_container_id = |project| {
+            Request::builder()
+                .method("GET")
+                .uri(format!("/projects/{project}/container-id"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The project has only just been created, it has no container yet
+        router
+            .call(get_container_id("matrix").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            })
+            .await
+            .unwrap();
+
         router
             .call(delete_project("matrix").with_header(&authorization))
             .map_ok(|resp| {
@@ -948,6 +2204,12 @@ pub mod tests {
             .await
             .unwrap();
 
+        router
+            .call(get_container_id("reloaded").with_header(&authorization))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::NOT_FOUND))
+            .await
+            .unwrap();
+
         router
             .call(delete_project("reloaded").with_header(&authorization))
             .map_ok(|resp| {
@@ -956,7 +2218,31 @@ pub mod tests {
             .await
             .unwrap();
 
-        let get_load = || {
+        // Suspended accounts are turned away, even from their own projects
+        let neo_authorization = Authorization::bearer(&neo_key).unwrap();
+        service
+            .set_account_suspended(&"neo".parse().unwrap(), true)
+            .await
+            .unwrap();
+
+        router
+            .call(get_project("reloaded").with_header(&neo_authorization))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::FORBIDDEN))
+            .await
+            .unwrap();
+
+        service
+            .set_account_suspended(&"neo".parse().unwrap(), false)
+            .await
+            .unwrap();
+
+        router
+            .call(get_project("reloaded").with_header(&neo_authorization))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
+            .await
+            .unwrap();
+
+        let get_load = || {
             Request::builder()
                 .method("GET")
                 .uri("/admin/stats/load")
@@ -1018,24 +2304,75 @@ pub mod tests {
         Ok(())
     }
 
-    #[tokio::test(flavor = "multi_thread")]
-    async fn status() {
+    #[tokio::test]
+    #[ignore]
+    async fn api_project_deployment() -> anyhow::Result<()> {
         let world = World::new().await;
         let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
 
-        let (sender, mut receiver) = channel::<BoxedTask>(1);
-        let (ctl_send, ctl_recv) = oneshot::channel();
-        let (done_send, done_recv) = oneshot::channel();
-        let worker = tokio::spawn(async move {
-            let mut done_send = Some(done_send);
-            // do not process until instructed
-            ctl_recv.await.unwrap();
+        let (sender, _receiver) = channel::<BoxedTask>(1);
 
-            while receiver.recv().await.is_some() {
-                done_send.take().unwrap().send(()).unwrap();
-                // do nothing
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let neo_key = world.create_user("neo");
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        let project = service
+            .create_project(matrix.clone(), "neo".parse().unwrap(), false, 0, true)
+            .await
+            .unwrap();
+        let token = crate::project::internal_token(&matrix, project.initial_key().unwrap());
+
+        let set_deployment = |token: Option<&str>| {
+            let mut req = Request::builder()
+                .method("PUT")
+                .uri(format!("/internal/projects/{matrix}/deployment"))
+                .header("Content-Type", "application/json");
+            if let Some(token) = token {
+                req = req.header("X-Shuttle-Admin-Secret", token);
             }
-        });
+            req.body("{\"deployment_id\": \"a1b2c3\"}".into()).unwrap()
+        };
+
+        for token in [None, Some("not-the-token")] {
+            let resp = router.call(set_deployment(token)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let resp = router.call(set_deployment(Some(&token))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let get_project = Request::builder()
+            .method("GET")
+            .uri(format!("/projects/{matrix}"))
+            .body(Body::empty())
+            .unwrap()
+            .with_header(&Authorization::bearer(&neo_key).unwrap());
+
+        let resp = router.call(get_project).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let project: project::Response = serde_json::from_slice(&body).unwrap();
+        assert_eq!(project.deployment_id.as_deref(), Some("a1b2c3"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_deploy_artifact() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let state = tempfile::tempdir().unwrap();
+        let service =
+            Arc::new(GatewayService::init(world.args(), world.pool(), state.path().into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
 
         let mut router = ApiBuilder::new()
             .with_service(Arc::clone(&service))
@@ -1044,45 +2381,1246 @@ pub mod tests {
             .with_auth_service(world.context().auth_uri)
             .into_router();
 
-        let get_status = || {
+        let neo_key = world.create_user("neo");
+        let trinity_key = world.create_user("trinity");
+
+        service
+            .create_project(
+                "matrix".parse().unwrap(),
+                "neo".parse().unwrap(),
+                false,
+                0,
+                true,
+            )
+            .await
+            .unwrap();
+
+        let deploy = |part: &str, contents: &[u8], key: &str| {
+            let mut body = format!(
+                "--BOUNDARY\r\n\
+                 Content-Disposition: form-data; name=\"{part}\"; filename=\"service.wasm\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n"
+            )
+            .into_bytes();
+            body.extend_from_slice(contents);
+            body.extend_from_slice(b"\r\n--BOUNDARY--\r\n");
+
+            Request::builder()
+                .method("POST")
+                .uri("/projects/matrix/deploy")
+                .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+                .body(Body::from(body))
+                .unwrap()
+                .with_header(&Authorization::bearer(key).unwrap())
+        };
+
+        // Only the owner can deploy
+        let resp = router
+            .call(deploy("binary", b"hello world", &trinity_key))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = router
+            .call(deploy("binary", b"hello world", &neo_key))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let deployed: DeployResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(deployed.size, 11);
+        assert_eq!(
+            deployed.sha256,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert!(state
+            .path()
+            .join("artifacts/matrix")
+            .join(&deployed.artifact_id)
+            .exists());
+
+        let resp = router
+            .call(deploy("source", b"hello world", &neo_key))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Larger than the 1 MiB the tests allow, and than axum lets through by default
+        let resp = router
+            .call(deploy("binary", &vec![0; 3 * 1024 * 1024], &neo_key))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_project_deployments() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let state = tempfile::tempdir().unwrap();
+        let service =
+            Arc::new(GatewayService::init(world.args(), world.pool(), state.path().into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let neo_key = world.create_user("neo");
+        let authorization = Authorization::bearer(&neo_key).unwrap();
+
+        service
+            .create_project(
+                "matrix".parse().unwrap(),
+                "neo".parse().unwrap(),
+                false,
+                0,
+                true,
+            )
+            .await
+            .unwrap();
+
+        let mut events = service.subscribe_project_events();
+
+        let deploy = || {
+            Request::builder()
+                .method("POST")
+                .uri("/projects/matrix/deploy")
+                .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+                .body(Body::from(
+                    "--BOUNDARY\r\n\
+                     Content-Disposition: form-data; name=\"binary\"\r\n\r\n\
+                     hello world\r\n\
+                     --BOUNDARY--\r\n",
+                ))
+                .unwrap()
+                .with_header(&authorization)
+        };
+        let get_deployments = || {
             Request::builder()
                 .method("GET")
-                .uri("/")
+                .uri("/projects/matrix/deployments")
                 .body(Body::empty())
                 .unwrap()
+                .with_header(&authorization)
         };
 
-        let resp = router.call(get_status()).await.unwrap();
+        let resp = router.call(get_deployments()).await.unwrap();
         assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"[]");
 
-        let matrix: ProjectName = "matrix".parse().unwrap();
+        let mut deployment_ids = Vec::new();
+        for _ in 0..2 {
+            let resp = router.call(deploy()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let deployed: DeployResponse = serde_json::from_slice(&body).unwrap();
+
+            let event = events.recv().await.unwrap();
+            assert_eq!(event.deployment_id.as_ref(), Some(&deployed.deployment_id));
+            assert_eq!(event.old, event.new);
+
+            deployment_ids.push(deployed.deployment_id);
+        }
+        assert_ne!(deployment_ids[0], deployment_ids[1]);
+
+        let resp = router.call(get_deployments()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let deployments: Vec<Deployment> = serde_json::from_slice(&body).unwrap();
+
+        let summary: Vec<_> = deployments
+            .iter()
+            .map(|deployment| {
+                (
+                    deployment.deployment_id.as_str(),
+                    deployment.status,
+                    deployment.finished_at.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (deployment_ids[1].as_str(), DeploymentStatus::Active, false),
+                (
+                    deployment_ids[0].as_str(),
+                    DeploymentStatus::Superseded,
+                    true
+                ),
+            ]
+        );
+
+        // Destroying the project is the end of its deployment
+        service
+            .finish_deployment(&"matrix".parse().unwrap())
+            .await?;
+        let deployments = service.list_deployments(&"matrix".parse().unwrap()).await?;
+        assert_eq!(deployments[0].status, DeploymentStatus::Destroyed);
+        assert!(deployments[0].finished_at.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_project_operations() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, mut receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
 
         let neo_key = world.create_user("neo");
         let authorization = Authorization::bearer(&neo_key).unwrap();
 
+        let get = |uri: String| {
+            Request::builder()
+                .method("GET")
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
         let create_project = Request::builder()
             .method("POST")
-            .uri(format!("/projects/{matrix}"))
+            .uri("/projects/matrix")
             .header("Content-Type", "application/json")
             .body("{\"idle_minutes\": 3}".into())
             .unwrap()
             .with_header(&authorization);
+        let resp = router.call(create_project).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let created: project::Response = serde_json::from_slice(&body).unwrap();
+        let operation_id = created.operation_id.unwrap();
+
+        // Queued up, the worker not having got to it yet
+        let work = receiver.recv().await.unwrap();
+        assert_eq!(
+            work.work().map(|work| work.id.as_str()),
+            Some(operation_id.as_str())
+        );
 
-        router.call(create_project).await.unwrap();
+        let resp = router
+            .call(get(format!("/projects/matrix/operations/{operation_id}")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let operation: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(operation["operation"], "start");
+        assert_eq!(operation["origin"], "api");
+        assert_eq!(operation["status"], "queued");
+        assert_eq!(operation["state"], "creating");
+
+        service.start_operation(&operation_id).await?;
+        service
+            .finish_operation(&operation_id, Some("docker went away"))
+            .await?;
+
+        let resp = router
+            .call(get("/projects/matrix/operations".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let operations: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0]["operation_id"], operation_id.as_str());
+        assert_eq!(operations[0]["status"], "failed");
+        assert_eq!(operations[0]["reason"], "docker went away");
+
+        let resp = router
+            .call(get("/projects/matrix/operations/nope".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
 
-        let resp = router.call(get_status()).await.unwrap();
-        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        Ok(())
+    }
 
-        ctl_send.send(()).unwrap();
-        done_recv.await.unwrap();
+    #[tokio::test]
+    async fn gzip_body_streams_a_valid_archive() {
+        use async_compression::tokio::bufread::GzipDecoder;
+        use tokio::io::AsyncReadExt;
+
+        // More than fits in the buffer between compressing and sending
+        let lines: Vec<String> = (0..100_000)
+            .map(|line| format!("2023-05-04T12:00:00Z line {line} of the logs\n"))
+            .collect();
+        let chunks =
+            futures::stream::iter(lines.clone()).map(|line| Ok::<_, Error>(Bytes::from(line)));
+
+        let archive = hyper::body::to_bytes(gzip_body(chunks)).await.unwrap();
+        assert!(archive.len() < lines.iter().map(String::len).sum::<usize>());
+
+        let mut decompressed = String::new();
+        GzipDecoder::new(&archive[..])
+            .read_to_string(&mut decompressed)
+            .await
+            .unwrap();
+        assert_eq!(decompressed, lines.concat());
+
+        // A failure along the way leaves the archive incomplete
+        let chunks = futures::stream::iter([
+            Ok(Bytes::from("the first line\n")),
+            Err(Error::from_kind(ErrorKind::Internal)),
+        ]);
+        let archive = hyper::body::to_bytes(gzip_body(chunks)).await.unwrap();
+        assert!(GzipDecoder::new(&archive[..])
+            .read_to_end(&mut Vec::new())
+            .await
+            .is_err());
+    }
 
-        let resp = router.call(get_status()).await.unwrap();
-        assert_eq!(resp.status(), StatusCode::OK);
+    #[tokio::test]
+    #[ignore]
+    async fn api_download_project_logs() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
 
-        worker.abort();
-        let _ = worker.await;
+        let (sender, mut receiver) = channel::<BoxedTask>(256);
+        tokio::spawn(async move { while receiver.recv().await.is_some() {} });
 
-        let resp = router.call(get_status()).await.unwrap();
-        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let neo_key = world.create_user("neo");
+        let authorization = Authorization::bearer(&neo_key).unwrap();
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix, neo, false, 0, true).await?;
+
+        let get = |uri: &str| {
+            Request::builder()
+                .method("GET")
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router
+            .call(get("/projects/matrix/logs/download?since=2023-05-04T12:00:00Z&until=2023-05-04T11:00:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = router
+            .call(get("/projects/matrix/logs/download?since=yesterday"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // No container to get the logs of yet
+        let resp = router
+            .call(get("/projects/matrix/logs/download"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_handlers_take_the_service_alone() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), neo.clone(), false, 0, true)
+            .await?;
+
+        let scoped_user = || ScopedUser {
+            user: User {
+                projects: vec![matrix.clone()],
+                claim: shuttle_common::claims::Claim::new(neo.to_string(), Vec::new()),
+                account: Account {
+                    name: neo.clone(),
+                    tier: AccountTier::Basic,
+                    suspended: false,
+                    super_user: false,
+                    created_at: Utc::now(),
+                    email: None,
+                },
+            },
+            scope: matrix.clone(),
+        };
+
+        // No router in between, the handlers are given their state as is
+        let AxumJson(response) = get_project(State(Arc::clone(&service)), scoped_user()).await?;
+        assert_eq!(response.name, "matrix");
+        assert_eq!(
+            response.state,
+            project::State::Creating { recreate_count: 0 }
+        );
+
+        let AxumJson(operations) =
+            get_project_operations(State(Arc::clone(&service)), scoped_user()).await?;
+        assert!(operations.is_empty());
+
+        let err = get_project_operation(
+            State(Arc::clone(&service)),
+            scoped_user(),
+            Path(("matrix".to_string(), "nope".to_string())),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OperationNotFound);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_project_diff() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let state = tempfile::tempdir().unwrap();
+        let service =
+            Arc::new(GatewayService::init(world.args(), world.pool(), state.path().into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let neo_key = world.create_user("neo");
+        let authorization = Authorization::bearer(&neo_key).unwrap();
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        service
+            .create_project(matrix.clone(), neo.clone(), false, 0, true)
+            .await
+            .unwrap();
+
+        let get_diff = || {
+            Request::builder()
+                .method("GET")
+                .uri("/projects/matrix/diff")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router.call(get_diff()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"previous":null}"#);
+
+        // Deploying through the API runs the default image for now
+        let resp = router
+            .call(
+                Request::builder()
+                    .method("POST")
+                    .uri("/projects/matrix/deploy")
+                    .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+                    .body(Body::from(
+                        "--BOUNDARY\r\n\
+                         Content-Disposition: form-data; name=\"binary\"\r\n\r\n\
+                         hello world\r\n\
+                         --BOUNDARY--\r\n",
+                    ))
+                    .unwrap()
+                    .with_header(&authorization),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Still only the one deployment
+        let resp = router.call(get_diff()).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<DeploymentDiffResponse>(&body).unwrap(),
+            DeploymentDiffResponse::NoPrevious { previous: () }
+        );
+
+        service
+            .record_deployment(&matrix, "shuttle/deployer:next", &[], &neo)
+            .await
+            .unwrap();
+
+        let resp = router.call(get_diff()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let DeploymentDiffResponse::Diff(diff) = serde_json::from_slice(&body).unwrap() else {
+            panic!("expected a diff, got {}", String::from_utf8_lossy(&body));
+        };
+
+        assert_eq!(diff.previous_image, world.args().image);
+        assert_eq!(diff.current_image, "shuttle/deployer:next");
+        assert!(diff.image_changed);
+        assert!(!diff.env_changed);
+        assert!(diff.deployed_at <= Utc::now());
+
+        // Only the environment changes, in whatever order it is given
+        let env = ["A=1".to_string(), "B=2".to_string()];
+        service
+            .record_deployment(&matrix, "shuttle/deployer:next", &env, &neo)
+            .await
+            .unwrap();
+        let diff = service.deployment_diff(&matrix).await.unwrap().unwrap();
+        assert!(!diff.image_changed);
+        assert!(diff.env_changed);
+
+        let env = ["B=2".to_string(), "A=1".to_string()];
+        service
+            .record_deployment(&matrix, "shuttle/deployer:next", &env, &neo)
+            .await
+            .unwrap();
+        let diff = service.deployment_diff(&matrix).await.unwrap().unwrap();
+        assert!(!diff.image_changed);
+        assert!(!diff.env_changed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_networks() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let get_networks = |authorization: &Authorization<Bearer>| {
+            Request::builder()
+                .method("GET")
+                .uri("/admin/networks")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(authorization)
+        };
+
+        let neo_key = world.create_user("neo");
+        let authorization = Authorization::bearer(&neo_key).unwrap();
+
+        let resp = router.call(get_networks(&authorization)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let admin_key = world.create_user("admin-neo");
+        world.set_super_user("admin-neo");
+        let authorization = Authorization::bearer(&admin_key).unwrap();
+
+        let resp = router.call(get_networks(&authorization)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Nothing is labelled with the prefix of a fresh gateway
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let networks: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(networks.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_containers() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let get_containers = |uri: &str, authorization: &Authorization<Bearer>| {
+            Request::builder()
+                .method("GET")
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(authorization)
+        };
+
+        let neo_key = world.create_user("neo");
+        let authorization = Authorization::bearer(&neo_key).unwrap();
+
+        let resp = router
+            .call(get_containers("/admin/containers", &authorization))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let admin_key = world.create_user("admin-neo");
+        world.set_super_user("admin-neo");
+        let authorization = Authorization::bearer(&admin_key).unwrap();
+
+        for uri in [
+            "/admin/containers",
+            "/admin/containers?status=running",
+            "/admin/containers?status=stopped",
+            "/admin/containers?status=all",
+        ] {
+            let resp = router
+                .call(get_containers(uri, &authorization))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+
+            // Nothing is labelled with the prefix of a fresh gateway
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let containers: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+            assert!(containers.is_empty(), "{uri}");
+        }
+
+        let resp = router
+            .call(get_containers(
+                "/admin/containers?status=paused",
+                &authorization,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_dead_lettered_projects() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), neo, false, 0, true)
+            .await?;
+        service
+            .dead_letter_project(&matrix, "docker went away")
+            .await?;
+
+        let admin_key = world.create_user("admin-neo");
+        world.set_super_user("admin-neo");
+        let authorization = Authorization::bearer(&admin_key).unwrap();
+
+        let resp = router
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri("/admin/projects/dead-letter")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&authorization),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let projects: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0]["project_name"], "matrix");
+        assert_eq!(projects[0]["last_error"], "docker went away");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_admin_projects() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let trinity: AccountName = "trinity".parse().unwrap();
+        for (project, account) in [
+            ("reloaded", &neo),
+            ("matrix", &neo),
+            ("zion", &trinity),
+            ("revolutions", &neo),
+        ] {
+            service
+                .create_project(project.parse().unwrap(), account.clone(), false, 0, true)
+                .await?;
+        }
+        let revolutions: ProjectName = "revolutions".parse().unwrap();
+        let destroyed = service.find_project(&revolutions).await?.destroy().unwrap();
+        service.update_project(&revolutions, &destroyed).await?;
+
+        let admin_key = world.create_user("admin-neo");
+        world.set_super_user("admin-neo");
+        let authorization = Authorization::bearer(&admin_key).unwrap();
+
+        let mut get_page = |uri: &'static str| {
+            let req = Request::builder()
+                .method("GET")
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization);
+            let resp = router.call(req);
+            async move {
+                let resp = resp.await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                let page: project::AdminPage = serde_json::from_slice(&body).unwrap();
+                let names: Vec<_> = page
+                    .projects
+                    .iter()
+                    .map(|project| project.project_name.clone())
+                    .collect();
+                assert!(
+                    page.projects
+                        .iter()
+                        .all(|project| project.created_at.is_some()),
+                    "{uri}"
+                );
+
+                (names, page.total, page.next_cursor)
+            }
+        };
+
+        assert_eq!(
+            get_page("/admin/projects").await,
+            (
+                vec![
+                    "matrix".to_string(),
+                    "reloaded".to_string(),
+                    "revolutions".to_string(),
+                    "zion".to_string()
+                ],
+                4,
+                None
+            )
+        );
+
+        // Paging through them all
+        assert_eq!(
+            get_page("/admin/projects?limit=2").await,
+            (
+                vec!["matrix".to_string(), "reloaded".to_string()],
+                4,
+                Some("reloaded".to_string())
+            )
+        );
+        assert_eq!(
+            get_page("/admin/projects?limit=2&cursor=reloaded").await,
+            (vec!["revolutions".to_string(), "zion".to_string()], 4, None)
+        );
+
+        // Filtering, with the total only counting those which match
+        assert_eq!(
+            get_page("/admin/projects?account=trinity").await,
+            (vec!["zion".to_string()], 1, None)
+        );
+        assert_eq!(
+            get_page("/admin/projects?state=destroyed").await,
+            (vec!["revolutions".to_string()], 1, None)
+        );
+        assert_eq!(
+            get_page("/admin/projects?account=neo&state=creating&limit=1").await,
+            (vec!["matrix".to_string()], 2, Some("matrix".to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_readyz() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, mut receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender.clone())
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let get_readyz = || {
+            Request::builder()
+                .method("GET")
+                .uri("/readyz")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let resp = router.call(get_readyz()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // No room left for more work
+        sender
+            .send(service.new_task().project("matrix".parse()?).build())
+            .await?;
+        let resp = router.call(get_readyz()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[RETRY_AFTER], "5");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status_code"], 503);
+        assert_eq!(body["error"]["code"], "not_ready");
+
+        receiver.recv().await.unwrap();
+        let resp = router.call(get_readyz()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Nothing to take the work anymore
+        drop(receiver);
+        let resp = router.call(get_readyz()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_config() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let admin_key = world.create_user("admin-neo");
+        world.set_super_user("admin-neo");
+        let authorization = Authorization::bearer(&admin_key).unwrap();
+
+        let get_config = || {
+            Request::builder()
+                .method("GET")
+                .uri("/admin/config")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender.clone())
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let resp = router.call(get_config()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_config([
+                ("prefix", "shuttle_test_".to_string()),
+                ("master_key", crate::args::REDACTED.to_string()),
+            ])
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let resp = router.call(get_config()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let config: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            config,
+            serde_json::json!({ "prefix": "shuttle_test_", "master_key": "[redacted]" })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_uptime() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let admin_key = world.create_user("admin-neo");
+        world.set_super_user("admin-neo");
+        let authorization = Authorization::bearer(&admin_key).unwrap();
+
+        let get_uptime = || {
+            Request::builder()
+                .method("GET")
+                .uri("/admin/uptime")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let mut uptimes = Vec::new();
+        for _ in 0..2 {
+            let resp = router.call(get_uptime()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            uptimes.push(serde_json::from_slice::<UptimeResponse>(&body).unwrap());
+
+            tokio::time::sleep(Duration::from_millis(1100)).await;
+        }
+
+        let (first, second) = (&uptimes[0], &uptimes[1]);
+        assert_eq!(first.started_at, second.started_at);
+        assert!(first.started_at <= Utc::now());
+        assert!(second.uptime_seconds > first.uptime_seconds);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn status() {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, mut receiver) = channel::<BoxedTask>(1);
+        let (ctl_send, ctl_recv) = oneshot::channel();
+        let (done_send, done_recv) = oneshot::channel();
+        let worker = tokio::spawn(async move {
+            let mut done_send = Some(done_send);
+            // do not process until instructed
+            ctl_recv.await.unwrap();
+
+            while receiver.recv().await.is_some() {
+                done_send.take().unwrap().send(()).unwrap();
+                // do nothing
+            }
+        });
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let get_status = || {
+            Request::builder()
+                .method("GET")
+                .uri("/")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let resp = router.call(get_status()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        let neo_key = world.create_user("neo");
+        let authorization = Authorization::bearer(&neo_key).unwrap();
+
+        let create_project = Request::builder()
+            .method("POST")
+            .uri(format!("/projects/{matrix}"))
+            .header("Content-Type", "application/json")
+            .body("{\"idle_minutes\": 3}".into())
+            .unwrap()
+            .with_header(&authorization);
+
+        router.call(create_project).await.unwrap();
+
+        let resp = router.call(get_status()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        ctl_send.send(()).unwrap();
+        done_recv.await.unwrap();
+
+        let resp = router.call(get_status()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        worker.abort();
+        let _ = worker.await;
+
+        let resp = router.call(get_status()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_create_reserved_project() {
+        let world = World::new().await;
+        let mut args = world.args();
+        args.reserved_project_names = vec!["ADMIN".to_string(), "www".to_string()];
+        let service = Arc::new(GatewayService::init(args, world.pool(), "".into()).await);
+
+        let (sender, mut receiver) = channel::<BoxedTask>(256);
+        tokio::spawn(async move {
+            while receiver.recv().await.is_some() {
+                // do not do any work with inbound requests
+            }
+        });
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let create_project = |project: &str, authorization: &Authorization<Bearer>| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("/projects/{project}"))
+                .header("Content-Type", "application/json")
+                .body("{\"idle_minutes\": 3}".into())
+                .unwrap()
+                .with_header(authorization)
+        };
+
+        let neo = Authorization::bearer(&world.create_user("neo")).unwrap();
+
+        let resp = router.call(create_project("admin", &neo)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = router.call(create_project("matrix", &neo)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let admin_key = world.create_user("admin-neo");
+        world.set_super_user("admin-neo");
+        let admin = Authorization::bearer(&admin_key).unwrap();
+
+        let resp = router.call(create_project("admin", &admin)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Kept for the gateway whatever the configuration says, as is
+        // the label projects are served under
+        for reserved in ["api", "www", "test"] {
+            let resp = router.call(create_project(reserved, &neo)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{reserved}");
+        }
+
+        let availability = |project: &str| {
+            Request::builder()
+                .method("GET")
+                .uri(format!("/projects/{project}/availability"))
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&neo)
+        };
+
+        for (project, expected) in [
+            ("reloaded", serde_json::json!({ "available": true })),
+            (
+                "api",
+                serde_json::json!({ "available": false, "reason": "reserved" }),
+            ),
+            (
+                "admin",
+                serde_json::json!({ "available": false, "reason": "taken" }),
+            ),
+            (
+                "matrix",
+                serde_json::json!({ "available": false, "reason": "taken" }),
+            ),
+            (
+                "-matrix-",
+                serde_json::json!({ "available": false, "reason": "invalid" }),
+            ),
+        ] {
+            let resp = router.call(availability(project)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body, expected, "{project}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn api_turns_work_away_when_queue_is_full() {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        // Nothing takes work off the queue, which fits a single task
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .with_auth_service(world.context().auth_uri)
+            .into_router();
+
+        let neo_key = world.create_user("neo");
+        let authorization = Authorization::bearer(&neo_key).unwrap();
+
+        let create_project = |project: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("/projects/{project}"))
+                .header("Content-Type", "application/json")
+                .body("{\"idle_minutes\": 3}".into())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router.call(create_project("matrix")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(service.metrics().worker_queue_full(), 0);
+
+        // The queue is full now, which is said straight away rather than
+        // after waiting for room
+        let started = Instant::now();
+        let resp = router.call(create_project("reloaded")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(started.elapsed() < TASK_SEND_TIMEOUT);
+        assert_eq!(service.metrics().worker_queue_full(), 1);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["message"].as_str().unwrap().contains("try again"));
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn api_cors_preflight() {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let (sender, _receiver) = channel::<BoxedTask>(1);
+
+        for max_age in [600, 0] {
+            let mut router = ApiBuilder::new()
+                .with_service(Arc::clone(&service))
+                .with_sender(sender.clone())
+                .with_default_routes()
+                .with_auth_service(world.context().auth_uri)
+                .with_cors(Duration::from_secs(max_age))
+                .into_router();
+
+            // Preflight requests carry no credentials, and are answered
+            // before authentication
+            let preflight = Request::builder()
+                .method("OPTIONS")
+                .uri("/projects/matrix")
+                .header("Origin", "https://console.shuttle.rs")
+                .header("Access-Control-Request-Method", "POST")
+                .header("Access-Control-Request-Headers", "authorization")
+                .body(Body::empty())
+                .unwrap();
+
+            let resp = router.call(preflight).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers()["Access-Control-Max-Age"],
+                max_age.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn openapi_lists_the_error_codes() {
+        use strum::IntoEnumIterator;
+
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let (_, schema) = openapi["components"]["schemas"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .find(|(name, _)| name.ends_with("ErrorKind"))
+            .unwrap();
+
+        let codes: Vec<_> = schema["enum"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|code| code.as_str().unwrap())
+            .collect();
+        let expected: Vec<_> = ErrorKind::iter().map(|kind| kind.code()).collect();
+        assert_eq!(codes, expected);
+    }
+
+    #[tokio::test]
+    async fn bind_is_retried_until_the_address_is_free() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+
+        let binding = tokio::spawn(bind_with_retry(addr, 5, Duration::from_millis(50)));
+
+        // Two tries in, the address is let go of
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!binding.is_finished());
+        drop(taken);
+
+        let server = binding.await.unwrap().unwrap();
+        assert_eq!(
+            server.serve(Router::new().into_make_service()).local_addr(),
+            addr
+        );
+    }
+
+    #[tokio::test]
+    async fn bind_fails_once_out_of_retries() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+
+        let started = Instant::now();
+        assert!(bind_with_retry(addr, 2, Duration::from_millis(10))
+            .await
+            .is_err());
+        // Waited 10ms, then 20ms
+        assert!(started.elapsed() >= Duration::from_millis(30));
     }
 }
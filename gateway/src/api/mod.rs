@@ -1,3 +1,4 @@
 mod auth_layer;
 
+pub mod extract;
 pub mod latest;
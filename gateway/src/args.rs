@@ -1,19 +1,257 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::error::Error as StdError;
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::builder::RangedU64ValueParser;
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::parser::ValueSource;
+use clap::{
+    Arg, ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
 use fqdn::FQDN;
 use http::Uri;
+use sqlx::sqlite::SqliteConnectOptions;
+
+use crate::encryption::MasterKey;
+use crate::tls::CaBundle;
+
+/// What the environment variables of the flags start with. Every flag has
+/// one, named after it: `--worker-concurrency` can be set with
+/// `SHUTTLE_GATEWAY_WORKER_CONCURRENCY`.
+pub const ENV_PREFIX: &str = "SHUTTLE_GATEWAY_";
 
 #[derive(Parser, Debug)]
 pub struct Args {
+    /// A TOML file of settings named after the flags, with dashes or
+    /// underscores (e.g. `worker-concurrency = 8`). The flags, and their
+    /// environment variables, take precedence over it
+    #[arg(long, env = "SHUTTLE_GATEWAY_CONFIG")]
+    pub config: Option<PathBuf>,
+
     /// Where to store gateway state (such as sqlite state, and certs)
-    #[arg(long, default_value = "./")]
+    #[arg(long, env = "SHUTTLE_GATEWAY_STATE", default_value = "./")]
     pub state: PathBuf,
 
     #[command(subcommand)]
     pub command: Commands,
 }
 
+impl Args {
+    /// The args of the process, with the settings of the `--config` file
+    /// under them. Like [`Parser::parse`], this exits with the error when
+    /// they do not make sense.
+    pub fn load() -> Self {
+        Self::try_load_from(std::env::args_os()).unwrap_or_else(|err| err.exit())
+    }
+
+    /// Parse `argv`, with the environment, then parse it again with the
+    /// settings of the `--config` file it names, if any, added for those
+    /// neither gives already
+    pub fn try_load_from<I, T>(argv: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let argv: Vec<OsString> = argv.into_iter().map(Into::into).collect();
+        let mut command = Self::command();
+        let matches = try_get_matches(&mut command, &argv)?;
+        let args = Self::from_arg_matches(&matches)?;
+
+        let Some(path) = args.config.as_deref() else {
+            return Ok(args);
+        };
+
+        let file = std::fs::read_to_string(path).map_err(|err| {
+            command.error(
+                ErrorKind::Io,
+                format!("failed to read the config file {}: {err}", path.display()),
+            )
+        })?;
+        let file: toml::value::Table = toml::from_str(&file).map_err(|err| {
+            command.error(
+                ErrorKind::InvalidValue,
+                format!("invalid config file {}: {err}", path.display()),
+            )
+        })?;
+
+        let argv =
+            with_config_file(&command, &matches, argv, file).map_err(|(kind, message)| {
+                command.error(
+                    kind,
+                    format!("{message} in the config file {}", path.display()),
+                )
+            })?;
+
+        let matches = try_get_matches(&mut command, &argv)?;
+        Self::from_arg_matches(&matches)
+    }
+}
+
+/// Parse `argv` with `command`, naming the environment variable a value
+/// which does not parse came from, where clap only names its flag
+fn try_get_matches(command: &mut Command, argv: &[OsString]) -> Result<ArgMatches, clap::Error> {
+    command.try_get_matches_from_mut(argv).map_err(|err| {
+        match env_variable_error(command, argv, &err) {
+            Some(message) => command.error(err.kind(), message),
+            None => err,
+        }
+    })
+}
+
+/// What to say of an invalid value taken from an environment variable, or
+/// nothing if the error is not about one. The values of sensitive settings
+/// are not repeated.
+fn env_variable_error(command: &Command, argv: &[OsString], err: &clap::Error) -> Option<String> {
+    if !matches!(
+        err.kind(),
+        ErrorKind::InvalidValue | ErrorKind::ValueValidation
+    ) {
+        return None;
+    }
+
+    let Some(ContextValue::String(flag)) = err.get(ContextKind::InvalidArg) else {
+        return None;
+    };
+    let arg = std::iter::once(command)
+        .chain(command.get_subcommands())
+        .flat_map(Command::get_arguments)
+        .find(|arg| arg.to_string() == *flag)?;
+    let env = arg.get_env()?;
+    let long = format!("--{}", arg.get_long()?);
+
+    // Given as a flag, or by the config file as one
+    let given = argv.iter().filter_map(|arg| arg.to_str()).any(|arg| {
+        arg == long
+            || arg
+                .strip_prefix(&long)
+                .map_or(false, |rest| rest.starts_with('='))
+    });
+    if given || std::env::var_os(env).is_none() {
+        return None;
+    }
+
+    let value = match err.get(ContextKind::InvalidValue) {
+        Some(ContextValue::String(value)) if !is_sensitive(arg.get_id().as_str()) => {
+            format!(" '{value}'")
+        }
+        _ => String::new(),
+    };
+    let expected = match (err.get(ContextKind::ValidValue), StdError::source(err)) {
+        (Some(ContextValue::Strings(values)), _) => {
+            format!("expected one of {}", values.join(", "))
+        }
+        (_, Some(source)) => source.to_string(),
+        _ => format!("expected a value for {long}"),
+    };
+
+    Some(format!(
+        "invalid value{value} in {} for {long}: {expected}",
+        env.to_string_lossy()
+    ))
+}
+
+/// Add the settings of a config file to `argv`, as flags, unless they
+/// were given already. Settings of the other subcommands are left out,
+/// for one file to do for all of them, but those of none are an error.
+fn with_config_file(
+    command: &Command,
+    matches: &ArgMatches,
+    argv: Vec<OsString>,
+    file: toml::value::Table,
+) -> Result<Vec<OsString>, (ErrorKind, String)> {
+    fn find<'c>(command: &'c Command, id: &str) -> Option<&'c Arg> {
+        command.get_arguments().find(|arg| arg.get_id() == id)
+    }
+
+    let (subcommand, sub_matches) = matches
+        .subcommand()
+        .expect("a subcommand is required to parse");
+
+    let mut top_flags = Vec::new();
+    let mut sub_flags = Vec::new();
+
+    for (key, value) in file {
+        let id = key.replace('-', "_");
+        if id == "config" {
+            return Err((
+                ErrorKind::ArgumentConflict,
+                format!("`{key}` cannot be set"),
+            ));
+        }
+
+        let sub_arg = command
+            .find_subcommand(subcommand)
+            .and_then(|sub| find(sub, &id));
+        let (arg, given, flags) = match (find(command, &id), sub_arg) {
+            (Some(arg), _) => (arg, matches, &mut top_flags),
+            (None, Some(arg)) => (arg, sub_matches, &mut sub_flags),
+            (None, None) => {
+                if command
+                    .get_subcommands()
+                    .any(|sub| find(sub, &id).is_some())
+                {
+                    continue;
+                }
+
+                return Err((
+                    ErrorKind::UnknownArgument,
+                    format!("unknown setting `{key}`"),
+                ));
+            }
+        };
+
+        // Given as a flag or an environment variable, which win
+        if matches!(
+            given.value_source(&id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let long = format!("--{}", arg.get_long().expect("settings are long flags"));
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+
+        for value in values {
+            let value = match value {
+                toml::Value::Boolean(set) if matches!(arg.get_action(), ArgAction::SetTrue) => {
+                    if set {
+                        flags.push(OsString::from(&long));
+                    }
+                    continue;
+                }
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => {
+                    return Err((
+                        ErrorKind::InvalidValue,
+                        format!(
+                            "`{key}` must be a string, a number, a boolean or an array of them"
+                        ),
+                    ))
+                }
+            };
+
+            flags.push(OsString::from(&long));
+            flags.push(OsString::from(value));
+        }
+    }
+
+    // The flags of the gateway go before the subcommand, those of the
+    // subcommand after it
+    let (bin, rest) = argv.split_at(argv.len().min(1));
+
+    Ok([bin, &top_flags[..], rest, &sub_flags[..]].concat())
+}
+
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum UseTls {
     Disable,
@@ -23,49 +261,993 @@ pub enum UseTls {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Start(StartArgs),
+    /// Replace the contents of the state database with a backup
+    Restore(RestoreArgs),
+    /// Encrypt every secret of the state database with a new master key
+    Rewrap(RewrapArgs),
 }
 
 #[derive(clap::Args, Debug, Clone)]
 pub struct StartArgs {
     /// Address to bind the control plane to
-    #[arg(long, default_value = "127.0.0.1:8001")]
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_CONTROL",
+        default_value = "127.0.0.1:8001"
+    )]
     pub control: SocketAddr,
+    /// How many more times to try binding the control plane when its
+    /// address is taken, as it can still be by the gateway this one
+    /// replaces
+    #[arg(long, env = "SHUTTLE_GATEWAY_API_BIND_RETRY", default_value = "5")]
+    pub api_bind_retry: u32,
+    /// How many seconds to wait before trying to bind the control plane
+    /// again. The wait doubles with every retry
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_API_BIND_RETRY_DELAY",
+        default_value = "2"
+    )]
+    pub api_bind_retry_delay: u64,
     /// Address to bind the bouncer service to
-    #[arg(long, default_value = "127.0.0.1:7999")]
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_BOUNCER",
+        default_value = "127.0.0.1:7999"
+    )]
     pub bouncer: SocketAddr,
     /// Address to bind the user proxy to
-    #[arg(long, default_value = "127.0.0.1:8000")]
+    #[arg(long, env = "SHUTTLE_GATEWAY_USER", default_value = "127.0.0.1:8000")]
     pub user: SocketAddr,
     /// Allows to disable the use of TLS in the user proxy service (DANGEROUS)
-    #[arg(long, default_value = "enable")]
+    #[arg(long, env = "SHUTTLE_GATEWAY_USE_TLS", default_value = "enable")]
     pub use_tls: UseTls,
+    /// How many seconds browsers can cache the answer to a CORS preflight
+    /// request to the control plane for. Setting it to 0 disables the
+    /// caching, which is handy in development
+    #[arg(long, env = "SHUTTLE_GATEWAY_CORS_MAX_AGE", default_value = "86400")]
+    pub cors_max_age: u64,
+    /// How many queued tasks to work on at the same time. Tasks for
+    /// the same project are always run one after the other
+    #[arg(long, env = "SHUTTLE_GATEWAY_WORKER_CONCURRENCY", default_value = "8")]
+    pub worker_concurrency: usize,
+    /// How many tasks can be queued up for the worker. Once it is full,
+    /// API requests which need the worker are turned away with a 503
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_WORKER_QUEUE_SIZE",
+        default_value = "2048"
+    )]
+    pub worker_queue_size: usize,
+    /// How many tasks a second the worker can start on, on average. Not
+    /// limited unless given
+    #[arg(long, env = "SHUTTLE_GATEWAY_WORKER_RATE_LIMIT")]
+    pub worker_rate_limit: Option<f64>,
+    /// How many health checks to run at the same time. They have a
+    /// worker of their own, so as not to hold up the lifecycle work of
+    /// projects
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_HEALTH_WORKER_CONCURRENCY",
+        default_value = "4"
+    )]
+    pub health_worker_concurrency: usize,
+    /// How many health checks can be queued up
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_HEALTH_WORKER_QUEUE_SIZE",
+        default_value = "2048"
+    )]
+    pub health_worker_queue_size: usize,
+    /// How many health checks a second can be started on, on average
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_HEALTH_WORKER_RATE_LIMIT",
+        default_value = "10"
+    )]
+    pub health_worker_rate_limit: f64,
+    /// How many seconds the tasks in flight get to finish when the
+    /// gateway is shut down. Those which do not are picked up again on
+    /// the next start
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_DRAIN_DEADLINE_SECS",
+        default_value = "30"
+    )]
+    pub drain_deadline_secs: u64,
+    /// How many seconds the worker can go without making progress
+    /// before it is considered stalled, and the gateway unready
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_WORKER_STALL_THRESHOLD_SECS",
+        default_value = "600"
+    )]
+    pub worker_stall_threshold_secs: u64,
+    /// Abort the task holding up the worker once it is stalled, rather
+    /// than only reporting it
+    #[arg(long, env = "SHUTTLE_GATEWAY_ABORT_STALLED_TASKS")]
+    pub abort_stalled_tasks: bool,
+    /// How often, in seconds, to look for projects stuck in the middle of
+    /// a transition
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_RECONCILE_INTERVAL_SECS",
+        default_value = "60"
+    )]
+    pub reconcile_interval_secs: u64,
+    /// How many seconds a project can sit in a transitional state before
+    /// the reconciler re-drives it
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_RECONCILE_STUCK_AFTER_SECS",
+        default_value = "600"
+    )]
+    pub reconcile_stuck_after_secs: u64,
+    /// How often, in seconds, to look for scheduled work which is due
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_SCHEDULER_INTERVAL_SECS",
+        default_value = "1"
+    )]
+    pub scheduler_interval_secs: u64,
+    /// How many seconds to spread the refresh of all projects over on
+    /// startup, so as not to flood the docker daemon with inspects
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_REFRESH_WINDOW_SECS",
+        default_value = "60"
+    )]
+    pub refresh_window_secs: u64,
     #[command(flatten)]
     pub context: ContextArgs,
 }
 
+#[derive(clap::Args, Debug, Clone)]
+pub struct RestoreArgs {
+    /// Path of the backup to restore from
+    #[arg(long, env = "SHUTTLE_GATEWAY_FROM")]
+    pub from: PathBuf,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RewrapArgs {
+    /// The master key the secrets are currently encrypted with, if any
+    #[arg(long, env = "SHUTTLE_GATEWAY_MASTER_KEY", hide_env_values = true)]
+    pub master_key: Option<MasterKey>,
+    /// The master key to encrypt the secrets with from now on
+    #[arg(long, env = "SHUTTLE_GATEWAY_NEW_MASTER_KEY", hide_env_values = true)]
+    pub new_master_key: MasterKey,
+}
+
+/// A path or `sqlite:` URL the state database can be opened at, checked
+/// the way it is opened
+fn sqlite_uri(uri: &str) -> Result<String, sqlx::Error> {
+    SqliteConnectOptions::from_str(uri)?;
+    Ok(uri.to_string())
+}
+
+fn ca_bundle(path: &str) -> Result<CaBundle, String> {
+    CaBundle::load(path)
+}
+
+/// A `hostname:ip` entry of `/etc/hosts`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraHost {
+    pub hostname: String,
+    pub ip: IpAddr,
+}
+
+impl FromStr for ExtraHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Hostnames have no colons in them, unlike IPv6 addresses
+        let (hostname, ip) = s
+            .split_once(':')
+            .ok_or_else(|| format!("`{s}` is not of the form `hostname:ip`"))?;
+
+        let is_label = |label: &str| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        };
+        if hostname.len() > 253 || !hostname.split('.').all(is_label) {
+            return Err(format!("`{hostname}` is not a valid hostname"));
+        }
+
+        let ip = ip
+            .parse()
+            .map_err(|_| format!("`{ip}` is not a valid IP address"))?;
+
+        Ok(Self {
+            hostname: hostname.to_string(),
+            ip,
+        })
+    }
+}
+
+/// In the `hostname:ip` form docker takes them in
+impl Display for ExtraHost {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.hostname, self.ip)
+    }
+}
+
 #[derive(clap::Args, Debug, Clone)]
 pub struct ContextArgs {
     /// Default image to deploy user runtimes into
-    #[arg(long, default_value = "public.ecr.aws/shuttle/deployer:latest")]
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_IMAGE",
+        default_value = "public.ecr.aws/shuttle/deployer:latest"
+    )]
     pub image: String,
     /// Prefix to add to the name of all docker resources managed by
     /// this service
-    #[arg(long, default_value = "shuttle_prod_")]
+    #[arg(long, env = "SHUTTLE_GATEWAY_PREFIX", default_value = "shuttle_prod_")]
     pub prefix: String,
     /// The address at which an active runtime container will find
-    /// the provisioner service
-    #[arg(long, default_value = "provisioner")]
+    /// the provisioner service. Can be prefixed with `http://` or
+    /// `https://` to pick the transport explicitly
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_PROVISIONER_HOST",
+        default_value = "provisioner"
+    )]
     pub provisioner_host: String,
+    /// A PEM bundle of the CA certificates the provisioner's certificate
+    /// is signed by. Given it, runtime containers reach the provisioner
+    /// over TLS, trusting these CAs, when `--provisioner-host` has no
+    /// scheme
+    #[arg(long, env = "SHUTTLE_GATEWAY_PROVISIONER_TLS_CA", value_parser = ca_bundle)]
+    pub provisioner_tls_ca: Option<CaBundle>,
+    /// Have runtime containers reach the provisioner over plain HTTP
+    /// when `--provisioner-host` has no scheme, even with a
+    /// `--provisioner-tls-ca`
+    #[arg(long, env = "SHUTTLE_GATEWAY_INSECURE_SKIP_PROVISIONER_TLS")]
+    pub insecure_skip_provisioner_tls: bool,
     /// Address to reach the authentication service at
-    #[arg(long, default_value = "http://127.0.0.1:8008")]
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_AUTH_URI",
+        default_value = "http://127.0.0.1:8008"
+    )]
     pub auth_uri: Uri,
     /// The Docker Network name in which to deploy user runtimes
-    #[arg(long, default_value = "shuttle_default")]
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_NETWORK_NAME",
+        default_value = "shuttle_default"
+    )]
     pub network_name: String,
+    /// The Docker network to put the runtimes of projects without
+    /// internet access in. It has to be an internal network with the
+    /// services the runtimes need on it, such as the provisioner. Projects
+    /// cannot be kept off the internet unless it is given
+    #[arg(long, env = "SHUTTLE_GATEWAY_ISOLATED_NETWORK_NAME")]
+    pub isolated_network_name: Option<String>,
+    /// An entry to add to the `/etc/hosts` of the runtimes, as
+    /// `hostname:ip`, for them to reach services which are not in DNS. Can
+    /// be given more than once, or comma separated
+    #[arg(
+        long = "container-extra-hosts",
+        env = "SHUTTLE_GATEWAY_CONTAINER_EXTRA_HOSTS",
+        value_delimiter = ','
+    )]
+    pub container_extra_hosts: Vec<ExtraHost>,
     /// FQDN where the proxy can be reached at
-    #[arg(long, default_value = "shuttleapp.rs")]
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_PROXY_FQDN",
+        default_value = "shuttleapp.rs"
+    )]
     pub proxy_fqdn: FQDN,
     /// The path to the docker daemon socket
-    #[arg(long, default_value = "/var/run/docker.sock")]
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_DOCKER_HOST",
+        default_value = "/var/run/docker.sock"
+    )]
     pub docker_host: String,
+    /// Where to store backups of the state database (defaults to a
+    /// `backups` directory in the state location)
+    #[arg(long, env = "SHUTTLE_GATEWAY_BACKUP_DIR")]
+    pub backup_dir: Option<PathBuf>,
+    /// How many backups of the state database to keep around, the one
+    /// just taken included, so at least 1
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_BACKUP_RETAIN",
+        default_value = "7",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub backup_retain: usize,
+    /// Where to store uploaded deployment artifacts (defaults to an
+    /// `artifacts` directory in the state location)
+    #[arg(long, env = "SHUTTLE_GATEWAY_ARTIFACTS_DIR")]
+    pub artifacts_dir: Option<PathBuf>,
+    /// The largest deployment artifact accepted, in bytes
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_MAX_ARTIFACT_SIZE",
+        default_value = "536870912"
+    )]
+    pub max_artifact_size: u64,
+    /// Path or `sqlite:` URL of a read-only replica of the state
+    /// database. Proxy lookups and project listings are served from it
+    /// when set, writes always go to the primary
+    #[arg(long, env = "SHUTTLE_GATEWAY_STATE_READ_REPLICA", value_parser = sqlite_uri)]
+    pub state_read_replica: Option<String>,
+    /// How many days a project can sit in the errored state, without
+    /// its owner touching it, before the owner is notified
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_ERRORED_STALE_AFTER_DAYS",
+        default_value = "14"
+    )]
+    pub errored_stale_after_days: u32,
+    /// How many days after notifying the owner a stale errored project
+    /// is archived
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_ERRORED_ARCHIVE_GRACE_DAYS",
+        default_value = "7"
+    )]
+    pub errored_archive_grace_days: u32,
+    /// How many days destroyed projects are kept around (with their name
+    /// reserved for their owner) before being purged
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_DESTROYED_RETENTION_DAYS",
+        default_value = "30"
+    )]
+    pub destroyed_retention_days: u32,
+    /// How many days archived projects are kept around before being
+    /// purged
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_ARCHIVED_RETENTION_DAYS",
+        default_value = "90"
+    )]
+    pub archived_retention_days: u32,
+    /// How many days operations on projects are kept around once over,
+    /// for the API to tell how they went
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_OPERATIONS_RETENTION_DAYS",
+        default_value = "7"
+    )]
+    pub operations_retention_days: u32,
+    /// How many rows to purge at a time, to keep the state database
+    /// available while purging
+    #[arg(long, env = "SHUTTLE_GATEWAY_PURGE_BATCH_SIZE", default_value = "100")]
+    pub purge_batch_size: u32,
+    /// Comma separated project names nobody but admins can create,
+    /// matched without regard to case. These come on top of the names
+    /// the gateway keeps for its own subdomains (e.g. `api` and `www`)
+    /// and the first label of `--proxy-fqdn`.
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_RESERVED_PROJECT_NAMES",
+        value_delimiter = ','
+    )]
+    pub reserved_project_names: Vec<String>,
+    /// Base64 encoded 32 bytes key to encrypt secrets at rest with.
+    /// Required once the state database holds encrypted secrets
+    #[arg(long, env = "SHUTTLE_GATEWAY_MASTER_KEY", hide_env_values = true)]
+    pub master_key: Option<MasterKey>,
+    /// How many inspects a second can be made to the docker daemon, on
+    /// average
+    #[arg(long, env = "SHUTTLE_GATEWAY_DOCKER_RATE_LIMIT", default_value = "50")]
+    pub docker_rate_limit: f64,
+    /// How many inspects can be made to the docker daemon in a burst,
+    /// after a quiet spell
+    #[arg(long, env = "SHUTTLE_GATEWAY_DOCKER_BURST", default_value = "100")]
+    pub docker_burst: u32,
+    /// How many calls changing containers, like creating or starting
+    /// them, can be made to the docker daemon a second, on average
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_DOCKER_CREATE_RATE_LIMIT",
+        default_value = "5"
+    )]
+    pub docker_create_rate_limit: f64,
+    /// How many calls changing containers can be made to the docker
+    /// daemon in a burst, after a quiet spell
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_DOCKER_CREATE_BURST",
+        default_value = "10"
+    )]
+    pub docker_create_burst: u32,
+    /// Call the docker daemon as often as needed, without any rate limit
+    #[arg(long, env = "SHUTTLE_GATEWAY_NO_DOCKER_RATE_LIMIT")]
+    pub no_docker_rate_limit: bool,
+    /// How many seconds a project task can run for, retries included,
+    /// when it starts out creating the project. Generous, to leave time
+    /// for pulling images
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_TASK_DEADLINE_CREATE_SECS",
+        default_value = "1800"
+    )]
+    pub task_deadline_create_secs: u64,
+    /// How many seconds a project task can run for, retries included,
+    /// when it starts out starting the project
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_TASK_DEADLINE_START_SECS",
+        default_value = "600"
+    )]
+    pub task_deadline_start_secs: u64,
+    /// How many seconds a project task can run for, retries included,
+    /// when it starts out stopping or destroying the project
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_TASK_DEADLINE_STOP_SECS",
+        default_value = "300"
+    )]
+    pub task_deadline_stop_secs: u64,
+    /// How many seconds a project task can run for, retries included,
+    /// when it starts out with the project ready
+    #[arg(
+        long,
+        env = "SHUTTLE_GATEWAY_TASK_DEADLINE_HEALTH_SECS",
+        default_value = "120"
+    )]
+    pub task_deadline_health_secs: u64,
+}
+
+/// What sensitive settings are shown as
+pub const REDACTED: &str = "[redacted]";
+
+/// Whether the setting called `name` holds something which should not
+/// be shown to anyone
+fn is_sensitive(name: &str) -> bool {
+    ["_key", "_token", "_secret"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
+/// The `(name, value)` pairs of some settings, with the sensitive
+/// values redacted
+#[derive(Default)]
+struct Settings(Vec<(&'static str, String)>);
+
+impl Settings {
+    fn add<V: ToString>(&mut self, name: &'static str, value: V) {
+        let value = if is_sensitive(name) {
+            REDACTED.to_string()
+        } else {
+            value.to_string()
+        };
+
+        self.0.push((name, value));
+    }
+
+    /// Add a setting which is not set as an empty value
+    fn add_path(&mut self, name: &'static str, path: &Option<PathBuf>) {
+        let value = path.as_ref().map(|path| path.display().to_string());
+        self.add(name, value.unwrap_or_default());
+    }
+}
+
+/// The effective configuration of the gateway, as `(name, value)` pairs
+/// named after the fields of the args. The values of the fields named
+/// `*_key`, `*_token` or `*_secret` are replaced with [`REDACTED`].
+impl<'a> IntoIterator for &'a Args {
+    type Item = (&'static str, String);
+
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut settings = Settings::default();
+        settings.add_path("config", &self.config);
+        settings.add("state", self.state.display());
+
+        match &self.command {
+            Commands::Start(args) => {
+                settings.add("command", "start");
+                args.add_to(&mut settings);
+            }
+            Commands::Restore(args) => {
+                settings.add("command", "restore");
+                settings.add("from", args.from.display());
+            }
+            Commands::Rewrap(args) => {
+                settings.add("command", "rewrap");
+                settings.add("master_key", format!("{:?}", args.master_key));
+                settings.add("new_master_key", format!("{:?}", args.new_master_key));
+            }
+        }
+
+        settings.0.into_iter()
+    }
+}
+
+impl StartArgs {
+    fn add_to(&self, settings: &mut Settings) {
+        let use_tls = self.use_tls.to_possible_value().unwrap();
+
+        settings.add("control", self.control);
+        settings.add("api_bind_retry", self.api_bind_retry);
+        settings.add("api_bind_retry_delay", self.api_bind_retry_delay);
+        settings.add("bouncer", self.bouncer);
+        settings.add("user", self.user);
+        settings.add("use_tls", use_tls.get_name());
+        settings.add("cors_max_age", self.cors_max_age);
+        settings.add("worker_concurrency", self.worker_concurrency);
+        settings.add("worker_queue_size", self.worker_queue_size);
+        settings.add(
+            "worker_rate_limit",
+            self.worker_rate_limit
+                .map(|rate| rate.to_string())
+                .unwrap_or_default(),
+        );
+        settings.add("health_worker_concurrency", self.health_worker_concurrency);
+        settings.add("health_worker_queue_size", self.health_worker_queue_size);
+        settings.add("health_worker_rate_limit", self.health_worker_rate_limit);
+        settings.add("drain_deadline_secs", self.drain_deadline_secs);
+        settings.add(
+            "worker_stall_threshold_secs",
+            self.worker_stall_threshold_secs,
+        );
+        settings.add("abort_stalled_tasks", self.abort_stalled_tasks);
+        settings.add("reconcile_interval_secs", self.reconcile_interval_secs);
+        settings.add(
+            "reconcile_stuck_after_secs",
+            self.reconcile_stuck_after_secs,
+        );
+        settings.add("scheduler_interval_secs", self.scheduler_interval_secs);
+        settings.add("refresh_window_secs", self.refresh_window_secs);
+        self.context.add_to(settings);
+    }
+}
+
+impl ContextArgs {
+    fn add_to(&self, settings: &mut Settings) {
+        settings.add("image", &self.image);
+        settings.add("prefix", &self.prefix);
+        settings.add("provisioner_host", &self.provisioner_host);
+        settings.add(
+            "provisioner_tls_ca",
+            self.provisioner_tls_ca
+                .as_ref()
+                .map(|ca| ca.path().display().to_string())
+                .unwrap_or_default(),
+        );
+        settings.add(
+            "insecure_skip_provisioner_tls",
+            self.insecure_skip_provisioner_tls,
+        );
+        settings.add("auth_uri", &self.auth_uri);
+        settings.add("network_name", &self.network_name);
+        settings.add(
+            "isolated_network_name",
+            self.isolated_network_name.as_deref().unwrap_or_default(),
+        );
+        settings.add(
+            "container_extra_hosts",
+            self.container_extra_hosts
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        settings.add("proxy_fqdn", &self.proxy_fqdn);
+        settings.add("docker_host", &self.docker_host);
+        settings.add_path("backup_dir", &self.backup_dir);
+        settings.add("backup_retain", self.backup_retain);
+        settings.add_path("artifacts_dir", &self.artifacts_dir);
+        settings.add("max_artifact_size", self.max_artifact_size);
+        settings.add(
+            "state_read_replica",
+            self.state_read_replica.as_deref().unwrap_or_default(),
+        );
+        settings.add("errored_stale_after_days", self.errored_stale_after_days);
+        settings.add(
+            "errored_archive_grace_days",
+            self.errored_archive_grace_days,
+        );
+        settings.add("destroyed_retention_days", self.destroyed_retention_days);
+        settings.add("archived_retention_days", self.archived_retention_days);
+        settings.add("operations_retention_days", self.operations_retention_days);
+        settings.add("purge_batch_size", self.purge_batch_size);
+        settings.add(
+            "reserved_project_names",
+            self.reserved_project_names.join(","),
+        );
+        settings.add("master_key", format!("{:?}", self.master_key));
+        settings.add("docker_rate_limit", self.docker_rate_limit);
+        settings.add("docker_burst", self.docker_burst);
+        settings.add("docker_create_rate_limit", self.docker_create_rate_limit);
+        settings.add("docker_create_burst", self.docker_create_burst);
+        settings.add("no_docker_rate_limit", self.no_docker_rate_limit);
+        settings.add("task_deadline_create_secs", self.task_deadline_create_secs);
+        settings.add("task_deadline_start_secs", self.task_deadline_start_secs);
+        settings.add("task_deadline_stop_secs", self.task_deadline_stop_secs);
+        settings.add("task_deadline_health_secs", self.task_deadline_health_secs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn args_into_iter() {
+        let master_key = base64::encode([7u8; 32]);
+        let args = Args::try_parse_from([
+            "gateway",
+            "--state",
+            "/var/lib/gateway",
+            "start",
+            "--use-tls",
+            "disable",
+            "--backup-dir",
+            "/backups",
+            "--master-key",
+            &master_key,
+        ])
+        .unwrap();
+
+        let settings: BTreeMap<_, _> = args.into_iter().collect();
+
+        assert_eq!(settings["state"], "/var/lib/gateway");
+        assert_eq!(settings["command"], "start");
+        assert_eq!(settings["control"], "127.0.0.1:8001");
+        assert_eq!(settings["use_tls"], "disable");
+        assert_eq!(settings["prefix"], "shuttle_prod_");
+        assert_eq!(settings["proxy_fqdn"], "shuttleapp.rs");
+        assert_eq!(settings["backup_dir"], "/backups");
+        assert_eq!(settings["artifacts_dir"], "");
+        assert_eq!(settings["master_key"], REDACTED);
+        assert!(settings.values().all(|value| !value.contains(&master_key)));
+
+        let args = Args::try_parse_from([
+            "gateway",
+            "rewrap",
+            "--master-key",
+            &master_key,
+            "--new-master-key",
+            &master_key,
+        ])
+        .unwrap();
+
+        let settings: Vec<_> = args.into_iter().collect();
+        assert_eq!(
+            settings,
+            vec![
+                ("config", "".to_string()),
+                ("state", "./".to_string()),
+                ("command", "rewrap".to_string()),
+                ("master_key", REDACTED.to_string()),
+                ("new_master_key", REDACTED.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sensitive_settings() {
+        assert!(is_sensitive("master_key"));
+        assert!(is_sensitive("admin_token"));
+        assert!(is_sensitive("admin_secret"));
+        assert!(!is_sensitive("keyring"));
+        assert!(!is_sensitive("prefix"));
+    }
+
+    fn config_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    fn start_args(args: &Args) -> &StartArgs {
+        let Commands::Start(start) = &args.command else {
+            panic!("should be the start command");
+        };
+        start
+    }
+
+    #[test]
+    fn config_file_precedence() {
+        let file = config_file(
+            r#"
+            state = "/var/lib/gateway"
+            control = "0.0.0.0:8001"
+            user = "0.0.0.0:8000"
+            bouncer = "0.0.0.0:7999"
+            docker-host = "/run/docker/docker.sock"
+            worker-concurrency = 16
+            abort_stalled_tasks = true
+            health-worker-rate-limit = 2.5
+            container-extra-hosts = ["vault.internal:10.99.0.42", "ledger:10.99.0.43"]
+            "#,
+        );
+        let config = file.path().to_str().unwrap();
+
+        // The file comes over the defaults
+        let args = Args::try_load_from(["gateway", "--config", config, "start"]).unwrap();
+        assert_eq!(args.state, PathBuf::from("/var/lib/gateway"));
+        let start = start_args(&args);
+        assert_eq!(start.control, "0.0.0.0:8001".parse().unwrap());
+        assert_eq!(start.user, "0.0.0.0:8000".parse().unwrap());
+        assert_eq!(start.bouncer, "0.0.0.0:7999".parse().unwrap());
+        assert_eq!(start.context.docker_host, "/run/docker/docker.sock");
+        assert_eq!(start.worker_concurrency, 16);
+        assert!(start.abort_stalled_tasks);
+        assert_eq!(start.health_worker_rate_limit, 2.5);
+        assert_eq!(start.context.container_extra_hosts.len(), 2);
+        // And leaves what it does not set to them
+        assert_eq!(start.context.prefix, "shuttle_prod_");
+
+        // The flags come over the file, wherever they are given
+        let args = Args::try_load_from([
+            "gateway",
+            "--config",
+            config,
+            "--state",
+            "/tmp/gateway",
+            "start",
+            "--control",
+            "127.0.0.1:9001",
+            "--image",
+            "deployer:dev",
+        ])
+        .unwrap();
+        assert_eq!(args.state, PathBuf::from("/tmp/gateway"));
+        let start = start_args(&args);
+        assert_eq!(start.control, "127.0.0.1:9001".parse().unwrap());
+        assert_eq!(start.user, "0.0.0.0:8000".parse().unwrap());
+        assert_eq!(start.context.image, "deployer:dev");
+
+        // And the settings of the other commands are left out
+        let args =
+            Args::try_load_from(["gateway", "--config", config, "restore", "--from", "b"]).unwrap();
+        assert_eq!(args.state, PathBuf::from("/var/lib/gateway"));
+        assert!(matches!(args.command, Commands::Restore(_)));
+
+        let settings: BTreeMap<_, _> = args.into_iter().collect();
+        assert_eq!(settings["config"], config);
+    }
+
+    #[test]
+    fn environment_comes_over_config_file() {
+        let from_file = base64::encode([1u8; 32]);
+        let from_env = base64::encode([2u8; 32]);
+        let file = config_file(&format!("master-key = \"{from_file}\""));
+        let config = file.path().to_str().unwrap();
+
+        let key_id = |args: &Args| {
+            let master_key = start_args(args).context.master_key.as_ref();
+            master_key.unwrap().id().to_string()
+        };
+        let id_of = |key: &str| key.parse::<MasterKey>().unwrap().id().to_string();
+
+        let args = Args::try_load_from(["gateway", "--config", config, "start"]).unwrap();
+        assert_eq!(key_id(&args), id_of(&from_file));
+
+        std::env::set_var("SHUTTLE_GATEWAY_MASTER_KEY", &from_env);
+        let args = Args::try_load_from(["gateway", "--config", config, "start"]);
+        std::env::remove_var("SHUTTLE_GATEWAY_MASTER_KEY");
+        assert_eq!(key_id(&args.unwrap()), id_of(&from_env));
+    }
+
+    #[test]
+    fn config_file_errors() {
+        let load = |contents: &str| {
+            let file = config_file(contents);
+            let config = file.path().to_str().unwrap().to_string();
+            Args::try_load_from(["gateway", "--config", &config, "start"])
+                .unwrap_err()
+                .to_string()
+        };
+
+        let err = load("contorl = \"0.0.0.0:8001\"");
+        assert!(err.contains("unknown setting `contorl`"), "{err}");
+
+        let err = load("config = \"other.toml\"");
+        assert!(err.contains("`config` cannot be set"), "{err}");
+
+        let err = load("control = { host = \"0.0.0.0\" }");
+        assert!(err.contains("`control` must be"), "{err}");
+
+        let err = load("control = ");
+        assert!(err.contains("invalid config file"), "{err}");
+
+        // The values are checked like those of the flags
+        let err = load("control = \"nowhere\"");
+        assert!(err.contains("--control"), "{err}");
+
+        let err = Args::try_load_from(["gateway", "--config", "/does/not/exist.toml", "start"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("failed to read the config file"), "{err}");
+    }
+
+    #[test]
+    fn every_flag_has_an_environment_variable() {
+        let command = Args::command();
+
+        for command in std::iter::once(&command).chain(command.get_subcommands()) {
+            for arg in command.get_arguments() {
+                let long = arg.get_long().unwrap();
+                let expected = format!("{ENV_PREFIX}{}", long.to_uppercase().replace('-', "_"));
+                assert_eq!(
+                    arg.get_env().and_then(|env| env.to_str()),
+                    Some(expected.as_str()),
+                    "--{long}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn environment_variables() {
+        std::env::set_var("SHUTTLE_GATEWAY_DOCKER_BURST", "42");
+        std::env::set_var("SHUTTLE_GATEWAY_DOCKER_CREATE_BURST", "3");
+        std::env::set_var("SHUTTLE_GATEWAY_NO_DOCKER_RATE_LIMIT", "true");
+        std::env::set_var("SHUTTLE_GATEWAY_RESERVED_PROJECT_NAMES", "billing,status");
+        let args = Args::try_load_from(["gateway", "start", "--docker-create-burst", "20"]);
+        for name in [
+            "SHUTTLE_GATEWAY_DOCKER_BURST",
+            "SHUTTLE_GATEWAY_DOCKER_CREATE_BURST",
+            "SHUTTLE_GATEWAY_NO_DOCKER_RATE_LIMIT",
+            "SHUTTLE_GATEWAY_RESERVED_PROJECT_NAMES",
+        ] {
+            std::env::remove_var(name);
+        }
+
+        let args = args.unwrap();
+        let context = &start_args(&args).context;
+        assert_eq!(context.docker_burst, 42);
+        assert!(context.no_docker_rate_limit);
+        assert_eq!(context.reserved_project_names, vec!["billing", "status"]);
+        // The flags come over the environment
+        assert_eq!(context.docker_create_burst, 20);
+    }
+
+    #[test]
+    fn environment_variable_errors() {
+        // Variables of their own, for the other tests parsing the args at
+        // the same time not to see them
+        let mut command = Command::new("gateway")
+            .arg(
+                Arg::new("port")
+                    .long("port")
+                    .env("SHUTTLE_GATEWAY_TESTS_PORT")
+                    .value_parser(clap::value_parser!(u16)),
+            )
+            .arg(
+                Arg::new("use_tls")
+                    .long("use-tls")
+                    .env("SHUTTLE_GATEWAY_TESTS_USE_TLS")
+                    .value_parser(clap::value_parser!(UseTls)),
+            )
+            .arg(
+                Arg::new("admin_key")
+                    .long("admin-key")
+                    .env("SHUTTLE_GATEWAY_TESTS_ADMIN_KEY")
+                    .value_parser(clap::value_parser!(u16)),
+            );
+        let mut parse = |name: &str, value: &str, argv: &[&str]| {
+            std::env::set_var(name, value);
+            let argv: Vec<OsString> = argv.iter().map(OsString::from).collect();
+            let err = try_get_matches(&mut command, &argv)
+                .unwrap_err()
+                .to_string();
+            std::env::remove_var(name);
+            err
+        };
+
+        let err = parse("SHUTTLE_GATEWAY_TESTS_PORT", "many", &["gateway"]);
+        assert!(
+            err.contains("invalid value 'many' in SHUTTLE_GATEWAY_TESTS_PORT for --port: "),
+            "{err}"
+        );
+
+        let err = parse("SHUTTLE_GATEWAY_TESTS_USE_TLS", "maybe", &["gateway"]);
+        assert!(
+            err.contains("in SHUTTLE_GATEWAY_TESTS_USE_TLS for --use-tls: expected one of "),
+            "{err}"
+        );
+        assert!(err.contains("disable") && err.contains("enable"), "{err}");
+
+        // Sensitive values are not repeated
+        let err = parse("SHUTTLE_GATEWAY_TESTS_ADMIN_KEY", "hunter2", &["gateway"]);
+        assert!(
+            err.contains("invalid value in SHUTTLE_GATEWAY_TESTS_ADMIN_KEY"),
+            "{err}"
+        );
+        assert!(!err.contains("hunter2"), "{err}");
+
+        // Those of the flags are not blamed on the environment
+        let err = parse(
+            "SHUTTLE_GATEWAY_TESTS_PORT",
+            "8001",
+            &["gateway", "--port=many"],
+        );
+        assert!(!err.contains("SHUTTLE_GATEWAY_TESTS_PORT"), "{err}");
+        assert!(err.contains("--port"), "{err}");
+    }
+
+    #[test]
+    fn backups_are_kept() {
+        let args = Args::try_parse_from(["gateway", "start", "--backup-retain", "1"]).unwrap();
+        assert_eq!(start_args(&args).context.backup_retain, 1);
+
+        // None would remove the backup just taken
+        assert!(Args::try_parse_from(["gateway", "start", "--backup-retain", "0"]).is_err());
+    }
+
+    #[test]
+    fn state_read_replica() {
+        let replica = |uri: &str| {
+            Args::try_parse_from(["gateway", "start", "--state-read-replica", uri])
+                .map(|args| start_args(&args).context.state_read_replica.clone())
+        };
+
+        assert_eq!(
+            replica("sqlite:///var/lib/replica.sqlite?mode=ro").unwrap(),
+            Some("sqlite:///var/lib/replica.sqlite?mode=ro".to_string())
+        );
+        assert!(replica("replica.sqlite").is_ok());
+        assert!(replica("sqlite:replica.sqlite?frobnicate=1").is_err());
+    }
+
+    #[test]
+    fn container_extra_hosts() {
+        let args = Args::try_parse_from([
+            "gateway",
+            "start",
+            "--container-extra-hosts",
+            "vault.internal:10.99.0.42",
+            "--container-extra-hosts",
+            "ledger:2001:db8::1",
+        ])
+        .unwrap();
+
+        let Commands::Start(StartArgs { context, .. }) = &args.command else {
+            panic!("should be the start command");
+        };
+        assert_eq!(
+            context.container_extra_hosts,
+            vec![
+                ExtraHost {
+                    hostname: "vault.internal".to_string(),
+                    ip: [10, 99, 0, 42].into(),
+                },
+                ExtraHost {
+                    hostname: "ledger".to_string(),
+                    ip: "2001:db8::1".parse().unwrap(),
+                },
+            ]
+        );
+
+        let settings: BTreeMap<_, _> = args.into_iter().collect();
+        assert_eq!(
+            settings["container_extra_hosts"],
+            "vault.internal:10.99.0.42,ledger:2001:db8::1"
+        );
+
+        for invalid in [
+            "vault.internal",
+            "vault.internal:",
+            ":10.99.0.42",
+            "vault.internal:10.99.0",
+            "vault..internal:10.99.0.42",
+            "-vault:10.99.0.42",
+            "vault_internal:10.99.0.42",
+        ] {
+            assert!(
+                Args::try_parse_from(["gateway", "start", "--container-extra-hosts", invalid])
+                    .is_err(),
+                "{invalid}"
+            );
+        }
+    }
 }
@@ -1,5 +1,4 @@
 use std::fmt::Debug;
-use std::str::FromStr;
 
 use axum::extract::{FromRef, FromRequestParts, Path};
 use axum::http::request::Parts;
@@ -8,18 +7,30 @@ use shuttle_common::claims::{Claim, Scope};
 use tracing::{trace, Span};
 
 use crate::api::latest::RouterState;
-use crate::{AccountName, Error, ErrorKind, ProjectName};
+use crate::{Account, AccountName, Error, ErrorKind, ProjectName};
 
 /// A wrapper to enrich a token with user details
 ///
-/// The `FromRequest` impl consumes the API claim and enriches it with project
-/// details. Generally you want to use [`ScopedUser`] instead to ensure the request
+/// The `FromRequest` impl consumes the API claim and enriches it with account
+/// and project details. Requests from suspended accounts are rejected here.
+/// Generally you want to use [`ScopedUser`] instead to ensure the request
 /// is valid against the user's owned resources.
 #[derive(Clone, Deserialize, PartialEq, Eq, Serialize, Debug)]
 pub struct User {
     pub projects: Vec<ProjectName>,
     pub claim: Claim,
-    pub name: AccountName,
+    pub account: Account,
+}
+
+impl User {
+    pub fn name(&self) -> &AccountName {
+        &self.account.name
+    }
+
+    /// Whether this user can act on resources they do not own
+    pub fn is_admin(&self) -> bool {
+        self.account.super_user || self.claim.scopes.contains(&Scope::Admin)
+    }
 }
 
 #[async_trait]
@@ -32,7 +43,7 @@ where
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let claim = parts.extensions.get::<Claim>().ok_or(ErrorKind::Internal)?;
-        let name = AccountName::from_str(&claim.sub)
+        let name = AccountName::existing(&claim.sub)
             .map_err(|err| Error::source(ErrorKind::Internal, err))?;
 
         // Record current account name for tracing purposes
@@ -40,10 +51,16 @@ where
 
         let RouterState { service, .. } = RouterState::from_ref(state);
 
+        let account = service.get_or_create_account(&name).await?;
+
+        if account.suspended {
+            return Err(Error::forbidden("account", &name.to_string()));
+        }
+
         let user = User {
             claim: claim.clone(),
             projects: service.iter_user_projects(&name).await?.collect(),
-            name,
+            account,
         };
 
         trace!(?user, "got user");
@@ -81,7 +98,14 @@ where
                 .unwrap(),
         };
 
-        if user.projects.contains(&scope) || user.claim.scopes.contains(&Scope::Admin) {
+        if user.projects.contains(&scope) {
+            // Any request by the owner (restart attempts included)
+            // counts as them still caring about the project
+            let RouterState { service, .. } = RouterState::from_ref(state);
+            service.touch_project(&scope).await?;
+
+            Ok(Self { user, scope })
+        } else if user.is_admin() {
             Ok(Self { user, scope })
         } else {
             Err(Error::from(ErrorKind::ProjectNotFound))
@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+
+use sqlx::sqlite::SqlitePool;
+use sqlx::{query, Connection, Row, SqliteConnection};
+use tracing::{debug, info};
+
+use crate::service::MIGRATIONS;
+use crate::{Error, ErrorKind};
+
+const BACKUP_PREFIX: &str = "gateway-";
+const BACKUP_EXTENSION: &str = "sqlite";
+
+/// Take a consistent snapshot of the state database into `dir`,
+/// keeping at most `retain` snapshots in there.
+///
+/// The snapshot is taken with `VACUUM INTO` which runs inside a read
+/// transaction, so concurrent writers cannot leave it half-written
+/// (as opposed to copying the database file).
+pub async fn backup(db: &SqlitePool, dir: &Path, retain: usize) -> Result<PathBuf, Error> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(|err| Error::source(ErrorKind::Internal, err))?;
+
+    let timestamp = chrono::Utc::now().format("%Y%m%d%H%M%S%3f");
+    let path = dir.join(format!("{BACKUP_PREFIX}{timestamp}.{BACKUP_EXTENSION}"));
+
+    query("VACUUM INTO ?1")
+        .bind(path.to_string_lossy().as_ref())
+        .execute(db)
+        .await?;
+
+    info!(path = %path.display(), "created a backup of the state database");
+
+    rotate(dir, retain).await?;
+
+    Ok(path)
+}
+
+/// Remove the oldest snapshots in `dir` until at most `retain` are left.
+async fn rotate(dir: &Path, retain: usize) -> Result<(), Error> {
+    let mut backups = Vec::new();
+
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .map_err(|err| Error::source(ErrorKind::Internal, err))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|err| Error::source(ErrorKind::Internal, err))?
+    {
+        let path = entry.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.starts_with(BACKUP_PREFIX))
+            .unwrap_or_default()
+            && path.extension().and_then(|ext| ext.to_str()) == Some(BACKUP_EXTENSION);
+
+        if is_backup {
+            backups.push(path);
+        }
+    }
+
+    // Timestamps in the names sort chronologically
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(retain);
+    for path in backups.into_iter().take(excess) {
+        debug!(path = %path.display(), "removing old backup");
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|err| Error::source(ErrorKind::Internal, err))?;
+    }
+
+    Ok(())
+}
+
+/// Replace the contents of the state database with those of the
+/// snapshot at `from`.
+///
+/// The snapshot has to be at the same migration version as this
+/// gateway, otherwise nothing is loaded.
+pub async fn restore(db: &SqlitePool, from: &Path) -> Result<(), Error> {
+    if !from.is_file() {
+        return Err(Error::custom(
+            ErrorKind::InvalidOperation,
+            format!("no backup found at {}", from.display()),
+        ));
+    }
+
+    let mut conn = db.acquire().await?;
+
+    query("ATTACH DATABASE ?1 AS backup")
+        .bind(from.to_string_lossy().as_ref())
+        .execute(&mut conn)
+        .await?;
+
+    let res = restore_attached(&mut conn).await;
+
+    query("DETACH DATABASE backup").execute(&mut conn).await?;
+
+    if res.is_ok() {
+        info!(path = %from.display(), "restored the state database from a backup");
+    }
+
+    res
+}
+
+async fn restore_attached(conn: &mut SqliteConnection) -> Result<(), Error> {
+    let expected = MIGRATIONS
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default();
+
+    let version: Option<i64> =
+        query("SELECT MAX(version) AS version FROM backup._sqlx_migrations WHERE success = 1")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|err| {
+                Error::custom(
+                    ErrorKind::InvalidOperation,
+                    format!("backup does not look like a gateway state database: {err}"),
+                )
+            })?
+            .get("version");
+
+    if version != Some(expected) {
+        return Err(Error::custom(
+            ErrorKind::InvalidOperation,
+            format!(
+                "backup is at schema version {}, but this gateway expects version {expected}",
+                version
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| "<none>".to_string())
+            ),
+        ));
+    }
+
+    let tables: Vec<String> = query(
+        "SELECT name FROM main.sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations'",
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| row.get("name"))
+    .collect();
+
+    let mut tx = conn.begin().await?;
+
+    // Tables reference each other, so only check foreign keys once
+    // everything has been loaded
+    query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut tx)
+        .await?;
+
+    for table in tables {
+        query(&format!(r#"DELETE FROM main."{table}""#))
+            .execute(&mut tx)
+            .await?;
+        query(&format!(
+            r#"INSERT INTO main."{table}" SELECT * FROM backup."{table}""#
+        ))
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::World;
+    use crate::AccountName;
+
+    #[tokio::test]
+    #[ignore]
+    async fn backup_mutate_restore() {
+        let world = World::new().await;
+        let db = world.pool();
+        let dir = tempfile::tempdir().unwrap();
+
+        let neo: AccountName = "neo".parse().unwrap();
+
+        query("INSERT INTO projects (project_name, account_name, initial_key, project_state) VALUES ('matrix', ?1, 'key', '{}')")
+            .bind(&neo)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let snapshot = backup(&db, dir.path(), 2).await.unwrap();
+
+        query("DELETE FROM projects").execute(&db).await.unwrap();
+        query("INSERT INTO projects (project_name, account_name, initial_key, project_state) VALUES ('reloaded', ?1, 'key', '{}')")
+            .bind(&neo)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        restore(&db, &snapshot).await.unwrap();
+
+        let projects: Vec<String> = query("SELECT project_name FROM projects")
+            .fetch_all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get("project_name"))
+            .collect();
+
+        assert_eq!(projects, vec!["matrix".to_string()]);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn backup_rotates() {
+        let world = World::new().await;
+        let db = world.pool();
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut snapshots = Vec::new();
+        for _ in 0..4 {
+            snapshots.push(backup(&db, dir.path(), 2).await.unwrap());
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        let remaining = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(remaining, 2);
+        assert!(snapshots[3].exists());
+        assert!(!snapshots[0].exists());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn restore_rejects_other_schema_versions() {
+        let world = World::new().await;
+        let db = world.pool();
+        let dir = tempfile::tempdir().unwrap();
+
+        let snapshot = backup(&db, dir.path(), 1).await.unwrap();
+
+        let other = SqlitePool::connect(&format!("sqlite://{}", snapshot.display()))
+            .await
+            .unwrap();
+        query("UPDATE _sqlx_migrations SET version = version + 1000")
+            .execute(&other)
+            .await
+            .unwrap();
+        other.close().await;
+
+        assert_eq!(
+            restore(&db, &snapshot).await.unwrap_err().kind(),
+            ErrorKind::InvalidOperation
+        );
+    }
+}
@@ -0,0 +1,363 @@
+//! A typed client for the API of the gateway, speaking in the same types
+//! the gateway itself does
+
+use std::time::Duration;
+
+use async_compression::tokio::bufread::GzipDecoder;
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use shuttle_common::models::error::ApiError;
+use shuttle_common::models::project;
+use shuttle_common::ApiKey;
+use strum::IntoEnumIterator;
+use tokio::io::AsyncReadExt;
+use tracing::trace;
+
+use crate::{Error, ErrorKind, ProjectName};
+
+pub struct GatewayClient {
+    base_url: String,
+    api_key: ApiKey,
+    http: reqwest::Client,
+}
+
+impl GatewayClient {
+    pub fn new(base_url: impl ToString, api_key: ApiKey) -> Self {
+        Self {
+            base_url: base_url.to_string().trim_end_matches('/').to_string(),
+            api_key,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn create_project(
+        &self,
+        project_name: &ProjectName,
+        idle_minutes: u64,
+        allow_internet: bool,
+    ) -> Result<project::Response, Error> {
+        let response = self
+            .request(Method::POST, &format!("/projects/{project_name}"))
+            .json(&project::Config {
+                idle_minutes,
+                allow_internet,
+            })
+            .send()
+            .await;
+
+        to_json(response).await
+    }
+
+    pub async fn delete_project(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<project::Response, Error> {
+        self.send(Method::DELETE, &format!("/projects/{project_name}"))
+            .await
+    }
+
+    pub async fn get_project(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<project::Response, Error> {
+        self.send(Method::GET, &format!("/projects/{project_name}"))
+            .await
+    }
+
+    pub async fn list_projects(&self) -> Result<Vec<project::Response>, Error> {
+        self.send(Method::GET, "/projects").await
+    }
+
+    /// The logs of a project, from `since` and up to `until` when given,
+    /// as they came out of its container
+    pub async fn get_logs(
+        &self,
+        project_name: &ProjectName,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<u8>, Error> {
+        let query: Vec<_> = [("since", since), ("until", until)]
+            .into_iter()
+            .filter_map(|(key, at)| at.map(|at| (key, at.to_rfc3339())))
+            .collect();
+
+        let response = self
+            .request(
+                Method::GET,
+                &format!("/projects/{project_name}/logs/download"),
+            )
+            .query(&query)
+            .send()
+            .await;
+        let archive = check_status(response)
+            .await?
+            .bytes()
+            .await
+            .map_err(|err| Error::source(ErrorKind::ServiceUnavailable, err))?;
+
+        let mut logs = Vec::new();
+        GzipDecoder::new(&archive[..])
+            .read_to_end(&mut logs)
+            .await
+            .map_err(|err| Error::source(ErrorKind::Internal, err))?;
+
+        Ok(logs)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{path}", self.base_url))
+            .bearer_auth(self.api_key.expose())
+    }
+
+    async fn send<R: DeserializeOwned>(&self, method: Method, path: &str) -> Result<R, Error> {
+        to_json(self.request(method, path).send().await).await
+    }
+}
+
+/// Turn what the gateway answered into the [`Error`] it had, when it was
+/// one, along with when to retry if it said. A gateway which could not be
+/// reached is reported as [`ErrorKind::ServiceUnavailable`].
+async fn check_status(response: reqwest::Result<Response>) -> Result<Response, Error> {
+    let response = response.map_err(|err| Error::source(ErrorKind::ServiceUnavailable, err))?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .map(Duration::from_secs);
+
+    let body = response
+        .bytes()
+        .await
+        .map_err(|err| Error::source(ErrorKind::ServiceUnavailable, err))?;
+
+    trace!(
+        response = std::str::from_utf8(&body).unwrap_or_default(),
+        "parsing response to an error"
+    );
+
+    let api_error: ApiError = serde_json::from_slice(&body).unwrap_or_else(|_| status.into());
+
+    let error = Error::source(error_kind(&api_error), api_error);
+
+    Err(match retry_after {
+        Some(retry_after) => error.with_retry_after(retry_after),
+        None => error,
+    })
+}
+
+async fn to_json<R: DeserializeOwned>(response: reqwest::Result<Response>) -> Result<R, Error> {
+    let body = check_status(response)
+        .await?
+        .bytes()
+        .await
+        .map_err(|err| Error::source(ErrorKind::ServiceUnavailable, err))?;
+
+    serde_json::from_slice(&body).map_err(|err| Error::source(ErrorKind::Internal, err))
+}
+
+/// The kind of error the gateway sent back, going by its code. Errors from
+/// before there were codes go by their message instead, which is the one
+/// of their kind. Errors which match none, from a gateway which does not
+/// know them as this one does, are [`ErrorKind::Internal`].
+fn error_kind(api_error: &ApiError) -> ErrorKind {
+    match &api_error.error {
+        Some(error) => ErrorKind::from_code(&error.code),
+        None => ErrorKind::iter().find(|kind| ApiError::from(*kind).message == api_error.message),
+    }
+    .unwrap_or(ErrorKind::Internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+
+    use async_compression::tokio::bufread::GzipEncoder;
+    use axum::extract::{Path, Query};
+    use axum::headers::authorization::Bearer;
+    use axum::headers::Authorization;
+    use axum::routing::get;
+    use axum::{Json, Router, TypedHeader};
+    use serde_json::json;
+
+    use super::*;
+
+    const API_KEY: &str = "ndhn1ns8Wz8Azwod";
+
+    /// Serve `router` on a port of its own, for the client to talk to
+    fn mock_gateway(router: Router) -> GatewayClient {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(router.into_make_service());
+        let base_url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        GatewayClient::new(base_url, ApiKey::parse(API_KEY).unwrap())
+    }
+
+    fn as_json(response: project::Response) -> serde_json::Value {
+        serde_json::to_value(response).unwrap()
+    }
+
+    fn kind<T>(result: Result<T, Error>) -> ErrorKind {
+        match result {
+            Ok(_) => panic!("the request should have failed"),
+            Err(err) => err.kind(),
+        }
+    }
+
+    fn project(name: &str, state: &str) -> serde_json::Value {
+        json!({ "name": name, "state": state })
+    }
+
+    #[tokio::test]
+    async fn client_calls_the_gateway_api() {
+        let checked_key =
+            |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>| {
+                assert_eq!(bearer.token(), API_KEY);
+            };
+
+        let client = mock_gateway(
+            Router::new()
+                .route(
+                    "/projects",
+                    get(move |key| async move {
+                        checked_key(key);
+                        Json(json!([
+                            project("matrix", "ready"),
+                            project("zion", "stopped")
+                        ]))
+                    }),
+                )
+                .route(
+                    "/projects/:project_name",
+                    get(move |key, Path(name): Path<String>| async move {
+                        checked_key(key);
+                        Json(project(&name, "ready"))
+                    })
+                    .post(
+                        move |key,
+                              Path(name): Path<String>,
+                              Json(config): Json<serde_json::Value>| async move {
+                            checked_key(key);
+                            assert_eq!(
+                                config,
+                                json!({ "idle_minutes": 30, "allow_internet": true })
+                            );
+                            Json(project(&name, "creating"))
+                        },
+                    )
+                    .delete(move |key, Path(name): Path<String>| async move {
+                        checked_key(key);
+                        Json(project(&name, "destroying"))
+                    }),
+                )
+                .route(
+                    "/projects/:project_name/logs/download",
+                    get(
+                        move |key, Query(query): Query<HashMap<String, String>>| async move {
+                            checked_key(key);
+                            assert_eq!(query["since"], "2023-05-01T00:00:00+00:00");
+                            assert!(!query.contains_key("until"));
+
+                            let mut archive = Vec::new();
+                            GzipEncoder::new(&b"starting\nstarted\n"[..])
+                                .read_to_end(&mut archive)
+                                .await
+                                .unwrap();
+                            archive
+                        },
+                    ),
+                ),
+        );
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        assert_eq!(
+            as_json(client.create_project(&matrix, 30, true).await.unwrap()),
+            project("matrix", "creating")
+        );
+        assert_eq!(
+            as_json(client.get_project(&matrix).await.unwrap()),
+            project("matrix", "ready")
+        );
+        assert_eq!(
+            as_json(client.delete_project(&matrix).await.unwrap()),
+            project("matrix", "destroying")
+        );
+        assert_eq!(
+            client
+                .list_projects()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(as_json)
+                .collect::<Vec<_>>(),
+            [project("matrix", "ready"), project("zion", "stopped")]
+        );
+
+        let since = "2023-05-01T00:00:00Z".parse().unwrap();
+        assert_eq!(
+            client.get_logs(&matrix, Some(since), None).await.unwrap(),
+            b"starting\nstarted\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_returns_the_errors_of_the_gateway() {
+        let client = mock_gateway(
+            Router::new()
+                .route(
+                    "/projects/:project_name",
+                    get(|project_name: ProjectName| async move {
+                        Error::forbidden("project", project_name.as_str())
+                    })
+                    .post(|| async { Error::from_kind(ErrorKind::ProjectAlreadyExists) })
+                    .delete(|| async { Error::rate_limited(Duration::from_secs(30)) }),
+                )
+                .route(
+                    "/projects",
+                    get(|| async { (http::StatusCode::BAD_GATEWAY, "not json") }),
+                ),
+        );
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        assert_eq!(
+            kind(client.get_project(&matrix).await),
+            ErrorKind::Forbidden
+        );
+        assert_eq!(
+            kind(client.create_project(&matrix, 30, true).await),
+            ErrorKind::ProjectAlreadyExists
+        );
+
+        let Err(rate_limited) = client.delete_project(&matrix).await else {
+            panic!("the request should have been rate limited");
+        };
+        assert_eq!(rate_limited.kind(), ErrorKind::RateLimited);
+        assert_eq!(rate_limited.retry_after(), Some(Duration::from_secs(30)));
+
+        // Not an error this gateway knows of
+        assert_eq!(kind(client.list_projects().await), ErrorKind::Internal);
+
+        // Nothing is listening on the port of a listener which is gone
+        let gone = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = GatewayClient::new(format!("http://{gone}"), ApiKey::parse(API_KEY).unwrap());
+        assert_eq!(
+            kind(client.list_projects().await),
+            ErrorKind::ServiceUnavailable
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! The email addresses of accounts, and how they get verified
+//!
+//! An account asks for an address with `PUT /users/me/email`, which is
+//! kept as pending while a token is sent to it through the gateway's
+//! [`EmailSender`]. Giving the token back makes it the address of the
+//! account. Only verified addresses are ever handed to what notifies
+//! users, see [`GatewayService::notification_email`].
+//!
+//! [`GatewayService::notification_email`]: crate::service::GatewayService::notification_email
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shuttle_common::models::error::ErrorKind;
+use tracing::info;
+
+use crate::{AccountName, Error, ProjectName};
+
+/// How long the token sent to an address can be given back for
+pub const EMAIL_VERIFICATION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long the tokens sent to addresses are
+pub const EMAIL_TOKEN_LEN: usize = 32;
+
+/// An email address of a plain `local@domain` form, the domain being
+/// lowercased
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct EmailAddress(String);
+
+impl EmailAddress {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for EmailAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::custom(ErrorKind::InvalidEmail, format!("`{s}` is not valid"));
+
+        let (local, domain) = s.rsplit_once('@').ok_or_else(invalid)?;
+
+        let is_local_char =
+            |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~.-".contains(c);
+        let local_is_valid = !local.is_empty()
+            && local.len() <= 64
+            && local.chars().all(is_local_char)
+            && !local.starts_with('.')
+            && !local.ends_with('.')
+            && !local.contains("..");
+
+        let is_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        };
+        let domain_is_valid = domain.contains('.') && domain.split('.').all(is_label);
+
+        if !local_is_valid || !domain_is_valid || s.len() > 254 {
+            return Err(invalid());
+        }
+
+        Ok(Self(format!("{local}@{}", domain.to_ascii_lowercase())))
+    }
+}
+
+impl<'de> Deserialize<'de> for EmailAddress {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Display for EmailAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// How the gateway gets verification tokens and notices to the addresses
+/// they are for. Deployments wanting actual emails plug in their own, see
+/// [`GatewayService::with_email_sender`].
+///
+/// [`GatewayService::with_email_sender`]: crate::service::GatewayService::with_email_sender
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send_verification(
+        &self,
+        account_name: &AccountName,
+        to: &EmailAddress,
+        token: &str,
+    ) -> Result<(), Error>;
+
+    /// Let the owner of a project which has been errored for long know
+    /// that it gets archived at `archive_at`, unless they do something
+    /// with it before then
+    async fn send_stale_project_notice(
+        &self,
+        account_name: &AccountName,
+        to: &EmailAddress,
+        project_name: &ProjectName,
+        archive_at: DateTime<Utc>,
+    ) -> Result<(), Error>;
+}
+
+/// Sends nothing and logs the token or notice instead, so the gateway does
+/// not need a mail server to run. The token is only good for the address it was
+/// asked for, and for [`EMAIL_VERIFICATION_TTL`].
+pub struct LogEmailSender;
+
+#[async_trait]
+impl EmailSender for LogEmailSender {
+    async fn send_verification(
+        &self,
+        account_name: &AccountName,
+        to: &EmailAddress,
+        token: &str,
+    ) -> Result<(), Error> {
+        info!(%account_name, %to, token, "not sending the email verification token");
+
+        Ok(())
+    }
+
+    async fn send_stale_project_notice(
+        &self,
+        account_name: &AccountName,
+        to: &EmailAddress,
+        project_name: &ProjectName,
+        archive_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        info!(%account_name, %to, %project_name, %archive_at, "not sending the stale project notice");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::assert_err_kind;
+
+    #[test]
+    fn email_addresses() {
+        for (email, expected) in [
+            ("neo@matrix.com", "neo@matrix.com"),
+            (
+                "Thomas.Anderson@Metacortex.COM",
+                "Thomas.Anderson@metacortex.com",
+            ),
+            ("neo+zion@mail.matrix.io", "neo+zion@mail.matrix.io"),
+            ("o'neil@the-matrix.com", "o'neil@the-matrix.com"),
+        ] {
+            assert_eq!(email.parse::<EmailAddress>().unwrap().as_str(), expected);
+        }
+
+        for email in [
+            "",
+            "neo",
+            "neo@",
+            "@matrix.com",
+            "neo@matrix",
+            "neo@@matrix.com",
+            "neo@matrix..com",
+            "neo@-matrix.com",
+            "neo@matrix_com.io",
+            ".neo@matrix.com",
+            "neo.@matrix.com",
+            "ne..o@matrix.com",
+            "n eo@matrix.com",
+            "neo@matrix.com ",
+            "neo\n@matrix.com",
+        ] {
+            assert_err_kind!(email.parse::<EmailAddress>(), ErrorKind::InvalidEmail);
+        }
+
+        let long_local = format!("{}@matrix.com", "n".repeat(65));
+        assert_err_kind!(long_local.parse::<EmailAddress>(), ErrorKind::InvalidEmail);
+
+        assert!(serde_json::from_str::<EmailAddress>(r#""neo@matrix""#).is_err());
+    }
+}
@@ -0,0 +1,668 @@
+//! Envelope encryption for the secret columns of the state database
+//! (`projects.initial_key` and `custom_domains.private_key`), and for the
+//! secrets held in the states of projects (`projects.project_state` and
+//! `archived_projects.project_state`): their initial keys, along with the
+//! admin secrets and gateway tokens their containers are created with.
+//!
+//! Every value is encrypted with its own random data key, which is in
+//! turn encrypted ("wrapped") with the master key given to the gateway.
+//! Rotating the master key then only means rewrapping the data keys.
+//! Values which were written before a master key was configured are
+//! kept in cleartext until they are rewrapped (see [`rewrap`]).
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use sqlx::sqlite::SqlitePool;
+use sqlx::types::Json as SqlxJson;
+use sqlx::{query, Row};
+use tracing::info;
+
+use crate::project::GATEWAY_TOKEN_ENV;
+use crate::{Error, ErrorKind};
+
+const SEALED_PREFIX: &str = "enc:v1:";
+const KEY_LEN: usize = 32;
+
+/// The container arguments whose value is a secret
+const SECRET_ARGS: &[&str] = &["--admin-secret"];
+/// The container environment variables whose value is a secret
+const SECRET_ENV: &[&str] = &[GATEWAY_TOKEN_ENV];
+
+#[derive(Debug)]
+pub enum EncryptionError {
+    /// The master key is not valid base64 or not 32 bytes long
+    InvalidMasterKey,
+    /// The value is encrypted but no master key was given
+    MissingMasterKey { key_id: String },
+    /// The value was encrypted with another master key
+    WrongMasterKey { key_id: String, expected: String },
+    /// The value does not look like something we encrypted
+    Malformed,
+    /// The value could not be authenticated, it has been tampered with
+    Corrupted,
+}
+
+impl Display for EncryptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMasterKey => {
+                write!(f, "the master key must be 32 bytes encoded in base64")
+            }
+            Self::MissingMasterKey { key_id } => write!(
+                f,
+                "the value is encrypted with master key {key_id} but no master key was given"
+            ),
+            Self::WrongMasterKey { key_id, expected } => write!(
+                f,
+                "the value is encrypted with master key {expected} but master key {key_id} was given"
+            ),
+            Self::Malformed => write!(f, "the encrypted value is malformed"),
+            Self::Corrupted => write!(f, "the encrypted value failed authentication"),
+        }
+    }
+}
+
+impl std::error::Error for EncryptionError {}
+
+/// The key every data key is wrapped with. Parsed from 32 bytes encoded
+/// in base64 (e.g. the output of `openssl rand -base64 32`).
+#[derive(Clone)]
+pub struct MasterKey {
+    key: [u8; KEY_LEN],
+    id: String,
+}
+
+impl MasterKey {
+    /// A short fingerprint of the key, safe to show and store, to tell
+    /// which key a value was encrypted with
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl FromStr for MasterKey {
+    type Err = EncryptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key: [u8; KEY_LEN] = base64::decode(s.trim())
+            .map_err(|_| EncryptionError::InvalidMasterKey)?
+            .try_into()
+            .map_err(|_| EncryptionError::InvalidMasterKey)?;
+
+        let id = digest(&SHA256, &key).as_ref()[..4]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        Ok(Self { key, id })
+    }
+}
+
+impl Debug for MasterKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypts and decrypts secret columns with an (optional) master key.
+/// Without a master key, new values are stored in cleartext.
+#[derive(Clone, Debug, Default)]
+pub struct SecretCipher {
+    master_key: Option<MasterKey>,
+}
+
+impl SecretCipher {
+    pub fn new(master_key: Option<MasterKey>) -> Self {
+        Self { master_key }
+    }
+
+    /// Encrypt `plaintext` for storage
+    pub fn seal(&self, plaintext: &str) -> String {
+        match &self.master_key {
+            Some(master_key) => seal_with(master_key, plaintext),
+            None => plaintext.to_string(),
+        }
+    }
+
+    /// Decrypt a value as it was stored, cleartext values are returned
+    /// as they are
+    pub fn open(&self, stored: &str) -> Result<String, EncryptionError> {
+        let Some(sealed) = Sealed::parse(stored)? else {
+            return Ok(stored.to_string());
+        };
+
+        let master_key = self.master_key_for(&sealed)?;
+        let data_key = unwrap_data_key(master_key, &sealed.wrapped_key)?;
+        let plaintext = open_in_place(&data_key, sealed.ciphertext)?;
+
+        String::from_utf8(plaintext).map_err(|_| EncryptionError::Malformed)
+    }
+
+    /// Re-encrypt a stored value for `new_key`. Encrypted values only
+    /// have their data key rewrapped, cleartext ones get encrypted.
+    pub fn rewrap(&self, stored: &str, new_key: &MasterKey) -> Result<String, EncryptionError> {
+        let Some(sealed) = Sealed::parse(stored)? else {
+            return Ok(seal_with(new_key, stored));
+        };
+
+        let master_key = self.master_key_for(&sealed)?;
+        let data_key = unwrap_data_key(master_key, &sealed.wrapped_key)?;
+
+        Ok(Sealed {
+            key_id: new_key.id().to_string(),
+            wrapped_key: seal_in_place(&new_key.key, data_key.to_vec()),
+            ciphertext: sealed.ciphertext,
+        }
+        .to_string())
+    }
+
+    /// Encrypt the secrets held in a serialized project state for
+    /// storage. Those already encrypted are left as they are.
+    pub fn seal_state(&self, state: &mut Value) {
+        visit_secrets(state, &mut |secret| {
+            if !secret.starts_with(SEALED_PREFIX) {
+                *secret = self.seal(secret);
+            }
+        });
+    }
+
+    /// Decrypt the secrets held in a project state as it was stored
+    pub fn open_state(&self, stored: &mut Value) -> Result<(), EncryptionError> {
+        visit_sealed(stored, &mut |sealed| {
+            *sealed = self.open(sealed)?;
+            Ok(())
+        })
+    }
+
+    /// Re-encrypt the secrets held in a stored project state for
+    /// `new_key`, as [`Self::rewrap`] does
+    pub fn rewrap_state(
+        &self,
+        stored: &mut Value,
+        new_key: &MasterKey,
+    ) -> Result<(), EncryptionError> {
+        visit_sealed(stored, &mut |sealed| {
+            *sealed = self.rewrap(sealed, new_key)?;
+            Ok(())
+        })?;
+        visit_secrets(stored, &mut |secret| {
+            if !secret.starts_with(SEALED_PREFIX) {
+                *secret = seal_with(new_key, secret);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn master_key_for(&self, sealed: &Sealed) -> Result<&MasterKey, EncryptionError> {
+        match &self.master_key {
+            Some(master_key) if master_key.id() == sealed.key_id => Ok(master_key),
+            Some(master_key) => Err(EncryptionError::WrongMasterKey {
+                key_id: master_key.id().to_string(),
+                expected: sealed.key_id.clone(),
+            }),
+            None => Err(EncryptionError::MissingMasterKey {
+                key_id: sealed.key_id.clone(),
+            }),
+        }
+    }
+}
+
+/// An encrypted value as stored:
+/// `enc:v1:<master key id>:<wrapped data key>:<ciphertext>`, where both
+/// the wrapped key and the ciphertext are a nonce followed by the sealed
+/// bytes, in base64.
+struct Sealed {
+    key_id: String,
+    wrapped_key: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+impl Sealed {
+    /// `None` if `stored` is in cleartext
+    fn parse(stored: &str) -> Result<Option<Self>, EncryptionError> {
+        let Some(rest) = stored.strip_prefix(SEALED_PREFIX) else {
+            return Ok(None);
+        };
+
+        let mut parts = rest.split(':');
+        let (Some(key_id), Some(wrapped_key), Some(ciphertext), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(EncryptionError::Malformed);
+        };
+
+        Ok(Some(Self {
+            key_id: key_id.to_string(),
+            wrapped_key: base64::decode(wrapped_key).map_err(|_| EncryptionError::Malformed)?,
+            ciphertext: base64::decode(ciphertext).map_err(|_| EncryptionError::Malformed)?,
+        }))
+    }
+}
+
+impl Display for Sealed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{SEALED_PREFIX}{}:{}:{}",
+            self.key_id,
+            base64::encode(&self.wrapped_key),
+            base64::encode(&self.ciphertext)
+        )
+    }
+}
+
+/// Call `f` on every secret held in a serialized project state. The
+/// secret container arguments and environment variables are found
+/// wherever a container configuration is, with the whole `NAME=value`
+/// being the secret for the latter.
+fn visit_secrets(value: &mut Value, f: &mut impl FnMut(&mut String)) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                match (key.as_str(), field) {
+                    ("initial_key", Value::String(secret)) => f(secret),
+                    ("Args" | "Cmd", Value::Array(args)) => {
+                        let mut is_secret = false;
+                        for arg in args.iter_mut() {
+                            let Value::String(arg) = arg else {
+                                is_secret = false;
+                                continue;
+                            };
+                            if is_secret {
+                                f(arg);
+                                is_secret = false;
+                            } else {
+                                is_secret = SECRET_ARGS.contains(&arg.as_str());
+                            }
+                        }
+                    }
+                    ("Env", Value::Array(env)) => {
+                        for var in env.iter_mut() {
+                            if let Value::String(var) = var {
+                                let name = var.split('=').next().unwrap_or_default();
+                                if SECRET_ENV.contains(&name) {
+                                    f(var);
+                                }
+                            }
+                        }
+                    }
+                    (_, field) => visit_secrets(field, f),
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values.iter_mut() {
+                visit_secrets(value, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Call `f` on every encrypted value in a stored project state
+fn visit_sealed(
+    value: &mut Value,
+    f: &mut impl FnMut(&mut String) -> Result<(), EncryptionError>,
+) -> Result<(), EncryptionError> {
+    match value {
+        Value::String(sealed) if sealed.starts_with(SEALED_PREFIX) => f(sealed),
+        Value::Array(values) => values
+            .iter_mut()
+            .try_for_each(|value| visit_sealed(value, f)),
+        Value::Object(fields) => fields
+            .values_mut()
+            .try_for_each(|value| visit_sealed(value, f)),
+        _ => Ok(()),
+    }
+}
+
+fn seal_with(master_key: &MasterKey, plaintext: &str) -> String {
+    let mut data_key = [0; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut data_key)
+        .expect("the system random number generator to be available");
+
+    Sealed {
+        key_id: master_key.id().to_string(),
+        wrapped_key: seal_in_place(&master_key.key, data_key.to_vec()),
+        ciphertext: seal_in_place(&data_key, plaintext.as_bytes().to_vec()),
+    }
+    .to_string()
+}
+
+fn unwrap_data_key(
+    master_key: &MasterKey,
+    wrapped_key: &[u8],
+) -> Result<[u8; KEY_LEN], EncryptionError> {
+    open_in_place(&master_key.key, wrapped_key.to_vec())?
+        .try_into()
+        .map_err(|_| EncryptionError::Malformed)
+}
+
+/// Returns the nonce followed by the sealed `data`
+fn seal_in_place(key: &[u8; KEY_LEN], mut data: Vec<u8>) -> Vec<u8> {
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .expect("the system random number generator to be available");
+
+    // Every nonce is random and data keys are never reused across
+    // values, so a nonce is never used twice with the same key
+    aead_key(key)
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .expect("secrets to be small enough to be sealed");
+
+    let mut sealed = nonce.to_vec();
+    sealed.append(&mut data);
+    sealed
+}
+
+fn open_in_place(key: &[u8; KEY_LEN], mut sealed: Vec<u8>) -> Result<Vec<u8>, EncryptionError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(EncryptionError::Malformed);
+    }
+
+    let mut data = sealed.split_off(NONCE_LEN);
+    let nonce =
+        Nonce::try_assume_unique_for_key(&sealed).map_err(|_| EncryptionError::Malformed)?;
+
+    let len = aead_key(key)
+        .open_in_place(nonce, Aad::empty(), &mut data)
+        .map_err(|_| EncryptionError::Corrupted)?
+        .len();
+    data.truncate(len);
+
+    Ok(data)
+}
+
+fn aead_key(key: &[u8; KEY_LEN]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).expect("keys to be 32 bytes long"))
+}
+
+/// Make sure every encrypted value in the state database can be
+/// decrypted with `master_key`, so that the gateway does not start
+/// without the key it needs.
+pub async fn check_master_key(
+    db: &SqlitePool,
+    master_key: Option<&MasterKey>,
+) -> Result<(), Error> {
+    let rows = query(
+        "SELECT initial_key AS secret FROM projects WHERE initial_key LIKE 'enc:%' \
+         UNION ALL SELECT private_key AS secret FROM custom_domains WHERE private_key LIKE 'enc:%' \
+         UNION ALL SELECT tree.value AS secret FROM projects, json_tree(projects.project_state) AS tree \
+           WHERE tree.type = 'text' AND tree.value LIKE 'enc:%' \
+         UNION ALL SELECT tree.value AS secret FROM archived_projects, json_tree(archived_projects.project_state) AS tree \
+           WHERE tree.type = 'text' AND tree.value LIKE 'enc:%'",
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut key_ids = BTreeSet::new();
+    for row in rows {
+        if let Ok(Some(sealed)) = Sealed::parse(row.get("secret")) {
+            key_ids.insert(sealed.key_id);
+        }
+    }
+
+    let key_ids: Vec<_> = key_ids.into_iter().collect();
+    match (master_key, key_ids.as_slice()) {
+        (_, []) => Ok(()),
+        (Some(master_key), [key_id]) if key_id == master_key.id() => Ok(()),
+        (None, _) => Err(Error::custom(
+            ErrorKind::Internal,
+            format!(
+                "the state database has values encrypted with master key(s) {} but no master key was given",
+                key_ids.join(", ")
+            ),
+        )),
+        (Some(master_key), _) => Err(Error::custom(
+            ErrorKind::Internal,
+            format!(
+                "the state database has values encrypted with master key(s) {} but master key {} was given; run `rewrap` to move them all to one key",
+                key_ids.join(", "),
+                master_key.id()
+            ),
+        )),
+    }
+}
+
+/// Re-encrypt every secret column of the state database for `new_key`,
+/// along with the secrets held in the states of projects, encrypting the
+/// values still in cleartext along the way. Values are decrypted with
+/// `current_key`. Returns how many values were rewritten, counting each
+/// state as one.
+///
+/// Everything happens in a single transaction: if a single value cannot
+/// be decrypted, nothing is changed.
+pub async fn rewrap(
+    db: &SqlitePool,
+    current_key: Option<&MasterKey>,
+    new_key: &MasterKey,
+) -> Result<usize, Error> {
+    let cipher = SecretCipher::new(current_key.cloned());
+    let mut tx = db.begin().await?;
+    let mut rewrapped = 0;
+
+    let projects = query("SELECT project_name, initial_key, project_state FROM projects")
+        .fetch_all(&mut tx)
+        .await?;
+    for row in projects {
+        let project_name: String = row.get("project_name");
+        let initial_key = cipher
+            .rewrap(row.get("initial_key"), new_key)
+            .map_err(|err| decryption_error("projects.initial_key", &project_name, err))?;
+        let SqlxJson(mut project_state) = row.try_get::<SqlxJson<Value>, _>("project_state")?;
+        cipher
+            .rewrap_state(&mut project_state, new_key)
+            .map_err(|err| decryption_error("projects.project_state", &project_name, err))?;
+
+        query("UPDATE projects SET initial_key = ?1, project_state = ?2 WHERE project_name = ?3")
+            .bind(initial_key)
+            .bind(SqlxJson(project_state))
+            .bind(&project_name)
+            .execute(&mut tx)
+            .await?;
+        rewrapped += 2;
+    }
+
+    let archived_projects =
+        query("SELECT rowid, project_name, project_state FROM archived_projects")
+            .fetch_all(&mut tx)
+            .await?;
+    for row in archived_projects {
+        let rowid: i64 = row.get("rowid");
+        let project_name: String = row.get("project_name");
+        let SqlxJson(mut project_state) = row.try_get::<SqlxJson<Value>, _>("project_state")?;
+        cipher
+            .rewrap_state(&mut project_state, new_key)
+            .map_err(|err| {
+                decryption_error("archived_projects.project_state", &project_name, err)
+            })?;
+
+        query("UPDATE archived_projects SET project_state = ?1 WHERE rowid = ?2")
+            .bind(SqlxJson(project_state))
+            .bind(rowid)
+            .execute(&mut tx)
+            .await?;
+        rewrapped += 1;
+    }
+
+    let custom_domains = query("SELECT fqdn, private_key FROM custom_domains")
+        .fetch_all(&mut tx)
+        .await?;
+    for row in custom_domains {
+        let fqdn: String = row.get("fqdn");
+        let private_key = cipher
+            .rewrap(row.get("private_key"), new_key)
+            .map_err(|err| decryption_error("custom_domains.private_key", &fqdn, err))?;
+
+        query("UPDATE custom_domains SET private_key = ?1 WHERE fqdn = ?2")
+            .bind(private_key)
+            .bind(&fqdn)
+            .execute(&mut tx)
+            .await?;
+        rewrapped += 1;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        rewrapped,
+        master_key = new_key.id(),
+        "rewrapped the secrets of the state database"
+    );
+
+    Ok(rewrapped)
+}
+
+/// An error saying which `column` of which `row` could not be decrypted
+pub fn decryption_error(column: &str, row: &str, err: EncryptionError) -> Error {
+    Error::source(ErrorKind::Internal, err)
+        .context(format!("could not decrypt {column} of `{row}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master_key() -> MasterKey {
+        base64::encode([7u8; KEY_LEN]).parse().unwrap()
+    }
+
+    #[test]
+    fn secrets_round_trip() {
+        let cipher = SecretCipher::new(Some(master_key()));
+
+        let sealed = cipher.seal("neo's secret");
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert!(!sealed.contains("neo's secret"));
+        // Every value gets its own data key and nonces
+        assert_ne!(sealed, cipher.seal("neo's secret"));
+
+        assert_eq!(cipher.open(&sealed).unwrap(), "neo's secret");
+
+        // Values written before encryption was turned on are still readable
+        assert_eq!(cipher.open("cleartext").unwrap(), "cleartext");
+
+        // Without a key, everything stays in cleartext
+        assert_eq!(SecretCipher::default().seal("cleartext"), "cleartext");
+    }
+
+    #[test]
+    fn secrets_rewrap() {
+        let old_key = master_key();
+        let new_key: MasterKey = base64::encode([42u8; KEY_LEN]).parse().unwrap();
+        assert_ne!(old_key.id(), new_key.id());
+
+        let old = SecretCipher::new(Some(old_key));
+        let new = SecretCipher::new(Some(new_key.clone()));
+
+        let sealed = old.seal("neo's secret");
+        let rewrapped = old.rewrap(&sealed, &new_key).unwrap();
+
+        assert_eq!(new.open(&rewrapped).unwrap(), "neo's secret");
+        assert!(matches!(
+            old.open(&rewrapped),
+            Err(EncryptionError::WrongMasterKey { .. })
+        ));
+        assert!(matches!(
+            SecretCipher::default().open(&rewrapped),
+            Err(EncryptionError::MissingMasterKey { .. })
+        ));
+
+        // Cleartext values get encrypted
+        let rewrapped = old.rewrap("cleartext", &new_key).unwrap();
+        assert!(rewrapped.starts_with(SEALED_PREFIX));
+        assert_eq!(new.open(&rewrapped).unwrap(), "cleartext");
+    }
+
+    #[test]
+    fn secrets_tampering_is_detected() {
+        let cipher = SecretCipher::new(Some(master_key()));
+
+        let sealed = cipher.seal("neo's secret");
+        let (head, ciphertext) = sealed.rsplit_once(':').unwrap();
+        let mut ciphertext = base64::decode(ciphertext).unwrap();
+        *ciphertext.last_mut().unwrap() ^= 1;
+        let tampered = format!("{head}:{}", base64::encode(ciphertext));
+
+        assert!(matches!(
+            cipher.open(&tampered),
+            Err(EncryptionError::Corrupted)
+        ));
+        assert!(matches!(
+            cipher.open("enc:v1:garbage"),
+            Err(EncryptionError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn project_state_secrets_round_trip() {
+        let cipher = SecretCipher::new(Some(master_key()));
+        let state = serde_json::json!({
+            "ready": {
+                "container": {
+                    "Args": ["--admin-secret", "neo's secret", "--project", "matrix"],
+                    "Config": {
+                        "Cmd": ["--admin-secret", "neo's secret", "--project", "matrix"],
+                        "Env": ["RUST_LOG=debug", format!("{GATEWAY_TOKEN_ENV}=neo's token")],
+                    }
+                },
+                "ctx": { "creating": { "initial_key": "neo's secret" } }
+            }
+        });
+
+        let mut sealed = state.clone();
+        cipher.seal_state(&mut sealed);
+        let stored = sealed.to_string();
+        assert!(!stored.contains("neo's secret"));
+        assert!(!stored.contains("neo's token"));
+        // What is not a secret stays readable
+        assert!(stored.contains("RUST_LOG=debug"));
+        assert!(stored.contains("\"--project\",\"matrix\""));
+
+        // Sealing again leaves the secrets as they were
+        let mut resealed = sealed.clone();
+        cipher.seal_state(&mut resealed);
+        assert_eq!(resealed, sealed);
+
+        let mut opened = sealed.clone();
+        cipher.open_state(&mut opened).unwrap();
+        assert_eq!(opened, state);
+
+        // States written before encryption was turned on are still readable
+        let mut cleartext = state.clone();
+        cipher.open_state(&mut cleartext).unwrap();
+        assert_eq!(cleartext, state);
+
+        let new_key: MasterKey = base64::encode([42u8; KEY_LEN]).parse().unwrap();
+        let new = SecretCipher::new(Some(new_key.clone()));
+        for mut stored in [sealed, state.clone()] {
+            cipher.rewrap_state(&mut stored, &new_key).unwrap();
+            assert!(!stored.to_string().contains("neo's secret"));
+            assert!(matches!(
+                cipher.open_state(&mut stored.clone()),
+                Err(EncryptionError::WrongMasterKey { .. })
+            ));
+            new.open_state(&mut stored).unwrap();
+            assert_eq!(stored, state);
+        }
+    }
+
+    #[test]
+    fn master_key_is_validated() {
+        assert!("not base64!".parse::<MasterKey>().is_err());
+        assert!(base64::encode([0u8; 16]).parse::<MasterKey>().is_err());
+        assert_eq!(master_key().id().len(), 8);
+        assert!(!format!("{:?}", master_key()).contains(&base64::encode([7u8; KEY_LEN])));
+    }
+}
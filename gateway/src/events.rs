@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{broadcast, watch};
+
+use crate::project::Project;
+use crate::task::{Origin, Work};
+use crate::ProjectName;
+
+/// How many events can be waiting on the slowest subscriber before it
+/// starts missing some
+const PROJECT_EVENTS_CAPACITY: usize = 1024;
+
+/// The state of a project was written to the state database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProjectEvent {
+    pub name: ProjectName,
+    /// The state the project was in, `None` if it was just created
+    pub old: Option<String>,
+    /// The state the project is now in, `None` if it was removed
+    pub new: Option<String>,
+    /// The version of the state the project is now at, `None` if it was
+    /// removed or its state was left as it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    /// The deployment which was just made, when that is what changed.
+    /// The state of the project is then left as it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment_id: Option<String>,
+    /// Where the work which made the change comes from, if it was done
+    /// by the worker
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    /// The request the work which made the change was queued up for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// The change feed of project states. Events are published once the
+/// state is committed, so subscribers can go back to the database to
+/// get the full picture.
+///
+/// Publishing never waits on subscribers: those which fall too far
+/// behind get a [`broadcast::error::RecvError::Lagged`] and are expected
+/// to resync from the database.
+#[derive(Clone)]
+pub struct ProjectEvents {
+    sender: broadcast::Sender<ProjectEvent>,
+}
+
+impl Default for ProjectEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProjectEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(PROJECT_EVENTS_CAPACITY);
+        Self { sender }
+    }
+
+    /// The state of the project was changed to `new`, at `version`
+    pub fn publish(
+        &self,
+        name: &ProjectName,
+        old: Option<&str>,
+        new: Option<&str>,
+        version: Option<i64>,
+    ) {
+        self.publish_by(name, old, new, version, None)
+    }
+
+    /// The state of the project was changed in the course of `work`, if
+    /// any
+    pub fn publish_by(
+        &self,
+        name: &ProjectName,
+        old: Option<&str>,
+        new: Option<&str>,
+        version: Option<i64>,
+        work: Option<&Work>,
+    ) {
+        // Nobody listening is fine
+        let _ = self.sender.send(ProjectEvent {
+            name: name.clone(),
+            old: old.map(ToString::to_string),
+            new: new.map(ToString::to_string),
+            version,
+            deployment_id: None,
+            origin: work.map(|work| work.origin),
+            request_id: work.map(|work| work.request_id.clone()),
+            at: Utc::now(),
+        });
+    }
+
+    /// A deployment of the project in `state` was made
+    pub fn publish_deployment(&self, name: &ProjectName, state: Option<&str>, deployment_id: &str) {
+        let _ = self.sender.send(ProjectEvent {
+            name: name.clone(),
+            old: state.map(ToString::to_string),
+            new: state.map(ToString::to_string),
+            version: None,
+            deployment_id: Some(deployment_id.to_string()),
+            origin: None,
+            request_id: None,
+            at: Utc::now(),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ProjectEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// The latest state of the projects being watched, for any number of
+/// watchers to follow along without each going back to the database.
+/// Nothing is kept for the projects nobody watches.
+///
+/// Only ever moves a project forward: a state older than the one it has
+/// for the project is dropped, whatever order the states come in.
+#[derive(Clone, Default)]
+pub struct ProjectWatches {
+    watched: Arc<Mutex<HashMap<ProjectName, Watched>>>,
+}
+
+struct Watched {
+    sender: watch::Sender<Project>,
+    version: i64,
+}
+
+impl ProjectWatches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow `name`, if it is watched already
+    pub fn subscribe(&self, name: &ProjectName) -> Option<watch::Receiver<Project>> {
+        self.watched
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|watched| watched.sender.subscribe())
+    }
+
+    /// Start watching `name` from `project` at `version`, unless it is
+    /// watched already, and follow it
+    pub fn watch(
+        &self,
+        name: &ProjectName,
+        project: Project,
+        version: i64,
+    ) -> watch::Receiver<Project> {
+        let mut watched = self.watched.lock().unwrap();
+
+        match watched.get_mut(name) {
+            Some(existing) => {
+                existing.update(project, version);
+                existing.sender.subscribe()
+            }
+            None => {
+                let (sender, receiver) = watch::channel(project);
+                watched.insert(name.clone(), Watched { sender, version });
+                receiver
+            }
+        }
+    }
+
+    /// Whether `name` is watched at an older version than `version`
+    pub fn is_behind(&self, name: &ProjectName, version: i64) -> bool {
+        self.watched
+            .lock()
+            .unwrap()
+            .get(name)
+            .map_or(false, |watched| watched.version < version)
+    }
+
+    /// The projects being watched
+    pub fn names(&self) -> Vec<ProjectName> {
+        self.watched.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Let the watchers of `name` know about its state at `version`,
+    /// unless they already know about a later one
+    pub fn update(&self, name: &ProjectName, project: Project, version: i64) {
+        let mut watched = self.watched.lock().unwrap();
+
+        if let Some(existing) = watched.get_mut(name) {
+            if existing.sender.receiver_count() == 0 {
+                watched.remove(name);
+            } else {
+                existing.update(project, version);
+            }
+        }
+    }
+
+    /// The project is gone, which its watchers are told by the channel
+    /// closing
+    pub fn remove(&self, name: &ProjectName) {
+        self.watched.lock().unwrap().remove(name);
+    }
+}
+
+impl Watched {
+    fn update(&mut self, project: Project, version: i64) {
+        if version > self.version {
+            self.sender.send_replace(project);
+            self.version = version;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::ProjectError;
+
+    #[test]
+    fn watches_only_move_forward() {
+        let watches = ProjectWatches::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let errored = Project::Errored(ProjectError::internal("test"));
+        let destroyed = errored.clone().destroy().unwrap();
+
+        // Nothing is kept for the projects nobody watches
+        watches.update(&matrix, errored.clone(), 1);
+        assert!(watches.subscribe(&matrix).is_none());
+
+        let receiver = watches.watch(&matrix, errored.clone(), 1);
+        watches.update(&matrix, destroyed.clone(), 3);
+        watches.update(&matrix, errored.clone(), 2);
+        assert_eq!(*receiver.borrow(), destroyed);
+        assert!(!watches.is_behind(&matrix, 3));
+        assert!(watches.is_behind(&matrix, 4));
+
+        // Nor does watching it again from an older state
+        let again = watches.watch(&matrix, errored, 2);
+        assert_eq!(*again.borrow(), destroyed);
+
+        watches.remove(&matrix);
+        assert!(watches.subscribe(&matrix).is_none());
+    }
+}
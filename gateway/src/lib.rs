@@ -1,32 +1,58 @@
 #[macro_use]
 extern crate async_trait;
 
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::error::Error as StdError;
 use std::fmt::Formatter;
 use std::io;
 use std::pin::Pin;
 use std::str::FromStr;
+use std::time::Duration;
 
 use acme::AcmeClientError;
+use axum::extract::rejection::PathRejection;
+use axum::extract::{FromRequestParts, Path};
+use axum::headers::{HeaderMapExt, Host};
+use axum::http::header::RETRY_AFTER;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, Uri};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use bollard::Docker;
+use futures::future::BoxFuture;
 use futures::prelude::*;
+use rand::Rng;
 use serde::{Deserialize, Deserializer, Serialize};
 use service::ContainerSettings;
 use shuttle_common::models::error::{ApiError, ErrorKind};
+use shuttle_common::models::project::ProjectStateView;
+use task::RetryPolicy;
+use throttle::{DockerCall, DockerLimiter};
 use tokio::sync::mpsc::error::SendError;
-use tracing::error;
+use tracing::{debug, error};
 
 pub mod acme;
 pub mod api;
 pub mod args;
 pub mod auth;
+pub mod backup;
+pub mod client;
+pub mod email;
+pub mod encryption;
+pub mod events;
+pub mod logs;
+pub mod metrics;
+pub mod problem;
 pub mod project;
 pub mod proxy;
+pub mod reconciler;
+pub mod replica;
+pub mod scheduler;
 pub mod service;
 pub mod task;
+pub mod telemetry;
+pub mod throttle;
 pub mod tls;
 pub mod worker;
 
@@ -42,6 +68,24 @@ pub mod worker;
 pub struct Error {
     kind: ErrorKind,
     source: Option<Box<dyn StdError + Sync + Send + 'static>>,
+    resource: Option<ErrorResource>,
+    retry_after: Option<Duration>,
+}
+
+/// How long to wait before asking a gateway which is
+/// [not ready](ErrorKind::NotReady) again
+pub const NOT_READY_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// How long to wait before asking for a project which is
+/// [not ready](ErrorKind::ProjectNotReady) again
+pub const PROJECT_NOT_READY_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// The named resource an [`Error`] is about. Unlike the source, this is
+/// safe to be shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorResource {
+    pub resource: String,
+    pub name: String,
 }
 
 impl Error {
@@ -49,6 +93,8 @@ impl Error {
         Self {
             kind,
             source: Some(Box::new(err)),
+            resource: None,
+            retry_after: None,
         }
     }
 
@@ -59,16 +105,196 @@ impl Error {
                 io::ErrorKind::Other,
                 message.as_ref().to_string(),
             ))),
+            resource: None,
+            retry_after: None,
         }
     }
 
     pub fn from_kind(kind: ErrorKind) -> Self {
-        Self { kind, source: None }
+        Self {
+            kind,
+            source: None,
+            resource: None,
+            retry_after: None,
+        }
+    }
+
+    /// The operation is not allowed on the `resource` (e.g. `project`)
+    /// called `name`. Both are included in the response body.
+    pub fn forbidden(resource: &str, name: &str) -> Self {
+        Self {
+            kind: ErrorKind::Forbidden,
+            source: None,
+            resource: Some(ErrorResource {
+                resource: resource.to_string(),
+                name: name.to_string(),
+            }),
+            retry_after: None,
+        }
+    }
+
+    /// Too many requests were made, and more will be turned away for
+    /// another `retry_after`
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        Self::from_kind(ErrorKind::RateLimited).with_retry_after(retry_after)
+    }
+
+    /// Tell the client how long to wait before trying again, in the
+    /// `Retry-After` header of the response
+    pub fn with_retry_after(self, retry_after: Duration) -> Self {
+        Self {
+            retry_after: Some(retry_after),
+            ..self
+        }
+    }
+
+    /// Put a message about what was being done in front of the source
+    /// of this error, like [`anyhow::Context`] does. The kind, and so
+    /// the response the error turns into, is left as it is.
+    ///
+    /// [`anyhow::Context`]: https://docs.rs/anyhow/latest/anyhow/trait.Context.html
+    pub fn context<S: AsRef<str>>(self, context: S) -> Self {
+        Self {
+            source: Some(Box::new(ContextError {
+                context: context.as_ref().to_string(),
+                source: self.source,
+            })),
+            ..self
+        }
+    }
+
+    /// Like [`Error::context`], with the message only built if needed
+    pub fn with_context<S, F>(self, f: F) -> Self
+    where
+        S: AsRef<str>,
+        F: FnOnce() -> S,
+    {
+        self.context(f())
+    }
+
+    pub fn resource(&self) -> Option<&ErrorResource> {
+        self.resource.as_ref()
     }
 
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    /// How long the client is told to wait before trying again. Unless
+    /// [set](Self::with_retry_after), the kinds of what is not ready yet
+    /// come with a default, the gateway taking less time to be ready
+    /// than a project to start.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after.or(match self.kind {
+            ErrorKind::NotReady => Some(NOT_READY_RETRY_AFTER),
+            ErrorKind::ProjectNotReady => Some(PROJECT_NOT_READY_RETRY_AFTER),
+            _ => None,
+        })
+    }
+
+    /// The error and everything that led to it, outermost first. The
+    /// causes which are part of the message of the one above them already
+    /// are not repeated.
+    pub fn chain(&self) -> String {
+        let mut chain = self.to_string();
+        let mut cause = self.source.as_deref().and_then(|source| source.source());
+        while let Some(err) = cause {
+            let message = err.to_string();
+            if !chain.ends_with(&message) {
+                chain.push_str(": ");
+                chain.push_str(&message);
+            }
+            cause = err.source();
+        }
+
+        chain
+    }
+
+    /// Whether what failed with this error could succeed if tried again,
+    /// as it was down to something passing, like a busy or unreachable
+    /// dependency, rather than to what was asked
+    pub fn is_retryable(&self) -> bool {
+        match self.kind {
+            ErrorKind::Internal
+            | ErrorKind::NotReady
+            | ErrorKind::ServiceUnavailable
+            | ErrorKind::StateStoreUnavailable
+            | ErrorKind::DockerUnavailable
+            | ErrorKind::ProjectUnavailable
+            | ErrorKind::ProjectNotReady => true,
+            ErrorKind::KeyMissing
+            | ErrorKind::BadHost
+            | ErrorKind::KeyMalformed
+            | ErrorKind::Unauthorized
+            | ErrorKind::Forbidden
+            | ErrorKind::UserNotFound
+            | ErrorKind::UserAlreadyExists
+            | ErrorKind::InvalidAccountName
+            | ErrorKind::InvalidEmail
+            | ErrorKind::ProjectNotFound
+            | ErrorKind::InvalidProjectName
+            | ErrorKind::ProjectAlreadyExists
+            | ErrorKind::CustomDomainNotFound
+            | ErrorKind::OperationNotFound
+            | ErrorKind::InvalidCustomDomain
+            | ErrorKind::CustomDomainAlreadyExists
+            | ErrorKind::InvalidOperation
+            | ErrorKind::Conflict
+            | ErrorKind::ArtifactTooLarge
+            | ErrorKind::QuotaExceeded
+            | ErrorKind::RateLimited => false,
+        }
+    }
+
+    /// The error of the highest [severity](ErrorKind::severity), the
+    /// first one winning ties
+    pub fn most_severe(errors: Vec<Error>) -> Option<Error> {
+        errors.into_iter().reduce(|most_severe, err| {
+            if err.kind.severity() > most_severe.kind.severity() {
+                err
+            } else {
+                most_severe
+            }
+        })
+    }
+}
+
+/// The source of an [`Error`] which was given some [context](Error::context)
+#[derive(Debug)]
+struct ContextError {
+    context: String,
+    source: Option<Box<dyn StdError + Sync + Send + 'static>>,
+}
+
+impl std::fmt::Display for ContextError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.context)?;
+        if let Some(source) = self.source.as_ref() {
+            write!(f, ": {source}")?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for ContextError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn StdError + 'static))
+    }
+}
+
+/// Errors are compared by kind only
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl PartialOrd for Error {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.kind.partial_cmp(&other.kind)
+    }
 }
 
 impl From<ErrorKind> for Error {
@@ -95,19 +321,83 @@ impl From<AcmeClientError> for Error {
     }
 }
 
+/// So that test helpers using `anyhow` can bubble their errors up as
+/// an [`Error`]. `anyhow::Error` does not implement [`StdError`] itself
+/// so it is boxed directly.
+#[cfg(test)]
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        Self {
+            kind: ErrorKind::Internal,
+            source: Some(error.into()),
+            resource: None,
+            retry_after: None,
+        }
+    }
+}
+
+/// Body of an error response about a named resource. It is a superset
+/// of [`ApiError`] so existing clients can still make sense of it.
+#[derive(Serialize)]
+struct ResourceApiError<'a> {
+    #[serde(flatten)]
+    resource: &'a ErrorResource,
+    #[serde(flatten)]
+    api_error: &'a ApiError,
+}
+
+/// Every error response of the gateway has the [code](ErrorKind::code) of
+/// its kind in its body, under `error`, whichever part of the gateway it
+/// comes from. Only the kind makes it into the body, so the whole
+/// [chain](Error::chain) is logged in the span of the request instead: as
+/// an error when it is the gateway's fault, and for debugging otherwise.
+///
+/// The response also carries the error as a [`Problem`](problem::Problem),
+/// for the clients asking for one to get it [instead](problem::ErrorFormat).
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        error!(error = %self, "request had an error");
-
         let error: ApiError = self.kind.into();
 
-        (error.status(), Json(error)).into_response()
+        if error.status().is_server_error() {
+            error!(error = %self.chain(), "request had an error");
+        } else {
+            debug!(error = %self.chain(), "request was refused");
+        }
+
+        let mut response = match self.resource.as_ref() {
+            Some(resource) => {
+                let body = ResourceApiError {
+                    resource,
+                    api_error: &error,
+                };
+                (error.status(), Json(body)).into_response()
+            }
+            None => (error.status(), Json(error)).into_response(),
+        };
+
+        if let Some(retry_after) = self.retry_after() {
+            // In whole seconds, rounded up so as not to invite a retry
+            // which is still too early
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+
+        response
+            .extensions_mut()
+            .insert(problem::Problem::new(self.kind, self.resource));
+
+        response
     }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.kind)?;
+        if let Some(ErrorResource { resource, name }) = self.resource.as_ref() {
+            write!(f, " ({resource} {name})")?;
+        }
         if let Some(source) = self.source.as_ref() {
             write!(f, ": ")?;
             source.fmt(f)?;
@@ -118,6 +408,12 @@ impl std::fmt::Display for Error {
 
 impl StdError for Error {}
 
+/// The name of a project, lowercased and checked when it is parsed or
+/// deserialized. It also goes through [`DisplayFromStr`], so that the
+/// nested structures of `serde_with` can have it in them, like maps keyed
+/// by it with `As::<HashMap<DisplayFromStr, Same>>`.
+///
+/// [`DisplayFromStr`]: serde_with::DisplayFromStr
 #[derive(Debug, sqlx::Type, Serialize, Clone, PartialEq, Eq, Hash)]
 #[sqlx(transparent)]
 pub struct ProjectName(String);
@@ -127,48 +423,247 @@ impl ProjectName {
         self.0.as_str()
     }
 
-    pub fn is_valid(&self) -> bool {
-        let name = self.0.clone();
+    /// Generate a random project name which is guaranteed to be valid.
+    /// Meant for tests, so names are prefixed with `test-`.
+    pub fn generate_unique() -> Self {
+        const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+        let mut rng = rand::thread_rng();
+
+        loop {
+            let suffix: String = (0..8)
+                .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+                .collect();
+
+            // Random letters can still trip the profanity filter
+            if let Ok(name) = format!("test-{suffix}").parse() {
+                return name;
+            }
+        }
+    }
+
+    /// The names of the subdomains the gateway has, or may come to have,
+    /// for itself, which are always [reserved](service::GatewayService::reserved_project_names)
+    pub const RESERVED: &'static [&'static str] = &[
+        "admin",
+        "api",
+        "auth",
+        "console",
+        "dashboard",
+        "docs",
+        "gateway",
+        "status",
+        "www",
+    ];
+
+    /// Whether this name is on the `reserved` list, whatever the case
+    /// either is in
+    pub fn is_reserved(&self, reserved: &[&str]) -> bool {
+        reserved
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(&self.0))
+    }
+
+    /// Parse `s` as a project name which is not on the `reserved` list
+    pub fn from_str_with_reserved(s: &str, reserved: &[&str]) -> Result<Self, Error> {
+        let project_name: Self = s.parse()?;
+
+        if project_name.is_reserved(reserved) {
+            return Err(Error::custom(
+                ErrorKind::InvalidProjectName,
+                format!("`{project_name}` is a reserved project name"),
+            ));
+        }
 
-        fn is_valid_char(byte: u8) -> bool {
-            matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'-')
+        Ok(project_name)
+    }
+
+    /// The fewest characters the name of a new project can have
+    pub const MIN_LEN: usize = 3;
+
+    /// The most characters the name of a new project can have, which is
+    /// the most a DNS label can have
+    pub const MAX_LEN: usize = 63;
+
+    /// The first rule for the names of new projects this name breaks, if
+    /// any. Those names have to be DNS labels, and then some: parsing
+    /// keeps underscores for the projects which were created before this
+    /// was checked, but they are rejected here rather than turned into
+    /// dashes, which could make the name of a new project the same as
+    /// that of an old one.
+    pub fn rule_violation(&self) -> Option<ProjectNameRule> {
+        let name = self.0.as_str();
+
+        if let Some(invalid) = name
+            .chars()
+            .find(|c| !matches!(c, 'a'..='z' | '0'..='9' | '-'))
+        {
+            Some(ProjectNameRule::Characters(invalid))
+        } else if name.len() < Self::MIN_LEN || name.len() > Self::MAX_LEN {
+            Some(ProjectNameRule::Length)
+        } else if name.starts_with('-') || name.ends_with('-') {
+            Some(ProjectNameRule::Ends)
+        } else if name.contains("--") {
+            Some(ProjectNameRule::ConsecutiveHyphens)
+        } else if name.bytes().all(|byte| byte.is_ascii_digit()) {
+            Some(ProjectNameRule::AllDigits)
+        } else {
+            None
         }
+    }
+
+    /// Whether a new project can be created under this name, as it breaks
+    /// none of the [rules](Self::rule_violation) for it
+    pub fn is_valid(&self) -> bool {
+        self.rule_violation().is_none()
+    }
+}
 
-        // each label in a hostname can be between 1 and 63 chars
-        let is_invalid_length = name.len() > 63;
+/// A rule for the names of new projects, which a name can break
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectNameRule {
+    /// Only lowercase letters, digits and dashes can be in a name
+    Characters(char),
+    /// A name has [`ProjectName::MIN_LEN`] to [`ProjectName::MAX_LEN`]
+    /// characters
+    Length,
+    /// A name starts and ends with a letter or a digit
+    Ends,
+    /// No two dashes follow each other, as they do in the punycode of
+    /// internationalized names (e.g. `xn--`)
+    ConsecutiveHyphens,
+    /// A name is not only digits, which some resolvers take for an address
+    AllDigits,
+}
 
-        !(name.bytes().any(|byte| !is_valid_char(byte))
-            || name.ends_with('-')
-            || name.starts_with('-')
-            || name.is_empty()
-            || is_invalid_length)
+impl std::fmt::Display for ProjectNameRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Characters(invalid) => write!(
+                f,
+                "it can only have lowercase letters, digits and dashes, not `{invalid}`"
+            ),
+            Self::Length => write!(
+                f,
+                "it must have {} to {} characters",
+                ProjectName::MIN_LEN,
+                ProjectName::MAX_LEN
+            ),
+            Self::Ends => write!(f, "it must start and end with a letter or a digit"),
+            Self::ConsecutiveHyphens => write!(f, "it cannot have two dashes in a row"),
+            Self::AllDigits => write!(f, "it cannot be only digits"),
+        }
     }
 }
 
+/// What the error of a project name which does not parse starts with,
+/// for it to be told apart from the other errors of what it was in
+pub(crate) const INVALID_PROJECT_NAME: &str = "invalid project name";
+
 impl<'de> Deserialize<'de> for ProjectName {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        String::deserialize(deserializer)?
-            .parse()
-            .map_err(<D::Error as serde::de::Error>::custom)
+        let name = String::deserialize(deserializer)?;
+
+        name.parse().map_err(|_| {
+            // Whatever parsing rejects breaks one of the rules for new
+            // names too, unless it is the profanity filter
+            let reason = ProjectName(name.to_ascii_lowercase())
+                .rule_violation()
+                .map(|rule| rule.to_string())
+                .unwrap_or_else(|| "it is not an allowed name".to_string());
+
+            <D::Error as serde::de::Error>::custom(format!(
+                "{INVALID_PROJECT_NAME} `{name}`: {reason}"
+            ))
+        })
     }
 }
 
 impl FromStr for ProjectName {
     type Err = Error;
 
+    /// Project names are lowercased, like the DNS labels they are served
+    /// under, so that `MyApp` and `myapp` are the same project
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.parse::<shuttle_common::project::ProjectName>()
+        s.to_ascii_lowercase()
+            .parse::<shuttle_common::project::ProjectName>()
             .map_err(|_| Error::from_kind(ErrorKind::InvalidProjectName))
             .map(|pn| Self(pn.to_string()))
     }
 }
 
+/// Extract the project a request is for from the first label of its
+/// `Host` header (e.g. `matrix` in `matrix.shuttleapp.rs`).
+impl TryFrom<&HeaderMap> for ProjectName {
+    type Error = Error;
+
+    fn try_from(headers: &HeaderMap) -> Result<Self, Self::Error> {
+        let host = headers
+            .typed_get::<Host>()
+            .ok_or_else(|| Error::from_kind(ErrorKind::BadHost))?;
+
+        host.hostname()
+            .split('.')
+            .next()
+            .ok_or_else(|| Error::from_kind(ErrorKind::BadHost))?
+            .parse()
+            .map_err(|_| Error::from_kind(ErrorKind::BadHost))
+    }
+}
+
+/// Extract the project a request is for from the `:project_name`
+/// parameter of its path, whatever other parameters the route has
+#[async_trait]
+impl<S> FromRequestParts<S> for ProjectName
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(mut params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| match rejection {
+                // The route is set up wrong, the request has nothing to do with it
+                PathRejection::MissingPathParams(_) => {
+                    Error::source(ErrorKind::Internal, rejection)
+                }
+                rejection => Error::source(ErrorKind::InvalidProjectName, rejection),
+            })?;
+
+        params
+            .remove("project_name")
+            .ok_or_else(|| {
+                Error::custom(
+                    ErrorKind::Internal,
+                    "the route has no `:project_name` parameter",
+                )
+            })?
+            .parse()
+    }
+}
+
+/// The bare name, as it goes in URLs, container names, headers and
+/// serialized data, all of which parse it back. The alternate form tags it
+/// for messages where the kind of name would otherwise be lost, so
+/// `format!("{name:#}")` gives `project:matrix`. Structured log fields are
+/// already named and keep the bare name.
 impl std::fmt::Display for ProjectName {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        if f.alternate() {
+            write!(f, "project:{}", self.0)
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
+impl From<ProjectName> for serde_json::Value {
+    fn from(project_name: ProjectName) -> Self {
+        Self::String(project_name.0)
     }
 }
 
@@ -176,17 +671,100 @@ impl std::fmt::Display for ProjectName {
 #[sqlx(transparent)]
 pub struct AccountName(String);
 
+impl AccountName {
+    /// The shortest a new account name can be
+    pub const MIN_LEN: usize = 3;
+    /// The longest a new account name can be
+    pub const MAX_LEN: usize = 32;
+    /// The longest name of an account which may have been created before
+    /// account names had rules, past which it cannot be one at all
+    const MAX_EXISTING_LEN: usize = 255;
+
+    /// Whether the name follows the rules for new accounts: 3 to 32
+    /// lowercase letters, digits, `-` or `_`
+    pub fn is_valid(&self) -> bool {
+        Self::is_valid_name(&self.0)
+    }
+
+    fn is_valid_name(name: &str) -> bool {
+        (Self::MIN_LEN..=Self::MAX_LEN).contains(&name.len())
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+    }
+
+    /// Parse the name of an account which may already exist. Accounts
+    /// created before names had rules may not follow them, so this only
+    /// rejects what cannot be an account name at all: nothing, whitespace,
+    /// control characters, slashes or a name longer than any account has.
+    /// New accounts must still be [valid](Self::is_valid).
+    pub fn existing(name: &str) -> Result<Self, Error> {
+        let well_formed = !name.is_empty()
+            && name.len() <= Self::MAX_EXISTING_LEN
+            && !name
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control() || c == '/');
+
+        if well_formed {
+            Ok(Self(name.to_string()))
+        } else {
+            Err(Error::custom(
+                ErrorKind::InvalidAccountName,
+                format!("not an account name: {name:?}"),
+            ))
+        }
+    }
+}
+
+/// Parse the name of a new account, which has to be [valid](AccountName::is_valid)
 impl FromStr for AccountName {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self(s.to_string()))
+        if Self::is_valid_name(s) {
+            Ok(Self(s.to_string()))
+        } else {
+            Err(Error::custom(
+                ErrorKind::InvalidAccountName,
+                format!("invalid account name: {s:?}"),
+            ))
+        }
     }
 }
 
+/// Take the account name from a path prefixed with it, as in
+/// `/accounts/alice/projects`, for the setups which route on it
+impl TryFrom<&Uri> for AccountName {
+    type Error = Error;
+
+    fn try_from(uri: &Uri) -> Result<Self, Self::Error> {
+        let mut segments = uri.path().split('/').filter(|segment| !segment.is_empty());
+
+        match (segments.next(), segments.next()) {
+            (Some("accounts"), Some(name)) => {
+                AccountName::existing(name).map_err(|_| Error::from_kind(ErrorKind::UserNotFound))
+            }
+            _ => Err(Error::from_kind(ErrorKind::UserNotFound)),
+        }
+    }
+}
+
+/// The bare name, as it is stored and sent. Like [`ProjectName`], the
+/// alternate form tags it for messages: `format!("{name:#}")` gives
+/// `account:neo`.
 impl std::fmt::Display for AccountName {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        if f.alternate() {
+            write!(f, "account:{}", self.0)
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
+impl From<AccountName> for serde_json::Value {
+    fn from(account_name: AccountName) -> Self {
+        Self::String(account_name.0)
     }
 }
 
@@ -195,16 +773,75 @@ impl<'de> Deserialize<'de> for AccountName {
     where
         D: Deserializer<'de>,
     {
-        String::deserialize(deserializer)?
-            .parse()
-            .map_err(|_err| todo!())
+        let name = String::deserialize(deserializer)?;
+
+        // What is deserialized names an account which may predate the rules
+        // for new ones; those are checked when the account is created
+        AccountName::existing(&name).map_err(|_| {
+            <D::Error as serde::de::Error>::custom(format!(
+                "invalid account name {name:?}: it must be 1 to {} characters with no \
+                 whitespace, control characters or slashes",
+                AccountName::MAX_EXISTING_LEN
+            ))
+        })
     }
 }
 
+/// The tier an account is on
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    sqlx::Type,
+    strum::Display,
+    strum::EnumString,
+)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum AccountTier {
+    #[default]
+    Basic,
+    Pro,
+    Team,
+    Admin,
+}
+
+/// An account as known to the gateway.
+///
+/// Authorization decisions should be made against this rather than
+/// a bare [`AccountName`], so the account's standing is always taken
+/// into account.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Account {
+    pub name: AccountName,
+    pub tier: AccountTier,
+    pub suspended: bool,
+    pub super_user: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// The verified email address of the account. It is only given out
+    /// by the admin API and to what notifies the account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProjectDetails {
     pub project_name: ProjectName,
     pub account_name: AccountName,
+    /// Whether the worker gave up on the project
+    pub dead_lettered: bool,
+    /// The state the project is in
+    pub state: ProjectStateView,
+    /// When the project was first created, if it was recorded
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The status docker last gave for the container of the project
+    pub container_status: Option<String>,
 }
 
 impl From<ProjectDetails> for shuttle_common::models::project::AdminResponse {
@@ -212,6 +849,10 @@ impl From<ProjectDetails> for shuttle_common::models::project::AdminResponse {
         Self {
             project_name: project.project_name.to_string(),
             account_name: project.account_name.to_string(),
+            dead_lettered: project.dead_lettered,
+            state: Some(project.state),
+            created_at: project.created_at,
+            container_status: project.container_status,
         }
     }
 }
@@ -220,6 +861,16 @@ pub trait DockerContext: Send + Sync {
     fn docker(&self) -> &Docker;
 
     fn container_settings(&self) -> &ContainerSettings;
+
+    /// The rate limit all calls to the docker daemon go through
+    fn docker_limiter(&self) -> &DockerLimiter;
+}
+
+/// Wait for the rate limit of `ctx` to let a `call` through, then hand
+/// out the docker client to make it with
+pub async fn limited_docker<C: DockerContext + ?Sized>(ctx: &C, call: DockerCall) -> &Docker {
+    ctx.docker_limiter().acquire(call).await;
+    ctx.docker()
 }
 
 #[async_trait]
@@ -246,6 +897,36 @@ pub trait State<Ctx>: Send {
     type Error;
 
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error>;
+
+    /// The longest a step out of this state is expected to take, `None`
+    /// if it can take however long it needs
+    fn timeout_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// How a task which failed out of this state is retried
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+}
+
+/// Take a step out of `state`, giving up on it once it has taken longer
+/// than [`State::timeout_duration`]. The limit which was hit is given
+/// back when that happens.
+pub async fn next_with_timeout<Ctx, S>(
+    state: S,
+    ctx: &Ctx,
+) -> Result<Result<S::Next, S::Error>, Duration>
+where
+    S: State<Ctx>,
+    Ctx: Sync,
+{
+    match state.timeout_duration() {
+        Some(limit) => tokio::time::timeout(limit, state.next(ctx))
+            .await
+            .map_err(|_| limit),
+        None => Ok(state.next(ctx).await),
+    }
 }
 
 pub type StateTryStream<'c, St, Err> = Pin<Box<dyn Stream<Item = Result<St, Err>> + Send + 'c>>;
@@ -266,6 +947,7 @@ where
     /// the generated states.
     ///
     /// This stream will not end.
+    #[must_use = "this stream must be driven to completion"]
     fn into_stream<'c>(self, ctx: &'c Ctx) -> StateTryStream<'c, Self, Self::ErrorVariant>
     where
         Self: 'c,
@@ -279,6 +961,45 @@ where
                 .map(|state| Some((state.clone(), (state, ctx))))
         }))
     }
+
+    /// Drive the state until it is done, skipping over the states in
+    /// between, and resolve to the one it ended in. A state which is
+    /// already done is given back as it is.
+    fn into_final<'c>(self, ctx: &'c Ctx) -> BoxFuture<'c, Result<Self, Self::ErrorVariant>>
+    where
+        Self: 'c,
+        Self::ErrorVariant: Send,
+    {
+        if self.is_done() {
+            return Box::pin(future::ready(self.into_result()));
+        }
+
+        Box::pin(async move {
+            self.into_stream(ctx)
+                .try_skip_while(|state| future::ready(Ok(!state.is_done())))
+                .try_next()
+                .await
+                .map(|state| state.expect("the stream of states does not end"))
+        })
+    }
+
+    /// Like [`EndStateExt::into_final`], giving up once the state has
+    /// not come to an end within `limit`. The limit is given back when
+    /// that happens.
+    fn into_final_with_timeout<'c>(
+        self,
+        ctx: &'c Ctx,
+        limit: Duration,
+    ) -> BoxFuture<'c, Result<Result<Self, Self::ErrorVariant>, Duration>>
+    where
+        Self: 'c,
+        Self::ErrorVariant: Send,
+    {
+        Box::pin(
+            tokio::time::timeout(limit, self.into_final(ctx))
+                .map(move |res| res.map_err(|_| limit)),
+        )
+    }
 }
 
 impl<Ctx, S> EndStateExt<Ctx> for S
@@ -321,18 +1042,22 @@ pub trait Refresh<Ctx>: Sized {
 
 #[cfg(test)]
 pub mod tests {
-    use std::collections::HashMap;
-    use std::env;
+    use std::collections::{BTreeMap, HashMap};
+    use std::convert::Infallible;
     use std::net::SocketAddr;
-    use std::str::FromStr;
     use std::sync::{Arc, Mutex};
     use std::time::Duration;
 
     use anyhow::{anyhow, Context as AnyhowContext};
     use axum::headers::authorization::Bearer;
     use axum::headers::Authorization;
+    use axum::http::header::RETRY_AFTER;
+    use axum::http::HeaderMap;
+    use axum::response::IntoResponse;
     use axum::routing::get;
     use axum::{extract, Router, TypedHeader};
+    use bollard::container::{ListContainersOptions, RemoveContainerOptions};
+    use bollard::service::ContainerSummary;
     use bollard::Docker;
     use fqdn::FQDN;
     use futures::prelude::*;
@@ -341,21 +1066,28 @@ pub mod tests {
     use hyper::http::Uri;
     use hyper::{Body, Client as HyperClient, Request, Response, StatusCode};
     use jsonwebtoken::EncodingKey;
-    use rand::distributions::{Alphanumeric, DistString, Distribution, Uniform};
+    use rand::distributions::{Alphanumeric, DistString};
     use ring::signature::{self, Ed25519KeyPair, KeyPair};
     use shuttle_common::backends::auth::ConvertResponse;
     use shuttle_common::claims::{Claim, Scope};
     use shuttle_common::models::project;
     use sqlx::SqlitePool;
     use tokio::sync::mpsc::channel;
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{self, SubscriberExt};
+    use tracing_subscriber::{Layer, Registry};
 
     use crate::acme::AcmeClient;
     use crate::api::latest::ApiBuilder;
-    use crate::args::{ContextArgs, StartArgs, UseTls};
+    use crate::args::{Args, Commands, ContextArgs, StartArgs};
     use crate::proxy::UserServiceBuilder;
     use crate::service::{ContainerSettings, GatewayService, MIGRATIONS};
+    use crate::throttle::DockerLimiter;
     use crate::worker::Worker;
-    use crate::DockerContext;
+    use crate::{
+        next_with_timeout, DockerContext, EndState, EndStateExt, ProjectName, State, TryState,
+    };
 
     macro_rules! value_block_helper {
         ($next:ident, $block:block) => {
@@ -557,6 +1289,7 @@ pub mod tests {
     pub struct WorldContext {
         pub docker: Docker,
         pub container_settings: ContainerSettings,
+        pub docker_limiter: DockerLimiter,
         pub hyper: HyperClient<HttpConnector, Body>,
         pub auth_uri: Uri,
     }
@@ -571,14 +1304,17 @@ pub mod tests {
                 .context(anyhow!("A docker daemon does not seem accessible",))
                 .unwrap();
 
-            let control: i16 = Uniform::from(9000..10000).sample(&mut rand::thread_rng());
-            let user = control + 1;
-            let bouncer = user + 1;
-            let auth = bouncer + 1;
-            let control = format!("127.0.0.1:{control}").parse().unwrap();
-            let user = format!("127.0.0.1:{user}").parse().unwrap();
-            let bouncer = format!("127.0.0.1:{bouncer}").parse().unwrap();
-            let auth: SocketAddr = format!("127.0.0.1:{auth}").parse().unwrap();
+            // Asked of the OS rather than picked at random, for the worlds
+            // of tests run in parallel, threads or processes, not to fight
+            // over them
+            let local = || {
+                let port = portpicker::pick_unused_port().expect("no free port for the world");
+                SocketAddr::from(([127, 0, 0, 1], port))
+            };
+            let control = local();
+            let user = local();
+            let bouncer = local();
+            let auth = local();
             let auth_uri: Uri = format!("http://{auth}").parse().unwrap();
 
             let auth_service = AuthService::new(auth);
@@ -588,30 +1324,39 @@ pub mod tests {
                 Alphanumeric.sample_string(&mut rand::thread_rng(), 4)
             );
 
-            let image = env::var("SHUTTLE_TESTS_RUNTIME_IMAGE")
-                .unwrap_or_else(|_| "public.ecr.aws/shuttle/deployer:latest".to_string());
-
-            let network_name =
-                env::var("SHUTTLE_TESTS_NETWORK").unwrap_or_else(|_| "shuttle_default".to_string());
-
-            let provisioner_host = "provisioner".to_string();
-
-            let docker_host = "/var/run/docker.sock".to_string();
-
-            let args = StartArgs {
-                control,
-                user,
-                bouncer,
-                use_tls: UseTls::Disable,
-                context: ContextArgs {
-                    docker_host,
-                    image,
-                    prefix,
-                    provisioner_host,
-                    auth_uri: auth_uri.clone(),
-                    network_name,
-                    proxy_fqdn: FQDN::from_str("test.shuttleapp.rs").unwrap(),
-                },
+            // Through the same parsing as the gateway's, so the runtime
+            // image and the networks of the world come from the
+            // `SHUTTLE_GATEWAY_*` environment variables like its own would
+            let argv = [
+                "gateway",
+                "start",
+                "--control",
+                &control.to_string(),
+                "--api-bind-retry",
+                "0",
+                "--user",
+                &user.to_string(),
+                "--bouncer",
+                &bouncer.to_string(),
+                "--use-tls",
+                "disable",
+                "--worker-concurrency",
+                "1",
+                "--health-worker-concurrency",
+                "1",
+                "--prefix",
+                &prefix,
+                "--insecure-skip-provisioner-tls",
+                "--auth-uri",
+                &auth_uri.to_string(),
+                "--proxy-fqdn",
+                "test.shuttleapp.rs",
+                "--max-artifact-size",
+                "1048576",
+                "--no-docker-rate-limit",
+            ];
+            let Commands::Start(args) = Args::try_load_from(argv).unwrap().command else {
+                panic!("should be the start command");
             };
 
             let settings = ContainerSettings::builder().from_args(&args.context).await;
@@ -677,12 +1422,100 @@ pub mod tests {
             WorldContext {
                 docker: self.docker.clone(),
                 container_settings: self.settings.clone(),
+                docker_limiter: DockerLimiter::disabled(),
                 hyper: self.hyper.clone(),
                 auth_uri: self.auth_uri.clone(),
             }
         }
     }
 
+    /// Dropping a world checks that the test left no container of its
+    /// prefix behind, see [`assert_no_orphan_containers`]. Whatever was
+    /// left behind is removed, for the next test runs not to trip on it.
+    impl Drop for World {
+        fn drop(&mut self) {
+            let prefix = self.args.context.prefix.clone();
+
+            // The world is dropped on the runtime of the test, which
+            // cannot be blocked on: docker is asked on a runtime of its
+            // own, on a thread of its own
+            let orphans = std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                runtime.block_on(async {
+                    let docker = Docker::connect_with_local_defaults().unwrap();
+                    let orphans = orphan_containers(&docker, &prefix).await;
+
+                    for id in orphans.iter().filter_map(|orphan| orphan.id.as_deref()) {
+                        let _ = docker
+                            .remove_container(
+                                id,
+                                Some(RemoveContainerOptions {
+                                    force: true,
+                                    v: true,
+                                    ..Default::default()
+                                }),
+                            )
+                            .await;
+                    }
+
+                    orphans
+                })
+            })
+            .join()
+            .unwrap_or_default();
+
+            // A test which failed already said what went wrong
+            if !orphans.is_empty() && !std::thread::panicking() {
+                panic!("{}", describe_orphans(&orphans));
+            }
+        }
+    }
+
+    /// Panic with the list of containers labelled with the prefix of
+    /// `world` which are still around, if there are any
+    pub async fn assert_no_orphan_containers(world: &World) {
+        let orphans = orphan_containers(&world.docker, &world.args.context.prefix).await;
+        assert!(orphans.is_empty(), "{}", describe_orphans(&orphans));
+    }
+
+    async fn orphan_containers(docker: &Docker, prefix: &str) -> Vec<ContainerSummary> {
+        let filters = HashMap::from([(
+            "label".to_string(),
+            vec![format!("shuttle.prefix={prefix}")],
+        )]);
+
+        docker
+            .list_containers(Some(ListContainersOptions {
+                all: true,
+                filters,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+    }
+
+    fn describe_orphans(orphans: &[ContainerSummary]) -> String {
+        let leaked: Vec<_> = orphans
+            .iter()
+            .map(|orphan| {
+                format!(
+                    "{} ({})",
+                    orphan.names.clone().unwrap_or_default().join(", "),
+                    orphan.state.as_deref().unwrap_or("unknown state"),
+                )
+            })
+            .collect();
+
+        format!(
+            "the test leaked {} container(s): {}",
+            leaked.len(),
+            leaked.join("; ")
+        )
+    }
+
     impl DockerContext for WorldContext {
         fn docker(&self) -> &Docker {
             &self.docker
@@ -691,6 +1524,10 @@ pub mod tests {
         fn container_settings(&self) -> &ContainerSettings {
             &self.container_settings
         }
+
+        fn docker_limiter(&self) -> &DockerLimiter {
+            &self.docker_limiter
+        }
     }
 
     struct AuthService {
@@ -748,8 +1585,824 @@ pub mod tests {
     }
 
     #[tokio::test]
+    async fn service_unavailable_is_told_apart_from_project_not_ready() {
+        let body_of = |resp: axum::response::Response| async move {
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let resp = crate::Error::from_kind(crate::ErrorKind::ServiceUnavailable).into_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let gateway_wide = body_of(resp).await;
+
+        let resp = crate::Error::from_kind(crate::ErrorKind::ProjectNotReady).into_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let project_wide = body_of(resp).await;
+
+        assert_eq!(project_wide["message"], "project not ready");
+        assert_ne!(gateway_wide["message"], project_wide["message"]);
+        assert_eq!(gateway_wide["status_code"], 503);
+
+        // Back-pressure from the worker is about the gateway as a whole
+        let (sender, receiver) = channel::<()>(1);
+        drop(receiver);
+        let err = crate::Error::from(sender.send(()).await.unwrap_err());
+        assert_eq!(err.kind(), crate::ErrorKind::ServiceUnavailable);
+    }
+
+    #[tokio::test]
+    async fn gateway_and_project_not_ready_are_told_apart() {
+        let code_of = |resp: axum::response::Response| async move {
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["error"]["code"].clone()
+        };
+
+        let resp = crate::Error::from_kind(crate::ErrorKind::NotReady).into_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[RETRY_AFTER], "5");
+        assert_eq!(code_of(resp).await, "not_ready");
+
+        let resp = crate::Error::from_kind(crate::ErrorKind::ProjectNotReady).into_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[RETRY_AFTER], "10");
+        assert_eq!(code_of(resp).await, "project_not_ready");
+
+        // A wait which is known beats the default
+        let resp = crate::Error::from_kind(crate::ErrorKind::ProjectNotReady)
+            .with_retry_after(Duration::from_secs(2))
+            .into_response();
+        assert_eq!(resp.headers()[RETRY_AFTER], "2");
+
+        // Other errors only get one when it is set
+        let resp = crate::Error::from_kind(crate::ErrorKind::ServiceUnavailable).into_response();
+        assert!(!resp.headers().contains_key(RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn forbidden_error_body_names_the_resource() {
+        let resp = crate::Error::forbidden("project", "my-project").into_response();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": { "code": "forbidden", "message": "forbidden" },
+                "resource": "project",
+                "name": "my-project",
+                "message": "forbidden",
+                "status_code": 403,
+            })
+        );
+
+        // Errors without a resource keep the plain body
+        let resp = crate::Error::from_kind(crate::ErrorKind::Forbidden).into_response();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": { "code": "forbidden", "message": "forbidden" },
+                "message": "forbidden",
+                "status_code": 403,
+            })
+        );
+    }
+
+    #[test]
+    fn limits_tell_when_to_retry() {
+        let resp = crate::Error::rate_limited(Duration::from_millis(1500)).into_response();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["retry-after"], "2");
+
+        let resp = crate::Error::from_kind(crate::ErrorKind::QuotaExceeded)
+            .with_retry_after(Duration::from_secs(3600))
+            .into_response();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(resp.headers()["retry-after"], "3600");
+
+        let resp = crate::Error::from_kind(crate::ErrorKind::QuotaExceeded).into_response();
+        assert!(!resp.headers().contains_key("retry-after"));
+    }
+
+    /// Keeps the level and the fields of the events made while it is the
+    /// subscriber
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<(Level, BTreeMap<String, String>)>>>);
+
+    struct FieldsVisitor<'a>(&'a mut BTreeMap<String, String>);
+
+    impl Visit for FieldsVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Events {
+        fn on_event(&self, event: &Event<'_>, _ctx: layer::Context<'_, S>) {
+            let mut fields = BTreeMap::new();
+            event.record(&mut FieldsVisitor(&mut fields));
+            self.0
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), fields));
+        }
+    }
+
+    /// What the disk said, behind a message which does not repeat it
+    #[derive(Debug)]
+    struct WriteError(std::io::Error);
+
+    impl std::fmt::Display for WriteError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "could not save the state")
+        }
+    }
+
+    impl std::error::Error for WriteError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn error_responses_log_what_their_body_leaves_out() {
+        let events = Events::default();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(events.clone()));
+
+        let disk = std::io::Error::new(std::io::ErrorKind::Other, "the disk is on fire");
+        let err = crate::Error::source(crate::ErrorKind::Internal, WriteError(disk))
+            .context("could not start matrix");
+        assert!(err
+            .chain()
+            .ends_with("could not start matrix: could not save the state: the disk is on fire"));
+
+        let resp = err.into_response();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!body.contains("the disk is on fire"), "{body}");
+        assert!(!body.contains("could not start matrix"), "{body}");
+
+        let resp = crate::Error::custom(crate::ErrorKind::ProjectNotFound, "neo has no zion")
+            .into_response();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!body.contains("neo has no zion"), "{body}");
+
+        let events = events.0.lock().unwrap().clone();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].0, Level::ERROR);
+        assert!(events[0].1["error"].ends_with("the disk is on fire"));
+        // Not the gateway's fault, so only there to debug the client
+        assert_eq!(events[1].0, Level::DEBUG);
+        assert!(events[1].1["error"].ends_with("neo has no zion"));
+    }
+
+    // Errors cross task and thread boundaries all the time, and end up
+    // boxed as the source of other errors
+    static_assertions::assert_impl_all!(crate::Error: Send, Sync, std::error::Error);
+
+    // Tests hand their world and its context to the tasks they spawn, and
+    // whichever thread or process runs them must not make a difference
+    static_assertions::assert_impl_all!(World: Send, Sync);
+    static_assertions::assert_impl_all!(WorldContext: Send, Sync);
+
+    #[test]
+    fn ui() {
+        let t = trybuild::TestCases::new();
+        t.compile_fail("tests/ui/*.rs");
+        t.pass("tests/ui/pass/*.rs");
+    }
+
+    #[test]
+    fn anyhow_errors_convert_to_internal() {
+        let err: crate::Error = anyhow!("the oracle is gone").into();
+
+        assert_eq!(err.kind(), crate::ErrorKind::Internal);
+        assert!(err.to_string().ends_with(": the oracle is gone"));
+    }
+
+    #[test]
+    fn error_context() {
+        use crate::{Error, ErrorKind};
+
+        let err = Error::custom(ErrorKind::Internal, "docker went away")
+            .context("could not start matrix")
+            .context("could not wake up neo's projects");
+        assert_eq!(err.kind(), ErrorKind::Internal);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}: could not wake up neo's projects: could not start matrix: docker went away",
+                ErrorKind::Internal
+            )
+        );
+
+        // There might not be a source to begin with
+        let err = Error::forbidden("project", "matrix").with_context(|| "not neo's to touch");
+        assert_eq!(err.kind(), ErrorKind::Forbidden);
+        assert_eq!(err.resource().unwrap().name, "matrix");
+        assert!(err
+            .to_string()
+            .ends_with(" (project matrix): not neo's to touch"));
+
+        let mut called = false;
+        let _ = Error::from_kind(ErrorKind::NotReady).with_context(|| {
+            called = true;
+            "still starting"
+        });
+        assert!(called);
+    }
+
+    #[test]
+    fn most_severe_error() {
+        use crate::{Error, ErrorKind};
+
+        assert!(Error::from_kind(ErrorKind::Internal) > Error::forbidden("project", "matrix"));
+        assert!(Error::from_kind(ErrorKind::ProjectNotFound) < ErrorKind::Forbidden.into());
+
+        assert!(Error::most_severe(Vec::new()).is_none());
+
+        let most_severe = Error::most_severe(vec![
+            Error::from_kind(ErrorKind::ProjectNotFound),
+            Error::forbidden("project", "matrix"),
+            Error::from_kind(ErrorKind::InvalidProjectName),
+            Error::forbidden("project", "reloaded"),
+        ])
+        .unwrap();
+
+        assert_eq!(most_severe.kind(), ErrorKind::Forbidden);
+        assert_eq!(most_severe.resource().unwrap().name, "matrix");
+    }
+
+    #[test]
+    fn retryable_errors() {
+        use crate::{Error, ErrorKind};
+
+        for kind in [
+            ErrorKind::Internal,
+            ErrorKind::NotReady,
+            ErrorKind::ServiceUnavailable,
+            ErrorKind::StateStoreUnavailable,
+            ErrorKind::DockerUnavailable,
+            ErrorKind::ProjectUnavailable,
+            ErrorKind::ProjectNotReady,
+        ] {
+            assert!(Error::from_kind(kind).is_retryable(), "{kind:?}");
+        }
+
+        for kind in [
+            ErrorKind::Forbidden,
+            ErrorKind::Unauthorized,
+            ErrorKind::InvalidProjectName,
+            ErrorKind::InvalidOperation,
+            ErrorKind::ProjectNotFound,
+            ErrorKind::Conflict,
+            ErrorKind::QuotaExceeded,
+        ] {
+            assert!(!Error::from_kind(kind).is_retryable(), "{kind:?}");
+        }
+
+        // Whatever the error is about or came from
+        assert!(!Error::forbidden("project", "matrix").is_retryable());
+        assert!(Error::source(
+            ErrorKind::Internal,
+            std::io::Error::from(std::io::ErrorKind::Other)
+        )
+        .is_retryable());
+    }
+
+    #[test]
+    fn project_name_from_host_header() {
+        let headers_with_host = |host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Host", host.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(
+            ProjectName::try_from(&headers_with_host("matrix.shuttleapp.rs")).unwrap(),
+            "matrix".parse().unwrap()
+        );
+        assert_eq!(
+            ProjectName::try_from(&headers_with_host("matrix.shuttleapp.rs:8000")).unwrap(),
+            "matrix".parse().unwrap()
+        );
+
+        assert_err_kind!(ProjectName::try_from(&HeaderMap::new()), ErrorKind::BadHost);
+        assert_err_kind!(
+            ProjectName::try_from(&headers_with_host("-matrix-.shuttleapp.rs")),
+            ErrorKind::BadHost
+        );
+        assert_err_kind!(
+            ProjectName::try_from(&headers_with_host("not a host")),
+            ErrorKind::BadHost
+        );
+    }
+
+    #[test]
+    fn account_name_from_uri() {
+        use crate::AccountName;
+
+        let account_name = |uri: &'static str| AccountName::try_from(&Uri::from_static(uri));
+
+        assert_eq!(
+            account_name("/accounts/alice/projects").unwrap(),
+            "alice".parse().unwrap()
+        );
+        assert_eq!(
+            account_name("https://api.shuttle.rs/accounts/alice?page=2").unwrap(),
+            "alice".parse().unwrap()
+        );
+
+        assert_err_kind!(account_name("/accounts"), ErrorKind::UserNotFound);
+        assert_err_kind!(account_name("/accounts/"), ErrorKind::UserNotFound);
+        assert_err_kind!(account_name("/projects/matrix"), ErrorKind::UserNotFound);
+        assert_err_kind!(account_name("/"), ErrorKind::UserNotFound);
+    }
+
+    #[test]
+    fn account_name_rules() {
+        use crate::AccountName;
+
+        for valid in [
+            "neo",
+            "trinity",
+            "agent-smith",
+            "the_oracle",
+            "n30",
+            "abcdefghijklmnopqrstuvwxyz012345",
+        ] {
+            let account_name: AccountName = valid.parse().unwrap();
+            assert!(account_name.is_valid(), "{valid}");
+            assert_eq!(AccountName::existing(valid).unwrap(), account_name);
+        }
+
+        // Not for new accounts, but existing ones may have such names
+        for legacy in [
+            "Neo",
+            "mr.anderson",
+            "github|1999",
+            "n",
+            "-neo-",
+            "x".repeat(33).as_str(),
+        ] {
+            assert_err_kind!(legacy.parse::<AccountName>(), ErrorKind::InvalidAccountName);
+            assert!(
+                !AccountName::existing(legacy).unwrap().is_valid(),
+                "{legacy}"
+            );
+        }
+
+        // Never account names
+        for invalid in [
+            "",
+            " ",
+            "neo smith",
+            "neo/admin",
+            "../neo",
+            "neo\n",
+            "x".repeat(256).as_str(),
+        ] {
+            assert_err_kind!(
+                invalid.parse::<AccountName>(),
+                ErrorKind::InvalidAccountName
+            );
+            assert_err_kind!(
+                AccountName::existing(invalid),
+                ErrorKind::InvalidAccountName
+            );
+        }
+    }
+
+    #[test]
+    fn account_name_from_json() {
+        use crate::AccountName;
+
+        let account_name: AccountName = serde_json::from_str(r#""neo""#).unwrap();
+        assert_eq!(account_name, "neo".parse().unwrap());
+
+        let legacy: AccountName = serde_json::from_str(r#""Neo""#).unwrap();
+        assert_eq!(legacy, AccountName::existing("Neo").unwrap());
+
+        for invalid in [r#""""#, r#""neo/admin""#, r#""neo smith""#] {
+            let error = serde_json::from_str::<AccountName>(invalid).unwrap_err();
+            assert!(
+                error.to_string().starts_with("invalid account name"),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn names_into_json() {
+        use crate::AccountName;
+
+        let project_name: ProjectName = "matrix".parse().unwrap();
+        let account_name: AccountName = "neo".parse().unwrap();
+
+        assert_eq!(
+            serde_json::Value::from(project_name.clone()),
+            serde_json::Value::String("matrix".to_string())
+        );
+        assert_eq!(
+            serde_json::Value::from(account_name.clone()),
+            serde_json::Value::String("neo".to_string())
+        );
+        assert_eq!(
+            serde_json::json!({ "project": project_name, "account": account_name }),
+            serde_json::json!({ "project": "matrix", "account": "neo" })
+        );
+    }
+
+    #[test]
+    fn project_name_from_json() {
+        let project_name: ProjectName = serde_json::from_str(r#""matrix""#).unwrap();
+        assert_eq!(project_name, "matrix".parse().unwrap());
+
+        let error = serde_json::from_str::<ProjectName>(r#""-matrix-""#).unwrap_err();
+        assert!(error.to_string().starts_with(&format!(
+            "{} `-matrix-`: {}",
+            crate::INVALID_PROJECT_NAME,
+            crate::ProjectNameRule::Ends
+        )));
+
+        assert!(serde_json::from_str::<ProjectName>("42").is_err());
+    }
+
+    #[test]
+    fn project_names_are_lowercased() {
+        let myapp: ProjectName = "myapp".parse().unwrap();
+
+        assert_eq!("MyApp".parse::<ProjectName>().unwrap(), myapp);
+        assert_eq!(
+            serde_json::from_str::<ProjectName>(r#""MYAPP""#).unwrap(),
+            myapp
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Host", "MyApp.shuttleapp.rs".parse().unwrap());
+        assert_eq!(ProjectName::try_from(&headers).unwrap(), myapp);
+
+        // Underscores are not turned into dashes: projects from before they
+        // were rejected can still be named, and no new one can be created
+        let my_app: ProjectName = "My_App".parse().unwrap();
+        assert_eq!(my_app.as_str(), "my_app");
+        assert!(!my_app.is_valid());
+        assert_ne!(my_app, "my-app".parse().unwrap());
+    }
+
+    #[test]
+    fn project_names_as_map_keys() {
+        use serde::{Deserialize, Serialize};
+        use serde_with::{As, DisplayFromStr, Same};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Deployments {
+            #[serde(with = "As::<HashMap<DisplayFromStr, Same>>")]
+            by_project: HashMap<ProjectName, u32>,
+        }
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let deployments = Deployments {
+            by_project: HashMap::from([(matrix.clone(), 3), ("reloaded".parse().unwrap(), 1)]),
+        };
+
+        let serialized = serde_json::to_value(&deployments).unwrap();
+        assert_eq!(
+            serialized,
+            serde_json::json!({ "by_project": { "matrix": 3, "reloaded": 1 } })
+        );
+        assert_eq!(
+            serde_json::from_value::<Deployments>(serialized).unwrap(),
+            deployments
+        );
+
+        // The keys are lowercased and checked like any other project name
+        let deployments: Deployments =
+            serde_json::from_str(r#"{ "by_project": { "Matrix": 3 } }"#).unwrap();
+        assert_eq!(deployments.by_project[&matrix], 3);
+        assert!(
+            serde_json::from_str::<Deployments>(r#"{ "by_project": { "-matrix-": 3 } }"#).is_err()
+        );
+
+        // Maps keyed by it directly round-trip through JSON too
+        let by_project: HashMap<ProjectName, u32> =
+            serde_json::from_value(serde_json::json!({ "Matrix": 3 })).unwrap();
+        assert_eq!(by_project[&matrix], 3);
+        assert_eq!(
+            serde_json::to_value(&by_project).unwrap(),
+            serde_json::json!({ "matrix": 3 })
+        );
+    }
+
+    #[test]
+    fn names_are_tagged_in_messages() {
+        let project: ProjectName = "matrix".parse().unwrap();
+        let account: AccountName = "neo".parse().unwrap();
+
+        assert_eq!(format!("{project:#}"), "project:matrix");
+        assert_eq!(format!("{account:#}"), "account:neo");
+
+        // The plain form is what gets parsed back, so it stays bare
+        assert_eq!(project.to_string(), "matrix");
+        assert_eq!(account.to_string(), "neo");
+        assert_eq!(project.to_string().parse::<ProjectName>().unwrap(), project);
+        assert_eq!(account.to_string().parse::<AccountName>().unwrap(), account);
+        assert_eq!(serde_json::to_value(&project).unwrap(), "matrix");
+        assert_eq!(serde_json::to_value(&account).unwrap(), "neo");
+    }
+
+    #[tokio::test]
+    async fn project_name_from_path() {
+        use shuttle_common::models::error::ApiError;
+        use tower::Service;
+
+        use crate::ErrorKind;
+
+        let mut router = Router::new()
+            .route(
+                "/projects/:project_name",
+                get(|project_name: ProjectName| async move { project_name.to_string() }),
+            )
+            .route(
+                "/projects/:project_name/domains/:fqdn",
+                get(|project_name: ProjectName| async move { project_name.to_string() }),
+            )
+            .route(
+                "/accounts/:account_name",
+                get(|project_name: ProjectName| async move { project_name.to_string() }),
+            );
+
+        let mut get_status = |uri: &'static str| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let response = router.call(request);
+            async move { response.await.unwrap().status() }
+        };
+
+        assert_eq!(get_status("/projects/matrix").await, StatusCode::OK);
+        assert_eq!(
+            get_status("/projects/-matrix-").await,
+            ApiError::from(ErrorKind::InvalidProjectName).status()
+        );
+        assert_eq!(
+            get_status("/projects/matrix/domains/matrix.com").await,
+            StatusCode::OK
+        );
+        // The route is set up wrong
+        assert_eq!(
+            get_status("/accounts/neo").await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        // The name is found by its key, wherever it is in the path
+        for uri in ["/projects/matrix", "/projects/matrix/domains/zion.com"] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let body = router.call(request).await.unwrap().into_body();
+            assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "matrix");
+        }
+    }
+
+    #[test]
+    fn reserved_project_names() {
+        use crate::ErrorKind;
+
+        let admin: ProjectName = "admin".parse().unwrap();
+
+        assert!(admin.is_reserved(&["ADMIN"]));
+        assert!(admin.is_reserved(&["www", "Admin"]));
+        assert!(!admin.is_reserved(&["admins", "dmin"]));
+        assert!(!admin.is_reserved(&[]));
+
+        assert_err_kind!(
+            ProjectName::from_str_with_reserved("admin", &["www", "ADMIN"]),
+            ErrorKind::InvalidProjectName
+        );
+        assert_eq!(
+            ProjectName::from_str_with_reserved("matrix", &["www", "ADMIN"]).unwrap(),
+            "matrix".parse().unwrap()
+        );
+        assert_eq!(
+            ProjectName::from_str_with_reserved("admin", &[]).unwrap(),
+            admin
+        );
+
+        // Invalid names are still invalid when nothing is reserved
+        assert_err_kind!(
+            ProjectName::from_str_with_reserved("-admin-", &[]),
+            ErrorKind::InvalidProjectName
+        );
+    }
+
+    #[test]
+    fn project_name_rules() {
+        use crate::ProjectNameRule;
+
+        let violation = |name: &str| name.parse::<ProjectName>().unwrap().rule_violation();
+
+        for valid in [
+            "matrix",
+            "zion-1",
+            "neo",
+            "7th-heaven",
+            "a1b",
+            &"a".repeat(63),
+        ] {
+            assert_eq!(violation(valid), None, "{valid}");
+        }
+
+        assert_eq!(violation("my_app"), Some(ProjectNameRule::Characters('_')));
+        assert_eq!(violation("ab"), Some(ProjectNameRule::Length));
+        assert_eq!(violation(&"a".repeat(64)), Some(ProjectNameRule::Length));
+        // Which parsing already rules out
+        assert_eq!(
+            ProjectName("matrix-".to_string()).rule_violation(),
+            Some(ProjectNameRule::Ends)
+        );
+        assert_eq!(
+            violation("xn--matrix"),
+            Some(ProjectNameRule::ConsecutiveHyphens)
+        );
+        assert_eq!(violation("1999"), Some(ProjectNameRule::AllDigits));
+
+        assert_eq!(
+            ProjectNameRule::Characters('_').to_string(),
+            "it can only have lowercase letters, digits and dashes, not `_`"
+        );
+        assert_eq!(
+            ProjectNameRule::Length.to_string(),
+            "it must have 3 to 63 characters"
+        );
+    }
+
+    /// Whether `name` is a label of a hostname, as RFC 1123 has it
+    fn is_dns_label(name: &str) -> bool {
+        (1..=63).contains(&name.len())
+            && name
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+            && !name.starts_with('-')
+            && !name.ends_with('-')
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn valid_project_names_are_dns_labels(name in "[a-zA-Z0-9_.-]{0,70}") {
+            if let Ok(project_name) = name.parse::<ProjectName>() {
+                if project_name.is_valid() {
+                    proptest::prop_assert!(is_dns_label(project_name.as_str()));
+                    proptest::prop_assert!(!project_name.as_str().contains("--"));
+                }
+            }
+        }
+
+        #[test]
+        fn dns_labels_make_valid_project_names(name in "[a-z]{2}([a-z0-9]|-[a-z0-9]){1,30}") {
+            // Unless they happen to spell something rude
+            if let Ok(project_name) = name.parse::<ProjectName>() {
+                proptest::prop_assert!(project_name.is_valid(), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn generated_project_names_are_valid() {
+        for _ in 0..1000 {
+            let name = ProjectName::generate_unique();
+
+            assert!(name.as_str().starts_with("test-"));
+            assert!(name.is_valid());
+            assert_eq!(name.as_str().parse::<ProjectName>().unwrap(), name);
+        }
+    }
+
+    /// Takes `sleep` to step out of, declaring it takes `limit` at most
+    struct Sleepy {
+        sleep: Duration,
+        limit: Option<Duration>,
+    }
+
+    #[async_trait]
+    impl State<()> for Sleepy {
+        type Next = ();
+
+        type Error = Infallible;
+
+        async fn next(self, _ctx: &()) -> Result<Self::Next, Self::Error> {
+            tokio::time::sleep(self.sleep).await;
+            Ok(())
+        }
+
+        fn timeout_duration(&self) -> Option<Duration> {
+            self.limit
+        }
+    }
+
+    #[tokio::test]
+    async fn next_with_timeout_applies_declared_timeout() {
+        let quick = Sleepy {
+            sleep: Duration::from_millis(10),
+            limit: Some(Duration::from_secs(5)),
+        };
+        assert_eq!(next_with_timeout(quick, &()).await, Ok(Ok(())));
+
+        let slow = Sleepy {
+            sleep: Duration::from_secs(10),
+            limit: Some(Duration::from_millis(50)),
+        };
+        assert_eq!(
+            next_with_timeout(slow, &()).await,
+            Err(Duration::from_millis(50))
+        );
+
+        let unbounded = Sleepy {
+            sleep: Duration::from_millis(100),
+            limit: None,
+        };
+        assert_eq!(next_with_timeout(unbounded, &()).await, Ok(Ok(())));
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Countdown {
+        Counting(u8),
+        Failing,
+        Stuck,
+        Done,
+        Failed,
+    }
+
+    #[async_trait]
+    impl State<()> for Countdown {
+        type Next = Self;
+
+        type Error = Infallible;
+
+        async fn next(self, _ctx: &()) -> Result<Self::Next, Self::Error> {
+            Ok(match self {
+                Self::Counting(0) => Self::Done,
+                Self::Counting(left) => Self::Counting(left - 1),
+                Self::Failing => Self::Failed,
+                Self::Stuck => {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    Self::Stuck
+                }
+                done => done,
+            })
+        }
+    }
+
+    impl EndState<()> for Countdown {
+        fn is_done(&self) -> bool {
+            matches!(self, Self::Done | Self::Failed)
+        }
+    }
+
+    impl TryState for Countdown {
+        type ErrorVariant = &'static str;
+
+        fn into_result(self) -> Result<Self, Self::ErrorVariant> {
+            match self {
+                Self::Failed => Err("failed"),
+                otherwise => Ok(otherwise),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn into_final_resolves_to_the_state_it_ends_in() {
+        assert_eq!(
+            Countdown::Counting(3).into_final(&()).await,
+            Ok(Countdown::Done)
+        );
+        assert_eq!(Countdown::Done.into_final(&()).await, Ok(Countdown::Done));
+        assert_eq!(Countdown::Failing.into_final(&()).await, Err("failed"));
+        assert_eq!(Countdown::Failed.into_final(&()).await, Err("failed"));
+    }
+
+    #[tokio::test]
+    async fn into_final_with_timeout_gives_up_on_states_which_do_not_end() {
+        let limit = Duration::from_millis(50);
+
+        assert_eq!(
+            Countdown::Counting(3)
+                .into_final_with_timeout(&(), limit)
+                .await,
+            Ok(Ok(Countdown::Done))
+        );
+        assert_eq!(
+            Countdown::Stuck.into_final_with_timeout(&(), limit).await,
+            Err(limit)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
     async fn end_to_end() {
         let world = World::new().await;
+        let matrix = ProjectName::generate_unique();
         let service = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
         let worker = Worker::new();
 
@@ -799,7 +2452,7 @@ pub mod tests {
         println!("Creating the matrix project");
         api_client
             .request(
-                Request::post("/projects/matrix")
+                Request::post(format!("/projects/{matrix}"))
                     .with_header(&authorization)
                     .header("Content-Type", "application/json")
                     .body("{\"idle_minutes\": 3}".into())
@@ -814,7 +2467,7 @@ pub mod tests {
         timed_loop!(wait: 1, max: 12, {
             let project: project::Response = api_client
                 .request(
-                    Request::get("/projects/matrix")
+                    Request::get(format!("/projects/{matrix}"))
                         .with_header(&authorization)
                         .body(Body::empty())
                         .unwrap(),
@@ -836,7 +2489,7 @@ pub mod tests {
         println!("get matrix project status");
         api_client
             .request(
-                Request::get("/projects/matrix/status")
+                Request::get(format!("/projects/{matrix}/status"))
                     .with_header(&authorization)
                     .body(Body::empty())
                     .unwrap(),
@@ -848,7 +2501,7 @@ pub mod tests {
         println!("delete matrix project");
         api_client
             .request(
-                Request::delete("/projects/matrix")
+                Request::delete(format!("/projects/{matrix}"))
                     .with_header(&authorization)
                     .body(Body::empty())
                     .unwrap(),
@@ -860,7 +2513,7 @@ pub mod tests {
         timed_loop!(wait: 1, max: 20, {
             let resp = api_client
                 .request(
-                    Request::get("/projects/matrix")
+                    Request::get(format!("/projects/{matrix}"))
                         .with_header(&authorization)
                         .body(Body::empty())
                         .unwrap(),
@@ -876,7 +2529,7 @@ pub mod tests {
         // Attempting to delete already Destroyed project will return Destroyed
         api_client
             .request(
-                Request::delete("/projects/matrix")
+                Request::delete(format!("/projects/{matrix}"))
                     .with_header(&authorization)
                     .body(Body::empty())
                     .unwrap(),
@@ -890,4 +2543,59 @@ pub mod tests {
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    #[ignore]
+    async fn world_without_leftovers_drops_quietly() {
+        let world = World::new().await;
+
+        assert_no_orphan_containers(&world).await;
+        drop(world);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn world_removes_leaked_containers() {
+        use bollard::container::{Config, CreateContainerOptions};
+        use bollard::errors::Error as DockerError;
+        use std::panic::AssertUnwindSafe;
+
+        let world = World::new().await;
+        let docker = world.docker.clone();
+        let args = world.args();
+
+        let name = format!("{}leaked_run", args.prefix);
+        docker
+            .create_container(
+                Some(CreateContainerOptions {
+                    name: name.as_str(),
+                    platform: None,
+                }),
+                Config {
+                    image: Some(args.image.as_str()),
+                    labels: Some(HashMap::from([("shuttle.prefix", args.prefix.as_str())])),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let checked = AssertUnwindSafe(assert_no_orphan_containers(&world))
+            .catch_unwind()
+            .await;
+        let message = *checked.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains(&name), "{message}");
+
+        let dropped = std::panic::catch_unwind(AssertUnwindSafe(|| drop(world)));
+        let message = *dropped.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains(&name), "{message}");
+
+        assert!(matches!(
+            docker.inspect_container(&name, None).await,
+            Err(DockerError::DockerResponseServerError {
+                status_code: 404,
+                ..
+            })
+        ));
+    }
 }
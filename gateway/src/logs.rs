@@ -0,0 +1,336 @@
+//! The gateway's own logs, as they happen, for `GET /admin/logs`
+//!
+//! [`GatewayLogs`] is a tracing layer keeping the latest events it sees,
+//! after they went through the gateway's filter, and handing them to
+//! whoever tails them. Those falling too far behind are dropped rather
+//! than holding the gateway back.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use futures::{stream, Stream, StreamExt};
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// How many of the latest events are kept for those starting to tail the
+/// logs, and how far behind them a tail can fall before it is dropped
+pub const GATEWAY_LOGS_CAPACITY: usize = 1000;
+
+/// An event logged by the gateway
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEvent {
+    pub at: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_level")]
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl LogEvent {
+    /// The last event of a tail which fell `missed` events behind
+    fn lagged(missed: u64) -> Self {
+        Self {
+            at: Utc::now(),
+            level: Level::WARN,
+            target: module_path!().to_string(),
+            message: "dropped for falling behind the logs".to_string(),
+            fields: BTreeMap::from([("missed".to_string(), Value::from(missed))]),
+        }
+    }
+}
+
+fn serialize_level<S: Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(level.as_str())
+}
+
+/// The latest events, and where the new ones go. The two are only ever
+/// touched together so that a new tail gets every event once.
+struct Inner {
+    recent: VecDeque<LogEvent>,
+    sender: broadcast::Sender<LogEvent>,
+}
+
+/// The tracing layer keeping the gateway's logs, and the handle to tail
+/// them. Clones share the same logs.
+#[derive(Clone)]
+pub struct GatewayLogs {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for GatewayLogs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GatewayLogs {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(GATEWAY_LOGS_CAPACITY);
+
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                recent: VecDeque::with_capacity(GATEWAY_LOGS_CAPACITY),
+                sender,
+            })),
+        }
+    }
+
+    fn push(&self, event: LogEvent) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.recent.len() == GATEWAY_LOGS_CAPACITY {
+            inner.recent.pop_front();
+        }
+        inner.recent.push_back(event.clone());
+
+        // Nobody tailing the logs is not an error
+        let _ = inner.sender.send(event);
+    }
+
+    /// The latest events at `level` or more severe, followed by those to
+    /// come. A tail which falls [`GATEWAY_LOGS_CAPACITY`] events behind
+    /// ends with a warning of how many it missed.
+    pub fn tail(&self, level: Level) -> impl Stream<Item = LogEvent> + Send + 'static {
+        let (recent, receiver) = {
+            let inner = self.inner.lock().unwrap();
+            (inner.recent.clone(), inner.sender.subscribe())
+        };
+
+        let recent = recent.into_iter().filter(move |event| event.level <= level);
+        let live = stream::unfold(Some(receiver), move |receiver| async move {
+            let mut receiver = receiver?;
+
+            loop {
+                match receiver.recv().await {
+                    Ok(event) if event.level <= level => return Some((event, Some(receiver))),
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        return Some((LogEvent::lagged(missed), None))
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        stream::iter(recent).chain(live)
+    }
+}
+
+impl<S: Subscriber> Layer<S> for GatewayLogs {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = FieldsVisitor::default();
+        event.record(&mut visitor);
+
+        self.push(LogEvent {
+            at: Utc::now(),
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+        });
+    }
+}
+
+#[derive(Default)]
+struct FieldsVisitor {
+    message: String,
+    fields: BTreeMap<String, Value>,
+}
+
+impl FieldsVisitor {
+    fn record(&mut self, field: &Field, value: Value) {
+        match (field.name(), value) {
+            ("message", Value::String(message)) => self.message = message,
+            (name, value) => {
+                self.fields.insert(name.to_string(), value);
+            }
+        }
+    }
+}
+
+impl Visit for FieldsVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, Value::from(format!("{value:?}")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::response::sse::{Event as SseEvent, Sse};
+    use axum::response::IntoResponse;
+    use hyper::body::HttpBody;
+    use serde_json::json;
+    use tracing::{debug, error, info, warn, Dispatch};
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    fn logging_to(logs: &GatewayLogs) -> Dispatch {
+        Dispatch::new(tracing_subscriber::registry().with(logs.clone()))
+    }
+
+    async fn next(tail: &mut (impl Stream<Item = LogEvent> + Unpin)) -> Option<LogEvent> {
+        tokio::time::timeout(Duration::from_secs(1), tail.next())
+            .await
+            .expect("to get the next event in time")
+    }
+
+    #[tokio::test]
+    async fn events_are_tailed() {
+        let logs = GatewayLogs::new();
+        let subscriber = logging_to(&logs);
+
+        tracing::dispatcher::with_default(&subscriber, || {
+            info!(project = "matrix", retries = 3, "before tailing");
+        });
+
+        let mut tail = Box::pin(logs.tail(Level::TRACE));
+
+        tracing::dispatcher::with_default(&subscriber, || {
+            debug!(ok = true, "after tailing");
+        });
+
+        let event = next(&mut tail).await.unwrap();
+        assert_eq!(event.level, Level::INFO);
+        assert_eq!(event.target, module_path!());
+        assert_eq!(event.message, "before tailing");
+        assert_eq!(
+            event.fields,
+            BTreeMap::from([
+                ("project".to_string(), json!("matrix")),
+                ("retries".to_string(), json!(3)),
+            ])
+        );
+
+        let event = next(&mut tail).await.unwrap();
+        assert_eq!(event.message, "after tailing");
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "at": event.at,
+                "level": "DEBUG",
+                "target": module_path!(),
+                "message": "after tailing",
+                "fields": { "ok": true },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn tails_filter_on_level() {
+        let logs = GatewayLogs::new();
+        let subscriber = logging_to(&logs);
+
+        tracing::dispatcher::with_default(&subscriber, || {
+            info!("recent info");
+            warn!("recent warn");
+        });
+
+        let mut tail = Box::pin(logs.tail(Level::WARN));
+
+        tracing::dispatcher::with_default(&subscriber, || {
+            debug!("live debug");
+            error!("live error");
+        });
+
+        assert_eq!(next(&mut tail).await.unwrap().message, "recent warn");
+        assert_eq!(next(&mut tail).await.unwrap().message, "live error");
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_events_are_kept() {
+        let logs = GatewayLogs::new();
+        let subscriber = logging_to(&logs);
+
+        tracing::dispatcher::with_default(&subscriber, || {
+            for i in 0..GATEWAY_LOGS_CAPACITY + 10 {
+                info!(i, "event");
+            }
+        });
+
+        let mut tail = Box::pin(logs.tail(Level::TRACE));
+        let first = next(&mut tail).await.unwrap();
+        assert_eq!(first.fields["i"], json!(10));
+    }
+
+    #[tokio::test]
+    async fn slow_tails_are_dropped() {
+        let logs = GatewayLogs::new();
+        let subscriber = logging_to(&logs);
+
+        let mut tail = Box::pin(logs.tail(Level::TRACE));
+
+        tracing::dispatcher::with_default(&subscriber, || {
+            for i in 0..GATEWAY_LOGS_CAPACITY + 10 {
+                info!(i, "event");
+            }
+        });
+
+        let lagged = next(&mut tail).await.unwrap();
+        assert_eq!(lagged.level, Level::WARN);
+        assert_eq!(lagged.fields["missed"], json!(10));
+        assert_eq!(next(&mut tail).await, None);
+
+        // Others can still tail the logs
+        let mut tail = Box::pin(logs.tail(Level::TRACE));
+        assert_eq!(next(&mut tail).await.unwrap().fields["i"], json!(10));
+    }
+
+    #[tokio::test]
+    async fn events_are_streamed_as_server_sent_events() {
+        let logs = GatewayLogs::new();
+        let subscriber = logging_to(&logs);
+
+        let tail = logs
+            .tail(Level::INFO)
+            .map(|event| SseEvent::default().json_data(event));
+        let mut body = Sse::new(tail).into_response().into_body();
+
+        tracing::dispatcher::with_default(&subscriber, || {
+            info!(project = "matrix", "streamed");
+        });
+
+        let chunk = tokio::time::timeout(Duration::from_secs(1), body.data())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let chunk = std::str::from_utf8(&chunk).unwrap();
+        let data = chunk.strip_prefix("data:").unwrap().trim();
+        let event: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(event["message"], "streamed");
+        assert_eq!(event["fields"]["project"], "matrix");
+    }
+}
@@ -1,18 +1,24 @@
-use clap::Parser;
 use futures::prelude::*;
 
 use shuttle_common::backends::tracing::setup_tracing;
 use shuttle_gateway::acme::{AcmeClient, CustomDomain};
 use shuttle_gateway::api::latest::{ApiBuilder, SVC_DEGRADED_THRESHOLD};
 use shuttle_gateway::args::StartArgs;
-use shuttle_gateway::args::{Args, Commands, UseTls};
+use shuttle_gateway::args::{Args, Commands, RestoreArgs, RewrapArgs, UseTls};
+use shuttle_gateway::backup;
+use shuttle_gateway::encryption;
+use shuttle_gateway::logs::GatewayLogs;
 use shuttle_gateway::proxy::UserServiceBuilder;
+use shuttle_gateway::reconciler::Reconciler;
+use shuttle_gateway::scheduler::Scheduler;
 use shuttle_gateway::service::{GatewayService, MIGRATIONS};
-use shuttle_gateway::task;
+use shuttle_gateway::task::{Operation, Origin, Priority, Work};
+use shuttle_gateway::telemetry;
+use shuttle_gateway::throttle::TokenBucket;
 use shuttle_gateway::tls::make_tls_acceptor;
-use shuttle_gateway::worker::{Worker, WORKER_QUEUE_SIZE};
+use shuttle_gateway::worker::{watchdog, Worker, HEALTH_EXECUTOR};
 use sqlx::migrate::MigrateDatabase;
-use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
 use sqlx::{Sqlite, SqlitePool};
 use std::io::{self, Cursor};
 
@@ -21,14 +27,17 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+use tracing_subscriber::prelude::*;
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> io::Result<()> {
-    let args = Args::parse();
+    let args = Args::load();
 
     trace!(args = ?args, "parsed args");
 
-    setup_tracing(tracing_subscriber::registry(), "gateway");
+    // Kept for `/admin/logs`
+    let logs = GatewayLogs::new();
+    setup_tracing(tracing_subscriber::registry().with(logs.clone()), "gateway");
 
     let db_path = args.state.join("gateway.sqlite");
     let db_uri = db_path.to_str().unwrap();
@@ -49,47 +58,213 @@ async fn main() -> io::Result<()> {
         .journal_mode(SqliteJournalMode::Wal)
         .synchronous(SqliteSynchronous::Normal);
 
-    let db = SqlitePool::connect_with(sqlite_options).await.unwrap();
+    // Give up quickly on getting a connection so that an unavailable
+    // state store is reported as such instead of hanging requests
+    let db = SqlitePoolOptions::new()
+        .acquire_timeout(Duration::from_secs(5))
+        .connect_with(sqlite_options)
+        .await
+        .unwrap();
     MIGRATIONS.run(&db).await.unwrap();
 
+    // Collected before the args are taken apart, to be shown as is at
+    // `/admin/config`
+    let config: Vec<_> = args.into_iter().collect();
+    info!(
+        "effective configuration: {}",
+        config
+            .iter()
+            .map(|(name, value)| format!("{name}={value:?}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
     match args.command {
-        Commands::Start(start_args) => start(db, args.state, start_args).await,
+        Commands::Start(start_args) => start(db, args.state, start_args, config, logs).await,
+        Commands::Restore(restore_args) => restore(db, restore_args).await,
+        Commands::Rewrap(rewrap_args) => rewrap(db, rewrap_args).await,
     }
 }
 
-async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
-    let gateway = Arc::new(GatewayService::init(args.context.clone(), db, fs).await);
+async fn restore(db: SqlitePool, args: RestoreArgs) -> io::Result<()> {
+    backup::restore(&db, &args.from)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
 
-    let worker = Worker::new();
+async fn rewrap(db: SqlitePool, args: RewrapArgs) -> io::Result<()> {
+    encryption::rewrap(&db, args.master_key.as_ref(), &args.new_master_key)
+        .await
+        .map(|_| ())
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
 
-    let sender = worker.sender();
+/// How many tasks a worker running `concurrency` of them at the same time
+/// can start on in a burst, after a quiet spell
+fn burst(concurrency: usize) -> u32 {
+    u32::try_from(concurrency).unwrap_or(u32::MAX)
+}
 
-    let worker_handle = tokio::spawn(
-        worker
-            .start()
-            .map_ok(|_| info!("worker terminated successfully"))
-            .map_err(|err| error!("worker error: {}", err)),
-    );
+/// Resolves once the gateway is asked to stop, with a Ctrl-C or a
+/// SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 
-    for (project_name, _) in gateway
-        .iter_projects()
+    info!("shutdown signal received");
+}
+
+async fn start(
+    db: SqlitePool,
+    fs: PathBuf,
+    args: StartArgs,
+    config: Vec<(&'static str, String)>,
+    logs: GatewayLogs,
+) -> io::Result<()> {
+    telemetry::mark_started();
+
+    // Refuse to start if the secrets cannot be read back
+    encryption::check_master_key(&db, args.context.master_key.as_ref())
         .await
-        .expect("could not list projects")
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    let gateway = Arc::new(GatewayService::init(args.context.clone(), db, fs).await);
+
+    // Which of the projects should keep the name is not for the gateway
+    // to decide, so they are left for an admin to sort out
+    for names in gateway
+        .project_name_collisions()
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
     {
-        gateway
-            .clone()
-            .new_task()
-            .project(project_name)
-            .and_then(task::refresh())
-            .send(&sender)
-            .await
-            .expect("to refresh old projects");
+        error!(
+            ?names,
+            "project names collide once lowercased, only the lowercase one can be reached"
+        );
+    }
+
+    let mut worker = Worker::new()
+        .with_concurrency(args.worker_concurrency)
+        .with_queue_size(args.worker_queue_size)
+        .with_metrics(gateway.metrics().clone())
+        .with_shutdown(
+            shutdown_signal(),
+            Duration::from_secs(args.drain_deadline_secs),
+        );
+    if let Some(rate) = args.worker_rate_limit {
+        worker = worker.with_rate_limit(TokenBucket::new(rate, burst(args.worker_concurrency)));
     }
 
+    let sender = worker.sender();
+    let worker_status = worker.status();
+
+    tokio::spawn(watchdog(
+        worker_status.heartbeat().clone(),
+        Duration::from_secs(args.worker_stall_threshold_secs),
+        args.abort_stalled_tasks,
+    ));
+
+    // Health checks have a worker of their own, for checking on every
+    // project not to hold up lifecycle work. Both end up taking the same
+    // per-project lock, so that a check never races a restart.
+    let health_worker = Worker::new()
+        .with_executor(HEALTH_EXECUTOR)
+        .with_concurrency(args.health_worker_concurrency)
+        .with_queue_size(args.health_worker_queue_size)
+        .with_rate_limit(TokenBucket::new(
+            args.health_worker_rate_limit,
+            burst(args.health_worker_concurrency),
+        ))
+        .with_metrics(gateway.metrics().clone())
+        .with_shutdown(
+            shutdown_signal(),
+            Duration::from_secs(args.drain_deadline_secs),
+        );
+
+    let health_sender = health_worker.sender();
+
+    tokio::spawn(watchdog(
+        health_worker.status().heartbeat().clone(),
+        Duration::from_secs(args.worker_stall_threshold_secs),
+        args.abort_stalled_tasks,
+    ));
+
+    // The health checks it did not get to are not worth picking up again
+    // on the next start, which checks on every project anyway
+    tokio::spawn(async move {
+        if let Err(err) = health_worker.start().await {
+            error!("health worker error: {}", err);
+        }
+    });
+
+    let worker_handle = tokio::spawn({
+        let gateway = Arc::clone(&gateway);
+        async move {
+            let worker = match worker.start().await {
+                Ok(worker) => worker,
+                Err(err) => {
+                    error!("worker error: {}", err);
+                    return;
+                }
+            };
+
+            // Whatever the worker could not get through is picked up
+            // again on the next start
+            match gateway.persist_pending_work(worker.unfinished()).await {
+                Ok(persisted) => info!(persisted, "worker terminated successfully"),
+                Err(err) => error!(
+                    error = %err,
+                    unfinished = worker.unfinished().len(),
+                    "could not persist the work the worker left unfinished"
+                ),
+            }
+        }
+    });
+
+    // Spread over a while so as not to flood the docker daemon, with the
+    // API up in the meantime to report how far along it is
+    tokio::spawn({
+        let gateway = Arc::clone(&gateway);
+        let sender = sender.clone();
+        let window = Duration::from_secs(args.refresh_window_secs);
+        async move {
+            for (project_name, err) in gateway
+                .refresh_projects(window)
+                .await
+                .expect("could not refresh old projects")
+            {
+                warn!(%project_name, error = %err, "could not refresh project");
+            }
+
+            if let Err(err) = gateway.resume_pending_work(&sender).await {
+                error!(error = %err, "could not resume the work left over from the last shutdown");
+            }
+        }
+    });
+
     // Every 60 secs go over all `::Ready` projects and check their health.
     let ambulance_handle = tokio::spawn({
         let gateway = Arc::clone(&gateway);
-        let sender = sender.clone();
+        let sender = health_sender;
+        let concurrency = args.health_worker_concurrency.max(1);
         async move {
             let mut interval = tokio::time::interval(Duration::from_secs(60));
             interval.tick().await; // first tick is immediate
@@ -97,7 +272,8 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
             loop {
                 interval.tick().await;
 
-                if sender.capacity() < WORKER_QUEUE_SIZE - SVC_DEGRADED_THRESHOLD {
+                let degraded_at = sender.max_capacity().saturating_sub(SVC_DEGRADED_THRESHOLD);
+                if sender.capacity() < degraded_at {
                     // If degraded, don't stack more health checks.
                     warn!(
                         sender.capacity = sender.capacity(),
@@ -106,31 +282,115 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
                     continue;
                 }
 
-                if let Ok(projects) = gateway.iter_projects().await {
+                if let Ok(projects) = gateway.iter_projects_to_reconcile().await {
                     let span = info_span!(
                         "running health checks",
                         healthcheck.num_projects = projects.len()
                     );
 
-                    let gateway = gateway.clone();
-                    let sender = sender.clone();
-                    async move {
-                        for (project_name, _) in projects {
-                            if let Ok(handle) = gateway
-                                .new_task()
-                                .project(project_name)
-                                .and_then(task::check_health())
-                                .send(&sender)
-                                .await
-                            {
-                                // We wait for the check to be done before
-                                // queuing up the next one.
-                                handle.await
+                    // Up to `concurrency` checks are queued up at a time,
+                    // with the next one queued up as soon as one is done
+                    stream::iter(projects)
+                        .for_each_concurrent(concurrency, |(project_name, _)| {
+                            let gateway = gateway.clone();
+                            let sender = sender.clone();
+                            async move {
+                                if let Ok(handle) = gateway
+                                    .new_task()
+                                    .work(Work::new(
+                                        project_name,
+                                        Operation::Refresh,
+                                        Origin::Health,
+                                    ))
+                                    .priority(Priority::Health)
+                                    .send(&sender)
+                                    .await
+                                {
+                                    handle.await
+                                }
                             }
-                        }
-                    }
-                    .instrument(span)
-                    .await;
+                        })
+                        .instrument(span)
+                        .await;
+                }
+            }
+        }
+    });
+
+    // Re-drive the projects which got stuck in the middle of a transition,
+    // say because the gateway went down while they were starting
+    tokio::spawn(
+        Reconciler::new(
+            Arc::clone(&gateway),
+            sender.clone(),
+            Duration::from_secs(args.reconcile_stuck_after_secs),
+        )
+        .run(Duration::from_secs(args.reconcile_interval_secs)),
+    );
+
+    // Queue up the work scheduled for later once it is due, whether it
+    // was scheduled before or after the gateway last started
+    tokio::spawn(
+        Scheduler::new(Arc::clone(&gateway), sender.clone())
+            .run(Duration::from_secs(args.scheduler_interval_secs)),
+    );
+
+    // Every 5 minutes, recount the projects in each state to correct any
+    // drift in the gauges. The first count happens straight away so that
+    // the gauges are right after a restart too.
+    tokio::spawn({
+        let gateway = Arc::clone(&gateway);
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5 * 60));
+
+            loop {
+                interval.tick().await;
+
+                if let Err(err) = gateway.recount_project_states().await {
+                    warn!(error = %err, "failed to recount the projects in each state");
+                }
+            }
+        }
+    });
+
+    // Every hour, purge the destroyed and archived projects which are
+    // past their retention window
+    tokio::spawn({
+        let gateway = Arc::clone(&gateway);
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+
+            loop {
+                interval.tick().await;
+
+                if let Err(err) = gateway
+                    .purge_expired()
+                    .instrument(info_span!("purging expired projects"))
+                    .await
+                {
+                    error!(error = %err, "failed to purge expired projects");
+                }
+            }
+        }
+    });
+
+    // Every hour, look for projects which have been errored for a long
+    // time and notify their owners or archive them.
+    tokio::spawn({
+        let gateway = Arc::clone(&gateway);
+        let sender = sender.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+
+            loop {
+                interval.tick().await;
+
+                if let Err(err) = gateway
+                    .sweep_stale_errored_projects(&sender)
+                    .instrument(info_span!("sweeping stale errored projects"))
+                    .await
+                {
+                    error!(error = %err, "failed to sweep stale errored projects");
                 }
             }
         }
@@ -141,7 +401,14 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
     let mut api_builder = ApiBuilder::new()
         .with_service(Arc::clone(&gateway))
         .with_sender(sender.clone())
-        .binding_to(args.control);
+        .with_worker_status(worker_status)
+        .with_config(config)
+        .with_logs(logs)
+        .binding_to(args.control)
+        .with_bind_retry(
+            args.api_bind_retry,
+            Duration::from_secs(args.api_bind_retry_delay),
+        );
 
     let mut user_builder = UserServiceBuilder::new()
         .with_service(Arc::clone(&gateway))
@@ -164,7 +431,10 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
             certificate,
             private_key,
             ..
-        } in gateway.iter_custom_domains().await.unwrap()
+        } in gateway
+            .iter_custom_domains()
+            .await
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
         {
             let mut buf = Vec::new();
             buf.extend(certificate.as_bytes());
@@ -193,6 +463,7 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
         .with_default_routes()
         .with_auth_service(args.context.auth_uri)
         .with_default_traces()
+        .with_cors(Duration::from_secs(args.cors_max_age))
         .serve();
 
     let user_handle = user_builder.serve();
@@ -201,7 +472,13 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
 
     tokio::select!(
         _ = worker_handle => info!("worker handle finished"),
-        _ = api_handle => error!("api handle finished"),
+        res = api_handle => match res {
+            Ok(()) => error!("api handle finished"),
+            Err(err) => {
+                error!(error = %err, "api server failed, giving up");
+                return Err(io::Error::new(io::ErrorKind::Other, err));
+            }
+        },
         _ = user_handle => error!("user handle finished"),
         _ = ambulance_handle => error!("ambulance handle finished"),
     );
@@ -0,0 +1,667 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::task::Priority;
+use crate::telemetry;
+
+/// The upper bounds, in seconds, of the buckets task durations are
+/// counted in
+const TASK_DURATION_BUCKETS: [f64; 9] = [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// How long things took, counted in [`TASK_DURATION_BUCKETS`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// How many observations fell in each bucket, and not in the ones
+    /// before it
+    pub buckets: [u64; TASK_DURATION_BUCKETS.len()],
+    pub sum: Duration,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, took: Duration) {
+        let secs = took.as_secs_f64();
+        if let Some(bucket) = TASK_DURATION_BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += took;
+        self.count += 1;
+    }
+}
+
+/// The gauges a single executor keeps track of
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ExecutorGauges {
+    /// Number of tasks the executor is running right now
+    in_flight: i64,
+    /// Total time tasks waited to be run, and how many tasks that is
+    queue_wait: (Duration, u64),
+    /// Number of tasks waiting in each lane of the queue
+    queue_depth: BTreeMap<Priority, usize>,
+    /// Number of tasks the executor took in, and started on
+    enqueued: u64,
+    dequeued: u64,
+}
+
+/// The gauges the gateway keeps track of, rendered in the Prometheus
+/// text format at `/admin/metrics`
+#[derive(Clone, Default)]
+pub struct GatewayMetrics {
+    /// Number of projects in each state, keyed by [`Project::label`]
+    ///
+    /// [`Project::label`]: crate::project::Project::label
+    project_states: Arc<Mutex<BTreeMap<String, i64>>>,
+    /// Number of rows purged from each table since the gateway started
+    purged_rows: Arc<Mutex<BTreeMap<String, u64>>>,
+    /// What each executor, that is each [`Worker`], reported on, keyed by
+    /// the name of the executor
+    ///
+    /// [`Worker`]: crate::worker::Worker
+    executors: Arc<Mutex<BTreeMap<String, ExecutorGauges>>>,
+    /// Number of times work was turned away for the worker queue being
+    /// full
+    worker_queue_full: Arc<Mutex<u64>>,
+    /// How long the steps of project tasks took, keyed by the
+    /// [label](crate::project::Project::label) of the state stepped from
+    worker_task_durations: Arc<Mutex<BTreeMap<String, Histogram>>>,
+    /// Number of times a failing project task was tried again
+    worker_task_retries: Arc<Mutex<u64>>,
+    /// Number of project tasks which ran past their deadline
+    worker_task_deadlines_exceeded: Arc<Mutex<u64>>,
+    /// Number of tasks which panicked while the worker ran them
+    worker_task_panics: Arc<Mutex<u64>>,
+    /// Number of projects the worker gave up on, now and since the
+    /// gateway started
+    dead_lettered: Arc<Mutex<i64>>,
+    dead_lettered_total: Arc<Mutex<u64>>,
+    /// Total time calls to the docker daemon waited on the rate limit,
+    /// and how many calls that is, keyed by the kind of call
+    docker_queue_wait: Arc<Mutex<BTreeMap<String, (Duration, u64)>>>,
+}
+
+impl GatewayMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a project moving from one state to another. `None` means
+    /// the project did not exist before, or does not anymore.
+    pub fn project_transition(&self, from: Option<&str>, to: Option<&str>) {
+        if from == to {
+            return;
+        }
+
+        let mut project_states = self.project_states.lock().unwrap();
+
+        if let Some(from) = from {
+            *project_states.entry(from.to_string()).or_default() -= 1;
+        }
+
+        if let Some(to) = to {
+            *project_states.entry(to.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Replace the project gauges with a full count, to correct any drift
+    pub fn set_project_counts(&self, counts: HashMap<String, usize>) {
+        let mut project_states = self.project_states.lock().unwrap();
+
+        // Keep the states which are gone around, at zero, so that they
+        // do not disappear from the dashboards
+        project_states.values_mut().for_each(|count| *count = 0);
+        for (state, count) in counts {
+            project_states.insert(state, count as i64);
+        }
+    }
+
+    pub fn project_counts(&self) -> BTreeMap<String, i64> {
+        self.project_states.lock().unwrap().clone()
+    }
+
+    pub fn record_purged(&self, table: &str, rows: u64) {
+        *self
+            .purged_rows
+            .lock()
+            .unwrap()
+            .entry(table.to_string())
+            .or_default() += rows;
+    }
+
+    pub fn purged_rows(&self) -> BTreeMap<String, u64> {
+        self.purged_rows.lock().unwrap().clone()
+    }
+
+    /// Change what is known of `executor` with `update`
+    fn update_executor(&self, executor: &str, update: impl FnOnce(&mut ExecutorGauges)) {
+        let mut executors = self.executors.lock().unwrap();
+        update(executors.entry(executor.to_string()).or_default());
+    }
+
+    /// What is known of `executor`, if it reported on anything yet
+    fn executor(&self, executor: &str) -> ExecutorGauges {
+        self.executors
+            .lock()
+            .unwrap()
+            .get(executor)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn executors(&self) -> BTreeMap<String, ExecutorGauges> {
+        self.executors.lock().unwrap().clone()
+    }
+
+    /// Have `executor` show up in the gauges, even before it gets any
+    /// work
+    pub fn register_executor(&self, executor: &str) {
+        self.update_executor(executor, |_| {});
+    }
+
+    /// Record `executor` starting on a task which waited `waited` for
+    /// its turn
+    pub fn worker_task_started(&self, executor: &str, waited: Duration) {
+        self.update_executor(executor, |gauges| {
+            gauges.in_flight += 1;
+            gauges.queue_wait.0 += waited;
+            gauges.queue_wait.1 += 1;
+        });
+    }
+
+    pub fn worker_task_finished(&self, executor: &str) {
+        self.update_executor(executor, |gauges| gauges.in_flight -= 1);
+    }
+
+    pub fn worker_in_flight(&self, executor: &str) -> i64 {
+        self.executor(executor).in_flight
+    }
+
+    /// Total time tasks waited to be run by `executor`, and how many
+    /// tasks that is
+    pub fn worker_queue_wait(&self, executor: &str) -> (Duration, u64) {
+        self.executor(executor).queue_wait
+    }
+
+    pub fn set_worker_queue_depth(&self, executor: &str, lane: Priority, depth: usize) {
+        self.update_executor(executor, |gauges| {
+            gauges.queue_depth.insert(lane, depth);
+        });
+    }
+
+    /// Number of tasks waiting in each lane of the queue of `executor`,
+    /// for the lanes it reported on
+    pub fn worker_queue_depth(&self, executor: &str) -> BTreeMap<Priority, usize> {
+        self.executor(executor).queue_depth
+    }
+
+    pub fn record_worker_queue_full(&self) {
+        *self.worker_queue_full.lock().unwrap() += 1;
+    }
+
+    pub fn worker_queue_full(&self) -> u64 {
+        *self.worker_queue_full.lock().unwrap()
+    }
+
+    pub fn record_worker_enqueued(&self, executor: &str) {
+        self.update_executor(executor, |gauges| gauges.enqueued += 1);
+    }
+
+    pub fn worker_enqueued(&self, executor: &str) -> u64 {
+        self.executor(executor).enqueued
+    }
+
+    pub fn record_worker_dequeued(&self, executor: &str) {
+        self.update_executor(executor, |gauges| gauges.dequeued += 1);
+    }
+
+    pub fn worker_dequeued(&self, executor: &str) -> u64 {
+        self.executor(executor).dequeued
+    }
+
+    /// Record a step of a project task, out of the state labelled `from`,
+    /// having taken `took`
+    pub fn record_worker_task_duration(&self, from: &str, took: Duration) {
+        self.worker_task_durations
+            .lock()
+            .unwrap()
+            .entry(from.to_string())
+            .or_default()
+            .observe(took);
+    }
+
+    pub fn worker_task_durations(&self) -> BTreeMap<String, Histogram> {
+        self.worker_task_durations.lock().unwrap().clone()
+    }
+
+    pub fn record_worker_task_retry(&self) {
+        *self.worker_task_retries.lock().unwrap() += 1;
+    }
+
+    pub fn worker_task_retries(&self) -> u64 {
+        *self.worker_task_retries.lock().unwrap()
+    }
+
+    pub fn record_worker_task_deadline_exceeded(&self) {
+        *self.worker_task_deadlines_exceeded.lock().unwrap() += 1;
+    }
+
+    pub fn worker_task_deadlines_exceeded(&self) -> u64 {
+        *self.worker_task_deadlines_exceeded.lock().unwrap()
+    }
+
+    pub fn record_worker_task_panic(&self) {
+        *self.worker_task_panics.lock().unwrap() += 1;
+    }
+
+    pub fn worker_task_panics(&self) -> u64 {
+        *self.worker_task_panics.lock().unwrap()
+    }
+
+    pub fn record_dead_lettered(&self) {
+        *self.dead_lettered_total.lock().unwrap() += 1;
+    }
+
+    pub fn dead_lettered_total(&self) -> u64 {
+        *self.dead_lettered_total.lock().unwrap()
+    }
+
+    pub fn set_dead_lettered(&self, count: i64) {
+        *self.dead_lettered.lock().unwrap() = count;
+    }
+
+    pub fn dead_lettered(&self) -> i64 {
+        *self.dead_lettered.lock().unwrap()
+    }
+
+    pub fn record_docker_queue_wait(&self, call: &str, waited: Duration) {
+        let mut docker_queue_wait = self.docker_queue_wait.lock().unwrap();
+        let queue_wait = docker_queue_wait.entry(call.to_string()).or_default();
+        queue_wait.0 += waited;
+        queue_wait.1 += 1;
+    }
+
+    /// Total time calls to the docker daemon waited on the rate limit,
+    /// and how many calls that is, for each kind of call made so far
+    pub fn docker_queue_wait(&self) -> BTreeMap<String, (Duration, u64)> {
+        self.docker_queue_wait.lock().unwrap().clone()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "# HELP gateway_projects Number of projects in each state"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_projects gauge").unwrap();
+        for (state, count) in self.project_counts() {
+            writeln!(out, "gateway_projects{{state=\"{state}\"}} {count}").unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP gateway_purged_rows_total Number of rows purged from each table"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_purged_rows_total counter").unwrap();
+        for (table, rows) in self.purged_rows() {
+            writeln!(out, "gateway_purged_rows_total{{table=\"{table}\"}} {rows}").unwrap();
+        }
+
+        let executors = self.executors();
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_tasks_in_flight Number of tasks the worker is running"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_tasks_in_flight gauge").unwrap();
+        for (executor, gauges) in &executors {
+            writeln!(
+                out,
+                "gateway_worker_tasks_in_flight{{executor=\"{executor}\"}} {}",
+                gauges.in_flight
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_queue_wait_seconds Time tasks waited before being run"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_queue_wait_seconds summary").unwrap();
+        for (executor, gauges) in &executors {
+            let (waited, tasks) = gauges.queue_wait;
+            writeln!(
+                out,
+                "gateway_worker_queue_wait_seconds_sum{{executor=\"{executor}\"}} {}",
+                waited.as_secs_f64()
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "gateway_worker_queue_wait_seconds_count{{executor=\"{executor}\"}} {tasks}"
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_queue_depth Number of tasks waiting in each worker lane"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_queue_depth gauge").unwrap();
+        for (executor, gauges) in &executors {
+            for (lane, depth) in &gauges.queue_depth {
+                let lane = lane.as_str();
+                writeln!(
+                    out,
+                    "gateway_worker_queue_depth{{executor=\"{executor}\",lane=\"{lane}\"}} {depth}"
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_queue_full_total Number of times the worker queue was full"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_queue_full_total counter").unwrap();
+        writeln!(
+            out,
+            "gateway_worker_queue_full_total {}",
+            self.worker_queue_full()
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_enqueued_total Number of tasks taken in by the worker"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_enqueued_total counter").unwrap();
+        for (executor, gauges) in &executors {
+            writeln!(
+                out,
+                "gateway_worker_enqueued_total{{executor=\"{executor}\"}} {}",
+                gauges.enqueued
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_dequeued_total Number of tasks the worker started on"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_dequeued_total counter").unwrap();
+        for (executor, gauges) in &executors {
+            writeln!(
+                out,
+                "gateway_worker_dequeued_total{{executor=\"{executor}\"}} {}",
+                gauges.dequeued
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_task_duration_seconds Time taken by the steps of project tasks"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_task_duration_seconds histogram").unwrap();
+        for (from, histogram) in self.worker_task_durations() {
+            let mut cumulative = 0;
+            for (le, count) in TASK_DURATION_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "gateway_worker_task_duration_seconds_bucket{{transition=\"{from}\",le=\"{le}\"}} {cumulative}"
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "gateway_worker_task_duration_seconds_bucket{{transition=\"{from}\",le=\"+Inf\"}} {}",
+                histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "gateway_worker_task_duration_seconds_sum{{transition=\"{from}\"}} {}",
+                histogram.sum.as_secs_f64()
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "gateway_worker_task_duration_seconds_count{{transition=\"{from}\"}} {}",
+                histogram.count
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_task_retries_total Number of times a failing project task was tried again"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_task_retries_total counter").unwrap();
+        writeln!(
+            out,
+            "gateway_worker_task_retries_total {}",
+            self.worker_task_retries()
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_task_deadline_exceeded_total Number of project tasks which ran past their deadline"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "# TYPE gateway_worker_task_deadline_exceeded_total counter"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "gateway_worker_task_deadline_exceeded_total {}",
+            self.worker_task_deadlines_exceeded()
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP gateway_worker_task_panics_total Number of tasks which panicked while the worker ran them"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_worker_task_panics_total counter").unwrap();
+        writeln!(
+            out,
+            "gateway_worker_task_panics_total {}",
+            self.worker_task_panics()
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP gateway_dead_lettered_total Number of projects the worker gave up on since the gateway started"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_dead_lettered_total counter").unwrap();
+        writeln!(
+            out,
+            "gateway_dead_lettered_total {}",
+            self.dead_lettered_total()
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP gateway_dead_lettered_projects Number of projects the worker gave up on"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_dead_lettered_projects gauge").unwrap();
+        writeln!(
+            out,
+            "gateway_dead_lettered_projects {}",
+            self.dead_lettered()
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "# HELP gateway_docker_queue_wait_seconds Time calls to the docker daemon waited on the rate limit"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE gateway_docker_queue_wait_seconds summary").unwrap();
+        for (call, (waited, calls)) in self.docker_queue_wait() {
+            writeln!(
+                out,
+                "gateway_docker_queue_wait_seconds_sum{{call=\"{call}\"}} {}",
+                waited.as_secs_f64()
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "gateway_docker_queue_wait_seconds_count{{call=\"{call}\"}} {calls}"
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP shuttle_gateway_uptime_seconds How long the gateway has been running for"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE shuttle_gateway_uptime_seconds gauge").unwrap();
+        writeln!(
+            out,
+            "shuttle_gateway_uptime_seconds {}",
+            telemetry::uptime().as_secs()
+        )
+        .unwrap();
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_gauges() {
+        let metrics = GatewayMetrics::new();
+
+        metrics.project_transition(None, Some("creating"));
+        metrics.project_transition(None, Some("creating"));
+        metrics.project_transition(Some("creating"), Some("ready"));
+        metrics.project_transition(Some("ready"), Some("ready"));
+
+        assert_eq!(
+            metrics.project_counts(),
+            BTreeMap::from([("creating".to_string(), 1), ("ready".to_string(), 1)])
+        );
+
+        metrics.set_project_counts(HashMap::from([("ready".to_string(), 2)]));
+        metrics.set_worker_queue_depth("lifecycle", Priority::Background, 3);
+        metrics.set_worker_queue_depth("lifecycle", Priority::Interactive, 1);
+        metrics.record_worker_enqueued("lifecycle");
+        metrics.record_worker_enqueued("lifecycle");
+        metrics.record_worker_dequeued("lifecycle");
+        metrics.register_executor("health");
+        metrics.record_worker_task_duration("creating", Duration::from_millis(500));
+        metrics.record_worker_task_duration("creating", Duration::from_secs(400));
+        metrics.record_worker_task_retry();
+        metrics.record_worker_task_deadline_exceeded();
+        metrics.record_worker_task_panic();
+        metrics.record_dead_lettered();
+        metrics.record_docker_queue_wait("create", Duration::from_millis(250));
+        metrics.record_docker_queue_wait("create", Duration::from_millis(250));
+
+        assert_eq!(
+            metrics.project_counts(),
+            BTreeMap::from([("creating".to_string(), 0), ("ready".to_string(), 2)])
+        );
+
+        let rendered = metrics.render();
+        let (rendered, uptime) = rendered
+            .split_once("# HELP shuttle_gateway_uptime_seconds")
+            .unwrap();
+
+        assert_eq!(
+            rendered,
+            "# HELP gateway_projects Number of projects in each state\n\
+             # TYPE gateway_projects gauge\n\
+             gateway_projects{state=\"creating\"} 0\n\
+             gateway_projects{state=\"ready\"} 2\n\
+             # HELP gateway_purged_rows_total Number of rows purged from each table\n\
+             # TYPE gateway_purged_rows_total counter\n\
+             # HELP gateway_worker_tasks_in_flight Number of tasks the worker is running\n\
+             # TYPE gateway_worker_tasks_in_flight gauge\n\
+             gateway_worker_tasks_in_flight{executor=\"health\"} 0\n\
+             gateway_worker_tasks_in_flight{executor=\"lifecycle\"} 0\n\
+             # HELP gateway_worker_queue_wait_seconds Time tasks waited before being run\n\
+             # TYPE gateway_worker_queue_wait_seconds summary\n\
+             gateway_worker_queue_wait_seconds_sum{executor=\"health\"} 0\n\
+             gateway_worker_queue_wait_seconds_count{executor=\"health\"} 0\n\
+             gateway_worker_queue_wait_seconds_sum{executor=\"lifecycle\"} 0\n\
+             gateway_worker_queue_wait_seconds_count{executor=\"lifecycle\"} 0\n\
+             # HELP gateway_worker_queue_depth Number of tasks waiting in each worker lane\n\
+             # TYPE gateway_worker_queue_depth gauge\n\
+             gateway_worker_queue_depth{executor=\"lifecycle\",lane=\"interactive\"} 1\n\
+             gateway_worker_queue_depth{executor=\"lifecycle\",lane=\"background\"} 3\n\
+             # HELP gateway_worker_queue_full_total Number of times the worker queue was full\n\
+             # TYPE gateway_worker_queue_full_total counter\n\
+             gateway_worker_queue_full_total 0\n\
+             # HELP gateway_worker_enqueued_total Number of tasks taken in by the worker\n\
+             # TYPE gateway_worker_enqueued_total counter\n\
+             gateway_worker_enqueued_total{executor=\"health\"} 0\n\
+             gateway_worker_enqueued_total{executor=\"lifecycle\"} 2\n\
+             # HELP gateway_worker_dequeued_total Number of tasks the worker started on\n\
+             # TYPE gateway_worker_dequeued_total counter\n\
+             gateway_worker_dequeued_total{executor=\"health\"} 0\n\
+             gateway_worker_dequeued_total{executor=\"lifecycle\"} 1\n\
+             # HELP gateway_worker_task_duration_seconds Time taken by the steps of project tasks\n\
+             # TYPE gateway_worker_task_duration_seconds histogram\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"0.1\"} 0\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"0.5\"} 1\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"1\"} 1\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"5\"} 1\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"10\"} 1\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"30\"} 1\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"60\"} 1\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"120\"} 1\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"300\"} 1\n\
+             gateway_worker_task_duration_seconds_bucket{transition=\"creating\",le=\"+Inf\"} 2\n\
+             gateway_worker_task_duration_seconds_sum{transition=\"creating\"} 400.5\n\
+             gateway_worker_task_duration_seconds_count{transition=\"creating\"} 2\n\
+             # HELP gateway_worker_task_retries_total Number of times a failing project task was tried again\n\
+             # TYPE gateway_worker_task_retries_total counter\n\
+             gateway_worker_task_retries_total 1\n\
+             # HELP gateway_worker_task_deadline_exceeded_total Number of project tasks which ran past their deadline\n\
+             # TYPE gateway_worker_task_deadline_exceeded_total counter\n\
+             gateway_worker_task_deadline_exceeded_total 1\n\
+             # HELP gateway_worker_task_panics_total Number of tasks which panicked while the worker ran them\n\
+             # TYPE gateway_worker_task_panics_total counter\n\
+             gateway_worker_task_panics_total 1\n\
+             # HELP gateway_dead_lettered_total Number of projects the worker gave up on since the gateway started\n\
+             # TYPE gateway_dead_lettered_total counter\n\
+             gateway_dead_lettered_total 1\n\
+             # HELP gateway_dead_lettered_projects Number of projects the worker gave up on\n\
+             # TYPE gateway_dead_lettered_projects gauge\n\
+             gateway_dead_lettered_projects 0\n\
+             # HELP gateway_docker_queue_wait_seconds Time calls to the docker daemon waited on the rate limit\n\
+             # TYPE gateway_docker_queue_wait_seconds summary\n\
+             gateway_docker_queue_wait_seconds_sum{call=\"create\"} 0.5\n\
+             gateway_docker_queue_wait_seconds_count{call=\"create\"} 2\n"
+        );
+        assert!(uptime.contains("# TYPE shuttle_gateway_uptime_seconds gauge\n"));
+        assert!(uptime.contains("\nshuttle_gateway_uptime_seconds "));
+    }
+}
@@ -0,0 +1,358 @@
+//! Error responses as [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807)
+//! problem documents, for the clients which ask for them
+//!
+//! Every [`Error`](crate::Error) response is JSON in the shape of an
+//! [`ApiError`] by default. Clients preferring `application/problem+json`
+//! over `application/json` in their `Accept` header get a [`Problem`]
+//! instead, whether the error comes from the control plane or the proxy.
+
+use axum::body::{boxed, Full};
+use axum::http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::Serialize;
+use shuttle_common::models::error::{ApiError, ErrorKind};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::ErrorResource;
+
+pub const APPLICATION_PROBLEM_JSON: &str = "application/problem+json";
+
+/// What the `type` of every problem starts with, followed by the
+/// [code](ErrorKind::code) of its kind
+pub const PROBLEM_TYPE_PREFIX: &str = "urn:shuttle:error:";
+
+pub const X_REQUEST_ID: &str = "x-request-id";
+
+/// A problem document. Besides the members of RFC 7807, it has the `code`
+/// clients already tell errors apart by, and the named resource the error
+/// is about, if any, as in the default body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    pub r#type: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The id of the request, as in its logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub code: String,
+    #[serde(flatten)]
+    pub resource: Option<ErrorResource>,
+}
+
+impl Problem {
+    pub fn new(kind: ErrorKind, resource: Option<ErrorResource>) -> Self {
+        let error = ApiError::from(kind);
+        let detail = resource.as_ref().map(|ErrorResource { resource, name }| {
+            format!("{} ({resource} {name})", error.message)
+        });
+
+        Self {
+            r#type: format!("{PROBLEM_TYPE_PREFIX}{}", kind.code()),
+            title: error.message,
+            status: error.status_code,
+            detail,
+            instance: None,
+            code: kind.code().to_string(),
+            resource,
+        }
+    }
+}
+
+/// How the error responses to a request are rendered, decided from the
+/// request before it is handled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorFormat {
+    problem_json: bool,
+    request_id: String,
+}
+
+impl ErrorFormat {
+    pub fn of(headers: &HeaderMap) -> Self {
+        Self {
+            problem_json: prefers_problem_json(headers),
+            request_id: request_id(headers),
+        }
+    }
+
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Turn `response` into a problem document if it is an error response
+    /// of the gateway and the request asked for one. Any other response,
+    /// like those of the projects, is left as it is.
+    pub fn render(&self, response: Response) -> Response {
+        if !self.problem_json {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let Some(mut problem) = parts.extensions.remove::<Problem>() else {
+            return Response::from_parts(parts, body);
+        };
+        problem.instance = Some(self.request_id.clone());
+
+        let body = match serde_json::to_vec(&problem) {
+            Ok(body) => body,
+            Err(error) => {
+                error!(error = %error, "failed to serialize a problem document");
+                return Response::from_parts(parts, body);
+            }
+        };
+
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(APPLICATION_PROBLEM_JSON),
+        );
+
+        Response::from_parts(parts, boxed(Full::from(body)))
+    }
+}
+
+/// Middleware rendering the error responses of the control plane as asked
+/// by their request. It gives the request an `X-Request-Id` if it came
+/// without one, so the id in its problem documents is the one in its logs.
+pub async fn negotiate<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let format = ErrorFormat::of(request.headers());
+    if !request.headers().contains_key(X_REQUEST_ID) {
+        if let Ok(id) = HeaderValue::from_str(format.request_id()) {
+            request.headers_mut().insert(X_REQUEST_ID, id);
+        }
+    }
+
+    let response = next.run(request).await;
+    format.render(response)
+}
+
+/// The `X-Request-Id` a request came with, or a new one
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(X_REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .map(ToString::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// Whether `application/problem+json` has a higher quality than
+/// `application/json` in the `Accept` headers. Each of them gets the
+/// quality of the most specific media range matching it, so wildcards and
+/// ties keep the default JSON body.
+pub fn prefers_problem_json(headers: &HeaderMap) -> bool {
+    let ranges: Vec<_> = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(MediaRange::parse)
+        .collect();
+
+    let quality = |r#type: &str, subtype: &str| {
+        ranges
+            .iter()
+            .filter_map(|range| range.specificity(r#type, subtype).map(|s| (s, range.q)))
+            // The first of the most specific ranges wins
+            .fold(
+                None,
+                |best: Option<(u8, f32)>, (specificity, q)| match best {
+                    Some((best_specificity, _)) if best_specificity >= specificity => best,
+                    _ => Some((specificity, q)),
+                },
+            )
+            .map(|(_, q)| q)
+            .unwrap_or(0.0)
+    };
+
+    quality("application", "problem+json") > quality("application", "json")
+}
+
+/// A media range of an `Accept` header, with its quality
+struct MediaRange<'a> {
+    r#type: &'a str,
+    subtype: &'a str,
+    q: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(range: &'a str) -> Option<Self> {
+        let mut params = range.split(';');
+        let (r#type, subtype) = params.next()?.trim().split_once('/')?;
+
+        let mut q = 1.0;
+        for param in params {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    q = value
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|q| (0.0..=1.0).contains(q))?;
+                }
+            }
+        }
+
+        Some(Self {
+            r#type: r#type.trim(),
+            subtype: subtype.trim(),
+            q,
+        })
+    }
+
+    /// How specifically this range matches the media type, if it does at
+    /// all: 2 for the type itself, 1 for `type/*` and 0 for `*/*`
+    fn specificity(&self, r#type: &str, subtype: &str) -> Option<u8> {
+        let matches = |range: &str, value: &str| range == "*" || range.eq_ignore_ascii_case(value);
+        if !matches(self.r#type, r#type) || !matches(self.subtype, subtype) {
+            return None;
+        }
+
+        Some(u8::from(self.r#type != "*") + u8::from(self.subtype != "*"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+    use hyper::{Body, StatusCode};
+    use serde_json::json;
+    use tower::Service;
+
+    use super::*;
+    use crate::Error;
+
+    fn accept(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(ACCEPT, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn negotiation_precedence() {
+        let prefers = |values: &[&str]| prefers_problem_json(&accept(values));
+
+        // No preference keeps the default
+        assert!(!prefers(&[]));
+        assert!(!prefers(&["*/*"]));
+        assert!(!prefers(&["application/*"]));
+        assert!(!prefers(&["text/html"]));
+        assert!(!prefers(&["application/json"]));
+
+        assert!(prefers(&["application/problem+json"]));
+        assert!(prefers(&["Application/Problem+JSON"]));
+        assert!(prefers(&["application/problem+json, */*;q=0.1"]));
+
+        // Ties go to the default, whichever comes first
+        assert!(!prefers(&["application/problem+json, application/json"]));
+        assert!(!prefers(&["application/json, application/problem+json"]));
+        assert!(!prefers(&[
+            "application/json;q=0.5, application/problem+json;q=0.5"
+        ]));
+
+        // Qualities decide, across headers too
+        assert!(prefers(&[
+            "application/json;q=0.5, application/problem+json"
+        ]));
+        assert!(!prefers(&[
+            "application/json, application/problem+json;q=0.9"
+        ]));
+        assert!(prefers(&[
+            "application/json;q=0.5",
+            "application/problem+json"
+        ]));
+        assert!(!prefers(&["application/problem+json;q=0"]));
+
+        // The most specific range matching a type gives its quality
+        assert!(prefers(&["application/*;q=0.2, application/problem+json"]));
+        assert!(!prefers(&["application/problem+json;q=0.2, application/*"]));
+        assert!(prefers(&["*/*;q=0.8, application/problem+json"]));
+        assert!(prefers(&[
+            "application/problem+json, application/json;q=0.1, */*"
+        ]));
+
+        // Ranges which do not parse are ignored
+        assert!(prefers(&["nonsense, application/problem+json"]));
+        assert!(!prefers(&["application/problem+json;q=high"]));
+    }
+
+    #[tokio::test]
+    async fn errors_are_problem_documents_when_asked_for() {
+        let format = ErrorFormat::of(&{
+            let mut headers = accept(&["application/problem+json"]);
+            headers.insert(X_REQUEST_ID, HeaderValue::from_static("some-request"));
+            headers
+        });
+        let resp = format.render(Error::forbidden("project", "my-project").into_response());
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(resp.headers()[CONTENT_TYPE], APPLICATION_PROBLEM_JSON);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "type": "urn:shuttle:error:forbidden",
+                "title": "forbidden",
+                "status": 403,
+                "detail": "forbidden (project my-project)",
+                "instance": "some-request",
+                "code": "forbidden",
+                "resource": "project",
+                "name": "my-project",
+            })
+        );
+
+        // Other responses are left as they are
+        let resp = format.render((StatusCode::NOT_FOUND, "not ours").into_response());
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/plain; charset=utf-8");
+
+        // And so are the errors of the clients which did not ask
+        let format = ErrorFormat::of(&accept(&["application/json"]));
+        let resp = format.render(Error::from_kind(ErrorKind::Forbidden).into_response());
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+    }
+
+    #[tokio::test]
+    async fn middleware_gives_the_logged_request_id() {
+        let seen = Arc::new(Mutex::new(None));
+        let mut router = Router::new()
+            .route(
+                "/",
+                get({
+                    let seen = Arc::clone(&seen);
+                    |headers: HeaderMap| async move {
+                        *seen.lock().unwrap() = Some(headers[X_REQUEST_ID].clone());
+                        Err::<(), _>(Error::from_kind(ErrorKind::ProjectNotFound))
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn(negotiate));
+
+        let resp = router
+            .call(
+                Request::get("/")
+                    .header(ACCEPT, APPLICATION_PROBLEM_JSON)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let seen = seen.lock().unwrap().take().unwrap();
+        assert_eq!(problem["instance"], seen.to_str().unwrap());
+        assert_eq!(problem["type"], "urn:shuttle:error:project_not_found");
+    }
+}
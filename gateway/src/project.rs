@@ -19,14 +19,20 @@ use hyper::client::HttpConnector;
 use hyper::Client;
 use once_cell::sync::Lazy;
 use rand::distributions::{Alphanumeric, DistString};
+use ring::hmac;
 use serde::{Deserialize, Serialize};
-use shuttle_common::models::project::{idle_minutes, IDLE_MINUTES};
+use shuttle_common::models::project::{
+    allow_internet, idle_minutes, ErroredKind, ProjectStateView, IDLE_MINUTES,
+};
 use tokio::time::{sleep, timeout};
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
 use crate::service::ContainerSettings;
+use crate::task::RetryPolicy;
+use crate::throttle::DockerCall;
 use crate::{
-    DockerContext, EndState, Error, ErrorKind, IntoTryState, ProjectName, Refresh, State, TryState,
+    limited_docker, DockerContext, EndState, Error, ErrorKind, IntoTryState, ProjectName, Refresh,
+    State, TryState,
 };
 
 macro_rules! safe_unwrap {
@@ -69,6 +75,24 @@ const MAX_RECREATES: usize = 5;
 const MAX_RESTARTS: usize = 5;
 const MAX_REBOOTS: usize = 3;
 
+/// The environment variable holding the token the deployer in a project
+/// container uses to call back into the gateway
+pub const GATEWAY_TOKEN_ENV: &str = "SHUTTLE_GATEWAY_TOKEN";
+
+/// The environment variable runtimes find the CAs of the provisioner's
+/// certificate in, as PEM, when they reach it over TLS
+pub const PROVISIONER_TLS_CA_ENV: &str = "SHUTTLE_PROVISIONER_TLS_CA";
+
+/// The token issued to the deployer of a project for calling back into the
+/// gateway. It is derived from the initial key so that nothing more needs
+/// to be stored, while not giving the initial key itself away.
+pub fn internal_token(project_name: &ProjectName, initial_key: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, initial_key.as_bytes());
+    let tag = hmac::sign(&key, project_name.as_str().as_bytes());
+
+    base64::encode_config(tag.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
 // Client used for health checks
 static CLIENT: Lazy<Client<HttpConnector>> = Lazy::new(Client::new);
 // Health check must succeed within 10 seconds
@@ -81,7 +105,8 @@ where
 {
     type Error = DockerError;
     async fn refresh(self, ctx: &Ctx) -> Result<Self, Self::Error> {
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Inspect)
+            .await
             .inspect_container(self.id.as_ref().unwrap(), None)
             .await
     }
@@ -99,6 +124,14 @@ pub trait ContainerInspectResponseExt {
             .map_err(|_| ProjectError::internal("invalid project name"))
     }
 
+    /// The project name the container was labelled with, as it was
+    /// written when the container was created
+    fn resource_name(&self) -> Result<String, ProjectError> {
+        let container = self.container();
+
+        Ok(safe_unwrap!(container.config.labels.get("shuttle.project")).to_string())
+    }
+
     fn idle_minutes(&self) -> u64 {
         let container = self.container();
 
@@ -113,6 +146,18 @@ pub trait ContainerInspectResponseExt {
         IDLE_MINUTES
     }
 
+    /// Whether the project was let onto the internet, which it is unless
+    /// its container was labelled otherwise
+    fn allow_internet(&self) -> bool {
+        self.container()
+            .config
+            .as_ref()
+            .and_then(|config| config.labels.as_ref())
+            .and_then(|labels| labels.get("shuttle.allow_internet"))
+            .map(|allow| allow != "false")
+            .unwrap_or(true)
+    }
+
     fn find_arg_and_then<'s, F, O>(&'s self, find: &str, and_then: F) -> Result<O, ProjectError>
     where
         F: FnOnce(&'s str) -> O,
@@ -149,10 +194,25 @@ impl ContainerInspectResponseExt for ContainerInspectResponse {
     }
 }
 
+/// Docker not having what was asked for means the project it is for is
+/// gone, and docker not being reachable is for the request to be tried
+/// again later. Anything else is internal.
 impl From<DockerError> for Error {
     fn from(err: DockerError) -> Self {
-        error!(error = %err, "internal Docker error");
-        Self::source(ErrorKind::Internal, err)
+        let kind = match &err {
+            DockerError::DockerResponseServerError {
+                status_code: 404, ..
+            } => ErrorKind::ProjectNotFound,
+            DockerError::IOError { .. }
+            | DockerError::HyperResponseError { .. }
+            | DockerError::RequestTimeoutError { .. } => ErrorKind::DockerUnavailable,
+            _ => ErrorKind::Internal,
+        };
+
+        if kind == ErrorKind::Internal {
+            error!(error = %err, "internal Docker error");
+        }
+        Self::source(kind, err)
     }
 }
 
@@ -196,7 +256,7 @@ impl Project {
         } else {
             Err(Error::custom(
                 ErrorKind::InvalidOperation,
-                format!("cannot stop a project in the `{}` state", self.state()),
+                format!("cannot stop a project in the `{}` state", self.label()),
             ))
         }
     }
@@ -207,7 +267,7 @@ impl Project {
         } else {
             Err(Error::custom(
                 ErrorKind::InvalidOperation,
-                format!("cannot reboot a project in the `{}` state", self.state()),
+                format!("cannot reboot a project in the `{}` state", self.label()),
             ))
         }
     }
@@ -229,11 +289,38 @@ impl Project {
         } else {
             Err(Error::custom(
                 ErrorKind::InvalidOperation,
-                format!("cannot start a project in the `{}` state", self.state()),
+                format!("cannot start a project in the `{}` state", self.label()),
             ))
         }
     }
 
+    /// The name of the state the project is in, for logs and API
+    /// responses. This is the same as its tag when serialized, and
+    /// unlike [`Project::state`] has no details on the attempt count.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Creating(_) => "creating",
+            Self::Attaching(_) => "attaching",
+            Self::Recreating(_) => "recreating",
+            Self::Starting(_) => "starting",
+            Self::Restarting(_) => "restarting",
+            Self::Started(_) => "started",
+            Self::Ready(_) => "ready",
+            Self::Rebooting(_) => "rebooting",
+            Self::Stopping(_) => "stopping",
+            Self::Stopped(_) => "stopped",
+            Self::Destroying(_) => "destroying",
+            Self::Destroyed(_) => "destroyed",
+            Self::Errored(_) => "errored",
+        }
+    }
+
+    /// The [view](ProjectStateView) of the state the project is in, as JSON
+    pub fn as_json_value(&self) -> serde_json::Value {
+        serde_json::to_value(ProjectStateView::from(self))
+            .expect("the view of a project state to serialize")
+    }
+
     pub fn is_ready(&self) -> bool {
         matches!(self, Self::Ready(_))
     }
@@ -329,36 +416,76 @@ impl Project {
     }
 }
 
-impl From<Project> for shuttle_common::models::project::State {
-    fn from(project: Project) -> Self {
+/// How the project is given out by every endpoint. The match is exhaustive
+/// so that no state can be added without deciding how it shows.
+impl From<&Project> for ProjectStateView {
+    fn from(project: &Project) -> Self {
+        let container_id = project.container_id();
+        let container_state = || project.container().and_then(|container| container.state);
+
         match project {
-            Project::Creating(ProjectCreating { recreate_count, .. }) => {
-                Self::Creating { recreate_count }
-            }
-            Project::Attaching(ProjectAttaching { recreate_count, .. }) => {
-                Self::Attaching { recreate_count }
-            }
-            Project::Recreating(ProjectRecreating { recreate_count, .. }) => {
-                Self::Recreating { recreate_count }
-            }
-            Project::Starting(ProjectStarting { restart_count, .. }) => {
-                Self::Starting { restart_count }
-            }
-            Project::Restarting(ProjectRestarting { restart_count, .. }) => {
-                Self::Restarting { restart_count }
-            }
-            Project::Started(_) => Self::Started,
-            Project::Ready(_) => Self::Ready,
-            Project::Stopping(_) => Self::Stopping,
-            Project::Stopped(_) => Self::Stopped,
-            Project::Rebooting(_) => Self::Rebooting,
-            Project::Destroying(_) => Self::Destroying,
-            Project::Destroyed(_) => Self::Destroyed,
-            Project::Errored(ProjectError { message, .. }) => Self::Errored { message },
+            Project::Creating(ProjectCreating { recreate_count, .. }) => Self::Creating {
+                recreate_count: *recreate_count,
+            },
+            Project::Attaching(ProjectAttaching { recreate_count, .. }) => Self::Attaching {
+                container_id,
+                recreate_count: *recreate_count,
+            },
+            Project::Recreating(ProjectRecreating { recreate_count, .. }) => Self::Recreating {
+                container_id,
+                recreate_count: *recreate_count,
+            },
+            Project::Starting(ProjectStarting { restart_count, .. }) => Self::Starting {
+                container_id,
+                restart_count: *restart_count,
+            },
+            Project::Restarting(ProjectRestarting { restart_count, .. }) => Self::Restarting {
+                container_id,
+                restart_count: *restart_count,
+            },
+            Project::Started(_) => Self::Started {
+                container_id,
+                started_at: docker_time(container_state().and_then(|state| state.started_at)),
+            },
+            Project::Ready(ready) => Self::Ready {
+                container_id,
+                target_ip: *ready.target_ip(),
+                started_at: docker_time(container_state().and_then(|state| state.started_at)),
+            },
+            Project::Rebooting(_) => Self::Rebooting { container_id },
+            Project::Stopping(_) => Self::Stopping { container_id },
+            Project::Stopped(_) => Self::Stopped {
+                container_id,
+                finished_at: docker_time(container_state().and_then(|state| state.finished_at)),
+            },
+            Project::Destroying(_) => Self::Destroying { container_id },
+            Project::Destroyed(_) => Self::Destroyed {},
+            Project::Errored(ProjectError { kind, message, ctx }) => Self::Errored {
+                container_id,
+                kind: kind.clone().into(),
+                message: message.clone(),
+                previous: ctx.as_ref().map(|previous| previous.label().to_string()),
+            },
         }
     }
 }
 
+/// The short form of the state, through its [view](ProjectStateView)
+impl From<Project> for shuttle_common::models::project::State {
+    fn from(project: Project) -> Self {
+        ProjectStateView::from(&project).into()
+    }
+}
+
+/// A time as docker gives it, which is the zero time when it has not
+/// happened yet
+fn docker_time(time: Option<String>) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(&time?)
+        .ok()
+        .map(|time| time.with_timezone(&chrono::Utc))
+        .filter(|time| time.timestamp() > 0)
+}
+
 #[async_trait]
 impl<Ctx> State<Ctx> for Project
 where
@@ -367,7 +494,7 @@ where
     type Next = Self;
     type Error = Infallible;
 
-    #[instrument(skip_all, fields(state = %self.state()))]
+    #[instrument(skip_all, fields(state = self.label()))]
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error> {
         let previous = self.clone();
         let previous_state = previous.state();
@@ -434,6 +561,42 @@ where
 
         new
     }
+
+    fn timeout_duration(&self) -> Option<Duration> {
+        match self {
+            Self::Creating(creating) => State::<Ctx>::timeout_duration(creating),
+            Self::Attaching(attaching) => State::<Ctx>::timeout_duration(attaching),
+            Self::Recreating(recreating) => State::<Ctx>::timeout_duration(recreating),
+            Self::Starting(starting) => State::<Ctx>::timeout_duration(starting),
+            Self::Restarting(restarting) => State::<Ctx>::timeout_duration(restarting),
+            Self::Started(started) => State::<Ctx>::timeout_duration(started),
+            Self::Ready(ready) => State::<Ctx>::timeout_duration(ready),
+            Self::Rebooting(rebooting) => State::<Ctx>::timeout_duration(rebooting),
+            Self::Stopping(stopping) => State::<Ctx>::timeout_duration(stopping),
+            Self::Stopped(stopped) => State::<Ctx>::timeout_duration(stopped),
+            Self::Destroying(destroying) => State::<Ctx>::timeout_duration(destroying),
+            Self::Destroyed(destroyed) => State::<Ctx>::timeout_duration(destroyed),
+            Self::Errored(errored) => State::<Ctx>::timeout_duration(errored),
+        }
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        match self {
+            Self::Creating(creating) => State::<Ctx>::retry_policy(creating),
+            Self::Attaching(attaching) => State::<Ctx>::retry_policy(attaching),
+            Self::Recreating(recreating) => State::<Ctx>::retry_policy(recreating),
+            Self::Starting(starting) => State::<Ctx>::retry_policy(starting),
+            Self::Restarting(restarting) => State::<Ctx>::retry_policy(restarting),
+            Self::Started(started) => State::<Ctx>::retry_policy(started),
+            Self::Ready(ready) => State::<Ctx>::retry_policy(ready),
+            Self::Rebooting(rebooting) => State::<Ctx>::retry_policy(rebooting),
+            Self::Stopping(stopping) => State::<Ctx>::retry_policy(stopping),
+            Self::Stopped(stopped) => State::<Ctx>::retry_policy(stopped),
+            Self::Destroying(destroying) => State::<Ctx>::retry_policy(destroying),
+            Self::Destroyed(destroyed) => State::<Ctx>::retry_policy(destroyed),
+            Self::Errored(errored) => State::<Ctx>::retry_policy(errored),
+        }
+    }
 }
 
 impl<Ctx> EndState<Ctx> for Project
@@ -584,6 +747,15 @@ pub struct ProjectCreating {
     /// Label set on container as to how many minutes to wait before a project is considered idle
     #[serde(default = "idle_minutes")]
     idle_minutes: u64,
+    /// Whether the container can reach the internet, rather than only the
+    /// services on the isolated network
+    #[serde(default = "allow_internet")]
+    allow_internet: bool,
+    /// The name the container and volume of the project go by, when it
+    /// is not the project name. Projects created before their names were
+    /// lowercased keep the resources they were created with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resource_name: Option<String>,
 }
 
 impl ProjectCreating {
@@ -596,6 +768,8 @@ impl ProjectCreating {
             from: None,
             recreate_count: 0,
             idle_minutes,
+            allow_internet: true,
+            resource_name: None,
         }
     }
 
@@ -605,7 +779,10 @@ impl ProjectCreating {
     ) -> Result<Self, ProjectError> {
         let project_name = container.project_name()?;
         let idle_minutes = container.idle_minutes();
+        let allow_internet = container.allow_internet();
         let initial_key = container.initial_key()?;
+        let resource_name = Some(container.resource_name()?)
+            .filter(|resource_name| resource_name != project_name.as_str());
 
         Ok(Self {
             project_name,
@@ -615,6 +792,8 @@ impl ProjectCreating {
             from: Some(container),
             recreate_count,
             idle_minutes,
+            allow_internet,
+            resource_name,
         })
     }
 
@@ -638,6 +817,18 @@ impl ProjectCreating {
         self
     }
 
+    pub fn with_allow_internet(mut self, allow_internet: bool) -> Self {
+        self.allow_internet = allow_internet;
+        self
+    }
+
+    /// Keep the container and volume of the project going by
+    /// `resource_name`, see [`crate::service::GatewayService::find_resource_name`]
+    pub fn with_resource_name(mut self, resource_name: String) -> Self {
+        self.resource_name = Some(resource_name).filter(|name| name != self.project_name.as_str());
+        self
+    }
+
     pub fn project_name(&self) -> &ProjectName {
         &self.project_name
     }
@@ -650,12 +841,16 @@ impl ProjectCreating {
         &self.fqdn
     }
 
-    fn container_name<C: DockerContext>(&self, ctx: &C) -> String {
-        let prefix = &ctx.container_settings().prefix;
-
-        let Self { project_name, .. } = &self;
+    /// The name the container and volume of the project go by
+    pub fn resource_name(&self) -> &str {
+        self.resource_name
+            .as_deref()
+            .unwrap_or(self.project_name.as_str())
+    }
 
-        format!("{prefix}{project_name}_run")
+    fn container_name<C: DockerContext>(&self, ctx: &C) -> String {
+        ctx.container_settings()
+            .container_name(self.resource_name())
     }
 
     fn generate_container_config<C: DockerContext>(
@@ -665,9 +860,9 @@ impl ProjectCreating {
         let ContainerSettings {
             image: default_image,
             prefix,
-            provisioner_host,
             auth_uri,
             fqdn: public,
+            extra_hosts,
             ..
         } = ctx.container_settings();
 
@@ -677,6 +872,7 @@ impl ProjectCreating {
             fqdn,
             image,
             idle_minutes,
+            allow_internet,
             ..
         } = &self;
 
@@ -690,13 +886,24 @@ impl ProjectCreating {
             .as_ref()
             .and_then(|container| container.config.clone())
             .unwrap_or_else(|| {
+                let gateway_token = internal_token(project_name, initial_key);
+                let mut env = vec![
+                    "RUST_LOG=debug,shuttle=trace,h2=warn".to_string(),
+                    format!("{GATEWAY_TOKEN_ENV}={gateway_token}"),
+                ];
+                if let Some(ca) = ctx.container_settings().provisioner_ca_pem() {
+                    env.push(format!("{PROVISIONER_TLS_CA_ENV}={ca}"));
+                }
+
                 deserialize_json!({
                     "Image": image.as_ref().unwrap_or(default_image),
                     "Hostname": format!("{prefix}{project_name}"),
                     "Labels": {
+                        "shuttle.managed": "true",
                         "shuttle.prefix": prefix,
-                        "shuttle.project": project_name,
+                        "shuttle.project": self.resource_name(),
                         "shuttle.idle_minutes": format!("{idle_minutes}"),
+                        "shuttle.allow_internet": format!("{allow_internet}"),
                     },
                     "Cmd": [
                         "--admin-secret",
@@ -706,7 +913,7 @@ impl ProjectCreating {
                         "--api-address",
                         format!("0.0.0.0:{RUNTIME_API_PORT}"),
                         "--provisioner-address",
-                        format!("http://{provisioner_host}:8000"),
+                        ctx.container_settings().provisioner_address(),
                         "--proxy-address",
                         "0.0.0.0:8000",
                         "--proxy-fqdn",
@@ -718,9 +925,7 @@ impl ProjectCreating {
                         "--auth-uri",
                         auth_uri,
                     ],
-                    "Env": [
-                        "RUST_LOG=debug,shuttle=trace,h2=warn",
-                    ]
+                    "Env": env,
                 })
             });
 
@@ -729,7 +934,7 @@ impl ProjectCreating {
         config.host_config = deserialize_json!({
             "Mounts": [{
                 "Target": "/opt/shuttle",
-                "Source": format!("{prefix}{project_name}_vol"),
+                "Source": ctx.container_settings().volume_name(self.resource_name()),
                 "Type": "volume"
             }],
             // https://docs.docker.com/config/containers/resource_constraints/#memory
@@ -740,6 +945,26 @@ impl ProjectCreating {
             "CpuQuota": 400000i64
         });
 
+        // Straight onto the isolated network, for the container not to
+        // be on the internet even before it is attached. Without one it
+        // is on no network at all, and fails to attach.
+        if !allow_internet {
+            if let Some(host_config) = config.host_config.as_mut() {
+                host_config.network_mode = Some(
+                    ctx.container_settings()
+                        .project_network(false)
+                        .unwrap_or("none")
+                        .to_string(),
+                );
+            }
+        }
+
+        if !extra_hosts.is_empty() {
+            if let Some(host_config) = config.host_config.as_mut() {
+                host_config.extra_hosts = Some(extra_hosts.clone());
+            }
+        }
+
         debug!(
             r"generated a container configuration:
 CreateContainerOpts: {create_container_options:#?}
@@ -764,17 +989,21 @@ where
         let container_name = self.container_name(ctx);
         let Self { recreate_count, .. } = self;
 
-        let container = ctx
-            .docker()
+        let container = limited_docker(ctx, DockerCall::Inspect)
+            .await
             // If container already exists, use that
             .inspect_container(&container_name.clone(), None)
             // Otherwise create it
             .or_else(|err| async move {
                 if matches!(err, DockerError::DockerResponseServerError { status_code, .. } if status_code == 404) {
                     let (opts, config) = self.generate_container_config(ctx);
-                    ctx.docker()
+                    limited_docker(ctx, DockerCall::Create)
+                        .await
                         .create_container(Some(opts), config)
-                        .and_then(|_| ctx.docker().inspect_container(&container_name, None))
+                        .await?;
+                    limited_docker(ctx, DockerCall::Inspect)
+                        .await
+                        .inspect_container(&container_name, None)
                         .await
                 } else {
                     Err(err)
@@ -809,14 +1038,19 @@ where
         let Self { container, .. } = self;
 
         let container_id = safe_unwrap!(container.id);
-        let ContainerSettings { network_name, .. } = ctx.container_settings();
+        let network_name = ctx
+            .container_settings()
+            .project_network(container.allow_internet())
+            .ok_or_else(|| {
+                ProjectError::internal("no network without internet access is set up")
+            })?;
 
         // Disconnect the bridge network before trying to start up
         // For docker bug https://github.com/docker/cli/issues/1891
         //
         // Also disconnecting from all network because docker just losses track of their IDs sometimes when restarting
         for network in safe_unwrap!(container.network_settings.networks).keys() {
-            ctx.docker().disconnect_network(network, DisconnectNetworkOptions{
+            limited_docker(ctx, DockerCall::Create).await.disconnect_network(network, DisconnectNetworkOptions{
             container: container_id,
             force: true,
         })
@@ -836,7 +1070,8 @@ where
             container: container_id,
             endpoint_config: Default::default(),
         };
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .connect_network(network_name, network_config)
             .await
             .or_else(|err| {
@@ -889,11 +1124,13 @@ where
         } = self;
         let container_id = safe_unwrap!(container.id);
 
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .stop_container(container_id, Some(StopContainerOptions { t: 1 }))
             .await
             .unwrap_or(());
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .remove_container(
                 container_id,
                 Some(RemoveContainerOptions {
@@ -937,7 +1174,8 @@ where
         let Self { container, .. } = self;
         let container_id = safe_unwrap!(container.id);
 
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .start_container::<String>(container_id, None)
             .await
             .or_else(|err| {
@@ -953,6 +1191,10 @@ where
 
         Ok(Self::Next::new(container, VecDeque::new()))
     }
+
+    fn timeout_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs(5 * 60))
+    }
 }
 
 /// Special state for when `ProjectStarting` fails to retry it
@@ -980,7 +1222,8 @@ where
         let container_id = safe_unwrap!(container.id);
 
         // Stop it just to be safe
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .stop_container(container_id, Some(StopContainerOptions { t: 1 }))
             .await
             .unwrap_or(());
@@ -1057,8 +1300,8 @@ where
                     stats,
                 }))
             } else {
-                let new_stat = ctx
-                    .docker()
+                let new_stat = limited_docker(ctx, DockerCall::Inspect)
+                    .await
                     .stats(
                         safe_unwrap!(container.id),
                         Some(StatsOptions {
@@ -1253,7 +1496,8 @@ where
     #[instrument(skip_all)]
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error> {
         let Self { mut container } = self;
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .stop_container(
                 safe_unwrap!(container.id),
                 Some(StopContainerOptions { t: 30 }),
@@ -1267,8 +1511,8 @@ where
         let until = chrono::Utc::now().timestamp().to_string();
 
         // Filter and collect `start` events for this project in the last 15 minutes
-        let start_events = ctx
-            .docker()
+        let start_events = limited_docker(ctx, DockerCall::Inspect)
+            .await
             .events(Some(EventsOptions::<&str> {
                 since: Some(since),
                 until: Some(until),
@@ -1325,7 +1569,8 @@ where
         //
         // In some future state when all deployers hadle `SIGTERM` correctly, this can be changed to docker stop
         // safely.
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .kill_container(
                 safe_unwrap!(container.id),
                 Some(KillContainerOptions { signal: "SIGKILL" }),
@@ -1335,6 +1580,10 @@ where
             container: container.refresh(ctx).await?,
         })
     }
+
+    fn timeout_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs(60))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -1373,11 +1622,13 @@ where
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error> {
         let Self { container } = self;
         let container_id = safe_unwrap!(container.id);
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .stop_container(container_id, Some(StopContainerOptions { t: 1 }))
             .await
             .unwrap_or(());
-        ctx.docker()
+        limited_docker(ctx, DockerCall::Create)
+            .await
             .remove_container(
                 container_id,
                 Some(RemoveContainerOptions {
@@ -1391,6 +1642,12 @@ where
             destroyed: Some(container),
         })
     }
+
+    /// Tearing the container down is not gone at twice, lest a retry
+    /// delete more than the first attempt already did
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::none()
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -1416,6 +1673,23 @@ where
 pub enum ProjectErrorKind {
     Internal,
     NoNetwork,
+    TimedOut,
+    RetriesExhausted,
+    TaskDeadline,
+    InternalPanic,
+}
+
+impl From<ProjectErrorKind> for ErroredKind {
+    fn from(kind: ProjectErrorKind) -> Self {
+        match kind {
+            ProjectErrorKind::Internal => Self::Internal,
+            ProjectErrorKind::NoNetwork => Self::NoNetwork,
+            ProjectErrorKind::TimedOut => Self::TimedOut,
+            ProjectErrorKind::RetriesExhausted => Self::RetriesExhausted,
+            ProjectErrorKind::TaskDeadline => Self::TaskDeadline,
+            ProjectErrorKind::InternalPanic => Self::InternalPanic,
+        }
+    }
 }
 
 /// A runtime error coming from inside a project
@@ -1442,6 +1716,60 @@ impl ProjectError {
             ctx: None,
         }
     }
+
+    /// The step out of `previous` took longer than `limit`
+    pub fn timed_out(previous: Project, limit: Duration) -> Self {
+        Self {
+            kind: ProjectErrorKind::TimedOut,
+            message: format!(
+                "project did not get out of the `{}` state within {}s",
+                previous.label(),
+                limit.as_secs()
+            ),
+            ctx: Some(Box::new(previous)),
+        }
+    }
+
+    /// The worker gave up on moving `previous` along after it failed
+    /// with each of `errors` in turn
+    pub fn retries_exhausted(previous: Project, errors: &[String]) -> Self {
+        Self {
+            kind: ProjectErrorKind::RetriesExhausted,
+            message: format!(
+                "gave up after {} failed attempts: {}",
+                errors.len(),
+                errors.join("; ")
+            ),
+            ctx: Some(Box::new(previous)),
+        }
+    }
+
+    /// The task which found the project in `previous` was not over
+    /// within `limit`
+    pub fn task_deadline(previous: Project, limit: Duration) -> Self {
+        Self {
+            kind: ProjectErrorKind::TaskDeadline,
+            message: format!(
+                "project task did not finish within {}s, starting out of the `{}` state",
+                limit.as_secs(),
+                previous.label()
+            ),
+            ctx: Some(Box::new(previous)),
+        }
+    }
+
+    /// The task which found the project in `previous` panicked with
+    /// `message`
+    pub fn internal_panic(previous: Project, message: &str) -> Self {
+        Self {
+            kind: ProjectErrorKind::InternalPanic,
+            message: format!(
+                "project task panicked out of the `{}` state: {message}",
+                previous.label()
+            ),
+            ctx: Some(Box::new(previous)),
+        }
+    }
 }
 
 impl std::fmt::Display for ProjectError {
@@ -1516,7 +1844,7 @@ pub mod exec {
 
     use crate::{
         service::GatewayService,
-        task::{self, BoxedTask, TaskResult},
+        task::{BoxedTask, Operation, Origin, Work},
     };
 
     use super::*;
@@ -1526,33 +1854,32 @@ pub mod exec {
         sender: Sender<BoxedTask>,
     ) -> Result<(), ProjectError> {
         for (project_name, _) in gateway
-            .iter_projects()
+            .iter_projects_to_reconcile()
             .await
             .expect("could not list projects")
         {
             match gateway.find_project(&project_name).await.unwrap() {
                 Project::Errored(ProjectError { ctx: Some(ctx), .. }) => {
                     if let Some(container) = ctx.container() {
-                        if let Ok(container) = gateway
-                            .context()
-                            .docker()
-                            .inspect_container(safe_unwrap!(container.id), None)
-                            .await
+                        if let Ok(container) =
+                            limited_docker(&gateway.context(), DockerCall::Inspect)
+                                .await
+                                .inspect_container(safe_unwrap!(container.id), None)
+                                .await
                         {
                             match container.state {
                                 Some(ContainerState {
                                     status: Some(ContainerStateStatusEnum::EXITED),
                                     ..
                                 }) => {
-                                    debug!("{} will be revived", project_name.clone());
+                                    debug!("{project_name:#} will be revived");
                                     _ = gateway
                                         .new_task()
-                                        .project(project_name)
-                                        .and_then(task::run(|ctx| async move {
-                                            TaskResult::Done(Project::Rebooting(ProjectRebooting {
-                                                container: ctx.state.container().unwrap(),
-                                            }))
-                                        }))
+                                        .work(Work::new(
+                                            project_name,
+                                            Operation::Restart,
+                                            Origin::Admin,
+                                        ))
                                         .send(&sender)
                                         .await;
                                 }
@@ -1565,18 +1892,15 @@ pub mod exec {
                                     ..
                                 }) => {
                                     debug!(
-                                    "{} is errored but ready according to docker. So restarting it",
-                                    project_name.clone()
-                                );
+                                        "{project_name:#} is errored but ready according to docker. So restarting it"
+                                    );
                                     _ = gateway
                                         .new_task()
-                                        .project(project_name)
-                                        .and_then(task::run(|ctx| async move {
-                                            TaskResult::Done(Project::Starting(ProjectStarting {
-                                                container: ctx.state.container().unwrap(),
-                                                restart_count: 0,
-                                            }))
-                                        }))
+                                        .work(Work::new(
+                                            project_name,
+                                            Operation::Start,
+                                            Origin::Admin,
+                                        ))
                                         .send(&sender)
                                         .await;
                                 }
@@ -1587,21 +1911,15 @@ pub mod exec {
                 }
                 // Currently nothing should enter the stopped state
                 Project::Stopped(ProjectStopped { container }) => {
-                    if let Ok(container) = gateway
-                        .context()
-                        .docker()
+                    if let Ok(container) = limited_docker(&gateway.context(), DockerCall::Inspect)
+                        .await
                         .inspect_container(safe_unwrap!(container.id), None)
                         .await
                     {
                         if container.state.is_some() {
                             _ = gateway
                                 .new_task()
-                                .project(project_name)
-                                .and_then(task::run(|ctx| async move {
-                                    TaskResult::Done(Project::Rebooting(ProjectRebooting {
-                                        container: ctx.state.container().unwrap(),
-                                    }))
-                                }))
+                                .work(Work::new(project_name, Operation::Restart, Origin::Admin))
                                 .send(&sender)
                                 .await;
                         }
@@ -1623,10 +1941,20 @@ pub mod exec {
             .await
             .expect("could not list projects")
         {
+            // Being told to destroy everything is as explicit as it gets
+            if let Err(err) = gateway.readmit_project(&project_name).await {
+                warn!(%project_name, error = %err, "could not readmit project");
+            }
+
+            gateway.cancellations().cancel(&project_name);
+
+            if let Err(err) = gateway.finish_deployment(&project_name).await {
+                warn!(%project_name, error = %err, "could not finish the deployment of the project");
+            }
+
             let _ = gateway
                 .new_task()
-                .project(project_name)
-                .and_then(task::destroy())
+                .work(Work::new(project_name, Operation::Destroy, Origin::Admin))
                 .send(&sender)
                 .await;
         }
@@ -1638,31 +1966,377 @@ pub mod exec {
 #[cfg(test)]
 pub mod tests {
 
+    use bollard::exec::{CreateExecOptions, StartExecResults};
     use bollard::models::ContainerState;
     use bollard::service::NetworkSettings;
     use futures::prelude::*;
     use hyper::{Body, Request, StatusCode};
+    use serde_json::json;
 
     use super::*;
-    use crate::tests::{assert_matches, assert_stream_matches, World};
+    use crate::tests::{assert_matches, assert_stream_matches, World, WorldContext};
     use crate::EndStateExt;
 
+    #[test]
+    fn docker_errors_are_classified() {
+        let kind = |err: DockerError| Error::from(err).kind();
+
+        assert_eq!(
+            kind(DockerError::DockerResponseServerError {
+                status_code: 404,
+                message: "No such container: matrix_run".to_string(),
+            }),
+            ErrorKind::ProjectNotFound
+        );
+        assert_eq!(
+            kind(DockerError::IOError {
+                err: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+            }),
+            ErrorKind::DockerUnavailable
+        );
+        assert_eq!(
+            kind(DockerError::RequestTimeoutError),
+            ErrorKind::DockerUnavailable
+        );
+        assert_eq!(
+            kind(DockerError::DockerResponseServerError {
+                status_code: 500,
+                message: "oops".to_string(),
+            }),
+            ErrorKind::Internal
+        );
+    }
+
+    #[test]
+    fn project_step_timeouts() {
+        let timeout_duration = |project: Project| State::<WorldContext>::timeout_duration(&project);
+
+        let container = ContainerInspectResponse::default();
+
+        assert_eq!(
+            timeout_duration(Project::Starting(ProjectStarting {
+                container: container.clone(),
+                restart_count: 0,
+            })),
+            Some(Duration::from_secs(5 * 60))
+        );
+        assert_eq!(
+            timeout_duration(Project::Stopping(ProjectStopping {
+                container: container.clone(),
+            })),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            timeout_duration(Project::Stopped(ProjectStopped { container })),
+            None
+        );
+        assert_eq!(
+            timeout_duration(Project::Destroyed(ProjectDestroyed { destroyed: None })),
+            None
+        );
+    }
+
+    fn the_container() -> ContainerInspectResponse {
+        ContainerInspectResponse {
+            id: Some("the-container".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// A project in every one of the states it can be in, in order
+    fn one_of_each_state() -> Vec<Project> {
+        let container = the_container();
+        let service = Service {
+            name: "matrix".parse().unwrap(),
+            target: IpAddr::from([10, 0, 0, 1]),
+            last_check: None,
+        };
+
+        vec![
+            Project::Creating(ProjectCreating::new(
+                "matrix".parse().unwrap(),
+                "initial-key".to_string(),
+                0,
+            )),
+            Project::Attaching(ProjectAttaching {
+                container: container.clone(),
+                recreate_count: 1,
+            }),
+            Project::Recreating(ProjectRecreating {
+                container: container.clone(),
+                recreate_count: 1,
+            }),
+            Project::Starting(ProjectStarting {
+                container: container.clone(),
+                restart_count: 2,
+            }),
+            Project::Restarting(ProjectRestarting {
+                container: container.clone(),
+                restart_count: 2,
+            }),
+            Project::Started(ProjectStarted::new(container.clone(), VecDeque::new())),
+            Project::Ready(ProjectReady {
+                container: container.clone(),
+                service,
+                stats: VecDeque::new(),
+            }),
+            Project::Rebooting(ProjectRebooting {
+                container: container.clone(),
+            }),
+            Project::Stopping(ProjectStopping {
+                container: container.clone(),
+            }),
+            Project::Stopped(ProjectStopped {
+                container: container.clone(),
+            }),
+            Project::Destroying(ProjectDestroying { container }),
+            Project::Destroyed(ProjectDestroyed { destroyed: None }),
+            Project::Errored(ProjectError::internal("there is no spoon")),
+        ]
+    }
+
+    #[test]
+    fn project_labels() {
+        let labels = [
+            "creating",
+            "attaching",
+            "recreating",
+            "starting",
+            "restarting",
+            "started",
+            "ready",
+            "rebooting",
+            "stopping",
+            "stopped",
+            "destroying",
+            "destroyed",
+            "errored",
+        ];
+
+        for (project, label) in one_of_each_state().into_iter().zip(labels) {
+            assert_eq!(project.label(), label);
+
+            // The label is what the state is stored and sent as
+            let serialized = serde_json::to_value(&project).unwrap();
+            assert!(
+                serialized.get(label).is_some(),
+                "{label} is not the tag of {serialized}"
+            );
+        }
+    }
+
+    #[test]
+    fn project_states_as_json() {
+        let expected = [
+            json!({ "state": "creating", "details": { "recreate_count": 0 } }),
+            json!({ "state": "attaching", "details": { "container_id": "the-container", "recreate_count": 1 } }),
+            json!({ "state": "recreating", "details": { "container_id": "the-container", "recreate_count": 1 } }),
+            json!({ "state": "starting", "details": { "container_id": "the-container", "restart_count": 2 } }),
+            json!({ "state": "restarting", "details": { "container_id": "the-container", "restart_count": 2 } }),
+            json!({ "state": "started", "details": { "container_id": "the-container" } }),
+            json!({ "state": "ready", "details": { "container_id": "the-container", "target_ip": "10.0.0.1" } }),
+            json!({ "state": "rebooting", "details": { "container_id": "the-container" } }),
+            json!({ "state": "stopping", "details": { "container_id": "the-container" } }),
+            json!({ "state": "stopped", "details": { "container_id": "the-container" } }),
+            json!({ "state": "destroying", "details": { "container_id": "the-container" } }),
+            json!({ "state": "destroyed", "details": {} }),
+            json!({ "state": "errored", "details": { "container_id": null, "kind": "Internal", "message": "there is no spoon" } }),
+        ];
+
+        let states = one_of_each_state();
+        assert_eq!(states.len(), expected.len());
+
+        for (project, expected) in states.into_iter().zip(expected) {
+            assert_eq!(project.as_json_value(), expected, "{}", project.label());
+        }
+
+        // An errored project still tells which container it was in
+        let stopping = Project::Stopping(ProjectStopping {
+            container: the_container(),
+        });
+        let errored = Project::Errored(ProjectError::timed_out(stopping, Duration::from_secs(60)));
+        assert_eq!(
+            errored.as_json_value()["details"]["container_id"],
+            "the-container"
+        );
+        assert_eq!(errored.as_json_value()["details"]["previous"], "stopping");
+    }
+
+    #[test]
+    fn project_state_views() {
+        use shuttle_common::models::project::State as ShortState;
+
+        let short_states = [
+            ShortState::Creating { recreate_count: 0 },
+            ShortState::Attaching { recreate_count: 1 },
+            ShortState::Recreating { recreate_count: 1 },
+            ShortState::Starting { restart_count: 2 },
+            ShortState::Restarting { restart_count: 2 },
+            ShortState::Started,
+            ShortState::Ready,
+            ShortState::Rebooting,
+            ShortState::Stopping,
+            ShortState::Stopped,
+            ShortState::Destroying,
+            ShortState::Destroyed,
+            ShortState::Errored {
+                message: "there is no spoon".to_string(),
+            },
+        ];
+
+        for (project, short_state) in one_of_each_state().into_iter().zip(short_states) {
+            let view = ProjectStateView::from(&project);
+
+            // Tagged with the label, and read back as it was given out
+            let serialized = serde_json::to_value(&view).unwrap();
+            assert_eq!(serialized["state"], project.label());
+            assert_eq!(
+                serde_json::from_value::<ProjectStateView>(serialized).unwrap(),
+                view
+            );
+
+            assert_eq!(ShortState::from(project), short_state);
+        }
+
+        for (kind, expected) in [
+            (ProjectErrorKind::Internal, ErroredKind::Internal),
+            (ProjectErrorKind::NoNetwork, ErroredKind::NoNetwork),
+            (ProjectErrorKind::TimedOut, ErroredKind::TimedOut),
+            (
+                ProjectErrorKind::RetriesExhausted,
+                ErroredKind::RetriesExhausted,
+            ),
+            (ProjectErrorKind::TaskDeadline, ErroredKind::TaskDeadline),
+            (ProjectErrorKind::InternalPanic, ErroredKind::InternalPanic),
+        ] {
+            // Both are stored and sent under the same name
+            let errored = ErroredKind::from(kind.clone());
+            assert_eq!(
+                serde_json::to_value(kind).unwrap(),
+                serde_json::to_value(errored).unwrap()
+            );
+            assert_eq!(errored, expected);
+        }
+    }
+
+    #[test]
+    fn project_state_view_timestamps() {
+        let container = ContainerInspectResponse {
+            state: Some(ContainerState {
+                started_at: Some("2023-04-01T10:00:00.5Z".to_string()),
+                finished_at: Some("0001-01-01T00:00:00Z".to_string()),
+                ..Default::default()
+            }),
+            ..the_container()
+        };
+
+        let started = Project::Started(ProjectStarted {
+            container: container.clone(),
+            service: None,
+            stats: Default::default(),
+        });
+        assert_eq!(
+            started.as_json_value()["details"]["started_at"],
+            "2023-04-01T10:00:00.500Z"
+        );
+
+        // Docker gives the zero time for what has not happened yet
+        let stopped = Project::Stopped(ProjectStopped { container });
+        assert_eq!(
+            ProjectStateView::from(&stopped),
+            ProjectStateView::Stopped {
+                container_id: Some("the-container".to_string()),
+                finished_at: None,
+            }
+        );
+    }
+
+    #[test]
+    fn project_retry_policies() {
+        for project in one_of_each_state() {
+            let expected = match project {
+                Project::Destroying(_) => RetryPolicy::none(),
+                _ => RetryPolicy::default(),
+            };
+
+            assert_eq!(
+                State::<WorldContext>::retry_policy(&project),
+                expected,
+                "{} has the wrong policy",
+                project.label()
+            );
+        }
+
+        assert!(RetryPolicy::none().is_exhausted(1));
+        assert!(!RetryPolicy::default().is_exhausted(1));
+    }
+
+    #[test]
+    fn project_recreated_from_container_keeps_its_resources() {
+        let container: ContainerInspectResponse = serde_json::from_value(json!({
+            "Id": "the-container",
+            "Args": ["--admin-secret", "key", "--project", "MyApp"],
+            "Config": { "Labels": { "shuttle.project": "MyApp" } }
+        }))
+        .unwrap();
+
+        let creating = ProjectCreating::from_container(container, 1).unwrap();
+        assert_eq!(creating.project_name().as_str(), "myapp");
+        assert_eq!(creating.resource_name(), "MyApp");
+
+        // Projects named in lowercase go by their name
+        let creating = ProjectCreating::new("myapp".parse().unwrap(), "key".to_string(), 0)
+            .with_resource_name("myapp".to_string());
+        assert_eq!(creating.resource_name(), "myapp");
+        assert_eq!(
+            serde_json::to_value(&creating)
+                .unwrap()
+                .get("resource_name"),
+            None
+        );
+    }
+
+    #[test]
+    fn project_timed_out_keeps_its_container() {
+        let container = ContainerInspectResponse {
+            id: Some("the-container".to_string()),
+            ..Default::default()
+        };
+        let stopping = Project::Stopping(ProjectStopping { container });
+
+        let errored = Project::Errored(ProjectError::timed_out(stopping, Duration::from_secs(60)));
+
+        assert_eq!(errored.container_id().as_deref(), Some("the-container"));
+        assert!(matches!(
+            errored,
+            Project::Errored(ProjectError {
+                kind: ProjectErrorKind::TimedOut,
+                ..
+            })
+        ));
+    }
+
     #[tokio::test]
+    #[ignore]
     async fn create_start_stop_destroy_project() -> anyhow::Result<()> {
         let world = World::new().await;
 
         let ctx = world.context();
 
+        let project_name = ProjectName::generate_unique();
+
         let project_started = assert_matches!(
             ctx,
             Project::Creating(ProjectCreating {
-                project_name: "my-project-test".parse().unwrap(),
+                project_name: project_name.clone(),
                 initial_key: "test".to_string(),
                 fqdn: None,
                 image: None,
                 from: None,
                 recreate_count: 0,
                 idle_minutes: 0,
+                allow_internet: true,
+                resource_name: None,
             }),
             #[assertion = "Container created, attach network"]
             Ok(Project::Attaching(ProjectAttaching {
@@ -1709,27 +2383,27 @@ pub mod tests {
             })) if id == container_id,
         );
 
-        let delay = sleep(Duration::from_secs(10));
-        futures::pin_mut!(delay);
-        let mut project_readying = project_started
+        let project_ready = project_started
             .unwrap()
-            .into_stream(&ctx)
-            .take_until(delay)
-            .try_skip_while(|state| future::ready(Ok(!matches!(state, Project::Ready(_)))));
-
-        let project_ready = assert_stream_matches!(
-            project_readying,
-            #[assertion = "Container is ready"]
-            Ok(Project::Ready(ProjectReady {
-                container: ContainerInspectResponse {
-                    state: Some(ContainerState {
-                        status: Some(ContainerStateStatusEnum::RUNNING),
+            .into_final_with_timeout(&ctx, Duration::from_secs(10))
+            .await
+            .expect("the project should be ready within 10 seconds");
+
+        assert!(
+            matches!(
+                &project_ready,
+                Ok(Project::Ready(ProjectReady {
+                    container: ContainerInspectResponse {
+                        state: Some(ContainerState {
+                            status: Some(ContainerStateStatusEnum::RUNNING),
+                            ..
+                        }),
                         ..
-                    }),
+                    },
                     ..
-                },
-                ..
-            })),
+                }))
+            ),
+            "Container is ready: {project_ready:#?}"
         );
 
         let target_addr = project_ready
@@ -1743,7 +2417,7 @@ pub mod tests {
 
         client
             .request(
-                Request::get("/projects/my-project-test/status")
+                Request::get(format!("/projects/{project_name}/status"))
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -1776,4 +2450,109 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    #[ignore]
+    async fn extra_hosts_end_up_in_etc_hosts() {
+        let world = World::new().await;
+
+        let mut ctx = world.context();
+        ctx.container_settings.extra_hosts = vec!["vault.internal:10.99.0.42".to_string()];
+
+        let project = Project::Creating(ProjectCreating::new(
+            ProjectName::generate_unique(),
+            "test".to_string(),
+            0,
+        ))
+        .into_final_with_timeout(&ctx, Duration::from_secs(10))
+        .await
+        .expect("the project should be ready within 10 seconds");
+
+        let Ok(Project::Ready(ProjectReady { container, .. })) = &project else {
+            panic!("Container is not ready: {project:#?}");
+        };
+
+        let exec = ctx
+            .docker()
+            .create_exec(
+                container.id.as_deref().unwrap(),
+                CreateExecOptions {
+                    cmd: Some(vec!["cat", "/etc/hosts"]),
+                    attach_stdout: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let StartExecResults::Attached { output, .. } =
+            ctx.docker().start_exec(&exec.id, None).await.unwrap()
+        else {
+            panic!("the exec should be attached to");
+        };
+        let hosts: String = output
+            .map_ok(|output| output.to_string())
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert!(
+            hosts
+                .lines()
+                .any(|line| line.split_whitespace().collect::<Vec<_>>()
+                    == ["10.99.0.42", "vault.internal"]),
+            "{hosts}"
+        );
+
+        project
+            .unwrap()
+            .destroy()
+            .unwrap()
+            .into_final(&ctx)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn projects_kept_off_the_internet_are_only_on_the_isolated_network() {
+        let world = World::new().await;
+
+        let ctx = world.context();
+
+        let Some(isolated_network_name) = ctx.container_settings.isolated_network_name.clone()
+        else {
+            // Only runs with `SHUTTLE_GATEWAY_ISOLATED_NETWORK_NAME` set
+            return;
+        };
+
+        let project = Project::Creating(
+            ProjectCreating::new(ProjectName::generate_unique(), "test".to_string(), 0)
+                .with_allow_internet(false),
+        )
+        .into_final_with_timeout(&ctx, Duration::from_secs(10))
+        .await
+        .expect("the project should be ready within 10 seconds");
+
+        let Ok(Project::Ready(ProjectReady { container, .. })) = &project else {
+            panic!("Container is not ready: {project:#?}");
+        };
+
+        assert!(!container.allow_internet());
+        assert_eq!(
+            container
+                .network_settings
+                .as_ref()
+                .and_then(|settings| settings.networks.as_ref())
+                .map(|networks| networks.keys().collect::<Vec<_>>()),
+            Some(vec![&isolated_network_name])
+        );
+
+        project
+            .unwrap()
+            .destroy()
+            .unwrap()
+            .into_final(&ctx)
+            .await
+            .unwrap();
+    }
 }
@@ -29,9 +29,10 @@ use tracing::{debug_span, error, field, trace};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::acme::{AcmeClient, ChallengeResponderLayer, CustomDomain};
+use crate::problem::ErrorFormat;
 use crate::service::GatewayService;
 use crate::task::BoxedTask;
-use crate::{Error, ErrorKind};
+use crate::{Error, ErrorKind, ProjectName};
 
 static PROXY_CLIENT: Lazy<ReverseProxy<HttpConnector<GaiResolver>>> =
     Lazy::new(|| ReverseProxy::new(Client::new()));
@@ -101,14 +102,10 @@ impl UserProxy {
 
         let project_name =
             if fqdn.is_subdomain_of(&self.public) && fqdn.depth() - self.public.depth() == 1 {
-                fqdn.labels()
-                    .next()
-                    .unwrap()
-                    .to_owned()
-                    .parse()
+                ProjectName::try_from(req.headers())
                     .map_err(|_| Error::from_kind(ErrorKind::ProjectNotFound))?
             } else if let Ok(CustomDomain { project_name, .. }) =
-                self.gateway.project_details_for_custom_domain(&fqdn).await
+                self.gateway.resolve_custom_domain(&fqdn).await
             {
                 project_name
             } else {
@@ -164,9 +161,12 @@ impl Service<Request<Body>> for UserProxy {
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
         let task_sender = self.task_sender.clone();
+        // Only the errors of the gateway itself, never the responses of the
+        // projects, are rendered as asked
+        let format = ErrorFormat::of(req.headers());
         self.clone()
             .proxy(task_sender, req)
-            .or_else(|err: Error| future::ready(Ok(err.into_response())))
+            .or_else(move |err: Error| future::ready(Ok(format.render(err.into_response()))))
             .boxed()
     }
 }
@@ -185,31 +185,29 @@ impl<'r> AsResponderTo<&'r AddrStream> for Bouncer {
 
 impl Bouncer {
     async fn bounce(self, req: Request<Body>) -> Result<Response, Error> {
-        let mut resp = Response::builder();
-
-        let host = req.headers().typed_get::<Host>().unwrap();
+        let Some(host) = req.headers().typed_get::<Host>() else {
+            return Ok(Error::from_kind(ErrorKind::BadHost).into_response());
+        };
         let hostname = host.hostname();
         let fqdn = fqdn!(hostname);
 
         let path = req.uri();
 
         if fqdn.is_subdomain_of(&self.public)
-            || self
-                .gateway
-                .project_details_for_custom_domain(&fqdn)
-                .await
-                .is_ok()
+            || self.gateway.resolve_custom_domain(&fqdn).await.is_ok()
         {
-            resp = resp
+            let body = <Body as HttpBody>::map_err(Body::empty(), axum::Error::new).boxed_unsync();
+
+            Ok(Response::builder()
                 .status(301)
-                .header("Location", format!("https://{hostname}{path}"));
+                .header("Location", format!("https://{hostname}{path}"))
+                .body(body)
+                .unwrap())
         } else {
-            resp = resp.status(404);
+            // Not a host of any project, in the same shape as any other
+            // error of the gateway
+            Ok(Error::from_kind(ErrorKind::ProjectNotFound).into_response())
         }
-
-        let body = <Body as HttpBody>::map_err(Body::empty(), axum::Error::new).boxed_unsync();
-
-        Ok(resp.body(body).unwrap())
     }
 }
 
@@ -224,7 +222,11 @@ impl Service<Request<Body>> for Bouncer {
     }
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        self.clone().bounce(req).boxed()
+        let format = ErrorFormat::of(req.headers());
+        self.clone()
+            .bounce(req)
+            .map_ok(move |response| format.render(response))
+            .boxed()
     }
 }
 
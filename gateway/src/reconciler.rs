@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, info, warn};
+
+use crate::service::GatewayService;
+use crate::task::{BoxedTask, Operation, Origin, Priority, TaskHandle, Work};
+use crate::{Error, ProjectName};
+
+/// Re-drives the projects which got stuck in the middle of a transition,
+/// say in `starting` when the gateway went down, with nothing queued up
+/// to move them along
+pub struct Reconciler {
+    service: Arc<GatewayService>,
+    sender: Sender<BoxedTask>,
+    stuck_after: chrono::Duration,
+    /// The work queued up for each project, so that no more is stacked on
+    /// top of it until it is done
+    queued: HashMap<ProjectName, TaskHandle>,
+}
+
+impl Reconciler {
+    /// Re-drive the projects which have not changed state for longer
+    /// than `stuck_after`
+    pub fn new(
+        service: Arc<GatewayService>,
+        sender: Sender<BoxedTask>,
+        stuck_after: Duration,
+    ) -> Self {
+        Self {
+            service,
+            sender,
+            stuck_after: chrono::Duration::from_std(stuck_after)
+                .unwrap_or_else(|_| chrono::Duration::max_value()),
+            queued: HashMap::new(),
+        }
+    }
+
+    /// Queue up work for the stuck projects which have none queued yet,
+    /// returning how many projects that is
+    pub async fn reconcile(&mut self) -> Result<usize, Error> {
+        let Some(since) = Utc::now().checked_sub_signed(self.stuck_after) else {
+            return Ok(0);
+        };
+
+        self.queued.retain(|_, handle| !handle.is_done());
+
+        let mut redriven = 0;
+        for (project_name, state, updated_at) in self.service.iter_stuck_projects(since).await? {
+            if self.queued.contains_key(&project_name) {
+                debug!(%project_name, state, "stuck project already has work queued");
+                continue;
+            }
+
+            let stuck_for = updated_at.and_then(|at| (Utc::now() - at).to_std().ok());
+            info!(
+                %project_name,
+                state,
+                ?stuck_for,
+                "re-driving project stuck in a transition"
+            );
+
+            let handle = self
+                .service
+                .new_task()
+                .work(Work::new(
+                    project_name.clone(),
+                    Operation::Refresh,
+                    Origin::Reconciler,
+                ))
+                .priority(Priority::Background)
+                .send(&self.sender)
+                .await?;
+
+            self.queued.insert(project_name, handle);
+            redriven += 1;
+        }
+
+        Ok(redriven)
+    }
+
+    /// Reconcile every `interval`, for as long as the gateway runs
+    pub async fn run(mut self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await; // first tick is immediate
+
+        loop {
+            interval.tick().await;
+
+            match self.reconcile().await {
+                Ok(0) => {}
+                Ok(redriven) => info!(redriven, "re-drove stuck projects"),
+                Err(err) => warn!(error = %err, "failed to reconcile stuck projects"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::query;
+
+    use super::*;
+    use crate::task::Task;
+    use crate::tests::World;
+    use crate::AccountName;
+
+    #[tokio::test]
+    #[ignore]
+    async fn reconciler_redrives_stuck_projects_once() {
+        let world = World::new().await;
+        let svc = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(8);
+        let mut reconciler = Reconciler::new(svc.clone(), sender, Duration::from_secs(60));
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        svc.create_project(matrix.clone(), neo, false, 0, true)
+            .await
+            .unwrap();
+
+        // Only just created, so not stuck yet
+        assert_eq!(reconciler.reconcile().await.unwrap(), 0);
+
+        query("UPDATE projects SET updated_at = ?1 WHERE project_name = ?2")
+            .bind(Utc::now() - chrono::Duration::minutes(5))
+            .bind(&matrix)
+            .execute(&world.pool())
+            .await
+            .unwrap();
+
+        assert_eq!(reconciler.reconcile().await.unwrap(), 1);
+
+        // The work is still queued up, so nothing more is added
+        assert_eq!(reconciler.reconcile().await.unwrap(), 0);
+
+        let task = receiver.recv().await.unwrap();
+        assert_eq!(task.project_name(), Some(matrix.clone()));
+        drop(task);
+
+        // Once it is over with the project still stuck, it is re-driven
+        // again
+        assert_eq!(reconciler.reconcile().await.unwrap(), 1);
+
+        // Dead-lettered projects are left alone
+        query("UPDATE projects SET dead_lettered_at = ?1 WHERE project_name = ?2")
+            .bind(Utc::now())
+            .bind(&matrix)
+            .execute(&world.pool())
+            .await
+            .unwrap();
+        drop(receiver.recv().await.unwrap());
+
+        assert_eq!(reconciler.reconcile().await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::events::ProjectEvent;
+use crate::ProjectName;
+
+/// How long lookups of a project keep going to the primary after this
+/// gateway changed its state, giving the replica time to catch up
+pub const REPLICA_LAG_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// A read-only copy of the state database, for the lookups which can
+/// live with it lagging a little behind the primary
+#[derive(Clone)]
+pub struct ReadReplica {
+    pool: SqlitePool,
+    /// When each project last changed state through this gateway
+    changed_at: Arc<Mutex<HashMap<ProjectName, Instant>>>,
+}
+
+impl ReadReplica {
+    /// Open the replica at `uri`, a path or `sqlite:` URL. Connections
+    /// are only made once needed, so a replica which is not there yet
+    /// does not keep the gateway from starting.
+    pub fn connect(
+        uri: &str,
+        events: broadcast::Receiver<ProjectEvent>,
+    ) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(uri)?.read_only(true);
+        let pool = SqlitePoolOptions::new()
+            .acquire_timeout(Duration::from_secs(5))
+            .connect_lazy_with(options);
+
+        Ok(Self::new(pool, events))
+    }
+
+    /// Serve reads from `pool`, going around it for the projects which
+    /// show up in `events`
+    pub fn new(pool: SqlitePool, events: broadcast::Receiver<ProjectEvent>) -> Self {
+        let changed_at = Arc::default();
+
+        tokio::spawn(track_changes(events, Arc::clone(&changed_at)));
+
+        Self { pool, changed_at }
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Whether the replica can be trusted to know about the latest
+    /// state of `project_name`
+    pub fn is_caught_up(&self, project_name: &ProjectName) -> bool {
+        let mut changed_at = self.changed_at.lock().unwrap();
+
+        match changed_at.get(project_name) {
+            Some(at) if at.elapsed() < REPLICA_LAG_TOLERANCE => false,
+            Some(_) => {
+                changed_at.remove(project_name);
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+async fn track_changes(
+    mut events: broadcast::Receiver<ProjectEvent>,
+    changed_at: Arc<Mutex<HashMap<ProjectName, Instant>>>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(ProjectEvent { name, .. }) => {
+                let mut changed_at = changed_at.lock().unwrap();
+                changed_at.retain(|_, at| at.elapsed() < REPLICA_LAG_TOLERANCE);
+                changed_at.insert(name, Instant::now());
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                // There is no telling which projects the missed events
+                // were about, so stale reads are possible for a moment
+                warn!(missed, "read replica fell behind on project changes");
+                events = events.resubscribe();
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, info, warn};
+
+use crate::service::GatewayService;
+use crate::task::{BoxedTask, Operation, TaskHandle};
+use crate::{Error, ProjectName};
+
+/// Queues up the work [scheduled](GatewayService::schedule_work) for
+/// later once it is due, e.g. destroying a project once its grace period
+/// is over
+pub struct Scheduler {
+    service: Arc<GatewayService>,
+    sender: Sender<BoxedTask>,
+    /// The work queued up for each project, so that scheduled work for
+    /// the same project waits until it is done
+    queued: HashMap<ProjectName, TaskHandle>,
+}
+
+impl Scheduler {
+    pub fn new(service: Arc<GatewayService>, sender: Sender<BoxedTask>) -> Self {
+        Self {
+            service,
+            sender,
+            queued: HashMap::new(),
+        }
+    }
+
+    /// Queue up the scheduled work which is due, returning how much of it
+    /// was. The same operation scheduled more than once for a project is
+    /// only queued up once, and work for a project which still has some
+    /// queued up is left for later.
+    pub async fn dispatch_due(&mut self) -> Result<usize, Error> {
+        self.queued.retain(|_, handle| !handle.is_done());
+
+        let mut seen: HashSet<(ProjectName, Operation)> = HashSet::new();
+        let mut dispatched = 0;
+
+        for scheduled in self.service.due_work(Utc::now()).await? {
+            let work = &scheduled.work;
+            let project_name = work.project.clone();
+
+            if !seen.insert((project_name.clone(), work.operation)) {
+                debug!(
+                    %project_name,
+                    operation = work.operation.as_str(),
+                    "dropping scheduled work which is already due"
+                );
+                self.service.forget_scheduled_work(scheduled.id).await?;
+                continue;
+            }
+
+            if self.queued.contains_key(&project_name) {
+                debug!(%project_name, "project already has scheduled work queued");
+                continue;
+            }
+
+            info!(
+                %project_name,
+                operation = work.operation.as_str(),
+                run_after = %scheduled.run_after,
+                "queuing up scheduled work"
+            );
+
+            let handle = self
+                .service
+                .new_task()
+                .work(scheduled.work.clone())
+                .priority(scheduled.priority)
+                .send(&self.sender)
+                .await?;
+
+            // Should this fail, the work is queued up again on the next
+            // round, which is better than it not being at all
+            if let Err(err) = self.service.forget_scheduled_work(scheduled.id).await {
+                warn!(error = %err, "could not forget about queued up scheduled work");
+            }
+
+            self.queued.insert(project_name, handle);
+            dispatched += 1;
+        }
+
+        Ok(dispatched)
+    }
+
+    /// Look for due work every `interval`, for as long as the gateway runs
+    pub async fn run(mut self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            match self.dispatch_due().await {
+                Ok(0) => {}
+                Ok(dispatched) => debug!(dispatched, "queued up scheduled work"),
+                Err(err) => warn!(error = %err, "failed to queue up scheduled work"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+    use crate::task::{Origin, Priority, Work};
+    use crate::tests::World;
+    use crate::worker::Worker;
+    use crate::AccountName;
+
+    #[tokio::test]
+    #[ignore]
+    async fn scheduled_work_survives_a_restart() {
+        let world = World::new().await;
+        let svc = Arc::new(GatewayService::init(world.args(), world.pool(), "".into()).await);
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        svc.create_project(matrix.clone(), neo, false, 0, true)
+            .await
+            .unwrap();
+
+        let work = Work::new(matrix.clone(), Operation::Destroy, Origin::Admin);
+        let run_after = Utc::now() + chrono::Duration::seconds(2);
+        svc.schedule_work(&work, Priority::Background, run_after)
+            .await
+            .unwrap();
+        // Scheduled twice over, but only carried out once
+        svc.schedule_work(&work, Priority::Background, run_after)
+            .await
+            .unwrap();
+
+        // Not due yet when the gateway goes down...
+        let worker = Worker::new();
+        let mut scheduler = Scheduler::new(svc.clone(), worker.sender());
+        assert_eq!(scheduler.dispatch_due().await.unwrap(), 0);
+        drop(scheduler);
+        drop(worker);
+
+        // ...but still there for the next one once it is
+        let worker = Worker::new();
+        let mut scheduler = Scheduler::new(svc.clone(), worker.sender());
+        tokio::spawn(worker.start());
+
+        sleep(Duration::from_secs(2)).await;
+        assert_eq!(scheduler.dispatch_due().await.unwrap(), 1);
+        assert_eq!(scheduler.dispatch_due().await.unwrap(), 0);
+        assert!(svc.due_work(Utc::now()).await.unwrap().is_empty());
+
+        timeout(Duration::from_secs(30), async {
+            while !svc.find_project(&matrix).await.unwrap().is_destroyed() {
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("the scheduled work was not carried out");
+    }
+}
@@ -1,19 +1,27 @@
-use futures::Future;
-use std::collections::VecDeque;
+use chrono::{DateTime, Utc};
+use futures::future::Shared;
+use futures::{Future, FutureExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::marker::PhantomData;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::Sender;
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch, Mutex as AsyncMutex, OwnedMutexGuard};
 use tokio::time::{sleep, timeout};
-use tracing::{error, info_span, trace, warn};
+use tracing::{debug, error, info, info_span, trace, warn};
 use uuid::Uuid;
 
 use crate::project::*;
-use crate::service::{GatewayContext, GatewayService};
-use crate::worker::TaskRouter;
-use crate::{AccountName, EndState, Error, ErrorKind, ProjectName, Refresh, State};
+use crate::service::{GatewayContext, GatewayService, TaskRetries, PROJECT_LEASE_DURATION};
+use crate::worker::{panic_message, TaskRouter};
+use crate::{
+    next_with_timeout, AccountName, EndState, Error, ErrorKind, ProjectName, Refresh, State,
+};
 
 // Default maximum _total_ time a task is allowed to run
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
@@ -21,6 +29,308 @@ pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
 pub const TASK_SEND_TIMEOUT: Duration = Duration::from_secs(9);
 // Maximum time before a task is considered degraded
 pub const PROJECT_TASK_MAX_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// How long to wait before trying again when the state store is unavailable
+pub const STATE_STORE_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+// How long to wait before checking again on a project leased by another gateway
+pub const LEASE_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+// How often the lease on a project is renewed while a task is polled, well
+// within the lease duration for a slow renewal not to let it run out
+pub const LEASE_HEARTBEAT_INTERVAL: Duration =
+    Duration::from_secs(PROJECT_LEASE_DURATION.as_secs() / 4);
+// How many times a failing project task is attempted before the project is errored
+pub const TASK_MAX_ATTEMPTS: u32 = 5;
+// How long to wait before the first retry of a failing project task, doubled for every next one
+pub const TASK_RETRY_BASE_BACKOFF: Duration = Duration::from_secs(2);
+// Longest we'll wait between two attempts at a failing project task
+pub const TASK_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How urgently a task needs to be run, which decides the lane of the
+/// [`Worker`] queue it waits in
+///
+/// [`Worker`]: crate::worker::Worker
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Someone is waiting on the other end, e.g. an API request
+    Interactive,
+    /// Reacting to something which happened, e.g. a webhook or a failed
+    /// health check
+    Health,
+    /// Nobody is waiting on it, e.g. sweeps and reconciliation
+    #[default]
+    Background,
+}
+
+impl Priority {
+    /// Every priority, most urgent first
+    pub const ALL: [Priority; 3] = [Self::Interactive, Self::Health, Self::Background];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Health => "health",
+            Self::Background => "background",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interactive" => Ok(Self::Interactive),
+            "health" => Ok(Self::Health),
+            "background" => Ok(Self::Background),
+            _ => Err(Error::custom(
+                ErrorKind::Internal,
+                format!("unknown priority: {s}"),
+            )),
+        }
+    }
+}
+
+/// How long a project task gets to run for, retries included, depending
+/// on the state it first finds the project in. Past it, the step under
+/// way is dropped and the project errored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskDeadlines {
+    /// Out of `creating`, `attaching` and `recreating`, which can involve
+    /// pulling an image
+    pub create: Duration,
+    /// Out of `starting`, `restarting`, `started`, `rebooting`, `stopped`
+    /// and `errored`
+    pub start: Duration,
+    /// Out of `stopping`, `destroying` and `destroyed`
+    pub stop: Duration,
+    /// Out of `ready`, which is mostly health checks
+    pub health: Duration,
+}
+
+impl TaskDeadlines {
+    /// The deadline of a task starting out with the project in `project`
+    pub fn for_project(&self, project: &Project) -> Duration {
+        match project {
+            Project::Creating(_) | Project::Attaching(_) | Project::Recreating(_) => self.create,
+            Project::Stopping(_) | Project::Destroying(_) | Project::Destroyed(_) => self.stop,
+            Project::Ready(_) => self.health,
+            Project::Starting(_)
+            | Project::Restarting(_)
+            | Project::Started(_)
+            | Project::Rebooting(_)
+            | Project::Stopped(_)
+            | Project::Errored(_) => self.start,
+        }
+    }
+}
+
+/// How a project task failing with an error which could be
+/// [retried](Error::is_retryable) is tried again, depending on the state it
+/// found the project in, see [`State::retry_policy`]
+///
+/// [`State::retry_policy`]: crate::State::retry_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many attempts the task gets before the project is errored,
+    /// the first one included
+    pub max_attempts: u32,
+    /// How long to wait before the first retry, doubled for every next one
+    pub base_backoff: Duration,
+    /// Longest to wait between two attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: TASK_MAX_ATTEMPTS,
+            base_backoff: TASK_RETRY_BASE_BACKOFF,
+            max_backoff: TASK_RETRY_MAX_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Error the project on the first failure, for the steps which could
+    /// do harm if run twice
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the task is out of attempts once it failed `attempts` times
+    pub fn is_exhausted(&self, attempts: u32) -> bool {
+        attempts >= self.max_attempts
+    }
+
+    /// How long to wait before attempt number `attempts + 1`: exponential
+    /// in the number of failed attempts, with jitter so that projects
+    /// which failed together do not all come back at once.
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(31);
+        let backoff = self
+            .base_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+
+        let half = backoff / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// What a piece of work queued up for a project is meant to do. Each
+/// operation maps onto the tasks which take the project there from
+/// whatever state it is in, see [`TaskBuilder::work`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Bring the project up, starting it if it is stopped or errored
+    Start,
+    /// Stop the project, keeping its container around
+    Stop,
+    /// Restart the container of the project
+    Restart,
+    /// Destroy the project
+    Destroy,
+    /// Destroy the project for it to be created anew by the tasks queued
+    /// up after it, e.g. with a new domain
+    Recreate,
+    /// See the project through to the end of the transition it is in,
+    /// then check its health
+    Refresh,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Stop => "stop",
+            Self::Restart => "restart",
+            Self::Destroy => "destroy",
+            Self::Recreate => "recreate",
+            Self::Refresh => "refresh",
+        }
+    }
+
+    /// Whether carrying out `self` makes carrying out `other` as well
+    /// pointless: the same operation twice over is done once, destroying
+    /// supersedes everything and recreating supersedes restarting
+    pub fn supersedes(&self, other: Operation) -> bool {
+        match (self, other) {
+            (this, other) if *this == other => true,
+            (Self::Destroy, _) => true,
+            (Self::Recreate, Self::Restart) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Operation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(Self::Start),
+            "stop" => Ok(Self::Stop),
+            "restart" => Ok(Self::Restart),
+            "destroy" => Ok(Self::Destroy),
+            "recreate" => Ok(Self::Recreate),
+            "refresh" => Ok(Self::Refresh),
+            _ => Err(Error::custom(
+                ErrorKind::Internal,
+                format!("unknown operation: {s}"),
+            )),
+        }
+    }
+}
+
+/// Where a piece of work comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    /// A request made to the API, or to a project through the proxy
+    Api,
+    /// The periodic health checks
+    Health,
+    /// The [`Reconciler`](crate::reconciler::Reconciler), re-driving
+    /// stuck projects
+    Reconciler,
+    /// The gateway picking up where it left off when it started
+    Startup,
+    /// The sweeps over idle and stale projects
+    Sweep,
+    /// An admin command
+    Admin,
+}
+
+impl Origin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Api => "api",
+            Self::Health => "health",
+            Self::Reconciler => "reconciler",
+            Self::Startup => "startup",
+            Self::Sweep => "sweep",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Origin {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "api" => Ok(Self::Api),
+            "health" => Ok(Self::Health),
+            "reconciler" => Ok(Self::Reconciler),
+            "startup" => Ok(Self::Startup),
+            "sweep" => Ok(Self::Sweep),
+            "admin" => Ok(Self::Admin),
+            _ => Err(Error::custom(
+                ErrorKind::Internal,
+                format!("unknown origin: {s}"),
+            )),
+        }
+    }
+}
+
+/// A piece of work queued up for a project: what is to be done to it,
+/// who asked for it and when. It follows the work through the worker
+/// into its logs and the events of the states it leads to, and how it
+/// goes is kept track of under its `id` for the API to tell.
+///
+/// It can be stored as JSON and loaded back as it was, for work to be
+/// picked up again after a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Work {
+    pub id: String,
+    pub project: ProjectName,
+    pub operation: Operation,
+    pub origin: Origin,
+    /// Ties the logs and events of the work back to what asked for it
+    pub request_id: String,
+    pub enqueued_at: DateTime<Utc>,
+}
+
+impl Work {
+    pub fn new(project: ProjectName, operation: Operation, origin: Origin) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            project,
+            operation,
+            origin,
+            request_id: Uuid::new_v4().to_string(),
+            enqueued_at: Utc::now(),
+        }
+    }
+
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = request_id;
+        self
+    }
+}
 
 #[async_trait]
 pub trait Task<Ctx>: Send {
@@ -29,6 +339,31 @@ pub trait Task<Ctx>: Send {
     type Error;
 
     async fn poll(&mut self, ctx: Ctx) -> TaskResult<Self::Output, Self::Error>;
+
+    /// A short human readable description of what this task is
+    /// working on, e.g. `project:my-project`
+    fn description(&self) -> Option<String> {
+        None
+    }
+
+    /// The project this task is about, if any. A [`Worker`] never runs
+    /// two tasks for the same project at the same time.
+    ///
+    /// [`Worker`]: crate::worker::Worker
+    fn project_name(&self) -> Option<ProjectName> {
+        None
+    }
+
+    /// How urgently this task needs to be run, and where it comes from
+    fn priority(&self) -> Priority {
+        Priority::default()
+    }
+
+    /// The piece of work this task carries out, if it was queued up as
+    /// one
+    fn work(&self) -> Option<&Work> {
+        None
+    }
 }
 
 #[async_trait]
@@ -44,6 +379,22 @@ where
     async fn poll(&mut self, ctx: Ctx) -> TaskResult<Self::Output, Self::Error> {
         self.as_mut().poll(ctx).await
     }
+
+    fn description(&self) -> Option<String> {
+        self.as_ref().description()
+    }
+
+    fn project_name(&self) -> Option<ProjectName> {
+        self.as_ref().project_name()
+    }
+
+    fn priority(&self) -> Priority {
+        self.as_ref().priority()
+    }
+
+    fn work(&self) -> Option<&Work> {
+        self.as_ref().work()
+    }
 }
 
 #[must_use]
@@ -135,6 +486,40 @@ pub fn start() -> impl Task<ProjectContext, Output = Project, Error = Error> {
     })
 }
 
+/// Start the project if it is stopped or errored, leaving it be if it
+/// is up or on its way there
+pub fn start_if_down() -> impl Task<ProjectContext, Output = Project, Error = Error> {
+    run(|ctx| async move {
+        let is_down = matches!(ctx.state, Project::Stopped(_) | Project::Errored(_));
+        if !is_down || ctx.state.container().is_none() {
+            return TaskResult::Done(ctx.state);
+        }
+
+        match ctx.state.start() {
+            Ok(state) => TaskResult::Done(state),
+            Err(err) => TaskResult::Err(err),
+        }
+    })
+}
+
+pub fn stop() -> impl Task<ProjectContext, Output = Project, Error = Error> {
+    run(|ctx| async move {
+        match ctx.state.stop() {
+            Ok(state) => TaskResult::Done(state),
+            Err(err) => TaskResult::Err(err),
+        }
+    })
+}
+
+pub fn reboot() -> impl Task<ProjectContext, Output = Project, Error = Error> {
+    run(|ctx| async move {
+        match ctx.state.reboot() {
+            Ok(state) => TaskResult::Done(state),
+            Err(err) => TaskResult::Err(err),
+        }
+    })
+}
+
 pub fn check_health() -> impl Task<ProjectContext, Output = Project, Error = Error> {
     run(|ctx| async move {
         match ctx.state.refresh(&ctx.gateway).await {
@@ -159,6 +544,11 @@ pub struct TaskBuilder {
     project_name: Option<ProjectName>,
     service: Arc<GatewayService>,
     timeout: Option<Duration>,
+    priority: Priority,
+    work: Option<Work>,
+    /// Whether tasks other than those of the operation of the work were
+    /// queued up, so that it does more than the operation says
+    custom: bool,
     tasks: VecDeque<BoxedTask<ProjectContext, Project>>,
 }
 
@@ -168,6 +558,9 @@ impl TaskBuilder {
             service,
             project_name: None,
             timeout: None,
+            priority: Priority::default(),
+            work: None,
+            custom: false,
             tasks: VecDeque::new(),
         }
     }
@@ -179,10 +572,34 @@ impl TaskBuilder {
         self
     }
 
+    /// Carry out `work`, queuing up the tasks of its operation for its
+    /// project. More tasks can be queued up after them.
+    pub fn work(mut self, work: Work) -> Self {
+        self.project_name = Some(work.project.clone());
+        self.custom |= !self.tasks.is_empty();
+        self = match work.operation {
+            Operation::Start => self
+                .and_then(start_if_down())
+                .and_then(run_until_done())
+                .and_then(check_health()),
+            Operation::Stop => self.and_then(stop()),
+            Operation::Restart => self
+                .and_then(reboot())
+                .and_then(run_until_done())
+                .and_then(check_health()),
+            Operation::Destroy => self.and_then(destroy()),
+            Operation::Recreate => self.and_then(destroy()).and_then(run_until_done()),
+            Operation::Refresh => self.and_then(run_until_done()).and_then(check_health()),
+        };
+        self.work = Some(work);
+        self
+    }
+
     pub fn and_then<T>(mut self, task: T) -> Self
     where
         T: Task<ProjectContext, Output = Project, Error = Error> + 'static,
     {
+        self.custom |= self.work.is_some();
         self.tasks.push_back(Box::new(task));
         self
     }
@@ -192,31 +609,126 @@ impl TaskBuilder {
         self
     }
 
+    /// Queue the task in the lane for `priority`, [`Priority::Background`]
+    /// unless told otherwise
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
     pub fn build(mut self) -> BoxedTask {
         self.tasks.push_back(Box::new(RunUntilDone));
 
         let timeout = self.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let project_name = self.project_name.expect("project_name is required");
+        let cancelled = self.service.cancellations().subscribe(&project_name);
 
         Box::new(WithTimeout::on(
             timeout,
             ProjectTask {
                 uuid: Uuid::new_v4(),
-                project_name: self.project_name.expect("project_name is required"),
+                project_name,
                 service: self.service,
                 tasks: self.tasks,
+                retries: None,
+                deadline: None,
+                priority: self.priority,
+                work: self.work,
+                work_started: false,
+                cancelled,
             },
         ))
     }
 
+    /// Queue the task up on `sender`. [Interactive](Priority::Interactive)
+    /// tasks fail straight away when the queue is full, for the caller
+    /// to be told to try again shortly, while the others wait up to
+    /// [`TASK_SEND_TIMEOUT`] for room.
+    ///
+    /// Work which is already taken care of by work queued up for the
+    /// project is not queued up again, the handle of that work being
+    /// given back instead, see [`QueuedWork`].
     pub async fn send(self, sender: &Sender<BoxedTask>) -> Result<TaskHandle, Error> {
         let project_name = self.project_name.clone().expect("project_name is required");
+        let service = self.service.clone();
         let task_router = self.service.task_router();
-        let (task, handle) = AndThenNotify::after(self.build());
-        let task = Route::<BoxedTask>::to(project_name, Box::new(task), task_router);
-        match timeout(TASK_SEND_TIMEOUT, sender.send(Box::new(task))).await {
-            Ok(Ok(_)) => Ok(handle),
-            _ => Err(Error::from_kind(ErrorKind::ServiceUnavailable)),
+        let metrics = self.service.metrics().clone();
+        let priority = self.priority;
+        let custom = self.custom;
+        let work = self.work.clone();
+
+        let (task, mut handle) = AndThenNotify::after(self.build());
+
+        let operation_id = match &work {
+            Some(work) => {
+                handle.operation_id = Some(work.id.clone());
+
+                // The task subscribed to cancellations along with this
+                let cancelled = service.cancellations().subscribe(&project_name);
+                if let Some(existing) =
+                    service
+                        .queued_work()
+                        .admit(work, priority, !custom, handle.clone(), cancelled)
+                {
+                    debug!(
+                        project_name = %work.project,
+                        operation = work.operation.as_str(),
+                        coalesced_into = existing.operation_id(),
+                        "work is taken care of by work already queued up"
+                    );
+                    return Ok(existing);
+                }
+
+                // Kept track of before it is queued up, for the worker
+                // could be onto it straight away
+                if let Err(err) = service.record_operation(work).await {
+                    service.queued_work().forget(&work.project, &work.id);
+                    return Err(err);
+                }
+                Some(work.id.clone())
+            }
+            None => None,
+        };
+
+        let task: BoxedTask = Box::new(
+            Route::<BoxedTask>::to(project_name, Box::new(task), task_router)
+                .with_priority(priority),
+        );
+
+        let queue_full = if priority == Priority::Interactive {
+            match sender.try_send(task) {
+                Ok(()) => return Ok(handle),
+                Err(err) => matches!(err, TrySendError::Full(_)),
+            }
+        } else {
+            match timeout(TASK_SEND_TIMEOUT, sender.send(task)).await {
+                Ok(Ok(())) => return Ok(handle),
+                Ok(Err(_)) => false,
+                Err(_) => true,
+            }
+        };
+
+        let err = if queue_full {
+            metrics.record_worker_queue_full();
+            Error::custom(ErrorKind::ServiceUnavailable, "the worker queue is full")
+        } else {
+            // Not a matter of load: nothing will take any work until the
+            // gateway is restarted
+            Error::custom(ErrorKind::NotReady, "the worker is not running")
+        };
+
+        if let Some(operation_id) = operation_id {
+            service.queued_work().forget(&project_name, &operation_id);
+
+            if let Err(err) = service
+                .finish_operation(&operation_id, Some(&err.to_string()))
+                .await
+            {
+                warn!(error = %err, "could not record the operation as failed");
+            }
         }
+
+        Err(err)
     }
 }
 
@@ -224,6 +736,7 @@ pub struct Route<T> {
     project_name: ProjectName,
     inner: Option<T>,
     router: TaskRouter<T>,
+    priority: Priority,
 }
 
 impl<T> Route<T> {
@@ -232,8 +745,14 @@ impl<T> Route<T> {
             project_name,
             inner: Some(what),
             router,
+            priority: Priority::default(),
         }
     }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
 }
 
 #[async_trait]
@@ -252,6 +771,25 @@ impl Task<()> for Route<BoxedTask> {
             TaskResult::Done(())
         }
     }
+
+    fn description(&self) -> Option<String> {
+        self.inner
+            .as_ref()
+            .and_then(|task| task.description())
+            .or_else(|| Some(format!("project:{}", self.project_name)))
+    }
+
+    fn project_name(&self) -> Option<ProjectName> {
+        Some(self.project_name.clone())
+    }
+
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    fn work(&self) -> Option<&Work> {
+        self.inner.as_ref().and_then(|task| task.work())
+    }
 }
 
 pub struct RunFn<F, O> {
@@ -284,16 +822,46 @@ impl Task<ProjectContext> for RunUntilDone {
     type Error = Error;
 
     async fn poll(&mut self, ctx: ProjectContext) -> TaskResult<Self::Output, Self::Error> {
-        if !<Project as EndState<GatewayContext>>::is_done(&ctx.state) {
-            TaskResult::Pending(ctx.state.next(&ctx.gateway).await.unwrap())
-        } else {
-            TaskResult::Done(ctx.state)
+        if <Project as EndState<GatewayContext>>::is_done(&ctx.state) {
+            return TaskResult::Done(ctx.state);
+        }
+
+        let previous = ctx.state.clone();
+        match next_with_timeout(ctx.state, &ctx.gateway).await {
+            Ok(next) => TaskResult::Pending(next.unwrap()),
+            Err(limit) => {
+                warn!(
+                    state = previous.label(),
+                    "project step timed out after {}s",
+                    limit.as_secs()
+                );
+                TaskResult::Pending(Project::Errored(ProjectError::timed_out(previous, limit)))
+            }
         }
     }
 }
 
+/// Resolves once a task is over. Clones resolve along with it.
+#[derive(Clone)]
 pub struct TaskHandle {
-    rx: oneshot::Receiver<()>,
+    done: Shared<oneshot::Receiver<()>>,
+    /// The ID of the operation the task carries out, when it carries out
+    /// a piece of [`Work`]
+    operation_id: Option<String>,
+}
+
+impl TaskHandle {
+    /// Whether the task is over, whether it ran to the end or was dropped
+    /// along the way
+    pub fn is_done(&mut self) -> bool {
+        self.done.clone().now_or_never().is_some()
+    }
+
+    /// The operation the task carries out, for it to be followed through
+    /// the API
+    pub fn operation_id(&self) -> Option<&str> {
+        self.operation_id.as_deref()
+    }
 }
 
 impl Future for TaskHandle {
@@ -303,7 +871,210 @@ impl Future for TaskHandle {
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
-        Pin::new(&mut self.rx).poll(cx).map(|_| ())
+        Pin::new(&mut self.done).poll(cx).map(|_| ())
+    }
+}
+
+/// The work queued up for each project which has not been started on
+/// yet. Work coming in which it already takes care of is coalesced into
+/// it rather than queued up too, e.g. a second restart, and the work it
+/// makes pointless is [superseded](Operation::supersedes) by it: skipped
+/// once the worker gets to it.
+#[derive(Clone, Default)]
+pub struct QueuedWork {
+    queued: Arc<Mutex<HashMap<ProjectName, Vec<QueuedOperation>>>>,
+}
+
+struct QueuedOperation {
+    operation: Operation,
+    priority: Priority,
+    /// Whether other work can be coalesced into it, which it cannot if it
+    /// does more than its operation says
+    coalescable: bool,
+    handle: TaskHandle,
+    /// Closed once the work is cancelled, which it is not coming back from
+    cancelled: watch::Receiver<()>,
+    /// The operation which made this one pointless, if any did
+    superseded_by: Option<String>,
+}
+
+impl QueuedWork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep track of `work` as queued up, unless the work queued up
+    /// already takes care of it, in which case the handle of that work is
+    /// given back instead. Work is only coalesced into work queued up in
+    /// a lane at least as urgent as `priority`, which has not been
+    /// cancelled since, as told by `cancelled`.
+    pub fn admit(
+        &self,
+        work: &Work,
+        priority: Priority,
+        coalescable: bool,
+        handle: TaskHandle,
+        cancelled: watch::Receiver<()>,
+    ) -> Option<TaskHandle> {
+        let mut queued = self.queued.lock().unwrap();
+
+        // Forget about the projects nothing is queued up for anymore,
+        // and about the work which was dropped or cancelled without being
+        // started
+        queued.retain(|_, operations| {
+            operations.retain_mut(|operation| {
+                !operation.handle.is_done() && operation.cancelled.has_changed().is_ok()
+            });
+            !operations.is_empty()
+        });
+
+        let operations = queued.entry(work.project.clone()).or_default();
+
+        if coalescable {
+            if let Some(existing) = operations.iter().find(|operation| {
+                operation.superseded_by.is_none()
+                    && operation.coalescable
+                    && operation.priority <= priority
+                    && operation.operation.supersedes(work.operation)
+            }) {
+                return Some(existing.handle.clone());
+            }
+        }
+
+        for operation in operations.iter_mut() {
+            // Work doing more than its operation says is only superseded
+            // by a different operation, which undoes the rest of it too
+            let supersedes = work.operation.supersedes(operation.operation)
+                && (operation.coalescable || work.operation != operation.operation);
+            if supersedes && operation.superseded_by.is_none() {
+                operation.superseded_by = Some(work.id.clone());
+            }
+        }
+
+        operations.push(QueuedOperation {
+            operation: work.operation,
+            priority,
+            coalescable,
+            handle,
+            cancelled,
+            superseded_by: None,
+        });
+
+        None
+    }
+
+    /// Have the work for `project_name` under `operation_id` be started
+    /// on, no longer taking other work in. If it was superseded in the
+    /// meantime, the operation it was superseded by is given back.
+    pub fn start(&self, project_name: &ProjectName, operation_id: &str) -> Option<String> {
+        self.take(project_name, operation_id)
+            .and_then(|queued| queued.superseded_by)
+    }
+
+    /// Forget about work which was not queued up after all, along with
+    /// it having superseded any other work
+    pub fn forget(&self, project_name: &ProjectName, operation_id: &str) {
+        self.take(project_name, operation_id);
+
+        if let Some(operations) = self.queued.lock().unwrap().get_mut(project_name) {
+            for operation in operations {
+                if operation.superseded_by.as_deref() == Some(operation_id) {
+                    operation.superseded_by = None;
+                }
+            }
+        }
+    }
+
+    fn take(&self, project_name: &ProjectName, operation_id: &str) -> Option<QueuedOperation> {
+        let mut queued = self.queued.lock().unwrap();
+
+        let operations = queued.get_mut(project_name)?;
+        let index = operations
+            .iter()
+            .position(|operation| operation.handle.operation_id() == Some(operation_id))?;
+        let taken = operations.remove(index);
+
+        if operations.is_empty() {
+            queued.remove(project_name);
+        }
+
+        Some(taken)
+    }
+}
+
+/// Lets the work queued up or running for a project be told to stop,
+/// e.g. because the project is being destroyed from under it
+#[derive(Clone, Default)]
+pub struct Cancellations {
+    senders: Arc<Mutex<HashMap<ProjectName, watch::Sender<()>>>>,
+}
+
+impl Cancellations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Be told when the work for `project_name` is cancelled, from now
+    /// on. The receiver is closed once it is.
+    pub fn subscribe(&self, project_name: &ProjectName) -> watch::Receiver<()> {
+        let mut senders = self.senders.lock().unwrap();
+
+        // Forget about the projects nothing is waiting on anymore
+        senders.retain(|_, sender| sender.receiver_count() > 0);
+
+        senders
+            .entry(project_name.clone())
+            .or_insert_with(|| watch::channel(()).0)
+            .subscribe()
+    }
+
+    /// Cancel the work subscribed for `project_name` so far, returning
+    /// whether there was any. Work subscribed after this is not affected.
+    pub fn cancel(&self, project_name: &ProjectName) -> bool {
+        match self.senders.lock().unwrap().remove(project_name) {
+            Some(sender) => sender.receiver_count() > 0,
+            None => false,
+        }
+    }
+}
+
+/// One lock per project, held by whatever is changing the state of the
+/// project so that only one thing does at a time. It must never be held
+/// while queuing up more work for the same project, as that work would
+/// then wait on it.
+#[derive(Clone, Default)]
+pub struct ProjectLocks {
+    locks: Arc<Mutex<HashMap<ProjectName, Arc<AsyncMutex<()>>>>>,
+}
+
+/// Held for as long as a project is being changed
+pub type ProjectGuard = OwnedMutexGuard<()>;
+
+impl ProjectLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, project_name: &ProjectName) -> Arc<AsyncMutex<()>> {
+        let mut locks = self.locks.lock().unwrap();
+
+        // Forget about the projects nothing is holding or waiting on
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+        locks.entry(project_name.clone()).or_default().clone()
+    }
+
+    /// Wait for the project to be free, then hold it
+    pub async fn lock(&self, project_name: &ProjectName) -> ProjectGuard {
+        self.get(project_name).lock_owned().await
+    }
+
+    /// Hold the project if it is free, for the callers which would
+    /// rather be told to come back later than wait
+    pub fn try_lock(&self, project_name: &ProjectName) -> Result<ProjectGuard, Error> {
+        self.get(project_name).try_lock_owned().map_err(|_| {
+            Error::custom(ErrorKind::InvalidOperation, "operation already in progress")
+        })
     }
 }
 
@@ -320,7 +1091,10 @@ impl<T> AndThenNotify<T> {
                 inner: task,
                 notify: Some(tx),
             },
-            TaskHandle { rx },
+            TaskHandle {
+                done: rx.shared(),
+                operation_id: None,
+            },
         )
     }
 }
@@ -344,6 +1118,22 @@ where
 
         out
     }
+
+    fn description(&self) -> Option<String> {
+        self.inner.description()
+    }
+
+    fn project_name(&self) -> Option<ProjectName> {
+        self.inner.project_name()
+    }
+
+    fn priority(&self) -> Priority {
+        self.inner.priority()
+    }
+
+    fn work(&self) -> Option<&Work> {
+        self.inner.work()
+    }
 }
 
 pub struct WithTimeout<T> {
@@ -387,26 +1177,80 @@ where
 
         self.inner.poll(ctx).await
     }
+
+    fn description(&self) -> Option<String> {
+        self.inner.description()
+    }
+
+    fn project_name(&self) -> Option<ProjectName> {
+        self.inner.project_name()
+    }
+
+    fn priority(&self) -> Priority {
+        self.inner.priority()
+    }
+
+    fn work(&self) -> Option<&Work> {
+        self.inner.work()
+    }
 }
 
 /// A collection of tasks scoped to a specific project.
 ///
-/// All the tasks in the collection are run to completion. If an error
-/// is encountered, the `ProjectTask` completes early passing through
+/// All the tasks in the collection are run to completion. Tasks failing
+/// with an error which could be [retried](Error::is_retryable) are given as
+/// many attempts as the [`RetryPolicy`] of the state they found the
+/// project in allows, after which the project is errored and
+/// dead-lettered. The attempts at [`Work`] after the first are
+/// scheduled, for the worker not to wait out the backoff.
+/// On any other error, the `ProjectTask` completes early passing through
 /// the error. The value returned by the inner tasks upon their
 /// completion is committed back to persistence through
 /// [GatewayService].
+///
+/// The task as a whole, retries included, is given a deadline depending
+/// on the state it first finds the project in, as per [`TaskDeadlines`].
+/// Past it, the step under way is dropped and the project errored.
+///
+/// Once [cancelled](Cancellations::cancel), the task stops at its next
+/// chance. A step already under way is let finish, so that whatever it
+/// did in Docker is known about, and what it comes back with is turned
+/// into destroying the project rather than committed as is.
 pub struct ProjectTask<T> {
     uuid: Uuid,
     project_name: ProjectName,
     service: Arc<GatewayService>,
     tasks: VecDeque<T>,
+    /// The retries of the project as last persisted, once loaded
+    retries: Option<TaskRetries>,
+    /// When the task has to be over by, and how long it was given, from
+    /// the first time the project is looked at
+    deadline: Option<(Instant, Duration)>,
+    priority: Priority,
+    work: Option<Work>,
+    /// Whether the work was recorded as under way yet
+    work_started: bool,
+    /// Closed once the work for the project is cancelled
+    cancelled: watch::Receiver<()>,
 }
 
 impl<T> ProjectTask<T> {
     pub fn uuid(&self) -> &Uuid {
         &self.uuid
     }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.has_changed().is_err()
+    }
+
+    /// How long until the task at the front is due for another attempt,
+    /// if it failed not long ago
+    fn retry_wait(&self) -> Option<Duration> {
+        self.retries
+            .as_ref()?
+            .next_retry_at
+            .and_then(|at| (at - Utc::now()).to_std().ok())
+    }
 }
 
 /// A context for tasks which are scoped to a specific project.
@@ -425,26 +1269,85 @@ pub struct ProjectContext {
     pub state: Project,
 }
 
+/// Hold the worker back for a bit while the state store cannot be
+/// reached, then have the task polled again from the top.
+async fn wait_for_state_store<T>(err: Error) -> TaskResult<T, Error> {
+    warn!(error = %err, "state store is unavailable, pausing");
+    sleep(STATE_STORE_RETRY_INTERVAL).await;
+    TaskResult::TryAgain
+}
+
+/// Drive `poll` to completion, renewing the lease on the project every
+/// [`LEASE_HEARTBEAT_INTERVAL`] until then, so that a step taking longer
+/// than [`PROJECT_LEASE_DURATION`] does not have the project taken over
+/// from under it
+async fn with_lease_heartbeat<F: Future>(
+    service: &GatewayService,
+    project_name: &ProjectName,
+    poll: F,
+) -> F::Output {
+    tokio::pin!(poll);
+
+    let start = tokio::time::Instant::now() + LEASE_HEARTBEAT_INTERVAL;
+    let mut heartbeat = tokio::time::interval_at(start, LEASE_HEARTBEAT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            res = &mut poll => return res,
+            _ = heartbeat.tick() => match service.acquire_lease(project_name).await {
+                Ok(true) => trace!("renewed the lease on the project"),
+                // The step under way is let finish, its outcome is only
+                // written if nobody else wrote the project in the meantime
+                Ok(false) => warn!("lost the lease on the project while polling it"),
+                Err(err) => warn!(error = %err, "could not renew the lease on the project"),
+            },
+        }
+    }
+}
+
+/// How long to wait before attempt number `attempts + 1` at a failing
+/// task, as per the default [`RetryPolicy`]
+pub fn retry_backoff(attempts: u32) -> Duration {
+    RetryPolicy::default().backoff(attempts)
+}
+
 pub type BoxedTask<Ctx = (), O = ()> = Box<dyn Task<Ctx, Output = O, Error = Error>>;
 
-#[async_trait]
-impl<T> Task<()> for ProjectTask<T>
+impl<T> ProjectTask<T>
 where
     T: Task<ProjectContext, Output = Project, Error = Error>,
 {
-    type Output = ();
+    /// Poll the task at the front, once this gateway holds the lease on
+    /// the project
+    async fn poll_leased(&mut self) -> TaskResult<(), Error> {
+        let ctx = self.service.context();
 
-    type Error = Error;
+        let retries = match self.retries.take() {
+            Some(retries) => retries,
+            None => match self.service.task_retries(&self.project_name).await {
+                Ok(retries) => retries,
+                Err(err) if err.kind() == ErrorKind::StateStoreUnavailable => {
+                    return wait_for_state_store(err).await
+                }
+                Err(err) => return TaskResult::Err(err),
+            },
+        };
 
-    async fn poll(&mut self, _: ()) -> TaskResult<Self::Output, Self::Error> {
-        if self.tasks.is_empty() {
-            return TaskResult::Done(());
+        // Waited out by `poll_project`, without a hold on the project
+        if retries.next_retry_at.map_or(false, |at| at > Utc::now()) {
+            self.retries = Some(retries);
+            return TaskResult::TryAgain;
         }
 
-        let ctx = self.service.context();
-
-        let project = match self.service.find_project(&self.project_name).await {
-            Ok(project) => project,
+        let (project, version) = match self
+            .service
+            .find_project_versioned(&self.project_name)
+            .await
+        {
+            Ok(found) => found,
+            Err(err) if err.kind() == ErrorKind::StateStoreUnavailable => {
+                return wait_for_state_store(err).await
+            }
             Err(err) => return TaskResult::Err(err),
         };
 
@@ -454,9 +1357,21 @@ where
             .await
         {
             Ok(account_name) => account_name,
+            Err(err) if err.kind() == ErrorKind::StateStoreUnavailable => {
+                return wait_for_state_store(err).await
+            }
             Err(err) => return TaskResult::Err(err),
         };
 
+        // Kept around to error the project with, should it run out of
+        // attempts or time
+        let previous = project.clone();
+
+        let (deadline, limit) = *self.deadline.get_or_insert_with(|| {
+            let limit = self.service.task_deadlines().for_project(&previous);
+            (Instant::now() + limit, limit)
+        });
+
         let project_ctx = ProjectContext {
             project_name: self.project_name.clone(),
             account_name: account_name.clone(),
@@ -468,37 +1383,94 @@ where
             "polling project",
             ctx.project = ?project_ctx.project_name,
             ctx.account = ?project_ctx.account_name,
-            ctx.state = project_ctx.state.state()
+            ctx.state = project_ctx.state.label(),
+            work.operation = tracing::field::Empty,
+            work.origin = tracing::field::Empty,
+            work.request_id = tracing::field::Empty,
         );
+        if let Some(work) = &self.work {
+            span.record("work.operation", work.operation.as_str());
+            span.record("work.origin", work.origin.as_str());
+            span.record("work.request_id", work.request_id.as_str());
+        }
         let _ = span.enter();
 
         let task = self.tasks.front_mut().unwrap();
 
-        let timeout = sleep(PROJECT_TASK_MAX_IDLE_TIMEOUT);
-        let res = {
-            let mut poll = task.poll(project_ctx);
+        let metrics = self.service.metrics();
+        let started = Instant::now();
+
+        let project_name = &self.project_name;
+        let idle = sleep(PROJECT_TASK_MAX_IDLE_TIMEOUT);
+        let res = timeout(deadline.saturating_duration_since(Instant::now()), async {
+            // A panicking transition errors this one project, rather than
+            // taking the rest of the work down with it
+            let mut poll = AssertUnwindSafe(task.poll(project_ctx)).catch_unwind();
             tokio::select! {
                 res = &mut poll => res,
-                _ = timeout => {
+                _ = idle => {
                     warn!(
-                        project_name = ?self.project_name,
+                        project_name = ?project_name,
                         account_name = ?account_name,
                         "a task has been idling for a long time"
                     );
                     poll.await
                 }
             }
+        })
+        .await;
+
+        metrics.record_worker_task_duration(previous.label(), started.elapsed());
+
+        let res = match res {
+            Ok(Ok(res)) => res,
+            Ok(Err(payload)) => {
+                let message = panic_message(payload.as_ref());
+                return self.give_up_on_panic(previous, version, message).await;
+            }
+            Err(_) => return self.give_up_on_deadline(previous, version, limit).await,
+        };
+
+        let cancelled = self.is_cancelled();
+        let res = match res {
+            TaskResult::Pending(update) | TaskResult::Done(update) if cancelled => {
+                info!(
+                    state = update.label(),
+                    "project work was cancelled, destroying the project instead"
+                );
+                match update.destroy() {
+                    Ok(destroying) => TaskResult::Done(destroying),
+                    Err(err) => TaskResult::Err(err),
+                }
+            }
+            res => res,
         };
 
         if let Some(update) = res.as_ref().ok() {
-            trace!(new_state = ?update.state(), "new state");
+            trace!(new_state = update.label(), "new state");
             match self
                 .service
-                .update_project(&self.project_name, update)
+                .update_project_versioned(&self.project_name, update, version, self.work.as_ref())
                 .await
             {
                 Ok(_) => {
-                    trace!(new_state = ?update.state(), "successfully updated project state");
+                    trace!(
+                        new_state = update.label(),
+                        "successfully updated project state"
+                    );
+                }
+                Err(err) if err.kind() == ErrorKind::Conflict => {
+                    // Someone else moved the project along while we were
+                    // working on it: drop our result and re-evaluate the
+                    // task against the fresh state on the next poll
+                    debug!(
+                        new_state = update.label(),
+                        "project state changed concurrently, trying again"
+                    );
+                    return TaskResult::TryAgain;
+                }
+                Err(err) if err.kind() == ErrorKind::StateStoreUnavailable => {
+                    return wait_for_state_store(err).await;
                 }
                 Err(err) => {
                     error!(err = %err, "could not update project state");
@@ -509,6 +1481,27 @@ where
 
         trace!(result = res.to_str(), "poll result");
 
+        if cancelled {
+            return TaskResult::Cancelled;
+        }
+
+        let res = match res {
+            TaskResult::Err(err) if err.is_retryable() => {
+                return self.retry_or_give_up(retries, previous, version, err).await
+            }
+            res => res,
+        };
+
+        if retries.attempts > 0 && matches!(res, TaskResult::Pending(_) | TaskResult::Done(_)) {
+            // Should this fail, the next failure just gets fewer retries
+            if let Err(err) = self.service.clear_task_retries(&self.project_name).await {
+                warn!(error = %err, "could not reset the retries of the project");
+            }
+            self.retries = Some(TaskRetries::default());
+        } else {
+            self.retries = Some(retries);
+        }
+
         match res {
             TaskResult::Pending(_) => TaskResult::Pending(()),
             TaskResult::TryAgain => TaskResult::TryAgain,
@@ -527,6 +1520,337 @@ where
             }
         }
     }
+
+    /// Take the lease on the project and wait for it to be free, then
+    /// poll the task at the front
+    async fn poll_project(&mut self) -> TaskResult<(), Error> {
+        if self.tasks.is_empty() {
+            return TaskResult::Done(());
+        }
+
+        if self.is_cancelled() {
+            debug!("project work was cancelled before it got going");
+            return TaskResult::Cancelled;
+        }
+
+        if !self.work_started {
+            if let Some(superseded_by) = self.leave_queue() {
+                debug!(
+                    superseded_by,
+                    "project work was superseded before it got going"
+                );
+                if let Err(err) = self
+                    .service
+                    .supersede_operation(&self.work.as_ref().unwrap().id, &superseded_by)
+                    .await
+                {
+                    warn!(error = %err, "could not record the operation as superseded");
+                }
+                return TaskResult::Done(());
+            }
+        }
+
+        if let Some(wait) = self.retry_wait() {
+            // Should this fail, the lease runs out on its own
+            if let Err(err) = self.service.release_lease(&self.project_name).await {
+                warn!(error = %err, "could not release the lease on the project");
+            }
+
+            debug!(wait = ?wait, "waiting to retry the project task");
+            tokio::select! {
+                _ = sleep(wait) => {}
+                _ = self.cancelled.changed() => return TaskResult::Cancelled,
+            }
+        }
+
+        match self.service.acquire_lease(&self.project_name).await {
+            Ok(true) => {}
+            Ok(false) => {
+                trace!("project is leased by another gateway, waiting");
+                sleep(LEASE_RETRY_INTERVAL).await;
+                return TaskResult::TryAgain;
+            }
+            Err(err) if err.kind() == ErrorKind::StateStoreUnavailable => {
+                return wait_for_state_store(err).await
+            }
+            Err(err) => return TaskResult::Err(err),
+        }
+
+        if !self.work_started {
+            self.start_work().await;
+        }
+
+        let res = {
+            let _guard = self.service.project_locks().lock(&self.project_name).await;
+            let service = Arc::clone(&self.service);
+            let project_name = self.project_name.clone();
+            with_lease_heartbeat(&service, &project_name, self.poll_leased()).await
+        };
+
+        if res.is_done() {
+            // Should this fail, the lease runs out on its own
+            if let Err(err) = self.service.release_lease(&self.project_name).await {
+                warn!(error = %err, "could not release the lease on the project");
+            }
+        }
+
+        res
+    }
+
+    /// Have the work the task carries out, if any, no longer be taken as
+    /// queued up, giving back the operation which superseded it if one
+    /// did
+    fn leave_queue(&self) -> Option<String> {
+        let work = self.work.as_ref()?;
+        self.service
+            .queued_work()
+            .start(&self.project_name, &work.id)
+    }
+
+    /// Have the work the task carries out, if any, known to be under way
+    async fn start_work(&mut self) {
+        self.work_started = true;
+
+        let Some(work) = &self.work else {
+            return;
+        };
+        if let Err(err) = self.service.start_operation(&work.id).await {
+            warn!(error = %err, "could not record the operation as running");
+        }
+    }
+
+    /// Have the work the task carries out, if any, known to be over with
+    /// the way it went
+    async fn finish_work(&self, res: &TaskResult<(), Error>) {
+        let Some(work) = &self.work else {
+            return;
+        };
+
+        let reason = match res {
+            TaskResult::Err(err) => Some(err.to_string()),
+            TaskResult::Cancelled => Some("the operation was cancelled".to_string()),
+            _ => None,
+        };
+        if let Err(err) = self
+            .service
+            .finish_operation(&work.id, reason.as_deref())
+            .await
+        {
+            warn!(error = %err, "could not record the operation as over");
+        }
+    }
+
+    /// Error the project for the task having run past its deadline of
+    /// `limit`
+    async fn give_up_on_deadline(
+        &mut self,
+        previous: Project,
+        version: i64,
+        limit: Duration,
+    ) -> TaskResult<(), Error> {
+        error!(
+            state = previous.label(),
+            limit = ?limit,
+            "project task ran past its deadline, giving up"
+        );
+        self.service
+            .metrics()
+            .record_worker_task_deadline_exceeded();
+
+        let errored = Project::Errored(ProjectError::task_deadline(previous, limit));
+        match self
+            .service
+            .update_project_versioned(&self.project_name, &errored, version, self.work.as_ref())
+            .await
+        {
+            Ok(_) => TaskResult::Err(Error::custom(
+                ErrorKind::Internal,
+                "project task ran past its deadline",
+            )),
+            // The project moved on in the meantime, have the task take
+            // another look at it
+            Err(err) if err.kind() == ErrorKind::Conflict => TaskResult::TryAgain,
+            Err(err) => TaskResult::Err(err),
+        }
+    }
+
+    /// Error the project for the task at the front having panicked with
+    /// `message`
+    async fn give_up_on_panic(
+        &mut self,
+        previous: Project,
+        version: i64,
+        message: String,
+    ) -> TaskResult<(), Error> {
+        error!(
+            state = previous.label(),
+            panic = message,
+            "project task panicked, giving up"
+        );
+        self.service.metrics().record_worker_task_panic();
+
+        let errored = Project::Errored(ProjectError::internal_panic(previous, &message));
+        match self
+            .service
+            .update_project_versioned(&self.project_name, &errored, version, self.work.as_ref())
+            .await
+        {
+            Ok(_) => TaskResult::Err(Error::custom(
+                ErrorKind::Internal,
+                format!("project task panicked: {message}"),
+            )),
+            // The project moved on in the meantime, have the task take
+            // another look at it
+            Err(err) if err.kind() == ErrorKind::Conflict => TaskResult::TryAgain,
+            Err(err) => TaskResult::Err(err),
+        }
+    }
+
+    /// Count a failed attempt at the task at the front, then either have
+    /// it tried again after a backoff or, once it is out of attempts,
+    /// error the project with everything that went wrong along the way.
+    /// Tasks carrying out [`Work`] end there, their retry being
+    /// [scheduled](GatewayService::schedule_work) for after the backoff.
+    async fn retry_or_give_up(
+        &mut self,
+        mut retries: TaskRetries,
+        previous: Project,
+        version: i64,
+        err: Error,
+    ) -> TaskResult<(), Error> {
+        let policy = State::<GatewayContext>::retry_policy(&previous);
+
+        retries.attempts += 1;
+        retries.errors.push(err.to_string());
+
+        if policy.is_exhausted(retries.attempts) {
+            error!(
+                err = %err,
+                attempts = retries.attempts,
+                "project task keeps failing, giving up"
+            );
+
+            let errored =
+                Project::Errored(ProjectError::retries_exhausted(previous, &retries.errors));
+            match self
+                .service
+                .update_project_versioned(&self.project_name, &errored, version, self.work.as_ref())
+                .await
+            {
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::Conflict => {
+                    // The project moved on in the meantime, have the task
+                    // take another look at it
+                    self.retries = Some(retries);
+                    return TaskResult::TryAgain;
+                }
+                Err(err) => return TaskResult::Err(err),
+            }
+
+            self.service.metrics().record_dead_lettered();
+
+            // Keep the sweeps from picking the project up again, only to
+            // have it fail all over
+            if let Err(err) = self
+                .service
+                .dead_letter_project(&self.project_name, &err.to_string())
+                .await
+            {
+                warn!(error = %err, "could not dead-letter the project");
+            }
+
+            return TaskResult::Err(err);
+        }
+
+        self.service.metrics().record_worker_task_retry();
+
+        let backoff = policy.backoff(retries.attempts);
+        let next_retry_at = Utc::now() + chrono::Duration::from_std(backoff).unwrap();
+        retries.next_retry_at = Some(next_retry_at);
+
+        // Persisted so that a restart of the gateway does not hand the
+        // task a fresh set of attempts
+        if let Err(err) = self
+            .service
+            .set_task_retries(&self.project_name, &retries)
+            .await
+        {
+            warn!(error = %err, "could not persist the retries of the project");
+        }
+
+        // The worker is handed back rather than held up for the backoff.
+        // The tasks cannot be rebuilt as they were, so the retry moves
+        // the project along from wherever it is, as after a restart.
+        if let Some(work) = &self.work {
+            let retry = Work::new(self.project_name.clone(), Operation::Refresh, work.origin)
+                .with_request_id(work.request_id.clone());
+            match self
+                .service
+                .schedule_work(&retry, self.priority, next_retry_at)
+                .await
+            {
+                Ok(()) => {
+                    warn!(
+                        err = %err,
+                        attempts = retries.attempts,
+                        backoff = ?backoff,
+                        "project task failed, retry scheduled"
+                    );
+                    return TaskResult::Err(err);
+                }
+                Err(err) => warn!(
+                    error = %err,
+                    "could not schedule the retry of the project task, waiting for it instead"
+                ),
+            }
+        }
+
+        warn!(
+            err = %err,
+            attempts = retries.attempts,
+            backoff = ?backoff,
+            "project task failed, retrying"
+        );
+
+        self.retries = Some(retries);
+        TaskResult::TryAgain
+    }
+}
+
+#[async_trait]
+impl<T> Task<()> for ProjectTask<T>
+where
+    T: Task<ProjectContext, Output = Project, Error = Error>,
+{
+    type Output = ();
+
+    type Error = Error;
+
+    async fn poll(&mut self, _: ()) -> TaskResult<Self::Output, Self::Error> {
+        let res = self.poll_project().await;
+
+        if res.is_done() {
+            self.finish_work(&res).await;
+        }
+
+        res
+    }
+
+    fn description(&self) -> Option<String> {
+        Some(format!("project:{}", self.project_name))
+    }
+
+    fn project_name(&self) -> Option<ProjectName> {
+        Some(self.project_name.clone())
+    }
+
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    fn work(&self) -> Option<&Work> {
+        self.work.as_ref()
+    }
 }
 
 #[cfg(test)]
@@ -562,4 +1886,272 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn cancellations_reach_earlier_work_only() {
+        let cancellations = Cancellations::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let reloaded: ProjectName = "reloaded".parse().unwrap();
+
+        assert!(!cancellations.cancel(&matrix));
+
+        let earlier = cancellations.subscribe(&matrix);
+        let other = cancellations.subscribe(&reloaded);
+        assert!(matches!(earlier.has_changed(), Ok(false)));
+
+        assert!(cancellations.cancel(&matrix));
+        let later = cancellations.subscribe(&matrix);
+
+        assert!(earlier.has_changed().is_err());
+        assert!(matches!(later.has_changed(), Ok(false)));
+        assert!(matches!(other.has_changed(), Ok(false)));
+    }
+
+    #[test]
+    fn operations_supersede_by_precedence() {
+        let supersedes = |this: Operation, other| this.supersedes(other);
+
+        assert!(supersedes(Operation::Restart, Operation::Restart));
+        assert!(supersedes(Operation::Destroy, Operation::Start));
+        assert!(supersedes(Operation::Destroy, Operation::Recreate));
+        assert!(supersedes(Operation::Recreate, Operation::Restart));
+
+        assert!(!supersedes(Operation::Restart, Operation::Recreate));
+        assert!(!supersedes(Operation::Start, Operation::Destroy));
+        assert!(!supersedes(Operation::Stop, Operation::Start));
+        assert!(!supersedes(Operation::Refresh, Operation::Restart));
+    }
+
+    /// Queued work for the tests, along with the task it would be carried
+    /// out by
+    struct Queuer {
+        queued: QueuedWork,
+        cancellations: Cancellations,
+        project_name: ProjectName,
+        tasks: HashMap<String, AndThenNotify<()>>,
+    }
+
+    impl Queuer {
+        fn new() -> Self {
+            Self {
+                queued: QueuedWork::new(),
+                cancellations: Cancellations::new(),
+                project_name: "matrix".parse().unwrap(),
+                tasks: HashMap::new(),
+            }
+        }
+
+        /// Queue up `operation`, giving back its ID if it was queued up
+        /// or the ID of the work it was coalesced into
+        fn admit(&mut self, operation: Operation, priority: Priority) -> Result<String, String> {
+            let work = Work::new(self.project_name.clone(), operation, Origin::Api);
+            let (task, mut handle) = AndThenNotify::after(());
+            handle.operation_id = Some(work.id.clone());
+
+            let cancelled = self.cancellations.subscribe(&self.project_name);
+            match self.queued.admit(&work, priority, true, handle, cancelled) {
+                Some(existing) => Err(existing.operation_id().unwrap().to_string()),
+                None => {
+                    self.tasks.insert(work.id.clone(), task);
+                    Ok(work.id)
+                }
+            }
+        }
+
+        fn start(&self, operation_id: &str) -> Option<String> {
+            self.queued.start(&self.project_name, operation_id)
+        }
+    }
+
+    #[test]
+    fn queued_work_coalesces_identical_operations() {
+        let mut queuer = Queuer::new();
+
+        let restart = queuer
+            .admit(Operation::Restart, Priority::Interactive)
+            .unwrap();
+        for _ in 0..4 {
+            assert_eq!(
+                queuer.admit(Operation::Restart, Priority::Interactive),
+                Err(restart.clone())
+            );
+        }
+
+        // Once started, more of the same is queued up again
+        assert_eq!(queuer.start(&restart), None);
+        let again = queuer
+            .admit(Operation::Restart, Priority::Interactive)
+            .unwrap();
+        assert_ne!(again, restart);
+
+        // As it is once the work is dropped without being started
+        queuer.tasks.remove(&again);
+        assert!(queuer
+            .admit(Operation::Restart, Priority::Interactive)
+            .is_ok());
+    }
+
+    #[test]
+    fn queued_work_follows_precedence() {
+        let mut queuer = Queuer::new();
+
+        let restart = queuer.admit(Operation::Restart, Priority::Health).unwrap();
+        let recreate = queuer.admit(Operation::Recreate, Priority::Health).unwrap();
+        assert_eq!(
+            queuer.admit(Operation::Restart, Priority::Health),
+            Err(recreate.clone())
+        );
+
+        let destroy = queuer.admit(Operation::Destroy, Priority::Health).unwrap();
+        for operation in [Operation::Start, Operation::Recreate, Operation::Refresh] {
+            assert_eq!(
+                queuer.admit(operation, Priority::Health),
+                Err(destroy.clone())
+            );
+        }
+
+        assert_eq!(queuer.start(&restart), Some(recreate.clone()));
+        assert_eq!(queuer.start(&recreate), Some(destroy.clone()));
+        assert_eq!(queuer.start(&destroy), None);
+    }
+
+    #[test]
+    fn queued_work_is_not_coalesced_into_less_urgent_work() {
+        let mut queuer = Queuer::new();
+
+        let background = queuer
+            .admit(Operation::Refresh, Priority::Background)
+            .unwrap();
+        let interactive = queuer
+            .admit(Operation::Refresh, Priority::Interactive)
+            .unwrap();
+        assert_eq!(
+            queuer.admit(Operation::Refresh, Priority::Background),
+            Err(interactive.clone())
+        );
+
+        assert_eq!(queuer.start(&background), Some(interactive));
+    }
+
+    #[test]
+    fn queued_work_forgets_cancelled_and_unqueued_work() {
+        let mut queuer = Queuer::new();
+
+        let destroy = queuer
+            .admit(Operation::Destroy, Priority::Interactive)
+            .unwrap();
+        queuer.cancellations.cancel(&queuer.project_name);
+        let again = queuer
+            .admit(Operation::Destroy, Priority::Interactive)
+            .unwrap();
+        assert_ne!(again, destroy);
+
+        // Work which did not make it to the queue supersedes nothing
+        let mut queuer = Queuer::new();
+        let stop = queuer.admit(Operation::Stop, Priority::Background).unwrap();
+        let urgent_stop = queuer
+            .admit(Operation::Stop, Priority::Interactive)
+            .unwrap();
+        queuer.queued.forget(&queuer.project_name, &urgent_stop);
+        assert_eq!(queuer.start(&stop), None);
+    }
+
+    #[tokio::test]
+    async fn project_locks_hold_one_project_at_a_time() {
+        let locks = ProjectLocks::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let reloaded: ProjectName = "reloaded".parse().unwrap();
+
+        let guard = locks.lock(&matrix).await;
+
+        let busy = locks.try_lock(&matrix).unwrap_err();
+        assert_eq!(busy.kind(), ErrorKind::InvalidOperation);
+        assert!(locks.try_lock(&reloaded).is_ok());
+
+        // Waiters are let through once the project is free
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            let matrix = matrix.clone();
+            async move {
+                let _guard = locks.lock(&matrix).await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        drop(guard);
+        waiter.await.unwrap();
+        assert!(locks.try_lock(&matrix).is_ok());
+    }
+
+    #[test]
+    fn retry_backoff_grows_up_to_a_limit() {
+        for attempts in 1..=10 {
+            let full = TASK_RETRY_BASE_BACKOFF
+                .saturating_mul(1 << (attempts - 1))
+                .min(TASK_RETRY_MAX_BACKOFF);
+
+            let backoff = retry_backoff(attempts);
+            assert!(
+                backoff >= full / 2,
+                "{backoff:?} is too short for {attempts}"
+            );
+            assert!(backoff <= full, "{backoff:?} is too long for {attempts}");
+        }
+
+        assert!(retry_backoff(u32::MAX) <= TASK_RETRY_MAX_BACKOFF);
+    }
+
+    #[test]
+    fn work_round_trips_through_json() {
+        let operations = [
+            Operation::Start,
+            Operation::Stop,
+            Operation::Restart,
+            Operation::Destroy,
+            Operation::Recreate,
+            Operation::Refresh,
+        ];
+        let origins = [
+            Origin::Api,
+            Origin::Health,
+            Origin::Reconciler,
+            Origin::Startup,
+            Origin::Sweep,
+            Origin::Admin,
+        ];
+
+        for operation in operations {
+            for origin in origins {
+                let work = Work::new("matrix".parse().unwrap(), operation, origin)
+                    .with_request_id("the-request".to_string());
+
+                let stored = serde_json::to_string(&work).unwrap();
+                assert!(stored.contains(&format!("\"operation\":\"{}\"", operation.as_str())));
+                assert!(stored.contains(&format!("\"origin\":\"{}\"", origin.as_str())));
+
+                let loaded: Work = serde_json::from_str(&stored).unwrap();
+                assert_eq!(loaded, work);
+            }
+        }
+    }
+
+    #[test]
+    fn task_deadlines_by_transition() {
+        let deadlines = TaskDeadlines {
+            create: Duration::from_secs(4),
+            start: Duration::from_secs(3),
+            stop: Duration::from_secs(2),
+            health: Duration::from_secs(1),
+        };
+
+        let creating = Project::Creating(ProjectCreating::new_with_random_initial_key(
+            "matrix".parse().unwrap(),
+            0,
+        ));
+        assert_eq!(deadlines.for_project(&creating), deadlines.create);
+
+        let errored = Project::Errored(ProjectError::internal("there is no spoon"));
+        assert_eq!(deadlines.for_project(&errored), deadlines.start);
+    }
 }
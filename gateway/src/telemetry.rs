@@ -0,0 +1,42 @@
+use std::time::{Duration, SystemTime};
+
+use once_cell::sync::OnceCell;
+
+static STARTED_AT: OnceCell<SystemTime> = OnceCell::new();
+
+/// Record now as the moment the gateway started. Only the first call
+/// has any effect.
+pub fn mark_started() -> SystemTime {
+    *STARTED_AT.get_or_init(SystemTime::now)
+}
+
+/// When the gateway started, or when this was first asked if
+/// [`mark_started`] was never called
+pub fn started_at() -> SystemTime {
+    mark_started()
+}
+
+/// How long the gateway has been running for
+pub fn uptime() -> Duration {
+    // The clock can go backwards, in which case we have not been up for
+    // any time at all as far as we can tell
+    started_at().elapsed().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uptime_increases() {
+        let start = mark_started();
+        assert_eq!(mark_started(), start);
+
+        let before = uptime();
+        std::thread::sleep(Duration::from_millis(10));
+        let after = uptime();
+
+        assert!(after > before);
+        assert_eq!(started_at(), start);
+    }
+}
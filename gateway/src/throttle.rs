@@ -0,0 +1,192 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep;
+
+use crate::metrics::GatewayMetrics;
+
+/// Hands out permits at a steady `rate` per second, letting up to `burst`
+/// of them be taken at once after a quiet spell. Clones share the same
+/// bucket.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    inner: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// A bucket which starts full
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+
+        Self {
+            rate: rate.max(f64::MIN_POSITIVE),
+            burst,
+            inner: Arc::new(Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Wait for a permit to be available and take it
+    pub async fn acquire(&self) {
+        while let Err(wait) = self.try_acquire() {
+            sleep(wait).await;
+        }
+    }
+
+    /// Take a permit if one is available, or tell how long until there
+    /// is one
+    fn try_acquire(&self) -> Result<(), Duration> {
+        let mut bucket = self.inner.lock().unwrap();
+
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+/// What a call to the docker daemon costs it, deciding which bucket of
+/// the [`DockerLimiter`] it takes its permit from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DockerCall {
+    /// Looking at what is there: inspects, lists, stats and events
+    Inspect,
+    /// Changing what is there: creating, starting, stopping and removing
+    /// containers, or connecting them to networks
+    Create,
+}
+
+impl DockerCall {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Inspect => "inspect",
+            Self::Create => "create",
+        }
+    }
+}
+
+/// Rate limits the calls made to the docker daemon by the whole gateway,
+/// so that health checks, refreshes, sweeps and user transitions do not
+/// overwhelm it between them. Clones share the same buckets.
+#[derive(Clone)]
+pub struct DockerLimiter {
+    /// The buckets for [`DockerCall::Inspect`] and [`DockerCall::Create`],
+    /// or none when calls are not limited
+    buckets: Option<(TokenBucket, TokenBucket)>,
+    metrics: GatewayMetrics,
+}
+
+impl DockerLimiter {
+    /// Limit inspects to the `inspect` bucket and everything else to the
+    /// `create` one, recording the time calls wait to `metrics`
+    pub fn new(inspect: TokenBucket, create: TokenBucket, metrics: GatewayMetrics) -> Self {
+        Self {
+            buckets: Some((inspect, create)),
+            metrics,
+        }
+    }
+
+    /// A limiter which lets every call straight through
+    pub fn disabled() -> Self {
+        Self {
+            buckets: None,
+            metrics: GatewayMetrics::new(),
+        }
+    }
+
+    /// Wait for the bucket of `call` to have a permit and take it
+    pub async fn acquire(&self, call: DockerCall) {
+        let Some((inspect, create)) = &self.buckets else {
+            return;
+        };
+
+        let bucket = match call {
+            DockerCall::Inspect => inspect,
+            DockerCall::Create => create,
+        };
+
+        let started = Instant::now();
+        bucket.acquire().await;
+        self.metrics
+            .record_docker_queue_wait(call.as_str(), started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn token_bucket_spreads_permits() {
+        let bucket = TokenBucket::new(50.0, 2);
+
+        // The burst is there straight away
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.clone().try_acquire().is_ok());
+
+        let wait = bucket.try_acquire().unwrap_err();
+        assert!(wait <= Duration::from_millis(20), "{wait:?}");
+
+        let started = Instant::now();
+        bucket.acquire().await;
+        bucket.acquire().await;
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn docker_limiter_keeps_creates_apart_from_inspects() {
+        let metrics = GatewayMetrics::new();
+        let limiter = DockerLimiter::new(
+            TokenBucket::new(1000.0, 10),
+            TokenBucket::new(10.0, 1),
+            metrics.clone(),
+        );
+
+        // The one create in the burst goes straight through, the next one
+        // waits for the bucket to refill...
+        let started = Instant::now();
+        limiter.acquire(DockerCall::Create).await;
+        limiter.acquire(DockerCall::Create).await;
+        assert!(started.elapsed() >= Duration::from_millis(80));
+
+        // ...while inspects are left alone
+        let started = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire(DockerCall::Inspect).await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(80));
+
+        let waits = metrics.docker_queue_wait();
+        assert_eq!(waits["create"].1, 2);
+        assert!(waits["create"].0 >= Duration::from_millis(80));
+        assert_eq!(waits["inspect"].1, 5);
+    }
+
+    #[tokio::test]
+    async fn disabled_docker_limiter_never_waits() {
+        let limiter = DockerLimiter::disabled();
+
+        let started = Instant::now();
+        for _ in 0..1000 {
+            limiter.acquire(DockerCall::Create).await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+}
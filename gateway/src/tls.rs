@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use axum_server::accept::DefaultAcceptor;
@@ -10,7 +10,7 @@ use futures::executor::block_on;
 use pem::Pem;
 use rustls::server::{ClientHello, ResolvesServerCert};
 use rustls::sign::{self, CertifiedKey};
-use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
 use rustls_pemfile::Item;
 use shuttle_common::models::error::ErrorKind;
 use tokio::runtime::Handle;
@@ -86,6 +86,61 @@ impl ChainAndPrivateKey {
     }
 }
 
+/// The certificates of the CAs to trust the provisioner's certificate
+/// with, when it is not signed by a public one
+#[derive(Debug, Clone)]
+pub struct CaBundle {
+    path: PathBuf,
+    pem: String,
+    roots: RootCertStore,
+}
+
+impl CaBundle {
+    /// Read the PEM bundle at `path`, which has to hold at least one CA
+    /// certificate and nothing else
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let pem = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+
+        let items = rustls_pemfile::read_all(&mut pem.as_bytes())
+            .map_err(|err| format!("{} is not PEM: {err}", path.display()))?;
+        let mut roots = RootCertStore::empty();
+        for item in items {
+            let Item::X509Certificate(cert) = item else {
+                return Err(format!("{} holds more than certificates", path.display()));
+            };
+            roots
+                .add(&Certificate(cert))
+                .map_err(|err| format!("invalid CA certificate in {}: {err}", path.display()))?;
+        }
+
+        if roots.is_empty() {
+            return Err(format!("no certificate in {}", path.display()));
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            pem,
+            roots,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The bundle as it was read, to hand to the runtimes
+    pub fn pem(&self) -> &str {
+        &self.pem
+    }
+
+    /// What a TLS client trusting this bundle alone has as its roots
+    pub fn roots(&self) -> &RootCertStore {
+        &self.roots
+    }
+}
+
 pub struct GatewayCertResolver {
     keys: RwLock<HashMap<String, Arc<CertifiedKey>>>,
     default: RwLock<Option<Arc<CertifiedKey>>>,
@@ -166,3 +221,104 @@ pub fn make_tls_acceptor() -> (Arc<GatewayCertResolver>, RustlsAcceptor<DefaultA
 
     (resolver, RustlsAcceptor::new(rustls_config))
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    use rustls::{ClientConfig, ClientConnection, ServerConnection};
+
+    use super::*;
+
+    /// A self-signed CA, and a bundle of it read from a file like the
+    /// one given to `--provisioner-tls-ca`
+    pub fn self_signed_ca() -> (rcgen::Certificate, CaBundle) {
+        let mut params = CertificateParams::new(Vec::<String>::new());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(ca.serialize_pem().unwrap().as_bytes())
+            .unwrap();
+        let bundle = CaBundle::load(file.path()).unwrap();
+
+        (ca, bundle)
+    }
+
+    /// Run the TLS handshake of a client trusting `bundle` with a server
+    /// for `provisioner` whose certificate is signed by `ca`
+    fn handshake(ca: &rcgen::Certificate, bundle: &CaBundle) -> Result<(), rustls::Error> {
+        let server_cert =
+            rcgen::Certificate::from_params(CertificateParams::new(vec!["provisioner".into()]))
+                .unwrap();
+        let chain = vec![Certificate(
+            server_cert.serialize_der_with_signer(ca).unwrap(),
+        )];
+        let key = PrivateKey(server_cert.serialize_private_key_der());
+
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        let client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(bundle.roots().clone())
+            .with_no_client_auth();
+
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), "provisioner".try_into().unwrap())
+                .unwrap();
+
+        while client.is_handshaking() || server.is_handshaking() {
+            let mut records = Vec::new();
+            client.write_tls(&mut records).unwrap();
+            server.read_tls(&mut records.as_slice()).unwrap();
+            server.process_new_packets()?;
+
+            let mut records = Vec::new();
+            server.write_tls(&mut records).unwrap();
+            client.read_tls(&mut records.as_slice()).unwrap();
+            client.process_new_packets()?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn provisioner_certificates_are_trusted_through_the_bundle() {
+        let (ca, bundle) = self_signed_ca();
+        assert!(bundle.pem().contains("BEGIN CERTIFICATE"));
+        handshake(&ca, &bundle).unwrap();
+
+        // Not with the bundle of another CA
+        let (other_ca, _) = self_signed_ca();
+        assert!(handshake(&other_ca, &bundle).is_err());
+    }
+
+    #[test]
+    fn invalid_bundles() {
+        let load = |contents: &str| {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+            CaBundle::load(file.path()).unwrap_err()
+        };
+
+        assert!(load("").contains("no certificate"));
+
+        let (ca, _) = self_signed_ca();
+        let with_key = format!(
+            "{}{}",
+            ca.serialize_pem().unwrap(),
+            ca.serialize_private_key_pem()
+        );
+        assert!(load(&with_key).contains("more than certificates"));
+
+        assert!(CaBundle::load("/does/not/exist.pem")
+            .unwrap_err()
+            .contains("failed to read"));
+    }
+}
@@ -1,19 +1,416 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures::future::{self, Future};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::sync::{oneshot, RwLock, Semaphore};
+use tokio::task::AbortHandle;
+use tokio::time::timeout;
+use tracing::{debug, error, field, info, info_span, instrument, warn, Instrument, Span};
 
-use crate::task::{BoxedTask, TaskResult};
+use crate::metrics::GatewayMetrics;
+use crate::task::{BoxedTask, Priority, Task, TaskResult};
+use crate::throttle::TokenBucket;
 use crate::{Error, ProjectName};
 
+/// How many tasks can be queued up for a [`Worker`], unless told
+/// otherwise. Past that, sending more work has to wait.
 pub const WORKER_QUEUE_SIZE: usize = 2048;
+/// How long the tasks in flight get to finish once the worker is told
+/// to shut down, unless told otherwise
+pub const DEFAULT_DRAIN_DEADLINE: Duration = Duration::from_secs(30);
+/// Longest the [`watchdog`] goes without checking on the heartbeat
+pub const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the heartbeat of a [`Worker`] can go without a beat before
+/// the [`watchdog`] considers it stalled, unless told otherwise
+pub const DEFAULT_STALL_THRESHOLD: Duration = Duration::from_secs(10 * 60);
+/// The `task_type` of the spans of tasks which do not carry typed
+/// [work](Task::work)
+pub const UNTYPED_TASK: &str = "untyped";
+/// Longest a panic message is kept, once made out by [`panic_message`]
+pub const PANIC_MESSAGE_MAX_LEN: usize = 256;
+
+/// The name of the [`Worker`] moving projects through their lifecycle,
+/// which is what workers are called unless told otherwise
+pub const LIFECYCLE_EXECUTOR: &str = "lifecycle";
+/// The name of the [`Worker`] running the periodic health checks of
+/// projects, apart from the lifecycle work so as not to hold it up
+pub const HEALTH_EXECUTOR: &str = "health";
+
+/// How many tasks are taken from each lane, for every task taken from
+/// [`Priority::Background`], when all of them have some waiting
+const LANE_WEIGHTS: [usize; 3] = [4, 2, 1];
+
+/// The intake of a [`Worker`], one queue per [`Priority`]. Lanes take
+/// turns by weight, so that the more urgent ones go first while the
+/// less urgent ones still get a go when everything is busy.
+struct Lanes<W> {
+    queues: [VecDeque<W>; 3],
+    /// How many more tasks each lane can take before the others get
+    /// their turn
+    credits: [usize; 3],
+}
+
+impl<W> Default for Lanes<W> {
+    fn default() -> Self {
+        Self {
+            queues: Default::default(),
+            credits: LANE_WEIGHTS,
+        }
+    }
+}
+
+impl<W: Task<()>> Lanes<W> {
+    fn push(&mut self, work: W) {
+        self.queues[work.priority() as usize].push_back(work);
+    }
+
+    /// Take the next task to run, if there is any
+    fn pop(&mut self) -> Option<W> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // Once every lane with work waiting has used up its turn, start
+        // over
+        let spent = (0..self.queues.len())
+            .all(|lane| self.queues[lane].is_empty() || self.credits[lane] == 0);
+        if spent {
+            self.credits = LANE_WEIGHTS;
+        }
+
+        let lane = (0..self.queues.len())
+            .find(|&lane| !self.queues[lane].is_empty() && self.credits[lane] > 0)?;
+        self.credits[lane] -= 1;
+        self.queues[lane].pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    /// How many tasks are waiting in each lane
+    fn depths(&self) -> impl Iterator<Item = (Priority, usize)> + '_ {
+        Priority::ALL
+            .into_iter()
+            .map(|priority| (priority, self.queues[priority as usize].len()))
+    }
+
+    /// Everything still waiting, most urgent first
+    fn drain(&mut self) -> impl Iterator<Item = W> + '_ {
+        self.queues.iter_mut().flat_map(|queue| queue.drain(..))
+    }
+}
+
+/// What a [`Worker`] is currently up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerStatus {
+    /// Waiting for work
+    Idle,
+    /// Working on a task
+    Processing,
+    /// Every sender is gone, or the worker was told to shut down, and
+    /// it is working through what is left
+    Draining,
+    /// Not processing anything anymore
+    Stopped,
+}
+
+/// A handle to look at the status of a [`Worker`] from the outside,
+/// even once it has started
+#[derive(Clone)]
+pub struct WorkerStatusHandle {
+    inner: Arc<RwLock<WorkerState>>,
+    heartbeat: WorkerHeartbeat,
+}
+
+struct WorkerState {
+    status: WorkerStatus,
+    /// The descriptions of the tasks being run, keyed by an id unique to
+    /// each run, oldest first
+    running: Vec<(u64, Option<String>)>,
+    next_id: u64,
+}
+
+impl WorkerStatusHandle {
+    fn new() -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(WorkerState {
+                status: WorkerStatus::Idle,
+                running: Vec::new(),
+                next_id: 0,
+            })),
+            heartbeat: WorkerHeartbeat::new(),
+        }
+    }
+
+    /// The heartbeat of the worker, to tell whether it is stalled
+    pub fn heartbeat(&self) -> &WorkerHeartbeat {
+        &self.heartbeat
+    }
+
+    /// The status of the worker, along with the description of the
+    /// task it most recently started working on (if any)
+    pub async fn get(&self) -> (WorkerStatus, Option<String>) {
+        let state = self.inner.read().await;
+        let current_task = state
+            .running
+            .last()
+            .and_then(|(_, description)| description.clone());
+
+        (state.status, current_task)
+    }
+
+    async fn set(&self, status: WorkerStatus) {
+        self.inner.write().await.status = status;
+    }
+
+    /// Note a task being started, returning the id to hand back to
+    /// [`WorkerStatusHandle::finished`]
+    async fn started(&self, status: WorkerStatus, description: Option<String>) -> u64 {
+        let mut state = self.inner.write().await;
+        state.status = status;
+        state.next_id += 1;
+
+        let id = state.next_id;
+        state.running.push((id, description));
+        id
+    }
+
+    /// Note a task being done, going to `status` unless other tasks are
+    /// still being run. Draining takes over no matter what, and a
+    /// stopped worker stays stopped.
+    async fn finished(&self, id: u64, status: WorkerStatus) {
+        let mut state = self.inner.write().await;
+        state.running.retain(|(running, _)| *running != id);
+
+        if state.status == WorkerStatus::Stopped {
+            return;
+        }
+
+        if state.running.is_empty() || status == WorkerStatus::Draining {
+            state.status = status;
+        }
+    }
+}
+
+/// A handle to tell whether a [`Worker`] is still making progress. The
+/// worker beats it every time it goes around its loop, and waiting for
+/// work does not count as being stuck, so it only goes stale when the
+/// worker is held up by the tasks it is running.
+#[derive(Clone)]
+pub struct WorkerHeartbeat {
+    last_beat: Arc<Mutex<Instant>>,
+    waiting_for_work: Arc<AtomicBool>,
+    /// The tasks taken off the queue which are not done yet, keyed by
+    /// the order they were taken in
+    in_flight: Arc<Mutex<BTreeMap<u64, InFlight>>>,
+    stalled: Arc<AtomicBool>,
+}
+
+/// A task taken off the queue by a [`Worker`]
+struct InFlight {
+    task: UnfinishedTask,
+    /// When the task started running, and how to abort it, once it has
+    running: Option<(Instant, AbortHandle)>,
+}
+
+impl WorkerHeartbeat {
+    fn new() -> Self {
+        Self {
+            last_beat: Arc::new(Mutex::new(Instant::now())),
+            waiting_for_work: Arc::default(),
+            in_flight: Arc::default(),
+            stalled: Arc::default(),
+        }
+    }
+
+    fn beat(&self) {
+        *self.last_beat.lock().unwrap() = Instant::now();
+    }
+
+    fn set_waiting_for_work(&self, waiting: bool) {
+        self.beat();
+        self.waiting_for_work.store(waiting, Ordering::SeqCst);
+    }
+
+    /// How long it has been since the worker last showed signs of life
+    pub fn age(&self) -> Duration {
+        if self.waiting_for_work.load(Ordering::SeqCst) {
+            return Duration::ZERO;
+        }
+
+        self.last_beat.lock().unwrap().elapsed()
+    }
+
+    /// Whether the [`watchdog`] found the worker stalled the last time
+    /// it looked
+    pub fn is_stalled(&self) -> bool {
+        self.stalled.load(Ordering::SeqCst)
+    }
+
+    /// The task which has been running the longest, and for how long
+    pub fn longest_running(&self) -> Option<(UnfinishedTask, Duration)> {
+        self.in_flight
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|in_flight| {
+                let (started, _) = in_flight.running.as_ref()?;
+                Some((in_flight.task.clone(), started.elapsed()))
+            })
+            .max_by_key(|(_, elapsed)| *elapsed)
+    }
+
+    /// Abort the task which has been running the longest, returning it
+    fn abort_longest_running(&self) -> Option<UnfinishedTask> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let longest = in_flight
+            .values_mut()
+            .filter(|in_flight| in_flight.running.is_some())
+            .min_by_key(|in_flight| in_flight.running.as_ref().map(|(started, _)| *started))?;
+
+        let (_, abort) = longest.running.take()?;
+        abort.abort();
+
+        Some(longest.task.clone())
+    }
+}
+
+/// Keep an eye on the `heartbeat` of a [`Worker`], flagging it as
+/// stalled and logging what it is stuck on once it goes `stall_after`
+/// without a beat. With `abort_stalled`, the task which has been running
+/// the longest is aborted to get the worker going again.
+pub async fn watchdog(heartbeat: WorkerHeartbeat, stall_after: Duration, abort_stalled: bool) {
+    let every = (stall_after / 4)
+        .min(WATCHDOG_INTERVAL)
+        .max(Duration::from_millis(1));
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        interval.tick().await;
+
+        let age = heartbeat.age();
+        if age < stall_after {
+            if heartbeat.stalled.swap(false, Ordering::SeqCst) {
+                info!(heartbeat_age = ?age, "worker is making progress again");
+            }
+            continue;
+        }
+
+        if !heartbeat.stalled.swap(true, Ordering::SeqCst) {
+            match heartbeat.longest_running() {
+                Some((task, elapsed)) => error!(
+                    project_name = ?task.project_name,
+                    task = ?task.description,
+                    ?elapsed,
+                    heartbeat_age = ?age,
+                    "worker is stalled"
+                ),
+                None => error!(heartbeat_age = ?age, "worker is stalled with nothing running"),
+            }
+        }
+
+        if abort_stalled {
+            if let Some(task) = heartbeat.abort_longest_running() {
+                warn!(
+                    project_name = ?task.project_name,
+                    task = ?task.description,
+                    "aborted the task holding up the worker"
+                );
+            }
+        }
+    }
+}
+
+/// The span the worker runs `work` in, telling what the task is about
+/// and, for typed [work](Task::work), what it was queued up for and by
+/// whom
+fn task_span<W: Task<()>>(work: &W) -> Span {
+    let span = info_span!(
+        "task",
+        project = field::Empty,
+        task_type = field::Empty,
+        origin = field::Empty,
+        request_id = field::Empty,
+    );
+
+    if let Some(project_name) = work.project_name() {
+        span.record("project", field::display(project_name));
+    }
+    match work.work() {
+        Some(work) => {
+            span.record("task_type", work.operation.as_str());
+            span.record("origin", work.origin.as_str());
+            span.record("request_id", work.request_id.as_str());
+        }
+        None => {
+            span.record("task_type", UNTYPED_TASK);
+        }
+    }
+
+    span
+}
+
+/// Make out what a panic was about from its payload, keeping it to one
+/// line of at most [`PANIC_MESSAGE_MAX_LEN`] characters so that it is
+/// fit to be logged and stored with the project
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "panicked with a non-string payload"
+    };
+
+    message
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(PANIC_MESSAGE_MAX_LEN)
+        .collect()
+}
+
+/// A task a [`Worker`] did not get through before shutting down
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnfinishedTask {
+    pub project_name: Option<ProjectName>,
+    pub description: Option<String>,
+}
+
+impl UnfinishedTask {
+    fn of<W: Task<()>>(work: &W) -> Self {
+        Self {
+            project_name: work.project_name(),
+            description: work.description(),
+        }
+    }
+}
 
 pub struct Worker<W = BoxedTask> {
     send: Option<Sender<W>>,
     recv: Receiver<W>,
+    status: WorkerStatusHandle,
+    concurrency: usize,
+    queue_size: usize,
+    rate_limit: Option<TokenBucket>,
+    executor: &'static str,
+    metrics: GatewayMetrics,
+    shutdown: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    drain_deadline: Duration,
+    unfinished: Vec<UnfinishedTask>,
 }
 
 impl<W> Default for Worker<W>
@@ -34,9 +431,82 @@ where
         Self {
             send: Some(send),
             recv,
+            status: WorkerStatusHandle::new(),
+            concurrency: 1,
+            queue_size: WORKER_QUEUE_SIZE,
+            rate_limit: None,
+            executor: LIFECYCLE_EXECUTOR,
+            metrics: GatewayMetrics::new(),
+            shutdown: None,
+            drain_deadline: DEFAULT_DRAIN_DEADLINE,
+            unfinished: Vec::new(),
         }
     }
 
+    /// Run up to `concurrency` tasks at the same time. Tasks for the
+    /// same project are still run one after the other, in the order they
+    /// were queued.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Queue up to `queue_size` tasks, instead of [`WORKER_QUEUE_SIZE`].
+    /// This has to be set before taking a [sender](Worker::sender), as
+    /// the queue is replaced.
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        let queue_size = queue_size.max(1);
+        let (send, recv) = channel(queue_size);
+
+        self.send = Some(send);
+        self.recv = recv;
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Start on no more tasks than `rate_limit` lets through, on top of
+    /// running no more than the [concurrency](Worker::with_concurrency)
+    /// at the same time
+    pub fn with_rate_limit(mut self, rate_limit: TokenBucket) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Call this worker `executor` in its metrics, instead of
+    /// [`LIFECYCLE_EXECUTOR`]
+    pub fn with_executor(mut self, executor: &'static str) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Report the tasks taken in and started on, the tasks in flight, how
+    /// long they waited, and how many are waiting in each lane to
+    /// `metrics`, labelled with the name of the
+    /// [executor](Worker::with_executor)
+    pub fn with_metrics(mut self, metrics: GatewayMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Stop taking work off the queue once `signal` resolves, giving the
+    /// tasks in flight up to `drain_deadline` to finish. Whatever does
+    /// not make it is listed by [`Worker::unfinished`] once the worker
+    /// has stopped.
+    pub fn with_shutdown<F>(mut self, signal: F, drain_deadline: Duration) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self.drain_deadline = drain_deadline;
+        self
+    }
+
+    /// The tasks which were still queued up or running when the worker
+    /// gave up on draining, oldest first
+    pub fn unfinished(&self) -> &[UnfinishedTask] {
+        &self.unfinished
+    }
+
     /// Returns a [Sender] to push work to this worker.
     ///
     /// # Panics
@@ -44,35 +514,261 @@ where
     pub fn sender(&self) -> Sender<W> {
         Sender::clone(self.send.as_ref().unwrap())
     }
+
+    /// Returns a [WorkerStatusHandle] to follow what this worker is doing.
+    pub fn status(&self) -> WorkerStatusHandle {
+        self.status.clone()
+    }
 }
 
 impl Worker<BoxedTask> {
     /// Starts the worker, waiting and processing elements from the
-    /// queue until the last sending end for the channel is dropped,
-    /// at which point this future resolves.
+    /// queue in the order given by their [priority](Task::priority),
+    /// until the last sending end for the channel is dropped or the
+    /// [shutdown signal](Worker::with_shutdown) resolves, at which point
+    /// this future resolves.
     ///
     /// # Panics
     /// If this worker has already started.
+    #[instrument(skip(self), fields(executor = self.executor, concurrency = self.concurrency))]
     pub async fn start(mut self) -> Result<Self, Error> {
         // Drop the self-sender owned by this worker to prevent a
         // deadlock if all the other senders have already been dropped
-        // at this point.
-        let _ = self.send.take().unwrap();
-        debug!("starting worker");
-
-        while let Some(mut work) = self.recv.recv().await {
-            loop {
-                match work.poll(()).await {
-                    TaskResult::Done(_) | TaskResult::Cancelled => break,
-                    TaskResult::Pending(_) | TaskResult::TryAgain => continue,
-                    TaskResult::Err(err) => {
-                        info!("task failed: {err}");
+        // at this point. Only a weak sender is kept around, to tell
+        // whether the queue is still being fed.
+        let send = self.send.take().unwrap().downgrade();
+        debug!(concurrency = self.concurrency, "starting worker");
+        self.metrics.register_executor(self.executor);
+
+        // Tasks wait for the previous task of their project before
+        // taking up a slot, so that a busy project does not hold up the
+        // others. Up to twice as many tasks as there are slots are taken
+        // off the queue, for there to be something else to run while
+        // some wait for their project.
+        let slots = Arc::new(Semaphore::new(self.concurrency));
+        let taken_max = 2 * self.concurrency;
+        let taken = Arc::new(Semaphore::new(taken_max));
+
+        // Resolves once the last task taken off the queue for each
+        // project is done
+        let mut last_of_project: HashMap<ProjectName, oneshot::Receiver<()>> = HashMap::new();
+
+        let heartbeat = self.status.heartbeat().clone();
+        let mut taken_count = 0;
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        let mut shutdown = self
+            .shutdown
+            .take()
+            .unwrap_or_else(|| Box::pin(future::pending()));
+
+        // The tasks received but not taken yet, sorted by priority
+        let mut lanes = Lanes::default();
+
+        loop {
+            heartbeat.beat();
+
+            let taken_permit = tokio::select! {
+                permit = Arc::clone(&taken).acquire_owned() => permit.unwrap(),
+                _ = &mut shutdown => {
+                    stopping.store(true, Ordering::SeqCst);
+                    break;
+                }
+            };
+
+            if lanes.is_empty() {
+                heartbeat.set_waiting_for_work(true);
+                let work = tokio::select! {
+                    work = self.recv.recv() => match work {
+                        Some(work) => work,
+                        None => break,
+                    },
+                    _ = &mut shutdown => {
+                        stopping.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                };
+                heartbeat.set_waiting_for_work(false);
+                self.metrics.record_worker_enqueued(self.executor);
+                lanes.push(work);
+            }
+
+            // Sort whatever else is queued into the lanes, so that urgent
+            // work can go ahead of what was queued before it
+            while lanes.len() < self.queue_size {
+                match self.recv.try_recv() {
+                    Ok(work) => {
+                        self.metrics.record_worker_enqueued(self.executor);
+                        lanes.push(work);
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            if let Some(rate_limit) = &self.rate_limit {
+                tokio::select! {
+                    _ = rate_limit.acquire() => {}
+                    _ = &mut shutdown => {
+                        stopping.store(true, Ordering::SeqCst);
                         break;
                     }
                 }
             }
+
+            let mut work = lanes.pop().unwrap();
+            let dequeued = Instant::now();
+            self.metrics.record_worker_dequeued(self.executor);
+
+            for (priority, depth) in lanes.depths() {
+                self.metrics
+                    .set_worker_queue_depth(self.executor, priority, depth);
+            }
+
+            taken_count += 1;
+            let taken_id = taken_count;
+            heartbeat.in_flight.lock().unwrap().insert(
+                taken_id,
+                InFlight {
+                    task: UnfinishedTask::of(&work),
+                    running: None,
+                },
+            );
+
+            // Forget about the projects which are not busy anymore
+            last_of_project.retain(|_, done| !matches!(done.try_recv(), Err(TryRecvError::Closed)));
+
+            // Made here, for it to be a child of the span of the worker
+            let span = task_span(&work);
+
+            let (done, done_recv) = oneshot::channel::<()>();
+            let previous = work
+                .project_name()
+                .and_then(|project_name| last_of_project.insert(project_name, done_recv));
+
+            let slots = Arc::clone(&slots);
+            let status = self.status.clone();
+            let metrics = self.metrics.clone();
+            let executor = self.executor;
+            let send = send.clone();
+            let heartbeat = heartbeat.clone();
+            let stopping = Arc::clone(&stopping);
+
+            tokio::spawn(async move {
+                if let Some(previous) = previous {
+                    // Never sent on, only dropped once the task is done
+                    let _ = previous.await;
+                }
+                let _slot = slots.acquire_owned().await.unwrap();
+
+                metrics.worker_task_started(executor, dequeued.elapsed());
+
+                let started = if send.upgrade().is_some() && !stopping.load(Ordering::SeqCst) {
+                    WorkerStatus::Processing
+                } else {
+                    WorkerStatus::Draining
+                };
+                let id = status.started(started, work.description()).await;
+
+                // Run on a task of its own, for the watchdog to be able
+                // to abort it should it get stuck
+                let run = tokio::spawn(
+                    async move {
+                        loop {
+                            match work.poll(()).await {
+                                TaskResult::Done(_) | TaskResult::Cancelled => break,
+                                TaskResult::Pending(_) | TaskResult::TryAgain => continue,
+                                TaskResult::Err(err) => {
+                                    info!("task failed: {err}");
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    .instrument(span),
+                );
+                if let Some(in_flight) = heartbeat.in_flight.lock().unwrap().get_mut(&taken_id) {
+                    in_flight.running = Some((Instant::now(), run.abort_handle()));
+                }
+
+                match run.await {
+                    Ok(()) => {}
+                    Err(err) if err.is_panic() => {
+                        let message = panic_message(err.into_panic().as_ref());
+                        error!(panic = message, "task panicked, carrying on");
+                        metrics.record_worker_task_panic();
+                    }
+                    Err(err) => warn!(error = %err, "task did not run to completion"),
+                }
+
+                metrics.worker_task_finished(executor);
+                heartbeat.in_flight.lock().unwrap().remove(&taken_id);
+
+                let finished = if send.upgrade().is_some() && !stopping.load(Ordering::SeqCst) {
+                    WorkerStatus::Idle
+                } else {
+                    WorkerStatus::Draining
+                };
+                status.finished(id, finished).await;
+
+                drop(done);
+                drop(taken_permit);
+            });
         }
 
+        if stopping.load(Ordering::SeqCst) {
+            self.status.set(WorkerStatus::Draining).await;
+
+            let running = heartbeat.in_flight.lock().unwrap().len();
+            info!(
+                running,
+                deadline = ?self.drain_deadline,
+                "shutting down the worker, waiting for the tasks in flight"
+            );
+
+            let drained = timeout(self.drain_deadline, taken.acquire_many(taken_max as u32))
+                .await
+                .is_ok();
+
+            // Whatever is still running by now is left to be cut short
+            // when the gateway exits, along with what was never started
+            let mut unfinished: Vec<_> = heartbeat
+                .in_flight
+                .lock()
+                .unwrap()
+                .values()
+                .map(|in_flight| in_flight.task.clone())
+                .collect();
+            let cut_short = unfinished.len();
+
+            unfinished.extend(lanes.drain().map(|work| UnfinishedTask::of(&work)));
+            self.recv.close();
+            while let Ok(work) = self.recv.try_recv() {
+                unfinished.push(UnfinishedTask::of(&work));
+            }
+
+            if drained {
+                info!(
+                    finished = running,
+                    never_started = unfinished.len(),
+                    "worker shut down"
+                );
+            } else {
+                warn!(
+                    finished = running - cut_short,
+                    cut_short,
+                    never_started = unfinished.len() - cut_short,
+                    "worker shut down before its tasks in flight were done"
+                );
+            }
+
+            self.unfinished = unfinished;
+        } else {
+            // Wait for everything taken off the queue to be done
+            let _ = taken.acquire_many(taken_max as u32).await.unwrap();
+        }
+
+        self.status.set(WorkerStatus::Stopped).await;
+
         Ok(self)
     }
 }
@@ -126,3 +822,650 @@ impl TaskRouter<BoxedTask> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+    use tracing::field::{Field, Visit};
+    use tracing::{span, Subscriber};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::{Layer, Registry};
+
+    use super::*;
+    use crate::task::{Operation, Origin, Work};
+
+    /// A task which only completes once its gate is opened, letting
+    /// it be known when it gets started
+    struct Gated {
+        name: &'static str,
+        gate: Option<oneshot::Receiver<()>>,
+        started: Option<oneshot::Sender<()>>,
+        priority: Priority,
+    }
+
+    impl Gated {
+        fn new(name: &'static str) -> (BoxedTask, oneshot::Sender<()>, oneshot::Receiver<()>) {
+            Self::with_priority(name, Priority::Background)
+        }
+
+        fn with_priority(
+            name: &'static str,
+            priority: Priority,
+        ) -> (BoxedTask, oneshot::Sender<()>, oneshot::Receiver<()>) {
+            let (open, gate) = oneshot::channel();
+            let (started, started_recv) = oneshot::channel();
+            let task = Self {
+                name,
+                gate: Some(gate),
+                started: Some(started),
+                priority,
+            };
+            (Box::new(task), open, started_recv)
+        }
+    }
+
+    #[async_trait]
+    impl Task<()> for Gated {
+        type Output = ();
+
+        type Error = Error;
+
+        async fn poll(&mut self, _ctx: ()) -> TaskResult<Self::Output, Self::Error> {
+            if let Some(started) = self.started.take() {
+                let _ = started.send(());
+            }
+            if let Some(gate) = self.gate.take() {
+                let _ = gate.await;
+            }
+            TaskResult::Done(())
+        }
+
+        fn description(&self) -> Option<String> {
+            Some(self.name.to_string())
+        }
+
+        fn project_name(&self) -> Option<ProjectName> {
+            self.name
+                .strip_prefix("project:")
+                .map(|name| name.parse().unwrap())
+        }
+
+        fn priority(&self) -> Priority {
+            self.priority
+        }
+    }
+
+    /// A task which panics as soon as it is polled, the way a bad
+    /// transition would
+    struct Panicking;
+
+    #[async_trait]
+    impl Task<()> for Panicking {
+        type Output = ();
+
+        type Error = Error;
+
+        async fn poll(&mut self, _ctx: ()) -> TaskResult<Self::Output, Self::Error> {
+            panic!("task panicked")
+        }
+    }
+
+    /// A task carrying typed work, which lets it be known when it is
+    /// done
+    struct Typed {
+        work: Work,
+        done: Option<oneshot::Sender<()>>,
+    }
+
+    impl Typed {
+        fn new(project: &str, operation: Operation) -> (BoxedTask, oneshot::Receiver<()>) {
+            let (done, done_recv) = oneshot::channel();
+            let task = Self {
+                work: Work::new(project.parse().unwrap(), operation, Origin::Api),
+                done: Some(done),
+            };
+            (Box::new(task), done_recv)
+        }
+    }
+
+    #[async_trait]
+    impl Task<()> for Typed {
+        type Output = ();
+
+        type Error = Error;
+
+        async fn poll(&mut self, _ctx: ()) -> TaskResult<Self::Output, Self::Error> {
+            if let Some(done) = self.done.take() {
+                let _ = done.send(());
+            }
+            TaskResult::Done(())
+        }
+
+        fn project_name(&self) -> Option<ProjectName> {
+            Some(self.work.project.clone())
+        }
+
+        fn work(&self) -> Option<&Work> {
+            Some(&self.work)
+        }
+    }
+
+    /// Keeps the fields of the `task` spans made while it is the
+    /// subscriber, along with the name of their parent
+    #[derive(Clone, Default)]
+    struct TaskSpans {
+        spans: Arc<Mutex<Vec<BTreeMap<String, String>>>>,
+        live: Arc<Mutex<HashMap<span::Id, usize>>>,
+    }
+
+    impl TaskSpans {
+        fn record(&self, id: &span::Id, record: impl FnOnce(&mut FieldsVisitor)) {
+            if let Some(index) = self.live.lock().unwrap().get(id) {
+                record(&mut FieldsVisitor(&mut self.spans.lock().unwrap()[*index]));
+            }
+        }
+
+        fn spans(&self) -> Vec<BTreeMap<String, String>> {
+            self.spans.lock().unwrap().clone()
+        }
+    }
+
+    struct FieldsVisitor<'a>(&'a mut BTreeMap<String, String>);
+
+    impl Visit for FieldsVisitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S> Layer<S> for TaskSpans
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+            if attrs.metadata().name() != "task" {
+                return;
+            }
+
+            let mut fields = BTreeMap::new();
+            if let Some(parent) = ctx.span(id).and_then(|span| span.parent()) {
+                fields.insert("parent".to_string(), parent.name().to_string());
+            }
+
+            let mut spans = self.spans.lock().unwrap();
+            self.live.lock().unwrap().insert(id.clone(), spans.len());
+            spans.push(fields);
+            drop(spans);
+
+            self.record(id, |visitor| attrs.record(visitor));
+        }
+
+        fn on_record(&self, id: &span::Id, values: &span::Record<'_>, _ctx: Context<'_, S>) {
+            self.record(id, |visitor| values.record(visitor));
+        }
+
+        fn on_close(&self, id: span::Id, _ctx: Context<'_, S>) {
+            self.live.lock().unwrap().remove(&id);
+        }
+    }
+
+    async fn wait_for(handle: &WorkerStatusHandle, expected: (WorkerStatus, Option<&str>)) {
+        let expected = (expected.0, expected.1.map(ToString::to_string));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while handle.get().await != expected {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("worker never got to {expected:?}"));
+    }
+
+    async fn wait_started(started: &mut oneshot::Receiver<()>) {
+        tokio::time::timeout(Duration::from_secs(5), started)
+            .await
+            .expect("task never started")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn worker_status_transitions() {
+        let worker = Worker::new();
+        let sender = worker.sender();
+        let status = worker.status();
+
+        tokio::spawn(worker.start());
+
+        wait_for(&status, (WorkerStatus::Idle, None)).await;
+
+        let (task, open, _) = Gated::new("project:matrix");
+        sender.send(task).await.unwrap();
+        wait_for(&status, (WorkerStatus::Processing, Some("project:matrix"))).await;
+
+        open.send(()).unwrap();
+        wait_for(&status, (WorkerStatus::Idle, None)).await;
+
+        // Queue up two tasks and go away while the first one is running
+        let (first, open_first, _) = Gated::new("project:reloaded");
+        let (second, open_second, _) = Gated::new("project:revolutions");
+        sender.send(first).await.unwrap();
+        sender.send(second).await.unwrap();
+        wait_for(
+            &status,
+            (WorkerStatus::Processing, Some("project:reloaded")),
+        )
+        .await;
+        drop(sender);
+
+        open_first.send(()).unwrap();
+        wait_for(
+            &status,
+            (WorkerStatus::Draining, Some("project:revolutions")),
+        )
+        .await;
+
+        open_second.send(()).unwrap();
+        wait_for(&status, (WorkerStatus::Stopped, None)).await;
+    }
+
+    #[tokio::test]
+    async fn worker_runs_projects_concurrently() {
+        let metrics = GatewayMetrics::new();
+        let worker = Worker::new()
+            .with_concurrency(2)
+            .with_metrics(metrics.clone());
+        let sender = worker.sender();
+        let status = worker.status();
+
+        tokio::spawn(worker.start());
+
+        let (first, open_first, mut first_started) = Gated::new("project:matrix");
+        let (second, open_second, mut second_started) = Gated::new("project:matrix");
+        let (other, open_other, mut other_started) = Gated::new("project:reloaded");
+        for task in [first, second, other] {
+            sender.send(task).await.unwrap();
+        }
+
+        // Another project gets going while `matrix` is busy...
+        wait_started(&mut first_started).await;
+        wait_started(&mut other_started).await;
+        assert_eq!(metrics.worker_in_flight(LIFECYCLE_EXECUTOR), 2);
+
+        // ...but the second task for `matrix` waits for the first one
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(second_started.try_recv(), Err(TryRecvError::Empty));
+
+        open_first.send(()).unwrap();
+        wait_started(&mut second_started).await;
+
+        open_second.send(()).unwrap();
+        open_other.send(()).unwrap();
+        wait_for(&status, (WorkerStatus::Idle, None)).await;
+
+        assert_eq!(metrics.worker_in_flight(LIFECYCLE_EXECUTOR), 0);
+        assert_eq!(metrics.worker_queue_wait(LIFECYCLE_EXECUTOR).1, 3);
+    }
+
+    #[test]
+    fn lanes_take_turns_by_weight() {
+        let mut lanes = Lanes::default();
+        for (name, priority, count) in [
+            ("background", Priority::Background, 6),
+            ("interactive", Priority::Interactive, 6),
+            ("health", Priority::Health, 2),
+        ] {
+            for _ in 0..count {
+                lanes.push(Gated::with_priority(name, priority).0);
+            }
+        }
+
+        assert_eq!(
+            lanes.depths().collect::<Vec<_>>(),
+            [
+                (Priority::Interactive, 6),
+                (Priority::Health, 2),
+                (Priority::Background, 6)
+            ]
+        );
+
+        let order: Vec<_> = std::iter::from_fn(|| lanes.pop())
+            .map(|task| task.description().unwrap())
+            .collect();
+        assert_eq!(
+            order,
+            [
+                "interactive",
+                "interactive",
+                "interactive",
+                "interactive",
+                "health",
+                "health",
+                "background",
+                "interactive",
+                "interactive",
+                "background",
+                "background",
+                "background",
+                "background",
+                "background",
+            ]
+        );
+        assert!(lanes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn worker_runs_interactive_work_first() {
+        let metrics = GatewayMetrics::new();
+        let worker = Worker::new().with_metrics(metrics.clone());
+        let sender = worker.sender();
+
+        // Everything is queued up before the worker gets going, so that
+        // it all gets sorted into the lanes at once
+        let (first, open_first, mut first_started) = Gated::new("project:matrix");
+        let (second, _open_second, _) = Gated::new("project:reloaded");
+        let (interactive, open_interactive, mut interactive_started) =
+            Gated::with_priority("project:revolutions", Priority::Interactive);
+        for task in [first, second, interactive] {
+            sender.send(task).await.unwrap();
+        }
+
+        tokio::spawn(worker.start());
+
+        wait_started(&mut interactive_started).await;
+        assert_eq!(first_started.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            metrics
+                .worker_queue_depth(LIFECYCLE_EXECUTOR)
+                .get(&Priority::Interactive),
+            Some(&0)
+        );
+
+        open_interactive.send(()).unwrap();
+        wait_started(&mut first_started).await;
+        open_first.send(()).unwrap();
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool, what: &str) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("{what} never happened"));
+    }
+
+    #[tokio::test]
+    async fn watchdog_flags_stalled_worker() {
+        let worker = Worker::new();
+        let sender = worker.sender();
+        let heartbeat = worker.status().heartbeat().clone();
+
+        tokio::spawn(worker.start());
+        tokio::spawn(watchdog(
+            heartbeat.clone(),
+            Duration::from_millis(200),
+            false,
+        ));
+
+        // Waiting for work is not being stuck
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!heartbeat.is_stalled());
+        assert_eq!(heartbeat.age(), Duration::ZERO);
+
+        // With a single slot, `matrix` holds it and `reloaded` waits for
+        // it, leaving the worker unable to take anything else
+        let (stuck, open_stuck, mut stuck_started) = Gated::new("project:matrix");
+        let (waiting, open_waiting, mut waiting_started) = Gated::new("project:reloaded");
+        for task in [stuck, waiting] {
+            sender.send(task).await.unwrap();
+        }
+        wait_started(&mut stuck_started).await;
+
+        wait_until(|| heartbeat.is_stalled(), "the stall").await;
+        let (task, elapsed) = heartbeat.longest_running().unwrap();
+        assert_eq!(task.project_name, Some("matrix".parse().unwrap()));
+        assert!(elapsed >= Duration::from_millis(200));
+
+        open_stuck.send(()).unwrap();
+        wait_started(&mut waiting_started).await;
+        wait_until(|| !heartbeat.is_stalled(), "the recovery").await;
+
+        open_waiting.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn watchdog_aborts_stalled_tasks() {
+        let worker = Worker::new();
+        let sender = worker.sender();
+        let heartbeat = worker.status().heartbeat().clone();
+
+        tokio::spawn(worker.start());
+        tokio::spawn(watchdog(
+            heartbeat.clone(),
+            Duration::from_millis(200),
+            true,
+        ));
+
+        let (stuck, _open_stuck, mut stuck_started) = Gated::new("project:matrix");
+        let (waiting, open_waiting, mut waiting_started) = Gated::new("project:reloaded");
+        for task in [stuck, waiting] {
+            sender.send(task).await.unwrap();
+        }
+        wait_started(&mut stuck_started).await;
+
+        // `matrix` never finishes on its own, so `reloaded` only gets to
+        // run once it is aborted
+        wait_started(&mut waiting_started).await;
+        wait_until(|| !heartbeat.is_stalled(), "the recovery").await;
+
+        open_waiting.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn worker_carries_on_after_task_panics() {
+        let metrics = GatewayMetrics::new();
+        let worker = Worker::new().with_metrics(metrics.clone());
+        let sender = worker.sender();
+        let status = worker.status();
+
+        tokio::spawn(worker.start());
+
+        let (after, open_after, mut after_started) = Gated::new("project:matrix");
+        sender.send(Box::new(Panicking)).await.unwrap();
+        sender.send(after).await.unwrap();
+
+        wait_started(&mut after_started).await;
+        assert_eq!(metrics.worker_task_panics(), 1);
+
+        open_after.send(()).unwrap();
+        wait_for(&status, (WorkerStatus::Idle, None)).await;
+    }
+
+    #[test]
+    fn panic_messages_are_kept_to_one_short_line() {
+        let payload: Box<dyn Any + Send> = Box::new("not yet implemented");
+        assert_eq!(panic_message(payload.as_ref()), "not yet implemented");
+
+        let payload: Box<dyn Any + Send> = Box::new(format!("bad\r\nresponse{}", "x".repeat(500)));
+        let message = panic_message(payload.as_ref());
+        assert!(message.starts_with("bad  response"));
+        assert_eq!(message.chars().count(), PANIC_MESSAGE_MAX_LEN);
+
+        let payload: Box<dyn Any + Send> = Box::new(42);
+        assert_eq!(
+            panic_message(payload.as_ref()),
+            "panicked with a non-string payload"
+        );
+    }
+
+    #[tokio::test]
+    async fn worker_runs_tasks_in_spans_of_their_own() {
+        let spans = TaskSpans::default();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(spans.clone()));
+
+        let worker = Worker::new();
+        let sender = worker.sender();
+        tokio::spawn(worker.start());
+
+        let (untyped, open_untyped, mut untyped_started) = Gated::new("project:matrix");
+        open_untyped.send(()).unwrap();
+        sender.send(untyped).await.unwrap();
+        wait_started(&mut untyped_started).await;
+
+        let operations = [
+            Operation::Start,
+            Operation::Stop,
+            Operation::Restart,
+            Operation::Destroy,
+            Operation::Recreate,
+            Operation::Refresh,
+        ];
+        for operation in operations {
+            let (typed, mut done) = Typed::new("reloaded", operation);
+            sender.send(typed).await.unwrap();
+            wait_started(&mut done).await;
+        }
+
+        let spans = spans.spans();
+        assert_eq!(spans.len(), 1 + operations.len());
+
+        assert_eq!(spans[0]["project"], "matrix");
+        assert_eq!(spans[0]["task_type"], UNTYPED_TASK);
+        assert!(!spans[0].contains_key("origin"));
+
+        for (span, operation) in spans[1..].iter().zip(operations) {
+            assert_eq!(span["project"], "reloaded");
+            assert_eq!(span["task_type"], operation.as_str());
+            assert_eq!(span["origin"], "api");
+            assert!(span.contains_key("request_id"));
+        }
+
+        // All of them come under the span of the worker
+        assert!(spans.iter().all(|span| span["parent"] == "start"));
+    }
+
+    #[tokio::test]
+    async fn executors_keep_to_their_own_budget() {
+        let metrics = GatewayMetrics::new();
+        let lifecycle = Worker::new().with_metrics(metrics.clone());
+        let health = Worker::new()
+            .with_executor(HEALTH_EXECUTOR)
+            .with_concurrency(2)
+            .with_rate_limit(TokenBucket::new(20.0, 1))
+            .with_metrics(metrics.clone());
+        let lifecycle_sender = lifecycle.sender();
+        let health_sender = health.sender();
+
+        tokio::spawn(lifecycle.start());
+        tokio::spawn(health.start());
+
+        let (stuck, open_stuck, mut stuck_started) = Gated::new("project:matrix");
+        lifecycle_sender.send(stuck).await.unwrap();
+        wait_started(&mut stuck_started).await;
+
+        // Health checks go ahead while the lifecycle worker is busy, no
+        // faster than their rate limit lets them
+        let started = Instant::now();
+        for name in [
+            "project:reloaded",
+            "project:revolutions",
+            "project:resurrections",
+        ] {
+            let (check, open_check, mut check_started) = Gated::new(name);
+            open_check.send(()).unwrap();
+            health_sender.send(check).await.unwrap();
+            wait_started(&mut check_started).await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(90));
+
+        assert_eq!(metrics.worker_dequeued(HEALTH_EXECUTOR), 3);
+        assert_eq!(metrics.worker_dequeued(LIFECYCLE_EXECUTOR), 1);
+        assert_eq!(metrics.worker_in_flight(LIFECYCLE_EXECUTOR), 1);
+
+        open_stuck.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn worker_drains_on_shutdown() {
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let worker = Worker::new().with_shutdown(
+            async move {
+                let _ = signal.await;
+            },
+            Duration::from_secs(5),
+        );
+        let sender = worker.sender();
+        let status = worker.status();
+
+        let handle = tokio::spawn(worker.start());
+
+        let (task, open, mut started) = Gated::new("project:matrix");
+        sender.send(task).await.unwrap();
+        wait_started(&mut started).await;
+
+        // The transition in flight gets to finish...
+        shutdown.send(()).unwrap();
+        wait_for(&status, (WorkerStatus::Draining, Some("project:matrix"))).await;
+        open.send(()).unwrap();
+
+        // ...after which the worker stops, with nothing left over
+        let worker = handle.await.unwrap().unwrap();
+        assert!(worker.unfinished().is_empty());
+        wait_for(&status, (WorkerStatus::Stopped, None)).await;
+    }
+
+    #[tokio::test]
+    async fn worker_lists_unfinished_work_after_drain_deadline() {
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let worker = Worker::new().with_shutdown(
+            async move {
+                let _ = signal.await;
+            },
+            Duration::from_millis(100),
+        );
+        let sender = worker.sender();
+
+        let handle = tokio::spawn(worker.start());
+
+        // With a single slot, `matrix` runs, `reloaded` is taken off the
+        // queue to wait for the slot, and `revolutions` stays queued
+        let (stuck, _open_stuck, mut stuck_started) = Gated::new("project:matrix");
+        let (waiting, _open_waiting, _) = Gated::new("project:reloaded");
+        let (queued, _open_queued, _) = Gated::new("project:revolutions");
+        for task in [stuck, waiting, queued] {
+            sender.send(task).await.unwrap();
+        }
+        wait_started(&mut stuck_started).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        shutdown.send(()).unwrap();
+
+        let worker = tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("worker never gave up on draining")
+            .unwrap()
+            .unwrap();
+
+        let unfinished: Vec<_> = worker
+            .unfinished()
+            .iter()
+            .map(|task| task.description.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            unfinished,
+            ["project:matrix", "project:reloaded", "project:revolutions"]
+        );
+        assert_eq!(
+            worker.unfinished()[0].project_name,
+            Some("matrix".parse().unwrap())
+        );
+    }
+}
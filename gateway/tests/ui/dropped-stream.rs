@@ -0,0 +1,11 @@
+#![deny(unused_must_use)]
+#![allow(dead_code)]
+
+use shuttle_gateway::project::Project;
+use shuttle_gateway::{DockerContext, EndStateExt};
+
+fn drop_stream<Ctx: DockerContext>(project: Project, ctx: &Ctx) {
+    project.into_stream(ctx);
+}
+
+fn main() {}
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+use std::thread;
+
+use shuttle_common::models::error::ErrorKind;
+use shuttle_gateway::Error;
+
+fn boxed(err: Error) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+    Box::new(err)
+}
+
+fn main() {
+    // Errors are moved out of worker tasks...
+    let err = Error::custom(ErrorKind::Internal, "docker went away");
+    let err = thread::spawn(move || err).join().unwrap();
+
+    // ...shared between them...
+    let shared = Arc::new(err);
+    let other = Arc::clone(&shared);
+    thread::spawn(move || other.to_string()).join().unwrap();
+
+    // ...and end up as the source of other errors
+    let _ = boxed(Error::from_kind(ErrorKind::NotReady));
+}